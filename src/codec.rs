@@ -12,21 +12,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::NameWithConfig;
 use crate::errors::Result;
+use serde_yaml::Value;
 use simd_json::BorrowedValue;
 use tremor_script::LineValue;
+pub(crate) mod avro;
 pub(crate) mod binflux;
+pub(crate) mod csv;
 pub(crate) mod influx;
 pub(crate) mod json;
 pub(crate) mod msgpack;
 pub(crate) mod null;
+pub(crate) mod protobuf;
 pub(crate) mod statsd;
 pub(crate) mod string;
+pub(crate) mod syslog;
 pub(crate) mod yaml;
 
 mod prelude {
     pub use super::Codec;
+    pub(crate) use super::Impl;
     pub use crate::errors::*;
+    pub use crate::utils::ConfigImpl;
     pub use simd_json::prelude::*;
     pub use tremor_script::prelude::*;
 }
@@ -39,18 +47,52 @@ pub trait Codec: Send + Sync {
     fn encode(&self, data: &BorrowedValue) -> Result<Vec<u8>>;
 }
 
+pub(crate) trait Impl {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Codec>>;
+}
+
+/// The codec setting of an onramp or offramp
+pub type CodecConfig = NameWithConfig;
+
 /// Codec lookup function
 #[cfg_attr(tarpaulin, skip)]
-pub fn lookup(name: &str) -> Result<Box<dyn Codec>> {
+pub fn lookup(name: &str, config: &Option<Value>) -> Result<Box<dyn Codec>> {
     match name {
-        "json" => Ok(Box::new(json::JSON {})),
-        "msgpack" => Ok(Box::new(msgpack::MsgPack {})),
-        "influx" => Ok(Box::new(influx::Influx {})),
-        "binflux" => Ok(Box::new(binflux::BInflux {})),
-        "null" => Ok(Box::new(null::Null {})),
-        "string" => Ok(Box::new(string::String {})),
-        "statsd" => Ok(Box::new(statsd::StatsD {})),
-        "yaml" => Ok(Box::new(yaml::YAML {})),
+        "avro" => avro::Avro::from_config(config),
+        "csv" => csv::Csv::from_config(config),
+        "json" => json::JSON::from_config(config),
+        "msgpack" => msgpack::MsgPack::from_config(config),
+        "influx" => influx::Influx::from_config(config),
+        "binflux" => binflux::BInflux::from_config(config),
+        "null" => null::Null::from_config(config),
+        "protobuf" => protobuf::Protobuf::from_config(config),
+        "string" => string::String::from_config(config),
+        "statsd" => statsd::StatsD::from_config(config),
+        "syslog" => syslog::Syslog::from_config(config),
+        "yaml" => yaml::YAML::from_config(config),
         _ => Err(format!("Codec '{}' not found.", name).into()),
     }
 }
+
+/// Looks up the codec for a codec setting
+pub fn from_config(codec: &CodecConfig) -> Result<Box<dyn Codec>> {
+    lookup(&codec.name, &codec.config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codec_config() -> Result<()> {
+        let c: CodecConfig = serde_yaml::from_str("json")?;
+        assert_eq!(CodecConfig::from("json"), c);
+        let c: CodecConfig = serde_yaml::from_str("{name: json, config: {pretty: true}}")?;
+        assert_eq!("json", c.name);
+        assert!(c.config.is_some());
+        assert!(from_config(&c).is_ok());
+        let c: CodecConfig = serde_yaml::from_str("{name: json, config: {snot: true}}")?;
+        assert!(from_config(&c).is_err());
+        Ok(())
+    }
+}
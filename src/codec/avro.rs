@@ -0,0 +1,929 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes Avro binary encoded data into records and encodes records back,
+//! validating them against the writer schema.
+//!
+//! The schema is either read from a fixed schema file, or, with a `registry`
+//! set, every message is expected in the Confluent wire format: a zero magic
+//! byte and a big endian 4 byte schema id, followed by the Avro data. Schemas
+//! are fetched from the registry on first use and kept in `cache_dir`, if
+//! the registry fails to provide a schema within 5 seconds it is asked again
+//! after a while.
+//!
+//! `bytes` and `fixed` values are represented as base64 strings.
+
+use super::prelude::*;
+use halfbrown::HashMap;
+use simd_json::borrowed::{Object, Value};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Path to the Avro schema (`.avsc`) to use for all messages
+    #[serde(default)]
+    pub schema: Option<String>,
+    /// Base URL of the schema registry to resolve schema ids with
+    #[serde(default)]
+    pub registry: Option<String>,
+    /// Directory to cache schemas fetched from the registry in
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+    /// Id of the schema to encode with when using a registry
+    #[serde(default)]
+    pub schema_id: Option<u32>,
+}
+
+impl ConfigImpl for Config {}
+
+const MAGIC_BYTE: u8 = 0;
+
+/// Nesting depth of records at which decoding gives up, recursive schemas
+/// could otherwise exhaust the stack
+const MAX_DEPTH: usize = 100;
+
+/// Most array items that take up no data, like `null`s, a message may
+/// hold
+const MAX_EMPTY_ITEMS: usize = 65_536;
+
+/// Time to wait before asking the registry for a schema again after it
+/// failed to provide it
+const REGISTRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest time decoding waits for the registry to provide a schema
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+struct RecordField {
+    name: String,
+    schema: Schema,
+    default: Option<Value<'static>>,
+}
+
+#[derive(Debug, Clone)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<RecordField>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    /// A reference to a named type, by full name
+    Named(String),
+}
+
+/// A parsed schema with all named types it defines
+#[derive(Debug, Clone)]
+struct Parsed {
+    root: Schema,
+    names: HashMap<String, Schema>,
+}
+
+fn full_name(name: &str, namespace: &str) -> String {
+    if name.contains('.') || namespace.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", namespace, name)
+    }
+}
+
+impl Parsed {
+    fn from_slice(schema: &[u8]) -> Result<Self> {
+        let mut schema = schema.to_vec();
+        let schema = simd_json::to_borrowed_value(&mut schema)?.clone_static();
+        let mut names = HashMap::new();
+        let root = parse_schema(&schema, "", &mut names)?;
+        Ok(Self { root, names })
+    }
+
+    fn resolve<'schema>(&'schema self, schema: &'schema Schema) -> Result<&'schema Schema> {
+        if let Schema::Named(name) = schema {
+            self.names
+                .get(name)
+                .ok_or_else(|| format!("Unknown Avro type {}", name).into())
+        } else {
+            Ok(schema)
+        }
+    }
+}
+
+fn parse_schema(
+    schema: &Value,
+    namespace: &str,
+    names: &mut HashMap<String, Schema>,
+) -> Result<Schema> {
+    if let Some(name) = schema.as_str() {
+        Ok(match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            name => {
+                // names are looked up in the enclosing namespace first
+                let full = full_name(name, namespace);
+                if names.contains_key(&full) {
+                    Schema::Named(full)
+                } else if names.contains_key(name) {
+                    Schema::Named(name.to_string())
+                } else {
+                    return Err(format!("Unknown Avro type {}", name).into());
+                }
+            }
+        })
+    } else if let Some(branches) = schema.as_array() {
+        Ok(Schema::Union(
+            branches
+                .iter()
+                .map(|b| parse_schema(b, namespace, names))
+                .collect::<Result<_>>()?,
+        ))
+    } else if let Some(o) = schema.as_object() {
+        let kind = o
+            .get("type")
+            .ok_or_else(|| Error::from("Avro schema without a type"))?;
+        let name = o.get("name").and_then(ValueTrait::as_str);
+        let kind = if let Some(kind) = kind.as_str() {
+            kind
+        } else {
+            return parse_schema(kind, namespace, names);
+        };
+        // named types define the namespace for everything nested in them
+        let (full, namespace) = if let Some(name) = name {
+            let namespace = o
+                .get("namespace")
+                .and_then(ValueTrait::as_str)
+                .unwrap_or(namespace);
+            let full = full_name(name, namespace);
+            let namespace = full.rsplitn(2, '.').nth(1).unwrap_or("").to_string();
+            (full, namespace)
+        } else {
+            (String::new(), namespace.to_string())
+        };
+        let schema = match kind {
+            "record" | "error" => {
+                // register the name first so records can refer to themselves
+                names.insert(full.clone(), Schema::Null);
+                let fields = o
+                    .get("fields")
+                    .and_then(ValueTrait::as_array)
+                    .ok_or_else(|| Error::from(format!("Avro record {} without fields", full)))?;
+                let fields = fields
+                    .iter()
+                    .map(|f| {
+                        let name = f
+                            .get("name")
+                            .and_then(ValueTrait::as_str)
+                            .ok_or_else(|| Error::from("Avro record field without a name"))?;
+                        let schema = f.get("type").ok_or_else(|| {
+                            Error::from(format!("Avro record field {} without a type", name))
+                        })?;
+                        Ok(RecordField {
+                            name: name.to_string(),
+                            schema: parse_schema(schema, &namespace, names)?,
+                            default: f.get("default").map(Value::clone_static),
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Schema::Record(fields)
+            }
+            "enum" => Schema::Enum(
+                o.get("symbols")
+                    .and_then(ValueTrait::as_array)
+                    .ok_or_else(|| Error::from(format!("Avro enum {} without symbols", full)))?
+                    .iter()
+                    .filter_map(|s| s.as_str().map(String::from))
+                    .collect(),
+            ),
+            "fixed" => Schema::Fixed(
+                o.get("size")
+                    .and_then(ValueTrait::as_u64)
+                    .and_then(|size| usize::try_from(size).ok())
+                    .ok_or_else(|| Error::from(format!("Avro fixed {} without a size", full)))?,
+            ),
+            "array" => Schema::Array(Box::new(parse_schema(
+                o.get("items")
+                    .ok_or_else(|| Error::from("Avro array without items"))?,
+                &namespace,
+                names,
+            )?)),
+            "map" => Schema::Map(Box::new(parse_schema(
+                o.get("values")
+                    .ok_or_else(|| Error::from("Avro map without values"))?,
+                &namespace,
+                names,
+            )?)),
+            // primitives with attributes, e.g. logical types
+            other => return parse_schema(&Value::from(other.to_string()), &namespace, names),
+        };
+        match schema {
+            Schema::Record(_) | Schema::Enum(_) | Schema::Fixed(_) => {
+                if full.is_empty() {
+                    return Err(format!("Avro {} without a name", kind).into());
+                }
+                names.insert(full.clone(), schema);
+                Ok(Schema::Named(full))
+            }
+            schema => Ok(schema),
+        }
+    } else {
+        Err(format!("Invalid Avro schema: {}", schema.encode()).into())
+    }
+}
+
+struct Reader<'data> {
+    data: &'data [u8],
+    pos: usize,
+    /// Number of items read that took up no data
+    empty_items: usize,
+}
+
+fn truncated() -> Error {
+    Error::from("Truncated Avro data")
+}
+
+impl<'data> Reader<'data> {
+    fn take(&mut self, len: usize) -> Result<&'data [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(truncated)?;
+        let data = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn long(&mut self) -> Result<i64> {
+        let mut v = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok((v >> 1) as i64 ^ -((v & 1) as i64));
+            }
+        }
+        Err("Invalid Avro varint".into())
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn len(&mut self) -> Result<usize> {
+        let len = self.long()?;
+        if len < 0 || len as u64 > self.data.len() as u64 {
+            Err(truncated())
+        } else {
+            Ok(len as usize)
+        }
+    }
+
+    fn bytes(&mut self) -> Result<&'data [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    /// Reads the item count of the next array or map block, items take at
+    /// least `item_width` bytes each so counts the remaining data can't
+    /// hold are rejected
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn block_len(&mut self, item_width: usize) -> Result<usize> {
+        let count = self.long()?;
+        if count < 0 {
+            // negative counts are followed by the size of the block in bytes
+            self.long()?;
+        }
+        let count = count.checked_abs().ok_or_else(truncated)? as u64;
+        if item_width == 0 {
+            let empty_items = (self.empty_items as u64).saturating_add(count);
+            if empty_items > MAX_EMPTY_ITEMS as u64 {
+                return Err(
+                    format!("Avro data has more than {} empty items", MAX_EMPTY_ITEMS).into(),
+                );
+            }
+            self.empty_items = empty_items as usize;
+        } else if count > ((self.data.len() - self.pos) / item_width) as u64 {
+            return Err(truncated());
+        }
+        Ok(count as usize)
+    }
+}
+
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+fn write_long(buf: &mut Vec<u8>, v: i64) {
+    let mut v = ((v << 1) ^ (v >> 63)) as u64;
+    while v >= 0x80 {
+        buf.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    write_long(buf, data.len() as i64);
+    buf.extend_from_slice(data);
+}
+
+impl Parsed {
+    /// The least number of bytes a value of the schema is encoded in,
+    /// either 0 or 1 as that is all array and map decoding cares about
+    fn min_width(&self, schema: &Schema, depth: usize) -> usize {
+        match self.resolve(schema) {
+            Ok(Schema::Null) | Ok(Schema::Fixed(0)) => 0,
+            Ok(Schema::Record(fields)) if depth < MAX_DEPTH => fields
+                .iter()
+                .map(|f| self.min_width(&f.schema, depth + 1))
+                .max()
+                .unwrap_or(0),
+            _ => 1,
+        }
+    }
+
+    fn decode(&self, schema: &Schema, r: &mut Reader, depth: usize) -> Result<Value<'static>> {
+        Ok(match self.resolve(schema)? {
+            Schema::Null => Value::null(),
+            Schema::Boolean => Value::from(r.take(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::from(r.long()?),
+            Schema::Float => {
+                let mut b = [0_u8; 4];
+                b.copy_from_slice(r.take(4)?);
+                Value::from(f64::from(f32::from_le_bytes(b)))
+            }
+            Schema::Double => {
+                let mut b = [0_u8; 8];
+                b.copy_from_slice(r.take(8)?);
+                Value::from(f64::from_le_bytes(b))
+            }
+            Schema::Bytes => Value::from(base64::encode(r.bytes()?)),
+            Schema::String => Value::from(std::str::from_utf8(r.bytes()?)?.to_string()),
+            Schema::Record(fields) => {
+                if depth >= MAX_DEPTH {
+                    return Err(
+                        format!("Avro record nested deeper than {} levels", MAX_DEPTH).into(),
+                    );
+                }
+                let mut record = Object::with_capacity(fields.len());
+                for field in fields {
+                    let value = self.decode(&field.schema, r, depth + 1)?;
+                    record.insert(field.name.clone().into(), value);
+                }
+                Value::from(record)
+            }
+            Schema::Enum(symbols) => {
+                let idx = r.long()?;
+                let symbol = usize::try_from(idx)
+                    .ok()
+                    .and_then(|idx| symbols.get(idx))
+                    .ok_or_else(|| Error::from(format!("Invalid Avro enum index {}", idx)))?;
+                Value::from(symbol.clone())
+            }
+            Schema::Array(items) => {
+                let item_width = self.min_width(items, depth);
+                let mut array = Vec::new();
+                loop {
+                    let len = r.block_len(item_width)?;
+                    if len == 0 {
+                        break;
+                    }
+                    for _ in 0..len {
+                        array.push(self.decode(items, r, depth)?);
+                    }
+                }
+                Value::from(array)
+            }
+            Schema::Map(values) => {
+                let mut map = Object::new();
+                loop {
+                    // every entry has at least the length of its key
+                    let len = r.block_len(1)?;
+                    if len == 0 {
+                        break;
+                    }
+                    for _ in 0..len {
+                        let key = std::str::from_utf8(r.bytes()?)?.to_string();
+                        map.insert(key.into(), self.decode(values, r, depth)?);
+                    }
+                }
+                Value::from(map)
+            }
+            Schema::Union(branches) => {
+                let idx = r.long()?;
+                let branch = usize::try_from(idx)
+                    .ok()
+                    .and_then(|idx| branches.get(idx))
+                    .ok_or_else(|| Error::from(format!("Invalid Avro union index {}", idx)))?;
+                self.decode(branch, r, depth)?
+            }
+            Schema::Fixed(size) => Value::from(base64::encode(r.take(*size)?)),
+            Schema::Named(name) => return Err(format!("Unresolved Avro type {}", name).into()),
+        })
+    }
+
+    /// A shallow check if a value fits a schema, used to pick union branches
+    fn matches(&self, schema: &Schema, value: &Value) -> bool {
+        match self.resolve(schema) {
+            Ok(Schema::Null) => value.is_null(),
+            Ok(Schema::Boolean) => value.is_bool(),
+            Ok(Schema::Int) | Ok(Schema::Long) => value.as_i64().is_some(),
+            Ok(Schema::Float) | Ok(Schema::Double) => value.cast_f64().is_some(),
+            Ok(Schema::Bytes) | Ok(Schema::String) | Ok(Schema::Fixed(_)) => value.is_str(),
+            Ok(Schema::Enum(symbols)) => value
+                .as_str()
+                .map_or(false, |s| symbols.iter().any(|sym| sym == s)),
+            Ok(Schema::Record(_)) | Ok(Schema::Map(_)) => value.is_object(),
+            Ok(Schema::Array(_)) => value.is_array(),
+            Ok(Schema::Union(_)) | Ok(Schema::Named(_)) | Err(_) => false,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn encode(&self, schema: &Schema, value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+        let mismatch = || {
+            Error::from(format!(
+                "Value {} does not match the Avro schema",
+                value.encode()
+            ))
+        };
+        match self.resolve(schema)? {
+            Schema::Null => {
+                if !value.is_null() {
+                    return Err(mismatch());
+                }
+            }
+            Schema::Boolean => buf.push(u8::from(value.as_bool().ok_or_else(mismatch)?)),
+            Schema::Int => {
+                let i = value
+                    .as_i64()
+                    .and_then(|i| i32::try_from(i).ok())
+                    .ok_or_else(mismatch)?;
+                write_long(buf, i64::from(i))
+            }
+            Schema::Long => write_long(buf, value.as_i64().ok_or_else(mismatch)?),
+            Schema::Float => {
+                let f = value.cast_f64().ok_or_else(mismatch)? as f32;
+                buf.extend_from_slice(&f.to_le_bytes())
+            }
+            Schema::Double => {
+                let f = value.cast_f64().ok_or_else(mismatch)?;
+                buf.extend_from_slice(&f.to_le_bytes())
+            }
+            Schema::Bytes => {
+                write_bytes(buf, &base64::decode(value.as_str().ok_or_else(mismatch)?)?)
+            }
+            Schema::String => write_bytes(buf, value.as_str().ok_or_else(mismatch)?.as_bytes()),
+            Schema::Record(fields) => {
+                let record = value.as_object().ok_or_else(mismatch)?;
+                for field in fields {
+                    let v = record
+                        .get(field.name.as_str())
+                        .or_else(|| field.default.as_ref())
+                        .ok_or_else(|| {
+                            Error::from(format!("Missing field {} for Avro record", field.name))
+                        })?;
+                    self.encode(&field.schema, v, buf)?;
+                }
+            }
+            Schema::Enum(symbols) => {
+                let s = value.as_str().ok_or_else(mismatch)?;
+                let idx = symbols
+                    .iter()
+                    .position(|sym| sym == s)
+                    .ok_or_else(mismatch)?;
+                write_long(buf, idx as i64);
+            }
+            Schema::Array(items) => {
+                let array = value.as_array().ok_or_else(mismatch)?;
+                if !array.is_empty() {
+                    write_long(buf, array.len() as i64);
+                    for v in array {
+                        self.encode(items, v, buf)?;
+                    }
+                }
+                write_long(buf, 0);
+            }
+            Schema::Map(values) => {
+                let map = value.as_object().ok_or_else(mismatch)?;
+                if !map.is_empty() {
+                    write_long(buf, map.len() as i64);
+                    for (k, v) in map.iter() {
+                        write_bytes(buf, k.as_bytes());
+                        self.encode(values, v, buf)?;
+                    }
+                }
+                write_long(buf, 0);
+            }
+            Schema::Union(branches) => {
+                let idx = branches
+                    .iter()
+                    .position(|b| self.matches(b, value))
+                    .ok_or_else(mismatch)?;
+                write_long(buf, idx as i64);
+                self.encode(&branches[idx], value, buf)?;
+            }
+            Schema::Fixed(size) => {
+                let data = base64::decode(value.as_str().ok_or_else(mismatch)?)?;
+                if data.len() != *size {
+                    return Err(mismatch());
+                }
+                buf.extend_from_slice(&data);
+            }
+            Schema::Named(name) => return Err(format!("Unresolved Avro type {}", name).into()),
+        }
+        Ok(())
+    }
+}
+
+/// Resolves schema ids against a Confluent compatible schema registry
+struct Registry {
+    url: String,
+    cache_dir: Option<PathBuf>,
+    schemas: HashMap<u32, Parsed>,
+    /// Schemas the registry failed to provide and when to ask again
+    failed: HashMap<u32, Instant>,
+}
+
+impl Registry {
+    async fn fetch(&self, id: u32) -> Result<Vec<u8>> {
+        let url = format!("{}/schemas/ids/{}", self.url.trim_end_matches('/'), id);
+        let mut res = surf::get(&url).await?;
+        let mut body = res.body_bytes().await?;
+        if !res.status().is_success() {
+            return Err(format!(
+                "Schema registry returned {} for schema {}",
+                res.status(),
+                id
+            )
+            .into());
+        }
+        let body = simd_json::to_borrowed_value(&mut body)?;
+        let schema = body
+            .get("schema")
+            .and_then(ValueTrait::as_str)
+            .ok_or_else(|| Error::from(format!("Schema registry returned no schema {}", id)))?;
+        Ok(schema.as_bytes().to_vec())
+    }
+
+    fn load(&mut self, id: u32) -> Result<&Parsed> {
+        if !self.schemas.contains_key(&id) {
+            let cached = self
+                .cache_dir
+                .as_ref()
+                .map(|d| d.join(format!("{}.avsc", id)));
+            let schema = match cached.as_ref().map(std::fs::read) {
+                Some(Ok(schema)) => schema,
+                _ => {
+                    // this blocks the decoding task, so a registry that is
+                    // down is only asked again after a while
+                    if let Some(retry) = self.failed.get(&id) {
+                        if Instant::now() < *retry {
+                            return Err(format!("Avro schema {} is unavailable", id).into());
+                        }
+                    }
+                    let fetched = async_std::task::block_on(async_std::future::timeout(
+                        REGISTRY_TIMEOUT,
+                        self.fetch(id),
+                    ))
+                    .unwrap_or_else(|_| {
+                        Err(format!("Schema registry timed out for schema {}", id).into())
+                    });
+                    let schema = match fetched {
+                        Ok(schema) => schema,
+                        Err(e) => {
+                            self.failed.insert(id, Instant::now() + REGISTRY_BACKOFF);
+                            return Err(e);
+                        }
+                    };
+                    self.failed.remove(&id);
+                    if let Some(cached) = cached {
+                        if let Err(e) = std::fs::write(&cached, &schema) {
+                            warn!("Failed to cache Avro schema {}: {}", cached.display(), e);
+                        }
+                    }
+                    schema
+                }
+            };
+            self.schemas.insert(id, Parsed::from_slice(&schema)?);
+        }
+        self.schemas
+            .get(&id)
+            .ok_or_else(|| format!("Unknown Avro schema {}", id).into())
+    }
+}
+
+enum Mode {
+    /// A fixed schema, messages are plain Avro data
+    Schema(Parsed),
+    /// Confluent wire format, with the schema to encode with
+    Registry(Registry, Option<(u32, Parsed)>),
+}
+
+pub struct Avro {
+    mode: Mode,
+}
+
+impl Avro {
+    fn with_config(config: Config) -> Result<Self> {
+        let mode = match (config.schema, config.registry) {
+            (Some(schema), None) => {
+                let data = std::fs::read(&schema).map_err(|e| {
+                    Error::from(format!("Failed to read Avro schema {}: {}", schema, e))
+                })?;
+                Mode::Schema(Parsed::from_slice(&data)?)
+            }
+            (None, Some(url)) => {
+                let cache_dir = config.cache_dir.map(PathBuf::from);
+                if let Some(cache_dir) = &cache_dir {
+                    std::fs::create_dir_all(cache_dir)?;
+                }
+                let mut registry = Registry {
+                    url,
+                    cache_dir,
+                    schemas: HashMap::new(),
+                    failed: HashMap::new(),
+                };
+                let writer = if let Some(id) = config.schema_id {
+                    Some((id, registry.load(id)?.clone()))
+                } else {
+                    None
+                };
+                Mode::Registry(registry, writer)
+            }
+            _ => return Err("The Avro codec needs either a `schema` or a `registry`".into()),
+        };
+        Ok(Self { mode })
+    }
+}
+
+impl Impl for Avro {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        if let Some(config) = config {
+            Ok(Box::new(Self::with_config(Config::new(config)?)?))
+        } else {
+            Err("Missing config for avro codec".into())
+        }
+    }
+}
+
+impl Codec for Avro {
+    fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
+        let value = match &mut self.mode {
+            Mode::Schema(schema) => {
+                let mut r = Reader {
+                    data: &data,
+                    pos: 0,
+                    empty_items: 0,
+                };
+                schema.decode(&schema.root, &mut r, 0)?
+            }
+            Mode::Registry(registry, _) => {
+                if data.len() < 5 || data[0] != MAGIC_BYTE {
+                    return Err("Avro data is not in the schema registry wire format".into());
+                }
+                let mut id = [0_u8; 4];
+                id.copy_from_slice(&data[1..5]);
+                let schema = registry.load(u32::from_be_bytes(id))?;
+                let mut r = Reader {
+                    data: &data,
+                    pos: 5,
+                    empty_items: 0,
+                };
+                schema.decode(&schema.root, &mut r, 0)?
+            }
+        };
+        Ok(Some(LineValue::new(vec![data], |_| value.into())))
+    }
+
+    fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match &self.mode {
+            Mode::Schema(schema) => schema.encode(&schema.root, data, &mut buf)?,
+            Mode::Registry(_, Some((id, schema))) => {
+                buf.push(MAGIC_BYTE);
+                buf.extend_from_slice(&id.to_be_bytes());
+                schema.encode(&schema.root, data, &mut buf)?;
+            }
+            Mode::Registry(_, None) => {
+                return Err("The Avro codec needs a `schema_id` to encode with".into())
+            }
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::task;
+    use simd_json::json;
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Event",
+        "namespace": "test",
+        "fields": [
+            {"name": "name", "type": "string"},
+            {"name": "count", "type": "long"},
+            {"name": "ratio", "type": ["null", "double"], "default": null},
+            {"name": "level", "type": {"type": "enum", "name": "Level", "symbols": ["DEBUG", "INFO"]}},
+            {"name": "tags", "type": {"type": "map", "values": "string"}},
+            {"name": "parent", "type": ["null", "Event"], "default": null},
+            {"name": "ids", "type": {"type": "array", "items": "int"}}
+        ]
+    }"#;
+
+    fn decode(codec: &mut Avro, data: Vec<u8>) -> Result<Value<'static>> {
+        let v = codec.decode(data, 0)?.expect("no value");
+        Ok(v.suffix().value().clone_static())
+    }
+
+    #[test]
+    fn schema_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("event.avsc");
+        std::fs::write(&path, SCHEMA)?;
+        let mut codec = Avro::with_config(Config {
+            schema: Some(path.to_string_lossy().to_string()),
+            ..Config::default()
+        })?;
+        let event: Value = json!({
+            "name": "snot",
+            "count": -2,
+            "ratio": 0.5,
+            "level": "INFO",
+            "tags": {"badger": "yes"},
+            "parent": {
+                "name": "badger",
+                "count": 1,
+                "ratio": null,
+                "level": "DEBUG",
+                "tags": {},
+                "parent": null,
+                "ids": []
+            },
+            "ids": [1, 2, 3]
+        })
+        .into();
+        let data = codec.encode(&event)?;
+        assert_eq!(&[0x08, b's', b'n', b'o', b't', 0x03], &data[0..6]);
+        assert_eq!(event, decode(&mut codec, data)?);
+
+        // defaults fill in missing fields, wrong types are rejected
+        let minimal: Value = json!({
+            "name": "snot", "count": 1, "level": "INFO", "tags": {}, "ids": []
+        })
+        .into();
+        assert!(codec.encode(&minimal).is_ok());
+        let invalid: Value = json!({
+            "name": "snot", "count": 1, "level": "WARN", "tags": {}, "ids": []
+        })
+        .into();
+        assert!(codec.encode(&invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bounded_input() -> Result<()> {
+        let schema = |schema: &str| -> Result<Avro> {
+            Ok(Avro {
+                mode: Mode::Schema(Parsed::from_slice(schema.as_bytes())?),
+            })
+        };
+        let mut nulls = schema(r#"{"type": "array", "items": "null"}"#)?;
+        let mut data = Vec::new();
+        write_long(&mut data, 2);
+        write_long(&mut data, 0);
+        assert_eq!(Value::from(json!([null, null])), decode(&mut nulls, data)?);
+        let mut data = Vec::new();
+        write_long(&mut data, 1 << 40);
+        assert!(nulls.decode(data, 0).is_err());
+
+        let mut ints = schema(r#"{"type": "array", "items": "int"}"#)?;
+        let mut data = Vec::new();
+        write_long(&mut data, 1000);
+        write_long(&mut data, 1);
+        assert!(ints.decode(data, 0).is_err());
+
+        // events nested through their parents deeper than allowed
+        let mut events = schema(SCHEMA)?;
+        let mut data = Vec::new();
+        for _ in 0..MAX_DEPTH {
+            // name, count, ratio, level, tags and a parent
+            data.extend_from_slice(&[0, 0, 0, 0, 0, 2]);
+        }
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0]);
+        data.extend(std::iter::repeat(0).take(MAX_DEPTH));
+        assert!(events.decode(data, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn registry_backoff() -> Result<()> {
+        let mut codec = Avro::with_config(Config {
+            registry: Some("http://127.0.0.1:1".to_string()),
+            ..Config::default()
+        })?;
+        assert!(codec.decode(vec![0, 0, 0, 0, 9, 0], 0).is_err());
+        if let Mode::Registry(registry, _) = &codec.mode {
+            assert!(registry.failed.contains_key(&9));
+        }
+        // the failure is remembered instead of asking the registry again
+        assert!(codec.decode(vec![0, 0, 0, 0, 9, 0], 0).is_err());
+        Ok(())
+    }
+
+    /// Serves schema 7 like a schema registry, the listener is bound before
+    /// this returns so there is no need to wait for it
+    async fn registry_server() -> Result<String> {
+        use async_std::net::TcpListener;
+        use async_std::prelude::*;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let mut body = Object::new();
+        body.insert("schema".into(), Value::from(SCHEMA));
+        let body = Value::from(body).encode();
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut head = Vec::new();
+                let mut buf = [0_u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = if head.starts_with(b"GET /schemas/ids/7 ") {
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_string()
+                };
+                stream.write_all(response.as_bytes()).await.ok();
+            }
+        });
+        Ok(url)
+    }
+
+    #[test]
+    fn registry() -> Result<()> {
+        let url = task::block_on(registry_server())?;
+        let cache = tempfile::tempdir()?;
+        let config = Config {
+            registry: Some(url),
+            cache_dir: Some(cache.path().to_string_lossy().to_string()),
+            schema_id: Some(7),
+            ..Config::default()
+        };
+        let mut codec = Avro::with_config(config.clone())?;
+        assert!(cache.path().join("7.avsc").exists());
+        let event: Value = json!({
+            "name": "snot", "count": 1, "ratio": null, "level": "INFO",
+            "tags": {}, "parent": null, "ids": [42]
+        })
+        .into();
+        let data = codec.encode(&event)?;
+        assert_eq!(&[0, 0, 0, 0, 7], &data[0..5]);
+        assert_eq!(event, decode(&mut codec, data.clone())?);
+
+        // unknown ids and data without the magic byte fail
+        assert!(codec.decode(vec![0, 0, 0, 0, 8, 0], 0).is_err());
+        assert!(codec.decode(vec![1, 0, 0, 0, 7, 0], 0).is_err());
+
+        // the cache is used when the registry is gone
+        let mut codec = Avro::with_config(Config {
+            registry: Some("http://127.0.0.1:1".to_string()),
+            ..config
+        })?;
+        assert_eq!(event, decode(&mut codec, data)?);
+        Ok(())
+    }
+}
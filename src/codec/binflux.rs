@@ -161,6 +161,12 @@ impl BInflux {
     }
 }
 
+impl Impl for BInflux {
+    fn from_config(_config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        Ok(Box::new(Self {}))
+    }
+}
+
 impl Codec for BInflux {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
         let r: std::result::Result<LineValue, RentalSnot> = LineValue::try_new(vec![data], |raw| {
@@ -0,0 +1,329 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes a row of delimited text per event, use it with the `lines`
+//! preprocessor. Rows become records keyed by the headers, or arrays if
+//! there are no headers.
+//!
+//! A `header_line` is only read from the first row the codec sees, see
+//! `Config::header_line`.
+
+use super::prelude::*;
+use crate::dflt;
+use csv::{QuoteStyle, ReaderBuilder, StringRecord, Terminator, WriterBuilder};
+use simd_json::borrowed::{Object, Value};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Single byte field delimiter, defaults to `","`
+    #[serde(default = "dflt_delimiter")]
+    pub delimiter: String,
+    /// Single byte quote character, defaults to `"\""`
+    #[serde(default = "dflt_quote")]
+    pub quote: String,
+    /// Handle quoted fields, defaults to true
+    #[serde(default = "dflt::d_true")]
+    pub quoting: bool,
+    /// Fixed column names
+    #[serde(default = "dflt::d")]
+    pub headers: Option<Vec<String>>,
+    /// Take the column names from the first row, defaults to false.
+    ///
+    /// The first row is taken once per codec, not per file or connection,
+    /// so sources that read several streams (globbed files, many tcp
+    /// clients) decode the header rows of all but the first one as data.
+    /// Use `headers` for those.
+    #[serde(default = "dflt::d_false")]
+    pub header_line: bool,
+    /// Turn fields that look like numbers or booleans into those,
+    /// defaults to true
+    #[serde(default = "dflt::d_true")]
+    pub infer_types: bool,
+}
+
+impl ConfigImpl for Config {}
+
+fn dflt_delimiter() -> String {
+    ",".to_string()
+}
+
+fn dflt_quote() -> String {
+    "\"".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            delimiter: dflt_delimiter(),
+            quote: dflt_quote(),
+            quoting: true,
+            headers: None,
+            header_line: false,
+            infer_types: true,
+        }
+    }
+}
+
+fn single_byte(name: &str, s: &str) -> Result<u8> {
+    if let [b] = s.as_bytes() {
+        Ok(*b)
+    } else {
+        Err(format!("CSV {} must be a single byte character, not {:?}", name, s).into())
+    }
+}
+
+#[derive(Clone)]
+pub struct Csv {
+    delimiter: u8,
+    quote: u8,
+    quoting: bool,
+    infer_types: bool,
+    /// headers still to be read from the first row
+    header_line: bool,
+    headers: Option<Vec<String>>,
+}
+
+impl Csv {
+    fn with_config(config: Config) -> Result<Self> {
+        if config.header_line && config.headers.is_some() {
+            return Err("CSV `headers` and `header_line` can not be combined".into());
+        }
+        Ok(Self {
+            delimiter: single_byte("delimiter", &config.delimiter)?,
+            quote: single_byte("quote", &config.quote)?,
+            quoting: config.quoting,
+            infer_types: config.infer_types,
+            header_line: config.header_line,
+            headers: config.headers,
+        })
+    }
+}
+
+impl Default for Csv {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            quoting: true,
+            infer_types: true,
+            header_line: false,
+            headers: None,
+        }
+    }
+}
+
+impl Impl for Csv {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        Ok(Box::new(Self::with_config(config)?))
+    }
+}
+
+fn infer(field: &str) -> Value<'static> {
+    match field {
+        "true" => Value::from(true),
+        "false" => Value::from(false),
+        _ => {
+            // only try numbers for fields that start like one, so `nan`
+            // or `inf` stay strings
+            let numeric = field.bytes().next().map_or(false, |c| {
+                c.is_ascii_digit() || c == b'-' || c == b'+' || c == b'.'
+            });
+            if numeric {
+                if let Ok(i) = field.parse::<i64>() {
+                    return Value::from(i);
+                } else if let Ok(f) = field.parse::<f64>() {
+                    return Value::from(f);
+                }
+            }
+            Value::from(field.to_string())
+        }
+    }
+}
+
+/// Strings are written as they are, everything else as JSON
+fn field(v: &Value) -> String {
+    if let Some(s) = v.as_str() {
+        s.to_string()
+    } else if v.is_null() {
+        String::new()
+    } else {
+        v.encode()
+    }
+}
+
+impl Csv {
+    fn field_value(&self, field: &str) -> Value<'static> {
+        if self.infer_types {
+            infer(field)
+        } else {
+            Value::from(field.to_string())
+        }
+    }
+}
+
+impl Codec for Csv {
+    fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
+        let mut record = StringRecord::new();
+        {
+            let mut reader = ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .delimiter(self.delimiter)
+                .quote(self.quote)
+                .quoting(self.quoting)
+                .from_reader(data.as_slice());
+            if !reader.read_record(&mut record)? {
+                return Ok(None);
+            }
+        }
+        if self.header_line {
+            self.headers = Some(record.iter().map(String::from).collect());
+            self.header_line = false;
+            return Ok(None);
+        }
+        let value = if let Some(headers) = &self.headers {
+            if record.len() > headers.len() {
+                return Err(format!(
+                    "CSV row has {} fields but there are only {} headers",
+                    record.len(),
+                    headers.len()
+                )
+                .into());
+            }
+            let mut o = Object::with_capacity(record.len());
+            for (name, f) in headers.iter().zip(record.iter()) {
+                o.insert(name.clone().into(), self.field_value(f));
+            }
+            Value::from(o)
+        } else {
+            Value::from(
+                record
+                    .iter()
+                    .map(|f| self.field_value(f))
+                    .collect::<Vec<Value<'static>>>(),
+            )
+        };
+        Ok(Some(LineValue::new(vec![data], |_| value.into())))
+    }
+
+    fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
+        let row: Vec<String> = if let Some(o) = data.as_object() {
+            if let Some(headers) = &self.headers {
+                headers
+                    .iter()
+                    .map(|h| o.get(h.as_str()).map_or_else(String::new, field))
+                    .collect()
+            } else {
+                // without headers columns are ordered by name so rows line up
+                let mut columns: Vec<_> = o.iter().collect();
+                columns.sort_by(|(a, _), (b, _)| a.cmp(b));
+                columns.into_iter().map(|(_, v)| field(v)).collect()
+            }
+        } else if let Some(a) = data.as_array() {
+            a.iter().map(field).collect()
+        } else {
+            vec![field(data)]
+        };
+        let mut writer = WriterBuilder::new()
+            .has_headers(false)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .quote_style(if self.quoting {
+                QuoteStyle::Necessary
+            } else {
+                QuoteStyle::Never
+            })
+            .terminator(Terminator::Any(b'\n'))
+            .from_writer(Vec::new());
+        writer.write_record(&row)?;
+        let mut res = writer
+            .into_inner()
+            .map_err(|e| Error::from(format!("CSV encoding error: {}", e)))?;
+        // the row terminator is left to the `lines` postprocessor
+        res.pop();
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn decode(codec: &mut Csv, row: &str) -> Result<Option<Value<'static>>> {
+        Ok(codec
+            .decode(row.as_bytes().to_vec(), 0)?
+            .map(|v| v.suffix().value().clone_static()))
+    }
+
+    #[test]
+    fn header_line() -> Result<()> {
+        let mut codec = Csv::with_config(Config {
+            header_line: true,
+            ..Config::default()
+        })?;
+        assert_eq!(None, decode(&mut codec, "name,count,ok,ratio")?);
+        let expected: Value =
+            json!({"name": "snot, badger", "count": 3, "ok": true, "ratio": 0.5}).into();
+        let decoded = decode(&mut codec, r#""snot, badger",3,true,0.5"#)?;
+        assert_eq!(Some(expected.clone()), decoded);
+        assert_eq!(
+            r#""snot, badger",3,true,0.5"#,
+            String::from_utf8(codec.encode(&expected)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tsv_without_headers() -> Result<()> {
+        let mut codec = Csv::with_config(Config {
+            delimiter: "\t".to_string(),
+            infer_types: false,
+            ..Config::default()
+        })?;
+        let expected: Value = json!(["snot", "42"]).into();
+        assert_eq!(Some(expected.clone()), decode(&mut codec, "snot\t42")?);
+        assert_eq!("snot\t42", String::from_utf8(codec.encode(&expected)?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_headers() -> Result<()> {
+        let mut codec = Csv::with_config(Config {
+            headers: Some(vec!["a".to_string(), "b".to_string()]),
+            ..Config::default()
+        })?;
+        let expected: Value = json!({"a": "nan", "b": -1}).into();
+        assert_eq!(Some(expected), decode(&mut codec, "nan,-1")?);
+        assert!(decode(&mut codec, "1,2,3").is_err());
+        // missing columns are encoded as empty fields
+        let record: Value = json!({ "b": null }).into();
+        assert_eq!(",", String::from_utf8(codec.encode(&record)?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn stable_column_order() -> Result<()> {
+        let codec = Csv::default();
+        let record: Value = json!({"c": 3, "a": 1, "b": [2]}).into();
+        assert_eq!("1,[2],3", String::from_utf8(codec.encode(&record)?)?);
+        Ok(())
+    }
+}
@@ -40,8 +40,60 @@ use super::prelude::*;
 use std::{mem, str};
 use tremor_influx as influx;
 
-#[derive(Clone)]
-pub struct Influx {}
+/// Precision of the timestamps in the line protocol
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self::Ns
+    }
+}
+
+impl Precision {
+    /// Nanoseconds per unit
+    fn factor(self) -> u64 {
+        match self {
+            Self::Ns => 1,
+            Self::Us => 1_000,
+            Self::Ms => 1_000_000,
+            Self::S => 1_000_000_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Precision of the timestamps, one of `ns`, `us`, `ms` or `s`.
+    /// Decoded events always carry nanoseconds. Defaults to `ns`
+    #[serde(default)]
+    pub precision: Precision,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Default)]
+pub struct Influx {
+    config: Config,
+}
+
+impl Impl for Influx {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        Ok(Box::new(Self { config }))
+    }
+}
 
 // This is ugly but we need to handle comments, thanks rental!
 #[allow(clippy::large_enum_variant)]
@@ -58,15 +110,23 @@ impl From<std::str::Utf8Error> for RentalSnot {
 
 impl Codec for Influx {
     fn decode(&mut self, data: Vec<u8>, ingest_ns: u64) -> Result<Option<LineValue>> {
+        let factor = self.config.precision.factor();
         let r: std::result::Result<LineValue, RentalSnot> = LineValue::try_new(vec![data], |raw| {
             // This is safe as from_utf8 does not change the memory locaiton
             // of the bytes, simply validatges that it's UTF8 and if so
             // change the type.
             //let s: &'static str = unsafe { mem::transmute(str::from_utf8(&raw[0])?) };
             let s: &str = unsafe { mem::transmute(str::from_utf8(&raw[0])?) };
-            match influx::decode::<'static, Value<'static>>(s, ingest_ns) {
+            // lines without a timestamp get the ingest time, which is
+            // scaled down here so it comes out unchanged below
+            match influx::decode::<'static, Value<'static>>(s, ingest_ns / factor) {
                 Ok(None) => Err(RentalSnot::Skip),
-                Ok(Some(v)) => Ok(v.into()),
+                Ok(Some(mut v)) => {
+                    if factor != 1 {
+                        scale_timestamp(&mut v, |ts| ts.saturating_mul(factor));
+                    }
+                    Ok(v.into())
+                }
                 Err(e) => Err(RentalSnot::Error(
                     ErrorKind::InvalidInfluxData(String::from_utf8_lossy(&raw[0]).to_string(), e)
                         .into(),
@@ -82,7 +142,25 @@ impl Codec for Influx {
     }
 
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
-        Ok(influx::encode(data)?)
+        let factor = self.config.precision.factor();
+        if factor == 1 {
+            Ok(influx::encode(data)?)
+        } else {
+            let mut data = data.clone();
+            scale_timestamp(&mut data, |ts| ts / factor);
+            Ok(influx::encode(&data)?)
+        }
+    }
+}
+
+fn scale_timestamp<F>(v: &mut Value, f: F)
+where
+    F: Fn(u64) -> u64,
+{
+    if let Some(ts) = v.get("timestamp").and_then(Value::as_u64) {
+        if let Some(o) = v.as_object_mut() {
+            o.insert("timestamp".into(), Value::from(f(ts)));
+        }
     }
 }
 
@@ -117,7 +195,7 @@ mod tests {
         })
         .into();
 
-        let codec = Influx {};
+        let codec = Influx::default();
 
         let encoded = codec.encode(&s).expect("failed to encode");
 
@@ -137,7 +215,7 @@ mod tests {
     #[test]
     pub fn decode_test() {
         let s = b"weather,location=us-midwest temperature=82 1465839830100400200".to_vec();
-        let mut codec = Influx {};
+        let mut codec = Influx::default();
 
         let decoded = codec
             .decode(s, 0)
@@ -321,7 +399,7 @@ mod tests {
         let pairs = get_data_for_tests();
 
         for case in &pairs {
-            let mut codec = Influx {};
+            let mut codec = Influx::default();
             let v = case.1.clone();
             let encoded = codec.encode(&v)?;
 
@@ -345,7 +423,7 @@ mod tests {
         let s =
             b"weather,location=us-midwest temperature=82,bug_concentration=98 1465839830100400200"
                 .to_vec();
-        let mut codec = Influx {};
+        let mut codec = Influx::default();
 
         let decoded = codec
             .decode(s, 0)
@@ -366,7 +444,7 @@ mod tests {
     #[test]
     pub fn parse_int_value() {
         let s = b"weather,location=us-midwest temperature=82i 1465839830100400200".to_vec();
-        let mut codec = Influx {};
+        let mut codec = Influx::default();
 
         let decoded = codec
             .decode(s, 0)
@@ -387,7 +465,7 @@ mod tests {
     pub fn live_usecase() {
         let s = b"kafka_BrokerTopicMetrics,agent=jmxtrans,dc=iad1,host_name=kafka-iad1-g4-1,junk=kafka_topic,kafka_type=server,metric_type=counter,topic_name=customerEmailServiceMessage BytesInPerSec=0i,BytesOutPerSec=0i,FailedFetchRequestsPerSec=0i,FetchMessageConversionsPerSec=0i,TotalFetchRequestsPerSec=1993153i 1562179275506000000".to_vec();
 
-        let mut codec = Influx {};
+        let mut codec = Influx::default();
 
         let e: Value = json!({
                     "measurement" : "kafka_BrokerTopicMetrics",
@@ -426,4 +504,45 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    pub fn precision() -> Result<()> {
+        let mut codec = Influx {
+            config: Config {
+                precision: Precision::Ms,
+            },
+        };
+        let decoded = codec
+            .decode(b"weather temperature=82i 1465839830100".to_vec(), 0)?
+            .expect("failed to decode");
+        assert_eq!(
+            Some(1_465_839_830_100_000_000),
+            decoded
+                .suffix()
+                .value()
+                .get("timestamp")
+                .and_then(Value::as_u64)
+        );
+        let encoded = codec.encode(decoded.suffix().value())?;
+        assert_eq!(
+            "weather temperature=82i 1465839830100",
+            str::from_utf8(&encoded)?
+        );
+
+        let decoded = codec
+            .decode(
+                b"weather temperature=82i".to_vec(),
+                1_465_839_830_100_400_200,
+            )?
+            .expect("failed to decode");
+        assert_eq!(
+            Some(1_465_839_830_100_000_000),
+            decoded
+                .suffix()
+                .value()
+                .get("timestamp")
+                .and_then(Value::as_u64)
+        );
+        Ok(())
+    }
 }
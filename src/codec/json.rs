@@ -14,8 +14,31 @@
 
 use super::prelude::*;
 
-#[derive(Clone)]
-pub struct JSON {}
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Encode with indentation and newlines, defaults to false
+    #[serde(default)]
+    pub pretty: bool,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Default)]
+pub struct JSON {
+    config: Config,
+}
+
+impl Impl for JSON {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        Ok(Box::new(Self { config }))
+    }
+}
 
 impl Codec for JSON {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
@@ -27,7 +50,11 @@ impl Codec for JSON {
     }
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
         let mut v = Vec::new();
-        data.write(&mut v)?;
+        if self.config.pretty {
+            data.write_pretty(&mut v)?;
+        } else {
+            data.write(&mut v)?;
+        }
         Ok(v)
     }
 }
@@ -44,7 +71,7 @@ mod test {
         let seed: OwnedValue = json!({ "snot": "badger" });
         let seed: BorrowedValue = seed.into();
 
-        let mut codec = JSON {};
+        let mut codec = JSON::default();
         let as_raw = codec.encode(&seed)?;
         let as_json = codec.decode(as_raw, 0);
 
@@ -52,4 +79,20 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_json_codec_pretty() -> Result<()> {
+        let seed: OwnedValue = json!({ "snot": "badger" });
+        let seed: BorrowedValue = seed.into();
+
+        let mut codec = JSON {
+            config: Config { pretty: true },
+        };
+        let as_raw = codec.encode(&seed)?;
+        assert!(as_raw.contains(&b'\n'));
+        let decoded = codec.decode(as_raw, 0)?.expect("no value");
+        assert_eq!(&seed, decoded.suffix().value());
+
+        Ok(())
+    }
 }
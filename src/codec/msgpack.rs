@@ -15,8 +15,32 @@
 use super::prelude::*;
 use rmp_serde as rmps;
 
-#[derive(Clone)]
-pub struct MsgPack {}
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Encode structures as maps keyed by field name rather than as arrays,
+    /// defaults to false
+    #[serde(default)]
+    pub struct_map: bool,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Default)]
+pub struct MsgPack {
+    config: Config,
+}
+
+impl Impl for MsgPack {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        Ok(Box::new(Self { config }))
+    }
+}
 
 impl Codec for MsgPack {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
@@ -27,7 +51,11 @@ impl Codec for MsgPack {
         .map_err(|e| e.0.into())
     }
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
-        Ok(rmps::to_vec(&data)?)
+        if self.config.struct_map {
+            Ok(rmps::to_vec_named(&data)?)
+        } else {
+            Ok(rmps::to_vec(&data)?)
+        }
     }
 }
 
@@ -43,7 +71,7 @@ mod test {
         let seed: OwnedValue = json!({ "snot": "badger" });
         let seed: BorrowedValue = seed.into();
 
-        let mut codec = MsgPack {};
+        let mut codec = MsgPack::default();
         let as_raw = codec.encode(&seed)?;
         let as_json = codec.decode(as_raw, 0);
 
@@ -17,6 +17,12 @@ use super::prelude::*;
 #[derive(Clone)]
 pub struct Null {}
 
+impl Impl for Null {
+    fn from_config(_config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        Ok(Box::new(Self {}))
+    }
+}
+
 impl Codec for Null {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
         Ok(Some(LineValue::new(vec![data], |_| Value::null().into())))
@@ -0,0 +1,928 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes protocol buffer messages of a single type into records and
+//! encodes records back. The message layout is taken from a compiled
+//! `FileDescriptorSet`, as written by `protoc --descriptor_set_out`.
+//!
+//! Enums are decoded to their names, repeated fields to arrays, map fields
+//! to records and `bytes` fields to base64 strings.
+
+use super::prelude::*;
+use halfbrown::HashMap;
+use simd_json::borrowed::{Object, Value};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Path to the compiled `FileDescriptorSet`
+    pub descriptor: String,
+    /// Fully qualified name of the message type, e.g. `my.package.Event`
+    pub message: String,
+}
+
+impl ConfigImpl for Config {}
+
+const WT_VARINT: u8 = 0;
+const WT_FIXED64: u8 = 1;
+const WT_LEN: u8 = 2;
+const WT_START_GROUP: u8 = 3;
+const WT_END_GROUP: u8 = 4;
+const WT_FIXED32: u8 = 5;
+
+/// Nesting depth of messages at which decoding gives up, like the
+/// recursion limit of the reference implementation
+const MAX_DEPTH: usize = 100;
+
+fn truncated() -> Error {
+    Error::from("Truncated protobuf data")
+}
+
+struct WireReader<'data> {
+    data: &'data [u8],
+    pos: usize,
+}
+
+impl<'data> WireReader<'data> {
+    fn new(data: &'data [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut v = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.data.get(self.pos).ok_or_else(truncated)?;
+            self.pos += 1;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err("Invalid protobuf varint".into())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'data [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(truncated)?;
+        let data = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
+
+    fn fixed32(&mut self) -> Result<u32> {
+        let mut b = [0_u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn fixed64(&mut self) -> Result<u64> {
+        let mut b = [0_u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn len_delimited(&mut self) -> Result<&'data [u8]> {
+        let len = self.varint()?;
+        if len > self.data.len() as u64 {
+            return Err(truncated());
+        }
+        self.take(len as usize)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(std::str::from_utf8(self.len_delimited()?)?.to_string())
+    }
+
+    /// Returns the field number and wire type of the next field
+    #[allow(clippy::cast_possible_truncation)]
+    fn key(&mut self) -> Result<(u64, u8)> {
+        let key = self.varint()?;
+        Ok((key >> 3, (key & 0x07) as u8))
+    }
+
+    fn skip(&mut self, wire_type: u8) -> Result<()> {
+        match wire_type {
+            WT_VARINT => self.varint().map(|_| ()),
+            WT_FIXED64 => self.take(8).map(|_| ()),
+            WT_LEN => self.len_delimited().map(|_| ()),
+            WT_FIXED32 => self.take(4).map(|_| ()),
+            WT_START_GROUP => {
+                // groups are skipped up to their matching end, counting
+                // nested groups instead of recursing into them
+                let mut depth = 1_usize;
+                while depth > 0 {
+                    match self.key()? {
+                        (_, WT_START_GROUP) => depth += 1,
+                        (_, WT_END_GROUP) => depth -= 1,
+                        (_, wt) => self.skip(wt)?,
+                    }
+                }
+                Ok(())
+            }
+            WT_END_GROUP => Err("Unexpected protobuf group end".into()),
+            other => Err(format!("Unsupported protobuf wire type {}", other).into()),
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, number: u64, wire_type: u8) {
+    write_varint(buf, number << 3 | u64::from(wire_type));
+}
+
+fn write_len(buf: &mut Vec<u8>, data: &[u8]) {
+    write_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Unsigned values that fit are kept signed, like the json codec does
+#[allow(clippy::cast_possible_wrap)]
+fn unsigned(v: u64) -> Value<'static> {
+    if v <= i64::max_value() as u64 {
+        Value::from(v as i64)
+    } else {
+        Value::from(v)
+    }
+}
+
+/// `FieldDescriptorProto.Type`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Group,
+    Message,
+    Bytes,
+    Uint32,
+    Enum,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+}
+
+impl FieldType {
+    fn from_u64(t: u64) -> Result<Self> {
+        Ok(match t {
+            1 => Self::Double,
+            2 => Self::Float,
+            3 => Self::Int64,
+            4 => Self::Uint64,
+            5 => Self::Int32,
+            6 => Self::Fixed64,
+            7 => Self::Fixed32,
+            8 => Self::Bool,
+            9 => Self::String,
+            10 => Self::Group,
+            11 => Self::Message,
+            12 => Self::Bytes,
+            13 => Self::Uint32,
+            14 => Self::Enum,
+            15 => Self::Sfixed32,
+            16 => Self::Sfixed64,
+            17 => Self::Sint32,
+            18 => Self::Sint64,
+            other => return Err(format!("Unknown protobuf field type {}", other).into()),
+        })
+    }
+
+    fn wire_type(self) -> u8 {
+        match self {
+            Self::Double | Self::Fixed64 | Self::Sfixed64 => WT_FIXED64,
+            Self::Float | Self::Fixed32 | Self::Sfixed32 => WT_FIXED32,
+            Self::String | Self::Bytes | Self::Message => WT_LEN,
+            Self::Group => WT_START_GROUP,
+            _ => WT_VARINT,
+        }
+    }
+
+    /// Scalar numeric types, the only ones that can be packed
+    fn is_scalar(self) -> bool {
+        match self {
+            Self::String | Self::Bytes | Self::Message | Self::Group => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    number: u64,
+    kind: FieldType,
+    /// fully qualified name of message and enum types
+    type_name: String,
+    repeated: bool,
+    packed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Message {
+    fields: Vec<Field>,
+    map_entry: bool,
+}
+
+/// Looks for a bool option in an options message
+fn bool_option(data: &[u8], number: u64) -> Result<Option<bool>> {
+    let mut r = WireReader::new(data);
+    let mut res = None;
+    while !r.is_empty() {
+        match r.key()? {
+            (n, WT_VARINT) if n == number => res = Some(r.varint()? != 0),
+            (_, wt) => r.skip(wt)?,
+        }
+    }
+    Ok(res)
+}
+
+impl Field {
+    fn parse(data: &[u8], proto3: bool) -> Result<Self> {
+        let mut r = WireReader::new(data);
+        let mut name = String::new();
+        let mut number = 0;
+        let mut repeated = false;
+        let mut kind = None;
+        let mut type_name = String::new();
+        let mut packed = None;
+        while !r.is_empty() {
+            match r.key()? {
+                (1, WT_LEN) => name = r.string()?,
+                (3, WT_VARINT) => number = r.varint()?,
+                (4, WT_VARINT) => repeated = r.varint()? == 3,
+                (5, WT_VARINT) => kind = Some(FieldType::from_u64(r.varint()?)?),
+                (6, WT_LEN) => type_name = r.string()?,
+                (8, WT_LEN) => packed = bool_option(r.len_delimited()?, 2)?,
+                (_, wt) => r.skip(wt)?,
+            }
+        }
+        let kind =
+            kind.ok_or_else(|| Error::from(format!("Protobuf field {} has no type", name)))?;
+        // proto3 packs repeated scalars unless told otherwise
+        let packed = repeated && kind.is_scalar() && packed.unwrap_or(proto3);
+        Ok(Self {
+            name,
+            number,
+            kind,
+            type_name,
+            repeated,
+            packed,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Descriptors {
+    messages: HashMap<String, Message>,
+    enums: HashMap<String, Vec<(String, i32)>>,
+}
+
+impl Descriptors {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut descriptors = Self::default();
+        let mut r = WireReader::new(data);
+        while !r.is_empty() {
+            match r.key()? {
+                (1, WT_LEN) => descriptors.parse_file(r.len_delimited()?)?,
+                (_, wt) => r.skip(wt)?,
+            }
+        }
+        Ok(descriptors)
+    }
+
+    fn parse_file(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = WireReader::new(data);
+        let mut package = String::new();
+        let mut proto3 = false;
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        while !r.is_empty() {
+            match r.key()? {
+                (2, WT_LEN) => package = r.string()?,
+                (4, WT_LEN) => messages.push(r.len_delimited()?),
+                (5, WT_LEN) => enums.push(r.len_delimited()?),
+                (12, WT_LEN) => proto3 = r.len_delimited()? == b"proto3",
+                (_, wt) => r.skip(wt)?,
+            }
+        }
+        let scope = if package.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", package)
+        };
+        for m in messages {
+            self.parse_message(&scope, m, proto3)?;
+        }
+        for e in enums {
+            self.parse_enum(&scope, e)?;
+        }
+        Ok(())
+    }
+
+    fn parse_message(&mut self, scope: &str, data: &[u8], proto3: bool) -> Result<()> {
+        let mut r = WireReader::new(data);
+        let mut name = String::new();
+        let mut fields = Vec::new();
+        let mut nested = Vec::new();
+        let mut enums = Vec::new();
+        let mut map_entry = false;
+        while !r.is_empty() {
+            match r.key()? {
+                (1, WT_LEN) => name = r.string()?,
+                (2, WT_LEN) => fields.push(Field::parse(r.len_delimited()?, proto3)?),
+                (3, WT_LEN) => nested.push(r.len_delimited()?),
+                (4, WT_LEN) => enums.push(r.len_delimited()?),
+                (7, WT_LEN) => map_entry = bool_option(r.len_delimited()?, 7)?.unwrap_or(false),
+                (_, wt) => r.skip(wt)?,
+            }
+        }
+        let full_name = format!("{}.{}", scope, name);
+        for m in nested {
+            self.parse_message(&full_name, m, proto3)?;
+        }
+        for e in enums {
+            self.parse_enum(&full_name, e)?;
+        }
+        self.messages
+            .insert(full_name, Message { fields, map_entry });
+        Ok(())
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn parse_enum(&mut self, scope: &str, data: &[u8]) -> Result<()> {
+        let mut r = WireReader::new(data);
+        let mut name = String::new();
+        let mut values = Vec::new();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, WT_LEN) => name = r.string()?,
+                (2, WT_LEN) => {
+                    let mut v = WireReader::new(r.len_delimited()?);
+                    let mut value_name = String::new();
+                    let mut number = 0;
+                    while !v.is_empty() {
+                        match v.key()? {
+                            (1, WT_LEN) => value_name = v.string()?,
+                            (2, WT_VARINT) => number = v.varint()? as i32,
+                            (_, wt) => v.skip(wt)?,
+                        }
+                    }
+                    values.push((value_name, number));
+                }
+                (_, wt) => r.skip(wt)?,
+            }
+        }
+        self.enums.insert(format!("{}.{}", scope, name), values);
+        Ok(())
+    }
+
+    fn message(&self, name: &str) -> Result<&Message> {
+        self.messages
+            .get(name)
+            .ok_or_else(|| format!("Unknown protobuf message type {}", name).into())
+    }
+
+    fn is_map(&self, field: &Field) -> bool {
+        field.repeated
+            && field.kind == FieldType::Message
+            && self
+                .messages
+                .get(&field.type_name)
+                .map_or(false, |m| m.map_entry)
+    }
+
+    fn decode_message(&self, name: &str, data: &[u8], depth: usize) -> Result<Value<'static>> {
+        if depth > MAX_DEPTH {
+            return Err(format!("Protobuf message nested deeper than {} levels", MAX_DEPTH).into());
+        }
+        let message = self.message(name)?;
+        let mut record = Object::with_capacity(message.fields.len());
+        let mut r = WireReader::new(data);
+        while !r.is_empty() {
+            let (number, wire_type) = r.key()?;
+            let field = if let Some(field) = message.fields.iter().find(|f| f.number == number) {
+                field
+            } else {
+                // unknown fields are dropped
+                r.skip(wire_type)?;
+                continue;
+            };
+            if field.repeated {
+                // packed and unpacked encodings both have to be accepted
+                let mut values = if wire_type == WT_LEN && field.kind.is_scalar() {
+                    let mut packed = WireReader::new(r.len_delimited()?);
+                    let mut values = Vec::new();
+                    while !packed.is_empty() {
+                        values.push(self.decode_scalar(field, &mut packed)?);
+                    }
+                    values
+                } else {
+                    vec![self.decode_value(field, wire_type, &mut r, depth)?]
+                };
+                let entry = record
+                    .entry(field.name.clone().into())
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(a) = entry {
+                    a.append(&mut values);
+                }
+            } else {
+                let value = self.decode_value(field, wire_type, &mut r, depth)?;
+                record.insert(field.name.clone().into(), value);
+            }
+        }
+        for field in message.fields.iter().filter(|f| self.is_map(f)) {
+            if let Some(Value::Array(entries)) = record.remove(field.name.as_str()) {
+                let mut map = Object::with_capacity(entries.len());
+                for entry in entries {
+                    let key = entry.get("key").map_or_else(String::new, |k| {
+                        k.as_str().map_or_else(|| k.encode(), String::from)
+                    });
+                    let value = entry.get("value").cloned().unwrap_or_else(Value::null);
+                    map.insert(key.into(), value);
+                }
+                record.insert(field.name.clone().into(), Value::from(map));
+            }
+        }
+        Ok(Value::from(record))
+    }
+
+    fn decode_value(
+        &self,
+        field: &Field,
+        wire_type: u8,
+        r: &mut WireReader,
+        depth: usize,
+    ) -> Result<Value<'static>> {
+        if wire_type != field.kind.wire_type() {
+            return Err(format!(
+                "Unexpected wire type {} for protobuf field {}",
+                wire_type, field.name
+            )
+            .into());
+        }
+        match field.kind {
+            FieldType::String => Ok(Value::from(r.string()?)),
+            FieldType::Bytes => Ok(Value::from(base64::encode(r.len_delimited()?))),
+            FieldType::Message => {
+                self.decode_message(&field.type_name, r.len_delimited()?, depth + 1)
+            }
+            FieldType::Group => Err("Protobuf groups are not supported".into()),
+            _ => self.decode_scalar(field, r),
+        }
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_possible_wrap,
+        clippy::cast_sign_loss
+    )]
+    fn decode_scalar(&self, field: &Field, r: &mut WireReader) -> Result<Value<'static>> {
+        Ok(match field.kind {
+            FieldType::Double => Value::from(f64::from_bits(r.fixed64()?)),
+            FieldType::Float => Value::from(f64::from(f32::from_bits(r.fixed32()?))),
+            FieldType::Int64 => Value::from(r.varint()? as i64),
+            FieldType::Uint64 => unsigned(r.varint()?),
+            FieldType::Int32 => Value::from(i64::from(r.varint()? as i32)),
+            FieldType::Fixed64 => unsigned(r.fixed64()?),
+            FieldType::Fixed32 => Value::from(i64::from(r.fixed32()?)),
+            FieldType::Bool => Value::from(r.varint()? != 0),
+            FieldType::Uint32 => Value::from(i64::from(r.varint()? as u32)),
+            FieldType::Sfixed32 => Value::from(i64::from(r.fixed32()? as i32)),
+            FieldType::Sfixed64 => Value::from(r.fixed64()? as i64),
+            FieldType::Sint32 | FieldType::Sint64 => {
+                let v = r.varint()?;
+                Value::from((v >> 1) as i64 ^ -((v & 1) as i64))
+            }
+            FieldType::Enum => {
+                let number = r.varint()? as i32;
+                // values missing from the descriptor are kept as numbers
+                self.enums
+                    .get(&field.type_name)
+                    .and_then(|values| values.iter().find(|(_, n)| *n == number))
+                    .map_or_else(
+                        || Value::from(i64::from(number)),
+                        |(name, _)| Value::from(name.clone()),
+                    )
+            }
+            FieldType::String | FieldType::Bytes | FieldType::Message | FieldType::Group => {
+                return Err(format!("Protobuf field {} can not be packed", field.name).into())
+            }
+        })
+    }
+
+    fn encode_message(&self, name: &str, value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+        let message = self.message(name)?;
+        let record = value.as_object().ok_or_else(|| {
+            Error::from(format!("Expected a record for protobuf message {}", name))
+        })?;
+        for field in &message.fields {
+            match record.get(field.name.as_str()) {
+                Some(v) if !v.is_null() => self.encode_field(field, v, buf)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_field(&self, field: &Field, value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+        if !field.repeated {
+            return self.encode_single(field, value, buf);
+        }
+        if self.is_map(field) {
+            let map = value
+                .as_object()
+                .ok_or_else(|| mismatch(field, "a record"))?;
+            let key_kind = self
+                .message(&field.type_name)?
+                .fields
+                .iter()
+                .find(|f| f.name == "key")
+                .map_or(FieldType::String, |f| f.kind);
+            for (k, v) in map.iter() {
+                let mut entry = Object::with_capacity(2);
+                entry.insert("key".into(), map_key(key_kind, k));
+                entry.insert("value".into(), v.clone());
+                self.encode_single(field, &Value::from(entry), buf)?;
+            }
+        } else {
+            let values = value
+                .as_array()
+                .ok_or_else(|| mismatch(field, "an array"))?;
+            if field.packed {
+                let mut packed = Vec::new();
+                for v in values {
+                    self.encode_scalar(field, v, &mut packed)?;
+                }
+                write_key(buf, field.number, WT_LEN);
+                write_len(buf, &packed);
+            } else {
+                for v in values {
+                    self.encode_single(field, v, buf)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_single(&self, field: &Field, value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+        write_key(buf, field.number, field.kind.wire_type());
+        match field.kind {
+            FieldType::String => {
+                let s = value.as_str().ok_or_else(|| mismatch(field, "a string"))?;
+                write_len(buf, s.as_bytes());
+            }
+            FieldType::Bytes => {
+                let s = value
+                    .as_str()
+                    .ok_or_else(|| mismatch(field, "a base64 string"))?;
+                write_len(buf, &base64::decode(s)?);
+            }
+            FieldType::Message => {
+                let mut inner = Vec::new();
+                self.encode_message(&field.type_name, value, &mut inner)?;
+                write_len(buf, &inner);
+            }
+            FieldType::Group => return Err("Protobuf groups are not supported".into()),
+            _ => self.encode_scalar(field, value, buf)?,
+        }
+        Ok(())
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_possible_wrap
+    )]
+    fn encode_scalar(&self, field: &Field, value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+        let int = || value.as_i64().ok_or_else(|| mismatch(field, "an integer"));
+        let uint = || {
+            value
+                .as_u64()
+                .ok_or_else(|| mismatch(field, "a positive integer"))
+        };
+        let float = || value.cast_f64().ok_or_else(|| mismatch(field, "a number"));
+        match field.kind {
+            FieldType::Double => buf.extend_from_slice(&float()?.to_bits().to_le_bytes()),
+            FieldType::Float => buf.extend_from_slice(&(float()? as f32).to_bits().to_le_bytes()),
+            // negative numbers are sign extended to 64 bit, as the spec wants
+            FieldType::Int64 | FieldType::Int32 => write_varint(buf, int()? as u64),
+            FieldType::Uint64 | FieldType::Uint32 => write_varint(buf, uint()?),
+            FieldType::Fixed64 => buf.extend_from_slice(&uint()?.to_le_bytes()),
+            FieldType::Fixed32 => buf.extend_from_slice(&(uint()? as u32).to_le_bytes()),
+            FieldType::Sfixed64 => buf.extend_from_slice(&int()?.to_le_bytes()),
+            FieldType::Sfixed32 => buf.extend_from_slice(&(int()? as i32).to_le_bytes()),
+            FieldType::Sint32 | FieldType::Sint64 => {
+                let v = int()?;
+                write_varint(buf, ((v << 1) ^ (v >> 63)) as u64)
+            }
+            FieldType::Bool => {
+                let b = value
+                    .as_bool()
+                    .ok_or_else(|| mismatch(field, "a boolean"))?;
+                write_varint(buf, u64::from(b))
+            }
+            FieldType::Enum => {
+                let number = if let Some(name) = value.as_str() {
+                    self.enums
+                        .get(&field.type_name)
+                        .and_then(|values| values.iter().find(|(n, _)| n == name))
+                        .map(|(_, number)| *number)
+                        .ok_or_else(|| {
+                            Error::from(format!(
+                                "Unknown value {} for protobuf enum {}",
+                                name, field.type_name
+                            ))
+                        })?
+                } else {
+                    int()? as i32
+                };
+                write_varint(buf, i64::from(number) as u64)
+            }
+            FieldType::String | FieldType::Bytes | FieldType::Message | FieldType::Group => {
+                return Err(format!("Protobuf field {} can not be packed", field.name).into())
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mismatch(field: &Field, expected: &str) -> Error {
+    Error::from(format!(
+        "Expected {} for protobuf field {}",
+        expected, field.name
+    ))
+}
+
+/// Record keys are strings, map keys in protobuf can be numbers or booleans
+fn map_key(kind: FieldType, key: &str) -> Value<'static> {
+    match kind {
+        FieldType::String => Value::from(key.to_string()),
+        FieldType::Bool => Value::from(key == "true"),
+        _ => key
+            .parse::<i64>()
+            .map(Value::from)
+            .or_else(|_| key.parse::<u64>().map(Value::from))
+            .unwrap_or_else(|_| Value::from(key.to_string())),
+    }
+}
+
+pub struct Protobuf {
+    descriptors: Descriptors,
+    message: String,
+}
+
+impl Protobuf {
+    fn from_descriptor_set(data: &[u8], message: &str) -> Result<Self> {
+        let descriptors = Descriptors::parse(data)?;
+        // descriptors refer to types with a leading `.`
+        let message = if message.starts_with('.') {
+            message.to_string()
+        } else {
+            format!(".{}", message)
+        };
+        descriptors.message(&message)?;
+        Ok(Self {
+            descriptors,
+            message,
+        })
+    }
+}
+
+impl Impl for Protobuf {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        if let Some(config) = config {
+            let config = Config::new(config)?;
+            let data = std::fs::read(&config.descriptor).map_err(|e| {
+                Error::from(format!(
+                    "Failed to read protobuf descriptor set {}: {}",
+                    config.descriptor, e
+                ))
+            })?;
+            Ok(Box::new(Self::from_descriptor_set(&data, &config.message)?))
+        } else {
+            Err("Missing config for protobuf codec".into())
+        }
+    }
+}
+
+impl Codec for Protobuf {
+    fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
+        let value = self.descriptors.decode_message(&self.message, &data, 0)?;
+        Ok(Some(LineValue::new(vec![data], |_| value.into())))
+    }
+
+    fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.descriptors
+            .encode_message(&self.message, data, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn len_field(buf: &mut Vec<u8>, number: u64, data: &[u8]) {
+        write_key(buf, number, WT_LEN);
+        write_len(buf, data);
+    }
+
+    fn varint_field(buf: &mut Vec<u8>, number: u64, v: u64) {
+        write_key(buf, number, WT_VARINT);
+        write_varint(buf, v);
+    }
+
+    fn field(name: &str, number: u64, kind: u64, repeated: bool, type_name: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        len_field(&mut buf, 1, name.as_bytes());
+        varint_field(&mut buf, 3, number);
+        varint_field(&mut buf, 4, if repeated { 3 } else { 1 });
+        varint_field(&mut buf, 5, kind);
+        if !type_name.is_empty() {
+            len_field(&mut buf, 6, type_name.as_bytes());
+        }
+        buf
+    }
+
+    /// package test;
+    /// enum Level { DEBUG = 0; INFO = 1; }
+    /// message Event {
+    ///   message Origin { string host = 1; Origin parent = 2; }
+    ///   string name = 1; sint64 delta = 2; repeated uint32 ids = 3;
+    ///   Level level = 4; Origin origin = 5; map<string, double> tags = 6;
+    ///   bytes raw = 7;
+    /// }
+    fn descriptor_set() -> Vec<u8> {
+        let mut origin = Vec::new();
+        len_field(&mut origin, 1, b"Origin");
+        len_field(&mut origin, 2, &field("host", 1, 9, false, ""));
+        len_field(
+            &mut origin,
+            2,
+            &field("parent", 2, 11, false, ".test.Event.Origin"),
+        );
+
+        let mut tags_entry = Vec::new();
+        len_field(&mut tags_entry, 1, b"TagsEntry");
+        len_field(&mut tags_entry, 2, &field("key", 1, 9, false, ""));
+        len_field(&mut tags_entry, 2, &field("value", 2, 1, false, ""));
+        let mut options = Vec::new();
+        varint_field(&mut options, 7, 1);
+        len_field(&mut tags_entry, 7, &options);
+
+        let mut event = Vec::new();
+        len_field(&mut event, 1, b"Event");
+        len_field(&mut event, 2, &field("name", 1, 9, false, ""));
+        len_field(&mut event, 2, &field("delta", 2, 18, false, ""));
+        len_field(&mut event, 2, &field("ids", 3, 13, true, ""));
+        len_field(&mut event, 2, &field("level", 4, 14, false, ".test.Level"));
+        len_field(
+            &mut event,
+            2,
+            &field("origin", 5, 11, false, ".test.Event.Origin"),
+        );
+        len_field(
+            &mut event,
+            2,
+            &field("tags", 6, 11, true, ".test.Event.TagsEntry"),
+        );
+        len_field(&mut event, 2, &field("raw", 7, 12, false, ""));
+        len_field(&mut event, 3, &origin);
+        len_field(&mut event, 3, &tags_entry);
+
+        let mut level = Vec::new();
+        len_field(&mut level, 1, b"Level");
+        for (name, number) in &[("DEBUG", 0), ("INFO", 1)] {
+            let mut value = Vec::new();
+            len_field(&mut value, 1, name.as_bytes());
+            varint_field(&mut value, 2, *number);
+            len_field(&mut level, 2, &value);
+        }
+
+        let mut file = Vec::new();
+        len_field(&mut file, 1, b"test.proto");
+        len_field(&mut file, 2, b"test");
+        len_field(&mut file, 4, &event);
+        len_field(&mut file, 5, &level);
+        len_field(&mut file, 12, b"proto3");
+
+        let mut set = Vec::new();
+        len_field(&mut set, 1, &file);
+        set
+    }
+
+    fn decode(codec: &mut Protobuf, data: Vec<u8>) -> Result<Value<'static>> {
+        let v = codec.decode(data, 0)?.expect("no value");
+        Ok(v.suffix().value().clone_static())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = Protobuf::from_descriptor_set(&descriptor_set(), "test.Event")?;
+        let event: Value = json!({
+            "name": "snot",
+            "delta": -3,
+            "ids": [1, 150],
+            "level": "INFO",
+            "origin": {"host": "badger"},
+            "tags": {"cpu": 0.5},
+            "raw": "AAEC"
+        })
+        .into();
+        let data = codec.encode(&event)?;
+        // the repeated uint32 is packed as this is a proto3 file
+        assert!(data.windows(5).any(|w| w == [0x1a, 0x03, 0x01, 0x96, 0x01]));
+        assert_eq!(event, decode(&mut codec, data)?);
+        Ok(())
+    }
+
+    #[test]
+    fn unpacked_and_unknown_fields() -> Result<()> {
+        let mut codec = Protobuf::from_descriptor_set(&descriptor_set(), ".test.Event")?;
+        let mut data = Vec::new();
+        varint_field(&mut data, 3, 1);
+        varint_field(&mut data, 3, 2);
+        varint_field(&mut data, 4, 7);
+        varint_field(&mut data, 99, 1);
+        let expected: Value = json!({"ids": [1, 2], "level": 7}).into();
+        assert_eq!(expected, decode(&mut codec, data)?);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_groups() -> Result<()> {
+        let mut codec = Protobuf::from_descriptor_set(&descriptor_set(), "test.Event")?;
+        let mut data = Vec::new();
+        // group 99 holding a varint and a nested group 98
+        write_key(&mut data, 99, WT_START_GROUP);
+        varint_field(&mut data, 1, 1);
+        write_key(&mut data, 98, WT_START_GROUP);
+        write_key(&mut data, 98, WT_END_GROUP);
+        write_key(&mut data, 99, WT_END_GROUP);
+        varint_field(&mut data, 4, 7);
+        let expected: Value = json!({"level": 7}).into();
+        assert_eq!(expected, decode(&mut codec, data)?);
+        Ok(())
+    }
+
+    #[test]
+    fn nesting_limit() -> Result<()> {
+        let mut codec = Protobuf::from_descriptor_set(&descriptor_set(), "test.Event")?;
+        // origins nest through their parents deeper than allowed
+        let mut origin = Vec::new();
+        for _ in 0..MAX_DEPTH {
+            let mut parent = Vec::new();
+            len_field(&mut parent, 2, &origin);
+            origin = parent;
+        }
+        let mut data = Vec::new();
+        len_field(&mut data, 5, &origin);
+        assert!(codec.decode(data, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bad_input() -> Result<()> {
+        assert!(Protobuf::from_descriptor_set(&descriptor_set(), "test.Nope").is_err());
+        let mut codec = Protobuf::from_descriptor_set(&descriptor_set(), "test.Event")?;
+        assert!(codec.decode(vec![0x0a, 0x05, b's'], 0).is_err());
+        let wrong: Value = json!({"name": 42}).into();
+        assert!(codec.encode(&wrong).is_err());
+        Ok(())
+    }
+}
@@ -19,6 +19,12 @@ use std::str;
 #[derive(Clone)]
 pub struct StatsD {}
 
+impl Impl for StatsD {
+    fn from_config(_config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        Ok(Box::new(Self {}))
+    }
+}
+
 impl Codec for StatsD {
     fn decode(&mut self, data: Vec<u8>, ingest_ns: u64) -> Result<Option<LineValue>> {
         LineValue::try_new(vec![data], |raw| {
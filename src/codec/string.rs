@@ -18,6 +18,12 @@ use simd_json::{self, BorrowedValue as Value};
 #[derive(Clone)]
 pub struct String {}
 
+impl Impl for String {
+    fn from_config(_config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        Ok(Box::new(Self {}))
+    }
+}
+
 impl Codec for String {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
         LineValue::try_new(vec![data], |data| {
@@ -0,0 +1,522 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes RFC 5424 and RFC 3164 syslog messages into records and encodes
+//! records back, using the `protocol` field to pick the format.
+//!
+//! Timestamps are nanoseconds since the epoch, RFC 3164 timestamps carry no
+//! year or timezone and are taken as UTC in the year the message was
+//! ingested. Structured data is a record of records keyed by SD-ID.
+
+use super::prelude::*;
+use chrono::{DateTime, Datelike, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use simd_json::borrowed::{Object, Value};
+use std::convert::TryFrom;
+
+const RFC5424: &str = "RFC5424";
+const RFC3164: &str = "RFC3164";
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "clockd", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// `user.notice`, the priority of messages without one
+const DEFAULT_PRIORITY: usize = 13;
+
+const BOM: char = '\u{feff}';
+
+fn invalid(msg: &str) -> Error {
+    Error::from(format!("Invalid syslog message: {}", msg))
+}
+
+/// Splits off the next space separated field
+fn word(s: &str) -> (&str, &str) {
+    if let Some(i) = s.find(' ') {
+        (&s[..i], &s[i + 1..])
+    } else {
+        (s, "")
+    }
+}
+
+/// The `-` nil value is decoded to null
+fn nil_or_string(s: &str) -> Value<'static> {
+    if s == "-" {
+        Value::null()
+    } else {
+        Value::from(s.to_string())
+    }
+}
+
+fn priority(s: &str) -> Result<(u8, &str)> {
+    if !s.starts_with('<') {
+        return Err(invalid("missing priority"));
+    }
+    let end = s
+        .find('>')
+        .ok_or_else(|| invalid("unterminated priority"))?;
+    let pri: u8 = s[1..end]
+        .parse()
+        .map_err(|_| invalid("priority is not a number"))?;
+    if end > 4 || usize::from(pri) >= FACILITIES.len() * SEVERITIES.len() {
+        return Err(invalid("priority out of range"));
+    }
+    Ok((pri, &s[end + 1..]))
+}
+
+fn structured_data(s: &str) -> Result<(Value<'static>, &str)> {
+    if s.starts_with('-') {
+        return Ok((Value::null(), &s[1..]));
+    }
+    let bytes = s.as_bytes();
+    let mut sd = Object::new();
+    let mut i = 0;
+    while bytes.get(i) == Some(&b'[') {
+        i += 1;
+        let id_end = s[i..]
+            .find(|c| c == ' ' || c == ']')
+            .ok_or_else(|| invalid("unterminated structured data"))?
+            + i;
+        let id = s[i..id_end].to_string();
+        i = id_end;
+        let mut params = Object::new();
+        while bytes.get(i) == Some(&b' ') {
+            i += 1;
+            let eq = s[i..]
+                .find('=')
+                .ok_or_else(|| invalid("structured data parameter without a value"))?
+                + i;
+            let name = s[i..eq].to_string();
+            i = eq + 1;
+            if bytes.get(i) != Some(&b'"') {
+                return Err(invalid("unquoted structured data parameter value"));
+            }
+            i += 1;
+            let mut value = String::new();
+            loop {
+                match bytes.get(i) {
+                    Some(b'\\')
+                        if matches!(bytes.get(i + 1), Some(b'"') | Some(b'\\') | Some(b']')) =>
+                    {
+                        value.push(char::from(bytes[i + 1]));
+                        i += 2;
+                    }
+                    Some(b'"') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(_) => {
+                        let c = s[i..].chars().next().unwrap_or_default();
+                        value.push(c);
+                        i += c.len_utf8();
+                    }
+                    None => return Err(invalid("unterminated structured data parameter value")),
+                }
+            }
+            params.insert(name.into(), Value::from(value));
+        }
+        if bytes.get(i) != Some(&b']') {
+            return Err(invalid("unterminated structured data"));
+        }
+        i += 1;
+        sd.insert(id.into(), Value::from(params));
+    }
+    if i == 0 {
+        return Err(invalid("missing structured data"));
+    }
+    Ok((Value::from(sd), &s[i..]))
+}
+
+fn decode_rfc5424(s: &str) -> Result<Object<'static>> {
+    let (version, s) = word(s);
+    let version: u8 = version.parse().map_err(|_| invalid("bad version"))?;
+    let (timestamp, s) = word(s);
+    let timestamp = if timestamp == "-" {
+        Value::null()
+    } else {
+        let ts = DateTime::parse_from_rfc3339(timestamp)?;
+        // nanoseconds since the epoch only fit an i64 from 1677 to 2262
+        let ns = ts
+            .timestamp()
+            .checked_mul(1_000_000_000)
+            .and_then(|ns| ns.checked_add(i64::from(ts.timestamp_subsec_nanos())))
+            .ok_or_else(|| invalid("timestamp out of range"))?;
+        Value::from(ns)
+    };
+    let (hostname, s) = word(s);
+    let (appname, s) = word(s);
+    let (procid, s) = word(s);
+    let (msgid, s) = word(s);
+    let (sd, s) = structured_data(s)?;
+    let msg = if s.is_empty() {
+        Value::null()
+    } else if s.starts_with(' ') {
+        Value::from(s[1..].trim_start_matches(BOM).to_string())
+    } else {
+        return Err(invalid("missing space before the message"));
+    };
+
+    let mut o = Object::with_capacity(12);
+    o.insert("protocol".into(), Value::from(RFC5424));
+    o.insert("version".into(), Value::from(i64::from(version)));
+    o.insert("timestamp".into(), timestamp);
+    o.insert("hostname".into(), nil_or_string(hostname));
+    o.insert("appname".into(), nil_or_string(appname));
+    o.insert("procid".into(), nil_or_string(procid));
+    o.insert("msgid".into(), nil_or_string(msgid));
+    o.insert("structured_data".into(), sd);
+    o.insert("msg".into(), msg);
+    Ok(o)
+}
+
+/// `Mmm dd hh:mm:ss`, with the day padded by a space
+const RFC3164_TS_LEN: usize = 15;
+
+#[allow(clippy::cast_possible_wrap)]
+fn decode_rfc3164(s: &str, ingest_ns: u64) -> Object<'static> {
+    let year = Utc.timestamp_nanos(ingest_ns as i64).year();
+    let timestamp = s
+        .get(..RFC3164_TS_LEN)
+        .filter(|_| s[RFC3164_TS_LEN..].starts_with(' '))
+        .and_then(|ts| {
+            NaiveDateTime::parse_from_str(&format!("{} {}", year, ts), "%Y %b %d %H:%M:%S").ok()
+        });
+
+    let mut o = Object::with_capacity(10);
+    o.insert("protocol".into(), Value::from(RFC3164));
+    // without a timestamp there is no hostname either, it's all message
+    let s = if let Some(timestamp) = timestamp {
+        let (hostname, s) = word(&s[RFC3164_TS_LEN + 1..]);
+        o.insert("timestamp".into(), Value::from(timestamp.timestamp_nanos()));
+        o.insert("hostname".into(), Value::from(hostname.to_string()));
+        s
+    } else {
+        o.insert("timestamp".into(), Value::null());
+        o.insert("hostname".into(), Value::null());
+        s
+    };
+
+    let mut appname = Value::null();
+    let mut procid = Value::null();
+    let mut msg = s;
+    if let Some(i) = s.find(|c| c == '[' || c == ':' || c == ' ') {
+        let rest = &s[i..];
+        if rest.starts_with('[') {
+            if let Some(end) = rest.find("]:") {
+                appname = Value::from(s[..i].to_string());
+                procid = Value::from(rest[1..end].to_string());
+                msg = &rest[end + 2..];
+            }
+        } else if rest.starts_with(':') {
+            appname = Value::from(s[..i].to_string());
+            msg = &rest[1..];
+        }
+    }
+    if msg.len() < s.len() && msg.starts_with(' ') {
+        msg = &msg[1..];
+    }
+    o.insert("appname".into(), appname);
+    o.insert("procid".into(), procid);
+    o.insert("msg".into(), Value::from(msg.to_string()));
+    o
+}
+
+fn code(v: &Value, names: &[&str]) -> Result<usize> {
+    v.as_str()
+        .and_then(|s| names.iter().position(|n| *n == s))
+        .or_else(|| v.as_u64().and_then(|c| usize::try_from(c).ok()))
+        .filter(|c| *c < names.len())
+        .ok_or_else(|| format!("Invalid syslog facility or severity {}", v.encode()).into())
+}
+
+/// Takes facility and severity by name or code, or a plain `priority`
+fn encode_priority(o: &Object) -> Result<usize> {
+    let facility = o.get("facility").filter(|v| !v.is_null());
+    let severity = o.get("severity").filter(|v| !v.is_null());
+    if facility.is_some() || severity.is_some() {
+        let facility = facility.map_or(Ok(DEFAULT_PRIORITY / 8), |f| code(f, &FACILITIES))?;
+        let severity = severity.map_or(Ok(DEFAULT_PRIORITY % 8), |s| code(s, &SEVERITIES))?;
+        Ok(facility * 8 + severity)
+    } else if let Some(pri) = o.get("priority").filter(|v| !v.is_null()) {
+        pri.as_u64()
+            .and_then(|p| usize::try_from(p).ok())
+            .filter(|p| *p < FACILITIES.len() * SEVERITIES.len())
+            .ok_or_else(|| format!("Invalid syslog priority {}", pri.encode()).into())
+    } else {
+        Ok(DEFAULT_PRIORITY)
+    }
+}
+
+fn field<'value>(o: &'value Object, name: &str) -> Option<&'value str> {
+    o.get(name)
+        .and_then(ValueTrait::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+fn nil<'value>(o: &'value Object, name: &str) -> &'value str {
+    field(o, name).unwrap_or("-")
+}
+
+fn escape_param(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '"' || c == '\\' || c == ']' {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+fn encode_structured_data(sd: Option<&Value>) -> String {
+    let sd = if let Some(sd) = sd
+        .and_then(ValueTrait::as_object)
+        .filter(|sd| !sd.is_empty())
+    {
+        sd
+    } else {
+        return "-".to_string();
+    };
+    let mut res = String::new();
+    // sorted so the output is stable
+    let mut elements: Vec<_> = sd.iter().collect();
+    elements.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (id, params) in elements {
+        res.push('[');
+        res.push_str(id);
+        if let Some(params) = params.as_object() {
+            let mut params: Vec<_> = params.iter().collect();
+            params.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, value) in params {
+                let value = value.as_str().map_or_else(|| value.encode(), String::from);
+                res.push_str(&format!(" {}=\"{}\"", name, escape_param(&value)));
+            }
+        }
+        res.push(']');
+    }
+    res
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn timestamp(o: &Object) -> Option<DateTime<Utc>> {
+    o.get("timestamp")
+        .and_then(ValueTrait::as_i64)
+        .map(|ns| Utc.timestamp_nanos(ns))
+}
+
+fn encode_rfc5424(pri: usize, o: &Object) -> String {
+    let version = o.get("version").and_then(ValueTrait::as_i64).unwrap_or(1);
+    let timestamp = timestamp(o).map_or_else(
+        || nil(o, "timestamp").to_string(),
+        |ts| ts.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    );
+    let mut res = format!(
+        "<{}>{} {} {} {} {} {} {}",
+        pri,
+        version,
+        timestamp,
+        nil(o, "hostname"),
+        nil(o, "appname"),
+        nil(o, "procid"),
+        nil(o, "msgid"),
+        encode_structured_data(o.get("structured_data"))
+    );
+    if let Some(msg) = o.get("msg").and_then(ValueTrait::as_str) {
+        res.push(' ');
+        res.push_str(msg);
+    }
+    res
+}
+
+fn encode_rfc3164(pri: usize, o: &Object) -> String {
+    let mut res = format!("<{}>", pri);
+    if let Some(ts) = timestamp(o) {
+        res.push_str(&ts.format("%b %e %H:%M:%S ").to_string());
+        res.push_str(nil(o, "hostname"));
+        res.push(' ');
+    }
+    if let Some(appname) = field(o, "appname") {
+        res.push_str(appname);
+        if let Some(procid) = field(o, "procid") {
+            res.push_str(&format!("[{}]", procid));
+        }
+        res.push_str(": ");
+    }
+    if let Some(msg) = o.get("msg").and_then(ValueTrait::as_str) {
+        res.push_str(msg);
+    }
+    res
+}
+
+#[derive(Clone)]
+pub struct Syslog {}
+
+impl Impl for Syslog {
+    fn from_config(_config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        Ok(Box::new(Self {}))
+    }
+}
+
+impl Codec for Syslog {
+    fn decode(&mut self, data: Vec<u8>, ingest_ns: u64) -> Result<Option<LineValue>> {
+        let value = {
+            let s = std::str::from_utf8(&data)?;
+            let s = s.trim_end_matches(|c| c == '\n' || c == '\r');
+            let (pri, rest) = priority(s)?;
+            // RFC 5424 messages have a version right after the priority
+            let is_rfc5424 = rest.find(' ').map_or(false, |i| {
+                i > 0 && rest[..i].bytes().all(|c| c.is_ascii_digit())
+            });
+            let mut o = if is_rfc5424 {
+                decode_rfc5424(rest)?
+            } else {
+                decode_rfc3164(rest, ingest_ns)
+            };
+            o.insert("priority".into(), Value::from(i64::from(pri)));
+            o.insert(
+                "facility".into(),
+                Value::from(FACILITIES[usize::from(pri / 8)]),
+            );
+            o.insert(
+                "severity".into(),
+                Value::from(SEVERITIES[usize::from(pri % 8)]),
+            );
+            Value::from(o)
+        };
+        Ok(Some(LineValue::new(vec![data], |_| value.into())))
+    }
+
+    fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
+        let o = data
+            .as_object()
+            .ok_or_else(|| Error::from("Syslog messages can only be encoded from records"))?;
+        let pri = encode_priority(o)?;
+        let msg = match o.get("protocol").and_then(ValueTrait::as_str) {
+            None | Some(RFC5424) => encode_rfc5424(pri, o),
+            Some(RFC3164) => encode_rfc3164(pri, o),
+            Some(other) => return Err(format!("Unknown syslog protocol {}", other).into()),
+        };
+        Ok(msg.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    // 2003-10-11T00:00:00Z
+    const INGEST_NS: u64 = 1_065_830_400_000_000_000;
+
+    fn decode(msg: &str) -> Result<Value<'static>> {
+        let v = Syslog {}
+            .decode(msg.as_bytes().to_vec(), INGEST_NS)?
+            .expect("no value");
+        Ok(v.suffix().value().clone_static())
+    }
+
+    fn encode(v: &Value) -> Result<String> {
+        Ok(String::from_utf8(Syslog {}.encode(v)?)?)
+    }
+
+    #[test]
+    fn rfc5424() -> Result<()> {
+        let msg = "<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed for lonvick on /dev/pts/8";
+        let expected: Value = json!({
+            "protocol": "RFC5424",
+            "version": 1,
+            "priority": 34,
+            "facility": "auth",
+            "severity": "crit",
+            "timestamp": 1_065_910_455_003_000_000_i64,
+            "hostname": "mymachine.example.com",
+            "appname": "su",
+            "procid": null,
+            "msgid": "ID47",
+            "structured_data": null,
+            "msg": "'su root' failed for lonvick on /dev/pts/8"
+        })
+        .into();
+        let decoded = decode(msg)?;
+        assert_eq!(expected, decoded);
+        assert_eq!(msg, encode(&decoded)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rfc5424_structured_data() -> Result<()> {
+        let msg = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="App\]lication"][examplePriority@32473 class="high"]"#;
+        let decoded = decode(msg)?;
+        let sd: Value = json!({
+            "exampleSDID@32473": {"iut": "3", "eventSource": "App]lication"},
+            "examplePriority@32473": {"class": "high"}
+        })
+        .into();
+        assert_eq!(Some(&sd), decoded.get("structured_data"));
+        assert_eq!(Some(&Value::from("local4")), decoded.get("facility"));
+        assert_eq!(Some(&Value::null()), decoded.get("msg"));
+        assert_eq!(decoded, decode(&encode(&decoded)?)?);
+        assert!(decode("<165>1 - - - - - [unterminated").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rfc5424_timestamp_out_of_range() {
+        assert!(decode("<34>1 1500-01-01T00:00:00Z - - - - -").is_err());
+        assert!(decode("<34>1 2300-01-01T00:00:00Z - - - - -").is_err());
+    }
+
+    #[test]
+    fn rfc3164() -> Result<()> {
+        let msg = "<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8";
+        let decoded = decode(msg)?;
+        let expected: Value = json!({
+            "protocol": "RFC3164",
+            "priority": 34,
+            "facility": "auth",
+            "severity": "crit",
+            "timestamp": 1_065_910_455_000_000_000_i64,
+            "hostname": "mymachine",
+            "appname": "su",
+            "procid": null,
+            "msg": "'su root' failed for lonvick on /dev/pts/8"
+        })
+        .into();
+        assert_eq!(expected, decoded);
+        assert_eq!(msg, encode(&decoded)?);
+
+        let decoded = decode("<13>Feb  5 17:32:18 10.0.0.99 myproc[10]: snot badger")?;
+        assert_eq!(Some(&Value::from("myproc")), decoded.get("appname"));
+        assert_eq!(Some(&Value::from("10")), decoded.get("procid"));
+        assert_eq!(Some(&Value::from("snot badger")), decoded.get("msg"));
+        assert!(decode("no priority").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode_defaults() -> Result<()> {
+        let v: Value = json!({"severity": "err", "msg": "snot"}).into();
+        assert_eq!("<11>1 - - - - - - snot", encode(&v)?);
+        let v: Value = json!({"facility": "local0", "severity": 6, "protocol": "RFC3164", "appname": "badger", "msg": "snot"}).into();
+        assert_eq!("<134>badger: snot", encode(&v)?);
+        let v: Value = json!({"severity": "loud"}).into();
+        assert!(encode(&v).is_err());
+        Ok(())
+    }
+}
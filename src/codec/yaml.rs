@@ -14,21 +14,81 @@
 
 use super::prelude::*;
 
-#[derive(Clone)]
-pub struct YAML {}
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Treat data as a stream of `---` separated documents, decoded into
+    /// and encoded from an array, defaults to false
+    #[serde(default)]
+    pub multi_document: bool,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Default)]
+pub struct YAML {
+    config: Config,
+}
+
+impl Impl for YAML {
+    fn from_config(config: &Option<serde_yaml::Value>) -> Result<Box<dyn Codec>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        Ok(Box::new(Self { config }))
+    }
+}
+
+/// Splits a YAML stream into its documents, keeping the `---` line
+/// that starts each of them
+fn split_documents(s: &str) -> Vec<&str> {
+    let mut docs = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for line in s.split('\n') {
+        if (line.trim_end() == "---" || line.starts_with("--- ")) && offset > start {
+            docs.push(&s[start..offset]);
+            start = offset;
+        }
+        offset += line.len() + 1;
+    }
+    if !s[start..].trim().is_empty() {
+        docs.push(&s[start..]);
+    }
+    docs
+}
 
 impl Codec for YAML {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
-        LineValue::try_new(vec![data], |data| {
-            serde_yaml::from_slice::<simd_json::OwnedValue>(&data[0])
-                .map(Value::from)
-                .map(ValueAndMeta::from)
+        let multi_document = self.config.multi_document;
+        LineValue::try_new(vec![data], |data| -> Result<ValueAndMeta> {
+            let value = if multi_document {
+                let docs = split_documents(std::str::from_utf8(&data[0])?)
+                    .into_iter()
+                    .map(|doc| serde_yaml::from_str::<simd_json::OwnedValue>(doc).map(Value::from))
+                    .collect::<std::result::Result<Vec<Value>, _>>()?;
+                Value::from(docs)
+            } else {
+                serde_yaml::from_slice::<simd_json::OwnedValue>(&data[0]).map(Value::from)?
+            };
+            Ok(ValueAndMeta::from(value))
         })
         .map(Some)
-        .map_err(|e| e.0.into())
+        .map_err(|e| e.0)
     }
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
-        Ok(serde_yaml::to_vec(data)?)
+        match data.as_array() {
+            Some(docs) if self.config.multi_document => {
+                let docs = docs
+                    .iter()
+                    .map(serde_yaml::to_string)
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                Ok(docs.join("\n").into_bytes())
+            }
+            _ => Ok(serde_yaml::to_vec(data)?),
+        }
     }
 }
 
@@ -44,7 +104,7 @@ mod test {
         let seed: OwnedValue = json!({ "snot": "badger" });
         let seed: BorrowedValue = seed.into();
 
-        let mut codec = YAML {};
+        let mut codec = YAML::default();
         let as_raw = codec.encode(&seed)?;
         let as_json = codec.decode(as_raw, 0);
 
@@ -52,4 +112,33 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_yaml_codec_multi_document() -> Result<()> {
+        let mut codec = YAML {
+            config: Config {
+                multi_document: true,
+            },
+        };
+        let decoded = codec
+            .decode(b"---\nsnot: badger\n---\n- 1\n- 2\n".to_vec(), 0)?
+            .expect("no value");
+        let expected: BorrowedValue = json!([{ "snot": "badger" }, [1, 2]]).into();
+        assert_eq!(&expected, decoded.suffix().value());
+
+        let as_raw = codec.encode(&expected)?;
+        let decoded = codec.decode(as_raw, 0)?.expect("no value");
+        assert_eq!(&expected, decoded.suffix().value());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_documents() {
+        assert_eq!(vec!["snot: 1\n"], split_documents("snot: 1\n"));
+        assert_eq!(
+            vec!["---\na: 1\n", "--- b\n"],
+            split_documents("---\na: 1\n--- b\n")
+        );
+        assert!(split_documents("").is_empty());
+    }
 }
@@ -12,9 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::codec::CodecConfig;
 use crate::dflt::dflt;
+use crate::postprocessor::PostprocessorConfig;
+use crate::preprocessor::PreprocessorConfig;
+use crate::ramp::queue::Config as QueueConfig;
 use crate::url::TremorURL;
 use hashbrown::HashMap;
+use serde_yaml::Value;
 use tremor_pipeline::config as dynaconfig;
 
 pub(crate) type ID = String;
@@ -52,9 +57,9 @@ pub struct OnRamp {
     #[serde(default = "dflt")]
     pub(crate) description: String,
     #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
-    pub(crate) codec: Option<String>,
+    pub(crate) codec: Option<CodecConfig>,
     #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
-    pub(crate) preprocessors: Option<Vec<String>>,
+    pub(crate) preprocessors: Option<Vec<PreprocessorConfig>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) metrics_interval_s: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -72,15 +77,67 @@ pub struct OffRamp {
     #[serde(default = "dflt")]
     pub(crate) description: String,
     #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
-    pub(crate) codec: Option<String>,
+    pub(crate) codec: Option<CodecConfig>,
     #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
-    pub(crate) postprocessors: Option<Vec<String>>,
+    pub(crate) postprocessors: Option<Vec<PostprocessorConfig>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) metrics_interval_s: Option<u64>,
+    /// On-disk queue for events that can't be delivered right away
+    #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
+    pub(crate) queue: Option<QueueConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) config: dynaconfig::ConfigMap,
 }
 
+/// A codec, preprocessor or postprocessor setting, given either as
+/// a plain name or as a `{name, config}` map
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "NameWithConfigRepr", into = "NameWithConfigRepr")]
+pub struct NameWithConfig {
+    /// Name of the codec or processor
+    pub name: String,
+    /// Its specific configuration
+    pub config: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum NameWithConfigRepr {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        config: Option<Value>,
+    },
+}
+
+impl From<NameWithConfigRepr> for NameWithConfig {
+    fn from(repr: NameWithConfigRepr) -> Self {
+        match repr {
+            NameWithConfigRepr::Name(name) => Self { name, config: None },
+            NameWithConfigRepr::Full { name, config } => Self { name, config },
+        }
+    }
+}
+
+impl From<NameWithConfig> for NameWithConfigRepr {
+    fn from(name_with_config: NameWithConfig) -> Self {
+        match name_with_config {
+            NameWithConfig { name, config: None } => Self::Name(name),
+            NameWithConfig { name, config } => Self::Full { name, config },
+        }
+    }
+}
+
+impl From<&str> for NameWithConfig {
+    fn from(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            config: None,
+        }
+    }
+}
+
 /// Configuration for a Binding
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -110,4 +167,22 @@ mod test {
         assert_eq!(&c.offramp[0].id, "blackhole");
         assert_eq!(&c.pipeline[0].id, "main");
     }
+
+    #[test]
+    fn name_with_config() {
+        let plain = NameWithConfig::from("json");
+        let yaml = serde_yaml::to_string(&plain).expect("could not serialize");
+        assert_eq!(
+            Value::String("json".to_string()),
+            serde_yaml::from_str::<Value>(&yaml).expect("could not parse")
+        );
+        assert_eq!(plain, serde_yaml::from_str(&yaml).expect("could not parse"));
+
+        let full: NameWithConfig =
+            serde_yaml::from_str("{name: csv, config: {delimiter: ';'}}").expect("could not parse");
+        assert_eq!("csv", full.name);
+        assert!(full.config.is_some());
+        let yaml = serde_yaml::to_string(&full).expect("could not serialize");
+        assert_eq!(full, serde_yaml::from_str(&yaml).expect("could not parse"));
+    }
 }
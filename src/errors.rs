@@ -134,6 +134,7 @@ error_chain! {
     }
     foreign_links {
         Base64Error(base64::DecodeError);
+        CsvError(csv::Error);
         YAMLError(serde_yaml::Error) #[doc = "Error during yaml parsing"];
         JSONError(simd_json::Error);
         Io(std::io::Error);
@@ -153,6 +154,7 @@ error_chain! {
         GrokError(grok::Error);
         DateTimeParseError(chrono::ParseError);
         SnappyError(snap::Error);
+        TlsError(rustls::TLSError);
         AddrParseError(std::net::AddrParseError);
         RegexError(regex::Error);
         WsError(tungstenite::Error);
@@ -146,7 +146,7 @@ mod test {
 
     #[test]
     fn pipeline_activation_lifecycle() {
-        let (world, _) = b!(World::start(10, None)).expect("failed to start world");
+        let (world, _) = b!(World::start(10, None, None)).expect("failed to start world");
 
         let config = slurp("tests/configs/ut.passthrough.yaml");
         let mut runtime = incarnate(config).expect("failed to incarnate runtime");
@@ -196,7 +196,7 @@ mod test {
 
     #[test]
     fn onramp_activation_lifecycle() {
-        let (world, _) = b!(World::start(10, None)).expect("failed to start world");
+        let (world, _) = b!(World::start(10, None, None)).expect("failed to start world");
 
         let config = slurp("tests/configs/ut.passthrough.yaml");
         let mut runtime = incarnate(config).expect("failed to incarnate runtime");
@@ -240,7 +240,7 @@ mod test {
 
     #[test]
     fn offramp_activation_lifecycle() {
-        let (world, _) = b!(World::start(10, None)).expect("failed to start world");
+        let (world, _) = b!(World::start(10, None, None)).expect("failed to start world");
 
         let config = slurp("tests/configs/ut.passthrough.yaml");
         let mut runtime = incarnate(config).expect("failed to incarnate runtime");
@@ -291,7 +291,7 @@ mod test {
 
     #[test]
     fn binding_activation_lifecycle() {
-        let (world, _) = b!(World::start(10, None)).expect("failed to start world");
+        let (world, _) = b!(World::start(10, None, None)).expect("failed to start world");
 
         let config = slurp("tests/configs/ut.passthrough.yaml");
         let mut runtime = incarnate(config).expect("failed to incarnate runtime");
@@ -27,6 +27,8 @@ pub(crate) struct Ramp {
     r#in: u64,
     out: u64,
     error: u64,
+    /// Events waiting in the on-disk queue, if the ramp has one
+    queue: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -46,6 +48,7 @@ impl RampReporter {
                 r#in: 0,
                 out: 0,
                 error: 0,
+                queue: None,
             },
             metrics_pipeline: None,
             flush_interval: flush_interval_s.map(|n| n * 1_000_000_000),
@@ -73,6 +76,11 @@ impl RampReporter {
         self.metrics.error += 1;
     }
 
+    #[inline]
+    pub fn set_queue_depth(&mut self, depth: u64) {
+        self.metrics.queue = Some(depth);
+    }
+
     #[inline]
     pub fn periodic_flush(&mut self, timestamp: u64) {
         if let Some(interval) = self.flush_interval {
@@ -86,6 +94,9 @@ impl RampReporter {
         self.send_metric(timestamp, "in", self.metrics.r#in);
         self.send_metric(timestamp, "out", self.metrics.out);
         self.send_metric(timestamp, "error", self.metrics.error);
+        if let Some(depth) = self.metrics.queue {
+            self.send_metric(timestamp, "queue", depth);
+        }
         self.last_flush_ns = timestamp;
     }
 
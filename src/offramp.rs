@@ -13,19 +13,27 @@
 // limitations under the License.
 
 use crate::codec::Codec;
-use crate::errors::Result;
+use crate::errors::{Error, Result};
 use crate::metrics::RampReporter;
 use crate::pipeline;
+use crate::postprocessor::PostprocessorConfig;
+use crate::ramp::queue::{Config as QueueConfig, Queue};
 use crate::registry::ServantId;
 use crate::system::METRICS_PIPELINE;
 use crate::url::TremorURL;
+use crate::utils::nanotime;
 use crate::{Event, OpConfig};
 use async_std::sync::channel;
 use async_std::task::{self, JoinHandle};
-use crossbeam_channel::{bounded, Sender as CbSender};
+use crossbeam_channel::{after, bounded, never, select, tick, Receiver, Sender as CbSender};
+use simd_json::borrowed::{Object, Value};
+use simd_json::prelude::*;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
 use std::thread;
+use std::time::Duration;
+use tremor_pipeline::{CBAction, EventKey};
 
 mod blackhole;
 mod debug;
@@ -40,6 +48,7 @@ mod kafka;
 mod newrelic;
 mod postgres;
 mod prelude;
+mod response;
 mod rest;
 mod stderr;
 mod stdout;
@@ -60,6 +69,15 @@ pub enum Msg {
         id: TremorURL,
         tx: CbSender<bool>,
     },
+    ConnectOutput {
+        port: Cow<'static, str>,
+        id: TremorURL,
+        addr: pipeline::Addr,
+    },
+    DisconnectOutput {
+        port: Cow<'static, str>,
+        id: TremorURL,
+    },
 }
 
 pub(crate) type Sender = async_std::sync::Sender<ManagerMsg>;
@@ -71,17 +89,82 @@ pub type Addr = CbSender<Msg>;
 // borrowed contest
 #[allow(clippy::borrowed_box)]
 pub trait Offramp: Send {
-    fn start(&mut self, codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()>;
+    fn start(
+        &mut self,
+        codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()>;
     fn on_event(&mut self, codec: &Box<dyn Codec>, input: String, event: Event) -> Result<()>;
     fn default_codec(&self) -> &str;
     fn add_pipeline(&mut self, id: TremorURL, addr: pipeline::Addr);
     fn remove_pipeline(&mut self, id: TremorURL) -> bool;
+    /// If events are acknowledged once `on_event` returns, offramps that
+    /// acknowledge events themselves once they are delivered return `false`
+    fn auto_ack(&self) -> bool {
+        true
+    }
+    /// How often `on_tick` is called, offramps without periodic work
+    /// return `None`
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+    /// Does periodic work like flushing buffered writes, independent of
+    /// incoming events
+    fn on_tick(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Connects a pipeline to an output port, for offramps that emit events
+    fn add_output(&mut self, _port: Cow<'static, str>, _id: TremorURL, _addr: pipeline::Addr) {}
+    fn remove_output(&mut self, _port: &str, _id: &TremorURL) {}
 }
 
 pub trait Impl {
     fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>>;
 }
 
+/// Pipelines connected to the output ports of an offramp
+#[derive(Debug, Clone, Default)]
+pub struct Outputs {
+    ports: halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, pipeline::Addr)>>,
+}
+
+impl Outputs {
+    pub fn add(&mut self, port: Cow<'static, str>, id: TremorURL, addr: pipeline::Addr) {
+        if let Some(pipelines) = self.ports.get_mut(&port) {
+            pipelines.retain(|(pid, _)| pid != &id);
+            pipelines.push((id, addr));
+        } else {
+            self.ports.insert(port, vec![(id, addr)]);
+        }
+    }
+
+    pub fn remove(&mut self, port: &str, id: &TremorURL) {
+        if let Some(pipelines) = self.ports.get_mut(port) {
+            pipelines.retain(|(pid, _)| pid != id);
+        }
+    }
+
+    /// If any pipeline is connected to the port
+    pub fn is_connected(&self, port: &str) -> bool {
+        self.ports.get(port).map_or(false, |p| !p.is_empty())
+    }
+
+    /// Sends an event to the pipelines connected to the port
+    pub fn send(&self, port: &str, event: Event) {
+        if let Some(pipelines) = self.ports.get(port) {
+            for (id, addr) in pipelines {
+                let input = id.instance_port().unwrap_or_else(|| "in".to_string());
+                if let Err(e) = addr.addr.send(pipeline::Msg::Event {
+                    input: input.into(),
+                    event: event.clone(),
+                }) {
+                    error!("Failed to send to pipeline {}: {}", id, e);
+                }
+            }
+        }
+    }
+}
+
 // just a lookup
 #[cfg_attr(tarpaulin, skip)]
 pub fn lookup(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
@@ -98,6 +181,7 @@ pub fn lookup(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Offramp>>
         "kafka" => kafka::Kafka::from_config(config),
         "newrelic" => newrelic::NewRelic::from_config(config),
         "postgres" => postgres::Postgres::from_config(config),
+        "response" => response::Response::from_config(config),
         "rest" => rest::Rest::from_config(config),
         "stdout" => stdout::StdOut::from_config(config),
         "stderr" => stderr::StdErr::from_config(config),
@@ -112,8 +196,9 @@ pub(crate) struct Create {
     pub id: ServantId,
     pub offramp: Box<dyn Offramp>,
     pub codec: Box<dyn Codec>,
-    pub postprocessors: Vec<String>,
+    pub postprocessors: Vec<PostprocessorConfig>,
     pub metrics_reporter: RampReporter,
+    pub queue: Option<QueueConfig>,
 }
 
 impl fmt::Debug for Create {
@@ -129,6 +214,403 @@ pub(crate) enum ManagerMsg {
     Stop,
 }
 
+/// Number of queued events delivered before handling messages again
+const QUEUE_BATCH: usize = 64;
+
+/// Output every offramp sends the events it failed to deliver to, with the
+/// `error`, the `offramp` and the number of `attempts` in their metadata
+pub(crate) const ERR: &str = "err";
+
+/// An event handed to an offramp that acknowledges events itself
+struct Inflight {
+    input: String,
+    /// `None` once the event is queued again or still is, or if there is
+    /// neither a queue nor an `err` output to send it to when it fails
+    event: Option<Event>,
+    /// If the pipelines still wait for the event to be acknowledged
+    notify: bool,
+    /// Times the offramp was handed the event, including this one
+    attempts: u64,
+}
+
+/// A running offramp
+struct Worker {
+    id: ServantId,
+    offramp: Box<dyn Offramp>,
+    codec: Box<dyn Codec>,
+    metrics_reporter: RampReporter,
+    pipelines: Vec<(TremorURL, pipeline::Addr)>,
+    outputs: Outputs,
+    queue: Option<Queue>,
+    retry_ms: u64,
+    /// When to retry delivering queued events
+    retry_at: u64,
+    /// Failed attempts to deliver the first queued event since it was read
+    front_attempts: u64,
+    /// The first queued event while an offramp that acknowledges events
+    /// itself delivers it, it stays queued until it is acknowledged
+    draining: Option<EventKey>,
+    /// Insights of an offramp that acknowledges events itself, they pass
+    /// through the worker so failed events can be queued or sent to `err`
+    insights: Option<Receiver<pipeline::Msg>>,
+    inflight: HashMap<EventKey, Inflight>,
+}
+
+impl Worker {
+    fn run(mut self, rx: Receiver<Msg>) {
+        info!("[Offramp::{}] started", self.id);
+        let insights = self.insights.clone().unwrap_or_else(never);
+        let ticks = self.offramp.tick_interval().map_or_else(never, tick);
+        loop {
+            self.drain_queue();
+            let retry = self.queue_due_in().map_or_else(never, after);
+            let msg = select! {
+                recv(rx) -> msg => if let Ok(msg) = msg { msg } else { break },
+                recv(insights) -> msg => {
+                    if let Ok(pipeline::Msg::Insight(insight)) = msg {
+                        self.on_insight(insight);
+                    }
+                    continue;
+                },
+                recv(retry) -> _ => continue,
+                recv(ticks) -> _ => {
+                    if let Err(e) = self.offramp.on_tick() {
+                        error!("[Offramp::{}] On Tick error: {}", self.id, e);
+                    }
+                    continue;
+                },
+            };
+            match msg {
+                Msg::Event { event, input } => self.on_event(input, event),
+                Msg::Connect { id, addr } => {
+                    if id == *METRICS_PIPELINE {
+                        info!(
+                            "[Offramp::{}] Connecting system metrics pipeline {}",
+                            self.id, id
+                        );
+                        self.metrics_reporter.set_metrics_pipeline((id, addr));
+                    } else {
+                        info!("[Offramp::{}] Connecting pipeline {}", self.id, id);
+                        self.pipelines.push((id.clone(), addr.clone()));
+                        if self.insights.is_none() {
+                            self.offramp.add_pipeline(id, addr);
+                        }
+                    }
+                }
+                Msg::Disconnect { id, tx } => {
+                    info!("[Offramp::{}] Disconnecting pipeline {}", self.id, id);
+                    self.pipelines.retain(|(pid, _)| pid != &id);
+                    let r = if self.insights.is_none() {
+                        self.offramp.remove_pipeline(id.clone())
+                    } else {
+                        self.pipelines.is_empty()
+                    };
+                    info!("[Offramp::{}] Pipeline {} disconnected", self.id, id);
+                    if r {
+                        info!("[Offramp::{}] Marked as done ", self.id);
+                    }
+
+                    if let Err(e) = tx.send(r) {
+                        error!("Failed to send reply: {}", e)
+                    }
+                }
+                Msg::ConnectOutput { port, id, addr } => {
+                    info!(
+                        "[Offramp::{}] Connecting output {} to pipeline {}",
+                        self.id, port, id
+                    );
+                    self.outputs.add(port.clone(), id.clone(), addr.clone());
+                    self.offramp.add_output(port, id, addr);
+                }
+                Msg::DisconnectOutput { port, id } => {
+                    info!(
+                        "[Offramp::{}] Disconnecting output {} from pipeline {}",
+                        self.id, port, id
+                    );
+                    self.outputs.remove(&port, &id);
+                    self.offramp.remove_output(&port, &id);
+                }
+            }
+        }
+        info!("[Offramp::{}] stopped", self.id);
+    }
+
+    fn on_event(&mut self, input: Cow<'static, str>, event: Event) {
+        self.metrics_reporter.periodic_flush(event.ingest_ns);
+        self.metrics_reporter.increment_in();
+        let auto_ack = self.offramp.auto_ack();
+        let relayed = self.insights.is_some();
+        let id = event.id;
+        let origin_uri = if auto_ack || relayed {
+            event.origin_uri.clone()
+        } else {
+            None
+        };
+        let failed = if self.outputs.is_connected(ERR) {
+            Some(event.clone())
+        } else {
+            None
+        };
+        let mut attempts = 0;
+        // If the offramp acknowledges the event once it is delivered
+        let mut pending = false;
+        let r = match self.queue.as_ref().map(Queue::is_empty) {
+            None => {
+                attempts += 1;
+                let r = self.send(input.to_string(), event, true, attempts);
+                pending = relayed && r.is_ok();
+                r
+            }
+            // Queued events go out first
+            Some(false) => self.enqueue(&input, &event, attempts),
+            Some(true) => {
+                let backup = event.clone();
+                attempts += 1;
+                if self.send(input.to_string(), event, true, attempts).is_ok() {
+                    pending = relayed;
+                    Ok(())
+                } else {
+                    // Give the offramp some time to recover
+                    self.retry_at = nanotime() + self.retry_ms * 1_000_000;
+                    self.enqueue(&input, &backup, attempts)
+                }
+            }
+        };
+        if let (Err(e), Some(event)) = (&r, failed) {
+            self.send_err(event, e, attempts);
+        }
+        if auto_ack || (relayed && !pending) {
+            let cb = if r.is_ok() {
+                CBAction::Ack
+            } else {
+                CBAction::Fail
+            };
+            self.send_insight(&Event::cb(cb, nanotime(), id, origin_uri));
+        }
+    }
+
+    /// Handles an insight of an offramp that acknowledges events itself,
+    /// events it failed to deliver are queued
+    fn on_insight(&mut self, insight: Event) {
+        let cb = insight.cb_action();
+        let key = (insight.id, insight.origin_uri.clone());
+        let inflight = cb.and_then(|_| self.inflight.remove(&key));
+        let (cb, inflight) = if let (Some(cb), Some(inflight)) = (cb, inflight) {
+            (cb, inflight)
+        } else {
+            self.send_insight(&insight);
+            return;
+        };
+        if self.draining.as_ref() == Some(&key) {
+            // The pipelines were acknowledged when it was queued
+            self.draining = None;
+            if cb == CBAction::Ack {
+                self.pop_front();
+            } else {
+                self.front_attempts += 1;
+                self.retry_at = nanotime() + self.retry_ms * 1_000_000;
+            }
+            return;
+        }
+        let delivered = match (cb, inflight.event) {
+            (CBAction::Ack, _) => true,
+            (CBAction::Fail, None) => false,
+            (CBAction::Fail, Some(event)) if self.queue.is_some() => {
+                self.retry_at = nanotime() + self.retry_ms * 1_000_000;
+                match self.enqueue(&inflight.input, &event, inflight.attempts) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        if self.outputs.is_connected(ERR) {
+                            self.send_err(event, &e, inflight.attempts);
+                        }
+                        false
+                    }
+                }
+            }
+            (CBAction::Fail, Some(event)) => {
+                if self.outputs.is_connected(ERR) {
+                    self.send_err(event, &"Delivery failed".into(), inflight.attempts);
+                }
+                false
+            }
+        };
+        if inflight.notify {
+            let cb = if delivered {
+                CBAction::Ack
+            } else {
+                CBAction::Fail
+            };
+            self.send_insight(&Event::cb(cb, nanotime(), key.0, key.1));
+        }
+    }
+
+    /// Sends an insight to all connected pipelines
+    fn send_insight(&self, insight: &Event) {
+        for (pid, p) in &self.pipelines {
+            if p.addr
+                .send(pipeline::Msg::Insight(insight.clone()))
+                .is_err()
+            {
+                error!(
+                    "[Offramp::{}] Failed to send ack to pipeline {}",
+                    self.id, pid
+                );
+            }
+        }
+    }
+
+    /// Hands an event to the offramp, `notify` if the pipelines wait for it
+    /// to be acknowledged
+    fn send(&mut self, input: String, event: Event, notify: bool, attempts: u64) -> Result<()> {
+        let key = (event.id, event.origin_uri.clone());
+        if self.insights.is_some() {
+            // Only keep the event if there is somewhere to send it on failure
+            let failed = if self.queue.is_some() || self.outputs.is_connected(ERR) {
+                Some(event.clone())
+            } else {
+                None
+            };
+            let inflight = Inflight {
+                input: input.clone(),
+                event: failed,
+                notify,
+                attempts,
+            };
+            self.inflight.insert(key.clone(), inflight);
+        }
+        // TODO FIXME implement postprocessors
+        let r = self.offramp.on_event(&self.codec, input, event);
+        match &r {
+            Ok(()) => self.metrics_reporter.increment_out(),
+            Err(e) => {
+                self.metrics_reporter.increment_error();
+                error!("[Offramp::{}] On Event error: {}", self.id, e);
+                // The failed event is queued right away, or still is
+                if let Some(inflight) = self.inflight.get_mut(&key) {
+                    inflight.event = None;
+                    inflight.notify = false;
+                }
+            }
+        }
+        r
+    }
+
+    /// Sends an event that could not be delivered to the `err` output
+    fn send_err(&self, mut event: Event, error: &Error, attempts: u64) {
+        let error = error.to_string();
+        let offramp = self.id.to_string();
+        event.data.rent_mut(|data| {
+            let meta = data.meta_mut();
+            if !meta.is_object() {
+                *meta = Value::from(Object::new());
+            }
+            if let Some(meta) = meta.as_object_mut() {
+                meta.insert("error".into(), Value::from(error));
+                meta.insert("offramp".into(), Value::from(offramp));
+                meta.insert("attempts".into(), Value::from(attempts));
+            }
+        });
+        self.outputs.send(ERR, event);
+    }
+
+    /// Writes an event to the queue, with the number of attempts to
+    /// deliver it so far
+    fn enqueue(&mut self, input: &str, event: &Event, attempts: u64) -> Result<()> {
+        if let Some(queue) = &mut self.queue {
+            let r = simd_json::to_vec(&(input, event, attempts))
+                .map_err(Error::from)
+                .and_then(|record| queue.push(&record));
+            if let Err(e) = &r {
+                error!("[Offramp::{}] Failed to queue event: {}", self.id, e);
+            }
+            self.metrics_reporter.set_queue_depth(queue.len());
+            r
+        } else {
+            Err("No queue".into())
+        }
+    }
+
+    /// Time until queued events are retried, `None` if there are none or
+    /// the first one is still being delivered
+    fn queue_due_in(&self) -> Option<Duration> {
+        self.queue
+            .as_ref()
+            .filter(|queue| !queue.is_empty() && self.draining.is_none())
+            .map(|_| Duration::from_nanos(self.retry_at.saturating_sub(nanotime())))
+    }
+
+    /// Removes the first queued event once it is delivered
+    fn pop_front(&mut self) {
+        self.front_attempts = 0;
+        if let Some(queue) = &mut self.queue {
+            if let Err(e) = queue.pop() {
+                error!("[Offramp::{}] Failed to read queue: {}", self.id, e);
+            }
+            self.metrics_reporter.set_queue_depth(queue.len());
+        }
+    }
+
+    /// Delivers queued events in order until one fails, offramps that
+    /// acknowledge events themselves are handed one at a time
+    fn drain_queue(&mut self) {
+        if self.queue.as_ref().map_or(true, Queue::is_empty)
+            || self.retry_at > nanotime()
+            || self.draining.is_some()
+        {
+            return;
+        }
+        for _ in 0..QUEUE_BATCH {
+            let mut record = match self.queue.as_mut().map(Queue::front) {
+                Some(Ok(Some(record))) => record,
+                Some(Ok(None)) | None => break,
+                Some(Err(e)) => {
+                    error!("[Offramp::{}] Failed to read queue: {}", self.id, e);
+                    self.retry_at = nanotime() + self.retry_ms * 1_000_000;
+                    break;
+                }
+            };
+            // Parsing works in place, unreadable records are reported as they are
+            let raw = if self.outputs.is_connected(ERR) {
+                Some(String::from_utf8_lossy(&record).to_string())
+            } else {
+                None
+            };
+            match simd_json::from_slice::<(String, Event, u64)>(&mut record) {
+                Ok((input, event, attempts)) => {
+                    let attempts = attempts + self.front_attempts + 1;
+                    let key = (event.id, event.origin_uri.clone());
+                    // The pipelines were acknowledged when it was queued
+                    if self.send(input, event, false, attempts).is_err() {
+                        self.front_attempts += 1;
+                        self.retry_at = nanotime() + self.retry_ms * 1_000_000;
+                        break;
+                    }
+                    if self.insights.is_some() {
+                        // Wait for the offramp to acknowledge it
+                        self.draining = Some(key);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "[Offramp::{}] Dropping unreadable queued event: {}",
+                        self.id, e
+                    );
+                    if let Some(raw) = raw {
+                        let event = Event {
+                            ingest_ns: nanotime(),
+                            data: (Value::from(raw), Value::from(Object::new())).into(),
+                            ..Event::default()
+                        };
+                        self.send_err(event, &Error::from(e), 0);
+                    }
+                }
+            }
+            self.pop_front();
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct Manager {
     qsize: usize,
@@ -157,6 +639,7 @@ impl Manager {
                             mut offramp,
                             postprocessors,
                             mut metrics_reporter,
+                            queue: queue_config,
                             id,
                         },
                     )) => {
@@ -168,65 +651,51 @@ impl Manager {
                             }
                         }
 
-                        let (tx, rx) = bounded(self.qsize);
-                        let offramp_id = id.clone();
-                        thread::spawn(move || {
-                            info!("[Offramp::{}] started", offramp_id);
-                            for m in rx {
-                                match m {
-                                    Msg::Event { event, input } => {
-                                        metrics_reporter.periodic_flush(event.ingest_ns);
-
-                                        metrics_reporter.increment_in();
-                                        // TODO FIXME implement postprocessors
-                                        match offramp.on_event(&codec, input.into(), event) {
-                                            Ok(_) => metrics_reporter.increment_out(),
-                                            Err(e) => {
-                                                metrics_reporter.increment_error();
-                                                error!(
-                                                    "[Offramp::{}] On Event error: {}",
-                                                    offramp_id, e
-                                                );
-                                            }
-                                        }
-                                    }
-                                    Msg::Connect { id, addr } => {
-                                        if id == *METRICS_PIPELINE {
-                                            info!(
-                                            "[Offramp::{}] Connecting system metrics pipeline {}",
-                                            offramp_id, id
-                                        );
-                                            metrics_reporter.set_metrics_pipeline((id, addr));
-                                        } else {
-                                            info!(
-                                                "[Offramp::{}] Connecting pipeline {}",
-                                                offramp_id, id
-                                            );
-                                            offramp.add_pipeline(id, addr);
-                                        }
-                                    }
-                                    Msg::Disconnect { id, tx } => {
-                                        info!(
-                                            "[Offramp::{}] Disconnecting pipeline {}",
-                                            offramp_id, id
-                                        );
-                                        let r = offramp.remove_pipeline(id.clone());
-                                        info!(
-                                            "[Offramp::{}] Pipeline {} disconnected",
-                                            offramp_id, id
-                                        );
-                                        if r {
-                                            info!("[Offramp::{}] Marked as done ", offramp_id);
-                                        }
-
-                                        if let Err(e) = tx.send(r) {
-                                            error!("Failed to send reply: {}", e)
-                                        }
-                                    }
-                                }
+                        let queue = match queue_config.as_ref().map(Queue::open).transpose() {
+                            Ok(queue) => queue,
+                            Err(e) => {
+                                error!("Failed to open queue of offramp {}: {}", id, e);
+                                r.send(Err(e)).await;
+                                continue;
                             }
-                            info!("[Offramp::{}] stopped", offramp_id);
-                        });
+                        };
+                        if let Some(queue) = &queue {
+                            metrics_reporter.set_queue_depth(queue.len());
+                        }
+
+                        // Offramps that acknowledge events themselves report
+                        // failures to the worker so it can queue the events
+                        // or send them to `err`
+                        let insights = if offramp.auto_ack() {
+                            None
+                        } else {
+                            let (tx, rx) = bounded(self.qsize);
+                            offramp.add_pipeline(
+                                id.clone(),
+                                pipeline::Addr {
+                                    addr: tx,
+                                    id: id.clone(),
+                                },
+                            );
+                            Some(rx)
+                        };
+                        let (tx, rx) = bounded(self.qsize);
+                        let worker = Worker {
+                            id,
+                            offramp,
+                            codec,
+                            metrics_reporter,
+                            pipelines: Vec::new(),
+                            outputs: Outputs::default(),
+                            queue,
+                            retry_ms: queue_config.map_or(0, |q| q.retry_ms),
+                            retry_at: 0,
+                            front_attempts: 0,
+                            draining: None,
+                            insights,
+                            inflight: HashMap::new(),
+                        };
+                        thread::spawn(move || worker.run(rx));
                         r.send(Ok(tx)).await
                     }
                     Err(e) => {
@@ -242,3 +711,195 @@ impl Manager {
         (h, tx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// An offramp that fails to deliver every event
+    struct Failing;
+
+    impl Offramp for Failing {
+        fn start(
+            &mut self,
+            _codec: &Box<dyn Codec>,
+            _postprocessors: &[PostprocessorConfig],
+        ) -> Result<()> {
+            Ok(())
+        }
+        fn on_event(
+            &mut self,
+            _codec: &Box<dyn Codec>,
+            _input: String,
+            _event: Event,
+        ) -> Result<()> {
+            Err("snot".into())
+        }
+        fn default_codec(&self) -> &str {
+            "json"
+        }
+        fn add_pipeline(&mut self, _id: TremorURL, _addr: pipeline::Addr) {}
+        fn remove_pipeline(&mut self, _id: TremorURL) -> bool {
+            true
+        }
+    }
+
+    /// An offramp that acknowledges events itself, recording the ids of
+    /// the events it is handed
+    struct Acking(Arc<Mutex<Vec<u64>>>);
+
+    impl Offramp for Acking {
+        fn start(
+            &mut self,
+            _codec: &Box<dyn Codec>,
+            _postprocessors: &[PostprocessorConfig],
+        ) -> Result<()> {
+            Ok(())
+        }
+        fn on_event(
+            &mut self,
+            _codec: &Box<dyn Codec>,
+            _input: String,
+            event: Event,
+        ) -> Result<()> {
+            self.0.lock()?.push(event.id);
+            Ok(())
+        }
+        fn default_codec(&self) -> &str {
+            "json"
+        }
+        fn add_pipeline(&mut self, _id: TremorURL, _addr: pipeline::Addr) {}
+        fn remove_pipeline(&mut self, _id: TremorURL) -> bool {
+            true
+        }
+        fn auto_ack(&self) -> bool {
+            false
+        }
+    }
+
+    fn worker(offramp: Box<dyn Offramp>) -> Result<Worker> {
+        let id = TremorURL::parse("/offramp/failing/01")?;
+        Ok(Worker {
+            id: id.clone(),
+            offramp,
+            codec: crate::codec::lookup("json", &None)?,
+            metrics_reporter: RampReporter::new(id, None),
+            pipelines: Vec::new(),
+            outputs: Outputs::default(),
+            queue: None,
+            retry_ms: 0,
+            retry_at: 0,
+            front_attempts: 0,
+            draining: None,
+            insights: None,
+            inflight: HashMap::new(),
+        })
+    }
+
+    #[test]
+    fn failed_events_go_to_err() -> Result<()> {
+        let mut worker = worker(Box::new(Failing))?;
+        let (tx, rx) = bounded(1);
+        let dead_letter = TremorURL::parse("/pipeline/dead_letter/01/in")?;
+        let addr = pipeline::Addr {
+            addr: tx,
+            id: dead_letter.clone(),
+        };
+        worker.outputs.add(ERR.into(), dead_letter, addr);
+
+        worker.on_event(
+            "in".into(),
+            Event {
+                id: 42,
+                ..Event::default()
+            },
+        );
+        if let Ok(pipeline::Msg::Event { input, event }) = rx.try_recv() {
+            assert_eq!("in", input);
+            assert_eq!(42, event.id);
+            let meta = event.data.suffix().meta();
+            assert_eq!(Some("snot"), meta.get("error").and_then(Value::as_str));
+            assert_eq!(
+                Some("tremor://localhost/offramp/failing/01"),
+                meta.get("offramp").and_then(Value::as_str)
+            );
+            assert_eq!(Some(1), meta.get("attempts").and_then(Value::as_u64));
+        } else {
+            panic!("No event on the err output");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn queued_events_stay_until_acknowledged() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut worker = worker(Box::new(Acking(sent.clone())))?;
+        worker.queue = Some(Queue::open(&QueueConfig {
+            path: dir.path().to_string_lossy().to_string(),
+            max_size: 1024 * 1024,
+            retry_ms: 0,
+        })?);
+        worker.insights = Some(never());
+        for id in 1..=2 {
+            let event = Event {
+                id,
+                ..Event::default()
+            };
+            worker.enqueue("in", &event, 1)?;
+        }
+        let cb = |cb, id| Event::cb(cb, 0, id, None);
+
+        // only the first queued event is handed to the offramp
+        worker.drain_queue();
+        worker.drain_queue();
+        assert_eq!(vec![1], *sent.lock()?);
+        // and stays queued when it fails, to be retried first
+        worker.on_insight(cb(CBAction::Fail, 1));
+        assert_eq!(Some(2), worker.queue.as_ref().map(Queue::len));
+        worker.drain_queue();
+        assert_eq!(vec![1, 1], *sent.lock()?);
+        // until it is acknowledged
+        worker.on_insight(cb(CBAction::Ack, 1));
+        assert_eq!(Some(1), worker.queue.as_ref().map(Queue::len));
+        worker.drain_queue();
+        assert_eq!(vec![1, 1, 2], *sent.lock()?);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_inflight_events_go_to_err() -> Result<()> {
+        let mut worker = worker(Box::new(Acking(Arc::new(Mutex::new(Vec::new())))))?;
+        worker.insights = Some(never());
+        let (tx, rx) = bounded(1);
+        let dead_letter = TremorURL::parse("/pipeline/dead_letter/01/in")?;
+        let addr = pipeline::Addr {
+            addr: tx,
+            id: dead_letter.clone(),
+        };
+        worker.outputs.add(ERR.into(), dead_letter, addr);
+
+        worker.on_event(
+            "in".into(),
+            Event {
+                id: 42,
+                ..Event::default()
+            },
+        );
+        assert!(rx.try_recv().is_err());
+        worker.on_insight(Event::cb(CBAction::Fail, 0, 42, None));
+        if let Ok(pipeline::Msg::Event { event, .. }) = rx.try_recv() {
+            assert_eq!(42, event.id);
+            let meta = event.data.suffix().meta();
+            assert_eq!(
+                Some("Delivery failed"),
+                meta.get("error").and_then(Value::as_str)
+            );
+            assert_eq!(Some(1), meta.get("attempts").and_then(Value::as_u64));
+        } else {
+            panic!("No event on the err output");
+        }
+        Ok(())
+    }
+}
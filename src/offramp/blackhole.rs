@@ -127,7 +127,11 @@ impl Offramp for Blackhole {
     fn default_codec(&self) -> &str {
         "null"
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
@@ -21,23 +21,41 @@
 //! See [Config](struct.Config.html) for details.
 //!
 //! ## Input Variables
+//!
+//! The `$elastic` metadata of an event controls how it is written:
+//!
 //!   * `index` - index to write to (required)
-//!   * `doc-type` - document type for the event (required)
-//!   * `pipeline` - pipeline to use
+//!   * `doc_type` - document type for the event (required)
+//!   * `id` - id of the document
+//!   * `pipeline` - ingest pipeline to use
+//!   * `action` - one of `index` (default), `create`, `update` or `delete`,
+//!     for `update` the event is sent as partial document, for `delete` it
+//!     is not sent at all
+//!
+//! `index`, `doc_type` and `pipeline` are also read from the top level of
+//! the metadata if they are not set in `$elastic`.
 //!
 //! ## Outputs
 //!
 //! The 1st additional output is used to send divert messages that can not be
 //! enqueued due to overload
+//!
+//! Documents that elastic search rejects are sent to the `err` output, with
+//! the reason in the `error` field of their metadata. Without anything
+//! connected to `err` an event with rejected documents is failed. If the
+//! whole bulk request fails the event is failed, and sent to `err` as a
+//! whole.
 
 use crate::offramp::prelude::make_postprocessors;
 use crate::offramp::prelude::*;
+use crate::offramp::ERR;
 use crate::postprocessor::Postprocessors;
 use crossbeam_channel::bounded;
 use elastic::prelude::*;
 use halfbrown::HashMap;
 use simd_json::borrowed::Object;
-use simd_json::json;
+use std::borrow::Cow;
+use std::io::Read;
 use std::str;
 use std::time::Instant;
 use threadpool::ThreadPool;
@@ -68,6 +86,7 @@ pub struct Elastic {
     queue: AsyncSink<u64>,
     // hostname: String,
     pipelines: HashMap<TremorURL, pipeline::Addr>,
+    outputs: Outputs,
     postprocessors: Postprocessors,
 }
 
@@ -97,6 +116,7 @@ impl offramp::Impl for Elastic {
             Ok(Box::new(Self {
                 client_idx: 0,
                 pipelines: HashMap::new(),
+                outputs: Outputs::default(),
                 postprocessors: vec![],
                 // config,
                 pool,
@@ -110,32 +130,171 @@ impl offramp::Impl for Elastic {
     }
 }
 
+/// Writes the bulk action and source lines for a document
+fn write_doc(payload: &mut Vec<u8>, value: &Value, meta: &Value) -> Result<()> {
+    let elastic = meta.get("elastic");
+    let get = |name: &str| elastic.and_then(|e| e.get(name));
+    let get_str = |name: &str| get(name).or_else(|| meta.get(name)).and_then(Value::as_str);
+    let index =
+        get_str("index").ok_or_else(|| Error::from("'index' not set for elastic offramp!"))?;
+    let doc_type = get_str("doc_type")
+        .ok_or_else(|| Error::from("'doc_type' not set for elastic offramp!"))?;
+    let action = get("action").and_then(Value::as_str).unwrap_or("index");
+
+    let mut header = Object::new();
+    header.insert("_index".into(), Value::from(index));
+    header.insert("_type".into(), Value::from(doc_type));
+    if let Some(id) = get("id") {
+        let id = id
+            .as_str()
+            .map_or_else(|| Value::from(id.encode()), Value::from);
+        header.insert("_id".into(), id);
+    }
+    match action {
+        "index" | "create" => {
+            if let Some(pipeline) = get_str("pipeline") {
+                header.insert("pipeline".into(), Value::from(pipeline));
+            }
+        }
+        "update" | "delete" => (),
+        other => return Err(format!("Unknown elastic action '{}'", other).into()),
+    }
+    let mut line = Object::with_capacity(1);
+    line.insert(action.into(), Value::from(header));
+    Value::from(line).write(payload)?;
+    payload.push(b'\n');
+
+    match action {
+        "delete" => (),
+        "update" => {
+            payload.extend_from_slice(b"{\"doc\":");
+            value.write(payload)?;
+            payload.extend_from_slice(b"}\n");
+        }
+        _ => {
+            value.write(payload)?;
+            payload.push(b'\n');
+        }
+    }
+    Ok(())
+}
+
+/// Positions and error reasons of the documents a bulk response rejected
+fn failed_items(body: &mut [u8]) -> Result<Vec<(usize, String)>> {
+    let res = simd_json::to_borrowed_value(body)?;
+    if let Some(error) = res.get("error") {
+        return Err(format!("Elastic search error: {}", error.encode()).into());
+    }
+    let items = if let Some(items) = res.get("items").and_then(Value::as_array) {
+        items
+    } else {
+        return Ok(Vec::new());
+    };
+    Ok(items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| {
+            // every item is a record with the action as its only key
+            let error = item.as_object()?.values().next()?.get("error")?;
+            let reason = error
+                .get("reason")
+                .and_then(Value::as_str)
+                .map_or_else(|| error.encode(), String::from);
+            Some((i, reason))
+        })
+        .collect())
+}
+
+/// A document of a bulk request, kept to report it should it be rejected
+struct Doc {
+    id: u64,
+    value: Value<'static>,
+    meta: Value<'static>,
+}
+
+impl Doc {
+    /// Sends the document to the `err` output
+    fn reject(self, outputs: &Outputs, reason: String) {
+        let Self { id, value, meta } = self;
+        let mut meta = if let Value::Object(meta) = meta {
+            *meta
+        } else {
+            Object::new()
+        };
+        meta.insert("error".into(), Value::from(reason));
+        outputs.send(
+            ERR,
+            Event {
+                is_batch: false,
+                id,
+                data: (value, Value::from(meta)).into(),
+                ingest_ns: nanotime(),
+                origin_uri: None,
+                kind: None,
+            },
+        );
+    }
+}
+
+/// Sends a bulk request, returns the documents it rejected
+fn bulk(client: &SyncClient, payload: Vec<u8>) -> Result<Vec<(usize, String)>> {
+    let mut res = client.request(BulkRequest::new(payload)).send()?.into_raw();
+    let mut body = Vec::new();
+    res.read_to_end(&mut body)?;
+    failed_items(&mut body)
+}
+
 impl Elastic {
-    fn flush(client: &SyncClient, payload: Vec<u8>) -> Result<u64> {
+    /// Sends a bulk request, returns how long it took and the number of
+    /// documents elastic search rejected
+    fn flush(
+        client: &SyncClient,
+        payload: Vec<u8>,
+        docs: Vec<Doc>,
+        outputs: &Outputs,
+    ) -> Result<(u64, usize)> {
         let start = Instant::now();
-        let res = client.request(BulkRequest::new(payload)).send()?;
-        for item in res.into_response::<BulkErrorsResponse>()? {
-            // TODO update error metric here?
-            error!("Elastic Search item error: {:?}", item);
-        }
+        let failed = bulk(client, payload)?;
         let d = start.elapsed();
         let d = duration_to_millis(d);
-        Ok(d)
+
+        let rejected = failed.len();
+        let mut docs: Vec<Option<Doc>> = docs.into_iter().map(Some).collect();
+        for (i, reason) in failed {
+            // TODO update error metric here?
+            error!("Elastic Search item error: {}", reason);
+            if let Some(doc) = docs.get_mut(i).and_then(Option::take) {
+                doc.reject(outputs, reason);
+            }
+        }
+        Ok((d, rejected))
     }
 
-    fn enqueue_send_future(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn pipelines(&self) -> Vec<(TremorURL, pipeline::Addr)> {
+        self.pipelines
+            .iter()
+            .map(|(i, p)| (i.clone(), p.clone()))
+            .collect()
+    }
+
+    fn enqueue_send_future(
+        &mut self,
+        payload: Vec<u8>,
+        docs: Vec<Doc>,
+        id: u64,
+        origin_uri: Option<EventOriginUri>,
+    ) -> Result<()> {
         self.client_idx = (self.client_idx + 1) % self.clients.len();
         let destination = self.clients[self.client_idx].clone();
         let (tx, rx) = bounded(1);
-        let pipelines: Vec<(TremorURL, pipeline::Addr)> = self
-            .pipelines
-            .iter()
-            .map(|(i, p)| (i.clone(), p.clone()))
-            .collect();
+        let pipelines = self.pipelines();
+        let outputs = self.outputs.clone();
         self.pool.execute(move || {
-            let r = Self::flush(&destination.client, payload);
+            // Documents are only kept if they can be sent to `err`
+            let reported = !docs.is_empty();
+            let r = Self::flush(&destination.client, payload, docs, &outputs);
             let mut m = Object::new();
-            if let Ok(t) = r {
+            if let Ok((t, _)) = r {
                 m.insert("time".into(), t.into());
             } else {
                 // TODO update error metric here?
@@ -150,24 +309,28 @@ impl Elastic {
                 origin_uri: None,
                 kind: None,
             };
-
-            for (pid, p) in pipelines {
-                if p.addr
-                    .send(pipeline::Msg::Insight(insight.clone()))
-                    .is_err()
-                {
-                    error!("Failed to send contraflow to pipeline {}", pid)
-                };
-            }
-            // TODO: Handle contraflow for notification
-            if let Err(e) = tx.send(r) {
+            send_insight(&pipelines, &insight);
+            // Rejected documents went to `err` if it was connected, otherwise
+            // they fail the event
+            let cb = match &r {
+                Ok((_, rejected)) if *rejected == 0 || reported => CBAction::Ack,
+                Ok(_) | Err(_) => CBAction::Fail,
+            };
+            send_insight(&pipelines, &Event::cb(cb, nanotime(), id, origin_uri));
+            if let Err(e) = tx.send(r.map(|(t, _)| t)) {
                 error!("Failed to send reply: {}", e)
             }
         });
         self.queue.enqueue(rx)?;
         Ok(())
     }
-    fn maybe_enque(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn maybe_enque(
+        &mut self,
+        payload: Vec<u8>,
+        docs: Vec<Doc>,
+        id: u64,
+        origin_uri: Option<EventOriginUri>,
+    ) -> Result<()> {
         match self.queue.dequeue() {
             Err(SinkDequeueError::NotReady) if !self.queue.has_capacity() => {
                 let mut m = Object::new();
@@ -181,26 +344,16 @@ impl Elastic {
                     origin_uri: None,
                     kind: None,
                 };
-
-                let pipelines: Vec<(TremorURL, pipeline::Addr)> = self
-                    .pipelines
-                    .iter()
-                    .map(|(i, p)| (i.clone(), p.clone()))
-                    .collect();
-                for (pid, p) in pipelines {
-                    if p.addr
-                        .send(pipeline::Msg::Insight(insight.clone()))
-                        .is_err()
-                    {
-                        error!("Failed to send contraflow to pipeline {}", pid)
-                    };
-                }
+                send_insight(&self.pipelines(), &insight);
 
                 error!("Dropped data due to es overload");
                 Err("Dropped data due to es overload".into())
             }
             _ => {
-                if self.enqueue_send_future(payload).is_err() {
+                if self
+                    .enqueue_send_future(payload, docs, id, origin_uri)
+                    .is_err()
+                {
                     // TODO: handle reply to the pipeline
                     error!("Failed to enqueue send request to elastic");
                     Err("Failed to enqueue send request to elastic".into())
@@ -210,47 +363,44 @@ impl Elastic {
             }
         }
     }
-}
 
-impl Offramp for Elastic {
-    // We enforce json here!
-    fn on_event(&mut self, _codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+    fn send_event(&mut self, event: Event) -> Result<()> {
         // We estimate a single message is 512 byte on everage, might be off but it's
         // a guess
         let mut payload = Vec::with_capacity(4096);
+        // Only keep the documents if there is anyone to report rejected ones to
+        let keep_docs = self.outputs.is_connected(ERR);
+        let mut docs = Vec::new();
 
         for (value, meta) in event.value_meta_iter() {
-            let index = meta
-                .get("index")
-                .and_then(Value::as_str)
-                .ok_or_else(|| Error::from("'index' not set for elastic offramp!"))?;
-            let doc_type = meta
-                .get("doc_type")
-                .and_then(Value::as_str)
-                .ok_or_else(|| Error::from("'doc-type' not set for elastic offramp!"))?;
-            match meta.get("pipeline").and_then(Value::as_str) {
-                None => json!({
-                "index":
-                {
-                    "_index": index,
-                    "_type": doc_type
-                }})
-                .write(&mut payload)?,
+            write_doc(&mut payload, value, meta)?;
+            if keep_docs {
+                docs.push(Doc {
+                    id: event.id,
+                    value: value.clone_static(),
+                    meta: meta.clone_static(),
+                });
+            }
+        }
+        self.maybe_enque(payload, docs, event.id, event.origin_uri)
+    }
+}
 
-                Some(pipeline) => json!({
-                "index":
-                {
-                    "_index": index,
-                    "_type": doc_type,
-                    "pipeline": pipeline
-                }})
-                .write(&mut payload)?,
-            };
-            payload.push(b'\n');
-            value.write(&mut payload)?;
-            payload.push(b'\n');
+impl Offramp for Elastic {
+    // We enforce json here!
+    fn on_event(&mut self, _codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+        let id = event.id;
+        let origin_uri = event.origin_uri.clone();
+        let r = self.send_event(event);
+        // Sent requests are acknowledged once elastic search answered them
+        if r.is_err() {
+            let insight = Event::cb(CBAction::Fail, nanotime(), id, origin_uri);
+            send_insight(&self.pipelines(), &insight);
         }
-        self.maybe_enque(payload)
+        r
+    }
+    fn auto_ack(&self) -> bool {
+        false
     }
     fn default_codec(&self) -> &str {
         "json"
@@ -262,8 +412,94 @@ impl Offramp for Elastic {
         self.pipelines.remove(&id);
         self.pipelines.is_empty()
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn add_output(&mut self, port: Cow<'static, str>, id: TremorURL, addr: pipeline::Addr) {
+        self.outputs.add(port, id, addr);
+    }
+    fn remove_output(&mut self, port: &str, id: &TremorURL) {
+        self.outputs.remove(port, id);
+    }
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn lines(value: &Value, meta: simd_json::OwnedValue) -> Result<String> {
+        let meta: Value = meta.into();
+        let mut payload = Vec::new();
+        write_doc(&mut payload, value, &meta)?;
+        Ok(String::from_utf8_lossy(&payload).to_string())
+    }
+
+    #[test]
+    fn doc_from_meta() -> Result<()> {
+        let value: Value = json!({"snot": "badger"}).into();
+        assert_eq!(
+            "{\"index\":{\"_index\":\"i\",\"_type\":\"t\"}}\n{\"snot\":\"badger\"}\n",
+            lines(&value, json!({"index": "i", "doc_type": "t"}))?
+        );
+        assert_eq!(
+            "{\"create\":{\"_index\":\"logs\",\"_type\":\"_doc\",\"_id\":\"42\",\"pipeline\":\"p\"}}\n{\"snot\":\"badger\"}\n",
+            lines(
+                &value,
+                json!({"index": "i", "elastic": {
+                    "index": "logs",
+                    "doc_type": "_doc",
+                    "id": 42,
+                    "pipeline": "p",
+                    "action": "create"
+                }})
+            )?
+        );
+        assert_eq!(
+            "{\"update\":{\"_index\":\"i\",\"_type\":\"t\",\"_id\":\"1\"}}\n{\"doc\":{\"snot\":\"badger\"}}\n",
+            lines(
+                &value,
+                json!({"elastic": {"index": "i", "doc_type": "t", "id": "1", "action": "update"}})
+            )?
+        );
+        assert_eq!(
+            "{\"delete\":{\"_index\":\"i\",\"_type\":\"t\",\"_id\":\"1\"}}\n",
+            lines(
+                &value,
+                json!({"elastic": {"index": "i", "doc_type": "t", "id": "1", "action": "delete"}})
+            )?
+        );
+        assert!(lines(&value, json!({"doc_type": "t"})).is_err());
+        assert!(lines(
+            &value,
+            json!({"elastic": {"index": "i", "doc_type": "t", "action": "upsert"}})
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_items() -> Result<()> {
+        let mut body = br#"{"took": 3, "errors": true, "items": [
+            {"index": {"_index": "i", "status": 201}},
+            {"create": {"_index": "i", "status": 409, "error": {"type": "version_conflict_engine_exception", "reason": "document already exists"}}},
+            {"delete": {"_index": "i", "status": 404, "error": {"type": "not_found"}}}
+        ]}"#
+        .to_vec();
+        assert_eq!(
+            vec![
+                (1, "document already exists".to_string()),
+                (2, "{\"type\":\"not_found\"}".to_string())
+            ],
+            failed_items(&mut body)?
+        );
+        let mut body = br#"{"error": {"reason": "bad"}, "status": 400}"#.to_vec();
+        assert!(failed_items(&mut body).is_err());
+        Ok(())
+    }
+}
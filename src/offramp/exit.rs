@@ -66,7 +66,11 @@ impl Offramp for Exit {
     fn default_codec(&self) -> &str {
         "json"
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
@@ -14,62 +14,392 @@
 
 //! # File Offramp
 //!
-//! Writes events to a file, one event per line
+//! Writes events to files, use the `lines` postprocessor to separate them
+//!
+//! The file name is a template, `{$meta.<key>}` is replaced by the metadata
+//! of the event and strftime escapes like `%Y-%m-%d` by its ingest time.
+//! Files are appended to and rotated once they reach `max_size` bytes or
+//! are open for `max_age_s` seconds. Rotated files are renamed to
+//! `<file>.1`, `<file>.2` and so on, optionally compressed, and only the
+//! last `keep` of them are kept.
+//!
+//! Files that weren't written to for `close_idle_s` seconds are closed so
+//! templated names don't leave a file open for every name ever written,
+//! their age keeps counting while they are closed. Writes are buffered and
+//! flushed once a second, files are rotated by age and closed at the same
+//! time, even without new events.
 //!
 //! ## Configuration
 //!
 //! See [Config](struct.Config.html) for details.
 
 use crate::offramp::prelude::*;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
 use halfbrown::HashMap;
-use std::fs::File as FSFile;
-use std::io::Write;
+use simd_json::borrowed::Value;
+use std::fs::{self, File as FSFile, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Filename to write to, may contain `{$meta.<key>}` placeholders and
+    /// strftime escapes
+    pub file: String,
+    /// Rotate a file before it grows past this many bytes
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Rotate a file after it was open for this many seconds
+    #[serde(default)]
+    pub max_age_s: Option<u64>,
+    /// Number of rotated files to keep, defaults to 10
+    #[serde(default = "dflt_keep")]
+    pub keep: usize,
+    /// Compression of rotated files, one of `gzip`, `zlib`, `xz2`,
+    /// `snappy` or `lz4`
+    #[serde(default)]
+    pub compression: Option<String>,
+    /// Close a file once it wasn't written to for this many seconds,
+    /// defaults to 60
+    #[serde(default = "dflt_close_idle")]
+    pub close_idle_s: u64,
+}
+
+impl ConfigImpl for Config {}
+
+fn dflt_keep() -> usize {
+    10
+}
+
+fn dflt_close_idle() -> u64 {
+    60
+}
+
+/// Time between flushes of the open files
+const FLUSH_INTERVAL_NS: u64 = 1_000_000_000;
+
+fn extension(compression: &str) -> Result<&'static str> {
+    match compression {
+        "gzip" => Ok("gz"),
+        "zlib" => Ok("zz"),
+        "xz2" => Ok("xz"),
+        "snappy" => Ok("sz"),
+        "lz4" => Ok("lz4"),
+        other => Err(format!("Unsupported file compression '{}'", other).into()),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    /// Literal text with strftime escapes
+    Time(String),
+    /// Path into the event metadata
+    Meta(Vec<String>),
+}
+
+/// A parsed file name template
+#[derive(Debug, PartialEq)]
+struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    fn time(parts: &mut Vec<Part>, s: &str) -> Result<()> {
+        if StrftimeItems::new(s).any(|i| i == Item::Error) {
+            Err(format!("Invalid time format in file name '{}'", s).into())
+        } else {
+            if !s.is_empty() {
+                parts.push(Part::Time(s.to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{$") {
+            Self::time(&mut parts, &rest[..start])?;
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::from(format!("Unterminated `{{$` in '{}'", template)))?;
+            let path: Vec<String> = rest[start + 2..start + end]
+                .split('.')
+                .map(String::from)
+                .collect();
+            match path.split_first() {
+                Some((meta, keys)) if meta == "meta" && !keys.is_empty() => {
+                    parts.push(Part::Meta(keys.to_vec()))
+                }
+                _ => {
+                    return Err(format!(
+                        "Only `{{$meta.<key>}}` is supported in file names, not '{}'",
+                        &rest[start..=start + end]
+                    )
+                    .into())
+                }
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Self::time(&mut parts, rest)?;
+        Ok(Self { parts })
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn render(&self, meta: &Value, ingest_ns: u64) -> Result<PathBuf> {
+        let time = Utc.timestamp_nanos(ingest_ns as i64);
+        let mut res = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Time(s) if s.contains('%') => res.push_str(&time.format(s).to_string()),
+                Part::Time(s) => res.push_str(s),
+                Part::Meta(keys) => {
+                    let value = keys
+                        .iter()
+                        .try_fold(meta, |v, k| v.get(k.as_str()))
+                        .ok_or_else(|| {
+                            Error::from(format!("Missing metadata $meta.{}", keys.join(".")))
+                        })?;
+                    let value = value.as_str().map_or_else(|| value.encode(), String::from);
+                    // metadata must not be able to leave the directory
+                    if value.is_empty() || value == "." || value == ".." {
+                        res.push('_');
+                    } else {
+                        res.extend(value.chars().map(|c| {
+                            if std::path::is_separator(c) {
+                                '_'
+                            } else {
+                                c
+                            }
+                        }));
+                    }
+                }
+            }
+        }
+        Ok(PathBuf::from(res))
+    }
+}
+
+/// A file currently written to
+struct Open {
+    file: BufWriter<FSFile>,
+    size: u64,
+    written_ns: u64,
+}
 
 /// An offramp that write a given file
 pub struct File {
-    file: FSFile,
+    template: Template,
+    max_size: Option<u64>,
+    max_age_ns: Option<u64>,
+    close_idle_ns: u64,
+    keep: usize,
+    compression: Option<(Box<dyn Postprocessor>, &'static str)>,
+    files: HashMap<PathBuf, Open>,
+    /// When files were first written to after their last rotation, closed
+    /// files included. Only tracked with a `max_age_s`.
+    opened: HashMap<PathBuf, u64>,
+    flushed_ns: u64,
     pipelines: HashMap<TremorURL, pipeline::Addr>,
     postprocessors: Postprocessors,
 }
 
-#[derive(Deserialize)]
-pub struct Config {
-    /// Filename to write to
-    pub file: String,
+fn rotated(path: &Path, n: usize, ext: Option<&str>) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    if let Some(ext) = ext {
+        name.push(format!(".{}", ext));
+    }
+    PathBuf::from(name)
 }
 
-impl ConfigImpl for Config {}
+impl File {
+    fn with_config(config: Config) -> Result<Self> {
+        let compression = if let Some(name) = &config.compression {
+            Some((postprocessor::lookup(name, &None)?, extension(name)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            template: Template::parse(&config.file)?,
+            max_size: config.max_size,
+            max_age_ns: config.max_age_s.map(|s| s * 1_000_000_000),
+            close_idle_ns: config.close_idle_s * 1_000_000_000,
+            keep: config.keep,
+            compression,
+            files: HashMap::new(),
+            opened: HashMap::new(),
+            flushed_ns: 0,
+            pipelines: HashMap::new(),
+            postprocessors: vec![],
+        })
+    }
+
+    fn open(path: &Path, now: u64) -> Result<Open> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Open {
+            size: file.metadata()?.len(),
+            file: BufWriter::new(file),
+            written_ns: now,
+        })
+    }
+
+    /// Closes the file and shifts it and its older rotations by one
+    fn rotate(&mut self, path: &Path) -> Result<()> {
+        if let Some(mut open) = self.files.remove(path) {
+            open.file.flush()?;
+        }
+        self.opened.remove(path);
+        let ext = self.compression.as_ref().map(|(_, ext)| *ext);
+        if self.keep == 0 {
+            return Ok(fs::remove_file(path)?);
+        }
+        let oldest = rotated(path, self.keep, ext);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for n in (1..self.keep).rev() {
+            let from = rotated(path, n, ext);
+            if from.exists() {
+                fs::rename(from, rotated(path, n + 1, ext))?;
+            }
+        }
+        if let Some((compressor, ext)) = &mut self.compression {
+            let data = fs::read(path)?;
+            let compressed = compressor.process(nanotime(), nanotime(), &data)?;
+            fs::write(rotated(path, 1, Some(*ext)), compressed.concat())?;
+            fs::remove_file(path)?;
+        } else {
+            fs::rename(path, rotated(path, 1, None))?;
+        }
+        debug!("[File Offramp] Rotated {}", path.display());
+        Ok(())
+    }
+
+    fn rotate_expired(&mut self, now: u64) -> Result<()> {
+        if let Some(max_age_ns) = self.max_age_ns {
+            let expired: Vec<PathBuf> = self
+                .opened
+                .iter()
+                .filter(|(_, opened_ns)| now.saturating_sub(**opened_ns) >= max_age_ns)
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in expired {
+                // Files removed behind our back have nothing to rotate
+                if self.files.contains_key(&path) || path.exists() {
+                    self.rotate(&path)?;
+                } else {
+                    self.opened.remove(&path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn close_idle(&mut self, now: u64) -> Result<()> {
+        let close_idle_ns = self.close_idle_ns;
+        let idle: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, open)| now.saturating_sub(open.written_ns) >= close_idle_ns)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in idle {
+            if let Some(mut open) = self.files.remove(&path) {
+                open.file.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, path: PathBuf, data: &[u8], now: u64) -> Result<()> {
+        let len = data.len() as u64;
+        let full = self.files.get(&path).map_or_else(
+            || path.metadata().map(|m| m.len()).unwrap_or(0),
+            |open| open.size,
+        );
+        if full > 0 && self.max_size.map_or(false, |max| full + len > max) {
+            self.rotate(&path)?;
+        }
+        if !self.files.contains_key(&path) {
+            let open = Self::open(&path, now)?;
+            self.files.insert(path.clone(), open);
+        }
+        if self.max_age_ns.is_some() && !self.opened.contains_key(&path) {
+            self.opened.insert(path.clone(), now);
+        }
+        if let Some(open) = self.files.get_mut(&path) {
+            open.file.write_all(data)?;
+            open.size += len;
+            open.written_ns = now;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for open in self.files.values_mut() {
+            open.file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Rotates expired files, closes idle ones and flushes the others if
+    /// they weren't flushed for a while
+    fn maintain(&mut self, now: u64) -> Result<()> {
+        self.rotate_expired(now)?;
+        self.close_idle(now)?;
+        if now.saturating_sub(self.flushed_ns) >= FLUSH_INTERVAL_NS {
+            self.flush()?;
+            self.flushed_ns = now;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("[File Offramp] Failed to flush files: {}", e);
+        }
+    }
+}
 
 impl offramp::Impl for File {
     fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
         if let Some(config) = config {
             let config: Config = Config::new(config)?;
-            let file = FSFile::create(config.file)?;
-            Ok(Box::new(Self {
-                file,
-                pipelines: HashMap::new(),
-                postprocessors: vec![],
-            }))
+            Ok(Box::new(Self::with_config(config)?))
         } else {
-            Err("Blackhole offramp requires a config".into())
+            Err("File offramp requires a config".into())
         }
     }
 }
 
 impl Offramp for File {
-    // TODO
     fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
-        for value in event.value_iter() {
+        let now = nanotime();
+        self.maintain(now)?;
+        for (value, meta) in event.value_meta_iter() {
+            let path = self.template.render(meta, event.ingest_ns)?;
             let raw = codec.encode(value)?;
             let packets = postprocess(&mut self.postprocessors, event.ingest_ns, raw.to_vec())?;
             for packet in packets {
-                self.file
-                    .write_all(&packet)
-                    .and_then(|_| self.file.write_all(b"\n"))?;
+                self.write(path.clone(), &packet, now)?;
             }
         }
         Ok(())
     }
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(Duration::from_nanos(FLUSH_INTERVAL_NS))
+    }
+    fn on_tick(&mut self) -> Result<()> {
+        self.maintain(nanotime())
+    }
     fn add_pipeline(&mut self, id: TremorURL, addr: pipeline::Addr) {
         self.pipelines.insert(id, addr);
     }
@@ -80,8 +410,147 @@ impl Offramp for File {
     fn default_codec(&self) -> &str {
         "json"
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    // 2020-09-01T12:00:00Z
+    const TS: u64 = 1_598_961_600_000_000_000;
+
+    fn config(file: &str) -> Config {
+        Config {
+            file: file.to_string(),
+            max_size: None,
+            max_age_s: None,
+            keep: 2,
+            compression: None,
+            close_idle_s: 60,
+        }
+    }
+
+    #[test]
+    fn template() -> Result<()> {
+        let t = Template::parse("/data/{$meta.tenant}/{$meta.a.b}-%Y-%m-%d.log")?;
+        let meta: Value = json!({"tenant": "snot", "a": {"b": 42}}).into();
+        assert_eq!(
+            PathBuf::from("/data/snot/42-2020-09-01.log"),
+            t.render(&meta, TS)?
+        );
+        let meta: Value = json!({"tenant": "../..", "a": {"b": ".."}}).into();
+        assert_eq!(
+            PathBuf::from("/data/.._../_-2020-09-01.log"),
+            t.render(&meta, TS)?
+        );
+        let meta: Value = json!({"tenant": "snot"}).into();
+        assert!(t.render(&meta, TS).is_err());
+
+        assert!(Template::parse("/data/{$meta.x").is_err());
+        assert!(Template::parse("/data/{$meta}").is_err());
+        assert!(Template::parse("/data/{$event.x}").is_err());
+        assert!(Template::parse("/data/%Q").is_err());
+        // `{` without `$` is just text
+        assert_eq!(
+            Template {
+                parts: vec![Part::Time("/data/{x}".to_string())]
+            },
+            Template::parse("/data/{x}")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_by_size() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("out.log");
+        let mut c = config(&path.to_string_lossy());
+        c.max_size = Some(4);
+        c.compression = Some("gzip".to_string());
+        let mut file = File::with_config(c)?;
+        for data in &["aaa\n", "bbb\n", "ccc\n", "ddd\n"] {
+            file.write(path.clone(), data.as_bytes(), 0)?;
+        }
+        file.flush()?;
+
+        assert_eq!("ddd\n", fs::read_to_string(&path)?);
+        assert!(!rotated(&path, 3, Some("gz")).exists());
+        let mut decoder =
+            libflate::gzip::Decoder::new(FSFile::open(rotated(&path, 1, Some("gz")))?)?;
+        let mut data = String::new();
+        decoder.read_to_string(&mut data)?;
+        assert_eq!("ccc\n", data);
+        assert!(rotated(&path, 2, Some("gz")).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_by_age() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("out.log");
+        let mut c = config(&path.to_string_lossy());
+        c.max_age_s = Some(10);
+        let mut file = File::with_config(c)?;
+        file.write(path.clone(), b"a", 0)?;
+        file.rotate_expired(5_000_000_000)?;
+        file.write(path.clone(), b"b", 5_000_000_000)?;
+        file.rotate_expired(10_000_000_000)?;
+        file.write(path.clone(), b"c", 10_000_000_000)?;
+        file.flush()?;
+
+        assert_eq!("c", fs::read_to_string(&path)?);
+        assert_eq!("ab", fs::read_to_string(rotated(&path, 1, None))?);
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_closed_by_age() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("out.log");
+        let mut c = config(&path.to_string_lossy());
+        c.max_age_s = Some(10);
+        c.close_idle_s = 2;
+        let mut file = File::with_config(c)?;
+        file.write(path.clone(), b"a", 0)?;
+        // Closing and opening the file again keeps its age
+        file.maintain(4_000_000_000)?;
+        file.write(path.clone(), b"b", 4_000_000_000)?;
+        file.maintain(8_000_000_000)?;
+        assert!(file.files.is_empty());
+        // It is rotated while it is closed
+        file.maintain(10_000_000_000)?;
+
+        assert!(!path.exists());
+        assert_eq!("ab", fs::read_to_string(rotated(&path, 1, None))?);
+        assert!(file.opened.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn close_idle() -> Result<()> {
+        let dir = tempdir()?;
+        let mut c = config(&dir.path().join("{$meta.tenant}.log").to_string_lossy());
+        c.close_idle_s = 10;
+        let mut file = File::with_config(c)?;
+        file.write(dir.path().join("snot.log"), b"a", 0)?;
+        file.write(dir.path().join("badger.log"), b"b", 5_000_000_000)?;
+        file.close_idle(10_000_000_000)?;
+        // only the file written to recently is still open, the other one
+        // was flushed when it was closed
+        assert_eq!(1, file.files.len());
+        assert!(file.files.contains_key(&dir.path().join("badger.log")));
+        assert_eq!("a", fs::read_to_string(dir.path().join("snot.log"))?);
+        Ok(())
+    }
+}
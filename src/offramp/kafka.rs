@@ -16,6 +16,19 @@
 //!
 //! The `kafka` offramp allows persisting events to a kafka queue.
 //!
+//! The `$kafka` metadata of an event can override where it is sent:
+//!
+//! * `topic` - the topic to send to
+//! * `key` - the message key
+//! * `partition` - the partition to send to
+//! * `headers` - a record of message headers, non string values are sent as json
+//! * `timestamp` - the message timestamp in milliseconds
+//!
+//! For every message an insight is sent back to the pipelines once it is
+//! delivered, its metadata holds `success` and the `kafka` topic, partition
+//! and offset, or the `error` if delivery failed. Once all messages of an
+//! event are delivered it is acknowledged, or failed if any of them wasn't.
+//!
 //! ## Configuration
 //!
 //! See [Config](struct.Config.html) for details.
@@ -23,7 +36,10 @@
 use crate::offramp::prelude::*;
 use halfbrown::HashMap;
 use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::message::OwnedHeaders;
+use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord};
+use simd_json::borrowed::{Object, Value};
+use std::convert::TryFrom;
 use std::fmt;
 
 #[derive(Deserialize)]
@@ -105,29 +121,132 @@ impl offramp::Impl for Kafka {
     }
 }
 
-impl Offramp for Kafka {
-    // TODO
-    fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
-        for value in event.value_iter() {
-            let raw = codec.encode(value)?;
-            let mut record = FutureRecord::to(&self.topic);
-            record = record.payload(&raw);
-            //TODO: Key
+/// Builds the record for a message, using the `$kafka` metadata over the
+/// configured topic and key
+fn record<'a>(
+    topic: &'a str,
+    key: Option<&'a str>,
+    meta: &'a Value,
+    payload: &'a [u8],
+) -> Result<FutureRecord<'a, str, [u8]>> {
+    let kafka = meta.get("kafka");
+    let get = |name: &str| kafka.and_then(|k| k.get(name));
+    let topic = get("topic").and_then(Value::as_str).unwrap_or(topic);
+    let mut record = FutureRecord::to(topic).payload(payload);
+    if let Some(key) = get("key").and_then(Value::as_str).or(key) {
+        record = record.key(key);
+    }
+    if let Some(partition) = get("partition").and_then(Value::as_i64) {
+        let partition = i32::try_from(partition)
+            .map_err(|_| Error::from(format!("Invalid kafka partition {}", partition)))?;
+        record = record.partition(partition);
+    }
+    if let Some(timestamp) = get("timestamp").and_then(Value::as_i64) {
+        record = record.timestamp(timestamp);
+    }
+    if let Some(headers) = get("headers").and_then(Value::as_object) {
+        let headers = headers.iter().fold(OwnedHeaders::new(), |h, (k, v)| {
+            let v = v.as_str().map_or_else(|| v.encode(), String::from);
+            h.add(k, v.as_str())
+        });
+        record = record.headers(headers);
+    }
+    Ok(record)
+}
 
-            let record = if let Some(ref k) = self.key {
-                record.key(k.as_str())
-            } else {
-                record
-            };
+/// Reports the delivery of a message to the pipelines
+fn report(
+    pipelines: &[(TremorURL, pipeline::Addr)],
+    id: u64,
+    topic: String,
+    result: std::result::Result<(i32, i64), String>,
+) {
+    let mut kafka = Object::new();
+    kafka.insert("topic".into(), Value::from(topic));
+    let mut m = Object::new();
+    match result {
+        Ok((partition, offset)) => {
+            kafka.insert("partition".into(), Value::from(i64::from(partition)));
+            kafka.insert("offset".into(), Value::from(offset));
+            m.insert("success".into(), Value::from(true));
+        }
+        Err(e) => {
+            m.insert("success".into(), Value::from(false));
+            m.insert("error".into(), Value::from(e));
+        }
+    }
+    m.insert("kafka".into(), Value::from(kafka));
+    let insight = Event {
+        is_batch: false,
+        id,
+        data: (Value::null(), m).into(),
+        ingest_ns: nanotime(),
+        origin_uri: None,
+        kind: None,
+    };
+    send_insight(pipelines, &insight);
+}
+
+impl Kafka {
+    /// Enqueues a message for every value of the event
+    fn enqueue(
+        &self,
+        codec: &dyn Codec,
+        event: &Event,
+        pipelines: &[(TremorURL, pipeline::Addr)],
+        deliveries: &mut Vec<(String, DeliveryFuture)>,
+    ) -> Result<()> {
+        for (value, meta) in event.value_meta_iter() {
+            let raw = codec.encode(value)?;
+            let record = record(&self.topic, self.key.as_deref(), meta, &raw)?;
+            let topic = record.topic.to_string();
             match self.producer.send_result(record) {
-                Ok(f) => {
-                    task::spawn(f);
+                Ok(f) => deliveries.push((topic, f)),
+                Err((e, _)) => {
+                    error!("[Kafka Offramp] failed to enque message: {}", e);
+                    report(pipelines, event.id, topic, Err(e.to_string()));
+                    return Err(e.into());
                 }
-                Err((e, _)) => error!("[Kafka Offramp] failed to enque message: {}", e),
             }
         }
         Ok(())
     }
+}
+
+impl Offramp for Kafka {
+    fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+        let pipelines: Vec<_> = self
+            .pipelines
+            .iter()
+            .map(|(pid, p)| (pid.clone(), p.clone()))
+            .collect();
+        let mut deliveries = Vec::new();
+        let res = self.enqueue(codec.as_ref(), &event, &pipelines, &mut deliveries);
+        let mut delivered = res.is_ok();
+        let id = event.id;
+        let origin_uri = event.origin_uri;
+        task::spawn(async move {
+            for (topic, f) in deliveries {
+                let result = match f.await {
+                    Ok(Ok(position)) => Ok(position),
+                    Ok(Err((e, _))) => Err(e.to_string()),
+                    Err(_) => Err("Delivery canceled".to_string()),
+                };
+                if let Err(e) = &result {
+                    error!("[Kafka Offramp] failed to deliver message: {}", e);
+                    delivered = false;
+                }
+                report(&pipelines, id, topic, result);
+            }
+            let cb = if delivered {
+                CBAction::Ack
+            } else {
+                CBAction::Fail
+            };
+            send_insight(&pipelines, &Event::cb(cb, nanotime(), id, origin_uri));
+        });
+        res
+    }
     fn add_pipeline(&mut self, id: TremorURL, addr: pipeline::Addr) {
         self.pipelines.insert(id, addr);
     }
@@ -138,8 +257,55 @@ impl Offramp for Kafka {
     fn default_codec(&self) -> &str {
         "json"
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn auto_ack(&self) -> bool {
+        false
+    }
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rdkafka::message::Headers;
+    use simd_json::json;
+
+    #[test]
+    fn record_from_meta() -> Result<()> {
+        let meta: Value = json!({}).into();
+        let r = record("snot", Some("badger"), &meta, b"data")?;
+        assert_eq!("snot", r.topic);
+        assert_eq!(Some("badger"), r.key);
+        assert_eq!(None, r.partition);
+        assert!(r.headers.is_none());
+
+        let meta: Value = json!({"kafka": {
+            "topic": "tenant-1",
+            "key": "k",
+            "partition": 3,
+            "timestamp": 1_598_961_600_000_i64,
+            "headers": {"h1": "v1", "h2": 2}
+        }})
+        .into();
+        let r = record("snot", Some("badger"), &meta, b"data")?;
+        assert_eq!("tenant-1", r.topic);
+        assert_eq!(Some("k"), r.key);
+        assert_eq!(Some(3), r.partition);
+        assert_eq!(Some(1_598_961_600_000), r.timestamp);
+        let headers = r.headers.expect("no headers");
+        assert_eq!(2, headers.count());
+        let mut headers: Vec<_> = (0..2).filter_map(|i| headers.get(i)).collect();
+        headers.sort();
+        assert_eq!(vec![("h1", &b"v1"[..]), ("h2", &b"2"[..])], headers);
+
+        let meta: Value = json!({"kafka": {"partition": 1_i64 << 40}}).into();
+        assert!(record("snot", None, &meta, b"data").is_err());
+        Ok(())
+    }
+}
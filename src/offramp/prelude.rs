@@ -16,25 +16,38 @@ pub(crate) use crate::async_sink::{AsyncSink, SinkDequeueError};
 pub(crate) use crate::codec::Codec;
 pub(crate) use crate::dflt::{self};
 pub(crate) use crate::errors::*;
-pub(crate) use crate::offramp::{self, Offramp};
+pub(crate) use crate::offramp::{self, Offramp, Outputs};
 pub(crate) use crate::pipeline;
-pub(crate) use crate::postprocessor::{self, Postprocessor, Postprocessors};
+pub(crate) use crate::postprocessor::{self, Postprocessor, PostprocessorConfig, Postprocessors};
 pub(crate) use crate::url::TremorURL;
 pub(crate) use crate::utils::ConfigImpl;
 pub(crate) use crate::utils::{duration_to_millis, hostname, nanotime};
 pub(crate) use crate::{Event, OpConfig};
 pub(crate) use async_std::task;
 pub(crate) use simd_json::prelude::*;
+pub(crate) use tremor_pipeline::CBAction;
 
 //pub(crate) use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use std::mem;
 
-pub fn make_postprocessors(postprocessors: &[String]) -> Result<Postprocessors> {
+pub fn make_postprocessors(postprocessors: &[PostprocessorConfig]) -> Result<Postprocessors> {
     postprocessors
         .iter()
-        .map(|n| postprocessor::lookup(&n))
+        .map(postprocessor::from_config)
         .collect()
 }
+/// Sends an insight to all pipelines connected to an offramp
+pub fn send_insight(pipelines: &[(TremorURL, pipeline::Addr)], insight: &Event) {
+    for (pid, p) in pipelines {
+        if p.addr
+            .send(pipeline::Msg::Insight(insight.clone()))
+            .is_err()
+        {
+            error!("Failed to send contraflow to pipeline {}", pid)
+        };
+    }
+}
+
 // We are borrowing a dyn box as we don't want to pass ownership.
 #[allow(clippy::borrowed_box)]
 pub fn postprocess(
@@ -0,0 +1,148 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Response offramp
+//!
+//! Sends events back to the client of a linked onramp (`rest` or `ws`),
+//! identified by the `$correlation` metadata the onramp set on the request.
+//!
+//! The status code and headers of the response are taken from
+//! `$response.status` and `$response.headers`.
+//!
+//! ## Configuration
+//!
+//! This offramp takes no configuration
+
+use crate::offramp::prelude::*;
+use crate::ramp::link;
+use halfbrown::HashMap;
+use simd_json::BorrowedValue as Value;
+
+pub struct Response {
+    pipelines: HashMap<TremorURL, pipeline::Addr>,
+    postprocessors: Postprocessors,
+}
+
+impl offramp::Impl for Response {
+    fn from_config(_config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        Ok(Box::new(Self {
+            pipelines: HashMap::new(),
+            postprocessors: vec![],
+        }))
+    }
+}
+
+fn headers(meta: &Value) -> Vec<(String, Vec<String>)> {
+    meta.get("response")
+        .and_then(|r| r.get("headers"))
+        .and_then(Value::as_object)
+        .map(|headers| {
+            headers
+                .iter()
+                .map(|(name, values)| {
+                    let values = if let Some(values) = values.as_array() {
+                        values
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(String::from)
+                            .collect()
+                    } else {
+                        values.as_str().map(String::from).into_iter().collect()
+                    };
+                    (name.to_string(), values)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl Offramp for Response {
+    fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+        for (value, meta) in event.value_meta_iter() {
+            let id = meta
+                .get(link::CORRELATION)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| Error::from("Event has no `$correlation` to respond to"))?;
+            let status = meta
+                .get("response")
+                .and_then(|r| r.get("status"))
+                .and_then(Value::as_u16);
+            let raw = codec.encode(value)?;
+            let data = postprocess(&mut self.postprocessors, event.ingest_ns, raw)?.concat();
+            link::reply(
+                id,
+                link::Response {
+                    status,
+                    headers: headers(meta),
+                    data,
+                },
+            )?;
+        }
+        Ok(())
+    }
+    fn add_pipeline(&mut self, id: TremorURL, addr: pipeline::Addr) {
+        self.pipelines.insert(id, addr);
+    }
+    fn remove_pipeline(&mut self, id: TremorURL) -> bool {
+        self.pipelines.remove(&id);
+        self.pipelines.is_empty()
+    }
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(postprocessors)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    #[test]
+    fn response_headers() {
+        let meta: Value = json!({
+            "response": {
+                "status": 201,
+                "headers": {
+                    "content-type": "application/json",
+                    "x-snot": ["badger", "badger"]
+                }
+            }
+        })
+        .into();
+        let mut headers = headers(&meta);
+        headers.sort();
+        assert_eq!(
+            vec![
+                (
+                    "content-type".to_string(),
+                    vec!["application/json".to_string()]
+                ),
+                (
+                    "x-snot".to_string(),
+                    vec!["badger".to_string(), "badger".to_string()]
+                ),
+            ],
+            headers
+        );
+        assert!(super::headers(&Value::null()).is_empty());
+    }
+}
@@ -12,13 +12,46 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! # REST Offramp
+//!
+//! The `rest` offramp sends every event as a HTTP request to one of the
+//! configured endpoints.
+//!
+//! The `$request` metadata of an event can override the request:
+//!
+//! * `method` - the HTTP method
+//! * `path` - the path of the URL, replacing the one of the endpoint
+//! * `query` - a record of query parameters, or a query string
+//! * `headers` - a record of headers, given as a string or a list of strings
+//!
+//! Requests that fail to be sent, or are answered with one of the
+//! `retry_on` status codes, are retried with exponential backoff. Events
+//! whose request is still answered with an error status after that fail.
+//!
+//! ## Outputs
+//!
+//! Successful responses are decoded with the `response_codec` and sent to
+//! the `out` output, with their `status` and `headers` in the `$response` metadata.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::codec::{self, CodecConfig};
 use crate::offramp::prelude::*;
 use crossbeam_channel::bounded;
 use halfbrown::HashMap;
+use http_types::headers::{HeaderName, HeaderValue};
+use http_types::Method;
 use simd_json::borrowed::Object;
-use std::str;
-use std::time::Instant;
+use std::borrow::Cow;
+use std::str::{self, FromStr};
+use std::time::{Duration, Instant};
 use tremor_script::prelude::*;
+use url::Url;
+
+/// Output responses are sent to
+const OUT: &str = "out";
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
@@ -32,6 +65,31 @@ pub struct Config {
     pub put: bool,
     #[serde(default = "dflt::d")]
     pub headers: HashMap<String, String>,
+    /// status codes to retry requests on (default: none)
+    #[serde(default = "dflt::d")]
+    pub retry_on: Vec<u16>,
+    /// maximum number of retries of a request (default: 3)
+    #[serde(default = "d_max_retries")]
+    pub max_retries: u32,
+    /// milliseconds to wait before the first retry, doubled for every
+    /// further one (default: 100)
+    #[serde(default = "d_backoff_ms")]
+    pub backoff_ms: u64,
+    /// codec to decode responses with (default: json)
+    #[serde(default = "d_response_codec")]
+    pub response_codec: CodecConfig,
+}
+
+fn d_max_retries() -> u32 {
+    3
+}
+
+fn d_backoff_ms() -> u64 {
+    100
+}
+
+fn d_response_codec() -> CodecConfig {
+    "json".into()
 }
 
 impl ConfigImpl for Config {}
@@ -41,6 +99,7 @@ pub struct Rest {
     config: Config,
     queue: AsyncSink<u64>,
     pipelines: HashMap<TremorURL, pipeline::Addr>,
+    outputs: Outputs,
     postprocessors: Postprocessors,
 }
 
@@ -53,6 +112,7 @@ impl offramp::Impl for Rest {
             Ok(Box::new(Self {
                 client_idx: 0,
                 pipelines: HashMap::new(),
+                outputs: Outputs::default(),
                 postprocessors: vec![],
                 config,
                 queue,
@@ -63,51 +123,219 @@ impl offramp::Impl for Rest {
     }
 }
 
-impl Rest {
-    async fn flush(endpoint: &str, config: Config, payload: Vec<u8>) -> Result<u64> {
-        let start = Instant::now();
-        let mut c = if config.put {
-            surf::put(endpoint)
+/// A request to send, from the config and the `$request` metadata
+#[derive(Debug, Clone, PartialEq)]
+struct Request {
+    method: Method,
+    url: Url,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn new(endpoint: &str, config: &Config, meta: &Value) -> Result<Self> {
+        let request = meta.get("request");
+        let get = |name: &str| request.and_then(|r| r.get(name));
+        let method = if let Some(method) = get("method").and_then(Value::as_str) {
+            Method::from_str(&method.to_uppercase())
+                .map_err(|_| Error::from(format!("Invalid HTTP method {}", method)))?
+        } else if config.put {
+            Method::Put
         } else {
-            surf::post(endpoint)
+            Method::Post
         };
-        c = c.body_bytes(&payload);
-        for (k, v) in config.headers {
-            use http_types::headers::HeaderName;
-            match HeaderName::from_bytes(k.as_str().as_bytes().to_vec()) {
-                Ok(h) => {
-                    c = c.set_header(h, v.as_str());
+
+        let mut url = Url::parse(endpoint)
+            .map_err(|e| Error::from(format!("Invalid endpoint {}: {}", endpoint, e)))?;
+        if let Some(path) = get("path").and_then(Value::as_str) {
+            url.set_path(path);
+        }
+        if let Some(query) = get("query") {
+            if let Some(query) = query.as_str() {
+                url.set_query(Some(query));
+            } else if let Some(query) = query.as_object() {
+                let mut pairs = url.query_pairs_mut();
+                for (k, v) in query.iter() {
+                    pairs.append_pair(k, &v.as_str().map_or_else(|| v.encode(), String::from));
                 }
-                Err(e) => error!("Bad header name: {}", e),
             }
         }
 
-        let mut reply = c.await?;
-        let status = reply.status();
-        if status.is_client_error() || status.is_server_error() {
-            if let Ok(body) = reply.body_string().await {
-                error!("HTTP request failed: {} => {}", status, body)
+        let mut headers: Vec<(String, String)> = config
+            .headers
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if let Some(meta_headers) = get("headers").and_then(Value::as_object) {
+            for (k, v) in meta_headers.iter() {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case(k));
+                if let Some(values) = v.as_array() {
+                    headers.extend(
+                        values
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(|v| (k.to_string(), v.to_string())),
+                    );
+                } else if let Some(v) = v.as_str() {
+                    headers.push((k.to_string(), v.to_string()));
+                }
+            }
+        }
+        Ok(Self {
+            method,
+            url,
+            headers,
+        })
+    }
+
+    fn send(&self, payload: &[u8]) -> surf::Request {
+        // All values of a header are set at once, setting them one by one
+        // would only keep the last
+        let mut headers: Vec<(HeaderName, Vec<HeaderValue>)> = Vec::new();
+        for (k, v) in &self.headers {
+            let name = match HeaderName::from_bytes(k.as_bytes().to_vec()) {
+                Ok(name) => name,
+                Err(e) => {
+                    error!("Bad header name: {}", e);
+                    continue;
+                }
+            };
+            let value = match HeaderValue::from_str(v) {
+                Ok(value) => value,
+                Err(e) => {
+                    error!("Bad value for header {}: {}", name, e);
+                    continue;
+                }
+            };
+            if let Some((_, values)) = headers.iter_mut().find(|(n, _)| n == &name) {
+                values.push(value);
             } else {
-                error!("HTTP request failed: {}", status)
+                headers.push((name, vec![value]));
             }
         }
+        let mut c = surf::Request::new(self.method, self.url.clone()).body_bytes(payload);
+        for (name, values) in headers {
+            c = c.set_header(name, values.as_slice());
+        }
+        c
+    }
+}
+
+impl Rest {
+    async fn flush(
+        request: Request,
+        config: Config,
+        payload: Vec<u8>,
+        id: u64,
+        outputs: Outputs,
+    ) -> Result<u64> {
+        let start = Instant::now();
+        let mut attempt = 0;
+        let mut reply = loop {
+            let reply = request.send(&payload).await;
+            let failure = match &reply {
+                Ok(reply) => {
+                    let status = u16::from(reply.status());
+                    if config.retry_on.contains(&status) {
+                        Some(status.to_string())
+                    } else {
+                        None
+                    }
+                }
+                // Transport and connection errors are always retried
+                Err(e) => Some(e.to_string()),
+            };
+            match failure {
+                Some(failure) if attempt < config.max_retries => {
+                    let backoff = config.backoff_ms << attempt.min(16);
+                    attempt += 1;
+                    warn!(
+                        "HTTP request to {} failed with {}, retrying in {}ms",
+                        request.url, failure, backoff
+                    );
+                    task::sleep(Duration::from_millis(backoff)).await;
+                }
+                _ => break reply?,
+            }
+        };
+        let status = reply.status();
+        // Requests that are still answered with an error once we are out of
+        // retries fail the event
+        if !status.is_success() || config.retry_on.contains(&u16::from(status)) {
+            let body = reply.body_string().await.unwrap_or_default();
+            return Err(format!(
+                "HTTP request to {} failed with {}: {}",
+                request.url, status, body
+            )
+            .into());
+        }
+        if !outputs.is_connected(OUT) {
+            return Ok(duration_to_millis(start.elapsed()));
+        }
+
+        let mut headers = Object::new();
+        for (name, values) in reply.iter() {
+            let values: Vec<Value> = values
+                .iter()
+                .map(|v| Value::from(v.as_str().to_string()))
+                .collect();
+            headers.insert(name.as_str().to_string().into(), Value::from(values));
+        }
+        let mut response = Object::with_capacity(2);
+        response.insert("status".into(), Value::from(u64::from(u16::from(status))));
+        response.insert("headers".into(), Value::from(headers));
+        let mut meta = Object::with_capacity(1);
+        meta.insert("response".into(), Value::from(response));
+        let meta = Value::from(meta);
 
-        let d = duration_to_millis(start.elapsed());
-        Ok(d)
+        let body = reply.body_bytes().await?;
+        let ingest_ns = nanotime();
+        let data = if body.is_empty() {
+            Some((Value::null(), meta).into())
+        } else {
+            let mut codec = codec::from_config(&config.response_codec)?;
+            codec.decode(body, ingest_ns)?.map(|mut data| {
+                data.rent_mut(|data| *data.meta_mut() = meta);
+                data
+            })
+        };
+        if let Some(data) = data {
+            outputs.send(
+                OUT,
+                Event {
+                    is_batch: false,
+                    id,
+                    data,
+                    ingest_ns,
+                    origin_uri: None,
+                    kind: None,
+                },
+            );
+        }
+        Ok(duration_to_millis(start.elapsed()))
     }
 
-    fn enqueue_send_future(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn pipelines(&self) -> Vec<(TremorURL, pipeline::Addr)> {
+        self.pipelines
+            .iter()
+            .map(|(i, p)| (i.clone(), p.clone()))
+            .collect()
+    }
+
+    fn enqueue_send_future(
+        &mut self,
+        payload: Vec<u8>,
+        id: u64,
+        origin_uri: Option<EventOriginUri>,
+        meta: &Value,
+    ) -> Result<()> {
         self.client_idx = (self.client_idx + 1) % self.config.endpoints.len();
-        let destination = self.config.endpoints[self.client_idx].clone();
+        let request = Request::new(&self.config.endpoints[self.client_idx], &self.config, meta)?;
         let (tx, rx) = bounded(1);
         let config = self.config.clone();
-        let pipelines: Vec<(TremorURL, pipeline::Addr)> = self
-            .pipelines
-            .iter()
-            .map(|(i, p)| (i.clone(), p.clone()))
-            .collect();
+        let outputs = self.outputs.clone();
+        let pipelines = self.pipelines();
         task::spawn(async move {
-            let r = Self::flush(&destination, config, payload).await;
+            let r = Self::flush(request, config, payload, id, outputs).await;
             let mut m = Object::new();
             if let Ok(t) = r {
                 m.insert("time".into(), t.into());
@@ -123,15 +351,13 @@ impl Rest {
                 origin_uri: None,
                 kind: None,
             };
-
-            for (pid, p) in pipelines {
-                if p.addr
-                    .send(pipeline::Msg::Insight(insight.clone()))
-                    .is_err()
-                {
-                    error!("Failed to send contraflow to pipeline {}", pid)
-                };
-            }
+            send_insight(&pipelines, &insight);
+            let cb = if r.is_ok() {
+                CBAction::Ack
+            } else {
+                CBAction::Fail
+            };
+            send_insight(&pipelines, &Event::cb(cb, nanotime(), id, origin_uri));
 
             if let Err(e) = tx.send(r) {
                 error!("Failed to send reply: {}", e)
@@ -140,7 +366,13 @@ impl Rest {
         self.queue.enqueue(rx)?;
         Ok(())
     }
-    fn maybe_enque(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn maybe_enque(
+        &mut self,
+        payload: Vec<u8>,
+        id: u64,
+        origin_uri: Option<EventOriginUri>,
+        meta: &Value,
+    ) -> Result<()> {
         match self.queue.dequeue() {
             Err(SinkDequeueError::NotReady) if !self.queue.has_capacity() => {
                 let mut m = Object::new();
@@ -157,27 +389,14 @@ impl Rest {
                     origin_uri: None,
                     kind: None,
                 };
-
-                let pipelines: Vec<(TremorURL, pipeline::Addr)> = self
-                    .pipelines
-                    .iter()
-                    .map(|(i, p)| (i.clone(), p.clone()))
-                    .collect();
-                for (pid, p) in pipelines {
-                    if p.addr
-                        .send(pipeline::Msg::Insight(insight.clone()))
-                        .is_err()
-                    {
-                        error!("Failed to send contraflow to pipeline {}", pid)
-                    };
-                }
+                send_insight(&self.pipelines(), &insight);
                 error!("Dropped data due to overload");
                 Err("Dropped data due to overload".into())
             }
             _ => {
-                if self.enqueue_send_future(payload).is_err() {
+                if let Err(e) = self.enqueue_send_future(payload, id, origin_uri, meta) {
                     // TODO: handle reply to the pipeline
-                    error!("Failed to enqueue send request");
+                    error!("Failed to enqueue send request: {}", e);
                     Err("Failed to enqueue send request".into())
                 } else {
                     Ok(())
@@ -185,22 +404,47 @@ impl Rest {
             }
         }
     }
-}
 
-impl Offramp for Rest {
-    fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+    fn send_event(&mut self, codec: &dyn Codec, event: Event) -> Result<()> {
         let mut payload = Vec::with_capacity(4096);
-        for value in event.value_iter() {
+        // The request settings of a batch are taken from its first event
+        let mut request_meta = None;
+        for (value, meta) in event.value_meta_iter() {
             let mut raw = codec.encode(value)?;
             payload.append(&mut raw);
             payload.push(b'\n');
+            if request_meta.is_none() {
+                request_meta = Some(meta.clone_static());
+            }
         }
-        self.maybe_enque(payload)
+        let meta = request_meta.unwrap_or_else(Value::null);
+        self.maybe_enque(payload, event.id, event.origin_uri, &meta)
+    }
+}
+
+impl Offramp for Rest {
+    fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+        let id = event.id;
+        let origin_uri = event.origin_uri.clone();
+        let r = self.send_event(codec.as_ref(), event);
+        // Sent requests are acknowledged once they are answered
+        if r.is_err() {
+            let insight = Event::cb(CBAction::Fail, nanotime(), id, origin_uri);
+            send_insight(&self.pipelines(), &insight);
+        }
+        r
+    }
+    fn auto_ack(&self) -> bool {
+        false
     }
     fn default_codec(&self) -> &str {
         "json"
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
@@ -211,4 +455,175 @@ impl Offramp for Rest {
         self.pipelines.remove(&id);
         self.pipelines.is_empty()
     }
+    fn add_output(&mut self, port: Cow<'static, str>, id: TremorURL, addr: pipeline::Addr) {
+        self.outputs.add(port, id, addr);
+    }
+    fn remove_output(&mut self, port: &str, id: &TremorURL) {
+        self.outputs.remove(port, id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn config() -> Config {
+        let mut headers = HashMap::new();
+        headers.insert("x-snot".to_string(), "badger".to_string());
+        headers.insert("content-type".to_string(), "application/json".to_string());
+        Config {
+            endpoints: vec!["http://localhost:8080/base".to_string()],
+            concurrency: 4,
+            put: false,
+            headers,
+            retry_on: vec![],
+            max_retries: d_max_retries(),
+            backoff_ms: d_backoff_ms(),
+            response_codec: d_response_codec(),
+        }
+    }
+
+    #[test]
+    fn request_from_meta() -> Result<()> {
+        let config = config();
+        let meta: Value = json!({}).into();
+        let r = Request::new("http://localhost:8080/base", &config, &meta)?;
+        assert_eq!(Method::Post, r.method);
+        assert_eq!("http://localhost:8080/base", r.url.as_str());
+        assert_eq!(2, r.headers.len());
+
+        let meta: Value = json!({"request": {
+            "method": "patch",
+            "path": "/hooks/1",
+            "query": {"a": "b c", "n": 1},
+            "headers": {"Content-Type": "text/plain", "x-multi": ["1", "2"]}
+        }})
+        .into();
+        let r = Request::new("http://localhost:8080/base", &config, &meta)?;
+        assert_eq!(Method::Patch, r.method);
+        assert_eq!("http://localhost:8080/hooks/1?a=b+c&n=1", r.url.as_str());
+        assert_eq!(
+            vec![
+                ("x-snot".to_string(), "badger".to_string()),
+                ("Content-Type".to_string(), "text/plain".to_string()),
+                ("x-multi".to_string(), "1".to_string()),
+                ("x-multi".to_string(), "2".to_string()),
+            ],
+            r.headers
+        );
+
+        let meta: Value = json!({"request": {"query": "q=1"}}).into();
+        let r = Request::new("http://localhost:8080/base", &config, &meta)?;
+        assert_eq!("http://localhost:8080/base?q=1", r.url.as_str());
+
+        let meta: Value = json!({"request": {"method": "sn ot"}}).into();
+        assert!(Request::new("http://localhost:8080/base", &config, &meta).is_err());
+        let meta: Value = json!({}).into();
+        assert!(Request::new("not a url", &config, &meta).is_err());
+        Ok(())
+    }
+
+    /// Answers every request with `response` until the test ends
+    async fn serve(response: &'static [u8]) -> Result<String> {
+        use async_std::net::TcpListener;
+        use async_std::prelude::*;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}/", listener.local_addr()?);
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut head = Vec::new();
+                let mut buf = [0_u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                stream.write_all(response).await.ok();
+            }
+        });
+        Ok(endpoint)
+    }
+
+    #[test]
+    fn error_status_fails() -> Result<()> {
+        task::block_on(async {
+            let meta: Value = json!({}).into();
+            let mut config = config();
+            config.retry_on = vec![503];
+            config.max_retries = 1;
+            config.backoff_ms = 1;
+
+            let endpoint = serve(
+                b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            )
+            .await?;
+            let request = Request::new(&endpoint, &config, &meta)?;
+            let r = Rest::flush(
+                request,
+                config.clone(),
+                b"{}".to_vec(),
+                1,
+                Outputs::default(),
+            );
+            assert!(r.await.is_err());
+
+            let endpoint =
+                serve(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .await?;
+            let request = Request::new(&endpoint, &config, &meta)?;
+            let r = Rest::flush(
+                request,
+                config.clone(),
+                b"{}".to_vec(),
+                2,
+                Outputs::default(),
+            );
+            assert!(r.await.is_err());
+
+            let endpoint =
+                serve(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").await?;
+            let request = Request::new(&endpoint, &config, &meta)?;
+            let r = Rest::flush(request, config, b"{}".to_vec(), 3, Outputs::default());
+            assert!(r.await.is_ok());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn multi_value_headers_on_the_wire() -> Result<()> {
+        use async_std::net::TcpListener;
+        use async_std::prelude::*;
+
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let endpoint = format!("http://{}/", listener.local_addr()?);
+            let server = task::spawn(async move {
+                let (mut stream, _) = listener.accept().await?;
+                let mut head = Vec::new();
+                let mut buf = [0_u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buf[..n]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await?;
+                Ok::<_, std::io::Error>(String::from_utf8_lossy(&head).to_lowercase())
+            });
+            let meta: Value = json!({"request": {"headers": {"x-multi": ["1", "2"]}}}).into();
+            let request = Request::new(&endpoint, &config(), &meta)?;
+            request.send(b"{}").await?;
+            let head = server.await?;
+            assert!(head.contains("x-multi: 1\r\n"));
+            assert!(head.contains("x-multi: 2\r\n"));
+            assert!(head.contains("x-snot: badger\r\n"));
+            Ok(())
+        })
+    }
 }
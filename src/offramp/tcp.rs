@@ -14,25 +14,72 @@
 
 //! # TCP Offramp
 //!
-//! Sends each message as a tcp stream
+//! Sends each message as a tcp stream, optionally over TLS
+//!
+//! The connection is made in the background and re-established with an
+//! exponential backoff when it is lost. Until then messages are kept in a
+//! bounded buffer. Connection changes are sent to the pipelines as insights
+//! with `connected` set to `true` or `false` in their metadata.
+//!
+//! Events are acknowledged once they are written to the connection, and
+//! failed when they are dropped from a full buffer or the offramp stops
+//! before sending them.
 //!
 //! ## Configuration
 //!
 //! See [Config](struct.Config.html) for details.
 
 use crate::offramp::prelude::*;
+use crate::ramp::tls;
 use halfbrown::HashMap;
-use std::io::Write;
-use std::net::TcpStream;
+use rustls::{ClientSession, StreamOwned};
+use simd_json::borrowed::Object;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tremor_pipeline::EventOriginUri;
+use tremor_script::prelude::*;
 
-/// An offramp streams over TCP/IP
-pub struct Tcp {
-    stream: TcpStream,
-    pipelines: HashMap<TremorURL, pipeline::Addr>,
-    postprocessors: Postprocessors,
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientSession, TcpStream>>),
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(s) => s.write(buf),
+            Self::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(s) => s.flush(),
+            Self::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// What to drop when the buffer is full
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DropPolicy {
+    Oldest,
+    Newest,
 }
 
-#[derive(Deserialize, Debug)]
+impl Default for DropPolicy {
+    fn default() -> Self {
+        Self::Oldest
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub host: String,
     pub port: u16,
@@ -40,22 +87,327 @@ pub struct Config {
     pub ttl: u32,
     #[serde(default = "dflt::d_true")]
     pub is_no_delay: bool,
+    /// Connect using TLS
+    #[serde(default)]
+    pub tls: Option<tls::ClientConfig>,
+    /// Delay before the first reconnect, doubled for every failed attempt,
+    /// defaults to 100ms
+    #[serde(default = "dflt_backoff_ms")]
+    pub backoff_ms: u64,
+    /// Longest delay between reconnects, defaults to 10s
+    #[serde(default = "dflt_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Number of events kept while disconnected, defaults to 1000
+    #[serde(default = "dflt_buffer")]
+    pub buffer: usize,
+    /// What to drop when the buffer is full, `oldest` or `newest`,
+    /// defaults to `oldest`
+    #[serde(default)]
+    pub drop: DropPolicy,
 }
 
 impl ConfigImpl for Config {}
 
+fn dflt_backoff_ms() -> u64 {
+    100
+}
+
+fn dflt_max_backoff_ms() -> u64 {
+    10_000
+}
+
+fn dflt_buffer() -> usize {
+    1000
+}
+
+/// The packets of an event waiting to be sent
+struct Message {
+    id: u64,
+    origin_uri: Option<EventOriginUri>,
+    /// Packets not written to the connection yet
+    packets: VecDeque<Vec<u8>>,
+}
+
+/// Messages waiting to be sent
+struct Buffer {
+    queue: VecDeque<Message>,
+    capacity: usize,
+    drop: DropPolicy,
+    stop: bool,
+}
+
+impl Buffer {
+    /// Queues a message, returns the message dropped if the buffer is full
+    fn push(&mut self, message: Message) -> Option<Message> {
+        if self.queue.len() < self.capacity {
+            self.queue.push_back(message);
+            None
+        } else if self.drop == DropPolicy::Oldest {
+            let dropped = self.queue.pop_front();
+            self.queue.push_back(message);
+            dropped
+        } else {
+            Some(message)
+        }
+    }
+
+    /// Puts a message that failed to send back to the front, returns the
+    /// message dropped if the buffer is full
+    fn requeue(&mut self, message: Message) -> Option<Message> {
+        if self.queue.len() < self.capacity {
+            self.queue.push_front(message);
+            None
+        } else if self.drop == DropPolicy::Newest {
+            let dropped = self.queue.pop_back();
+            self.queue.push_front(message);
+            dropped
+        } else {
+            Some(message)
+        }
+    }
+}
+
+/// State shared with the thread writing to the connection
+struct Shared {
+    buffer: Mutex<Buffer>,
+    ready: Condvar,
+    pipelines: Mutex<HashMap<TremorURL, pipeline::Addr>>,
+}
+
+impl Shared {
+    fn push(&self, message: Message) -> Result<()> {
+        let dropped = self.buffer.lock()?.push(message);
+        self.ready.notify_one();
+        if let Some(dropped) = dropped {
+            self.dropped(&dropped);
+        }
+        Ok(())
+    }
+
+    /// Waits for the next message, `None` once the offramp is stopped
+    fn next(&self) -> Option<Message> {
+        let mut buffer = self.buffer.lock().ok()?;
+        loop {
+            if buffer.stop {
+                return None;
+            } else if let Some(packet) = buffer.queue.pop_front() {
+                return Some(packet);
+            }
+            buffer = self.ready.wait(buffer).ok()?;
+        }
+    }
+
+    fn requeue(&self, message: Message) {
+        let dropped = if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.requeue(message)
+        } else {
+            Some(message)
+        };
+        if let Some(dropped) = dropped {
+            self.dropped(&dropped);
+        }
+    }
+
+    fn dropped(&self, message: &Message) {
+        warn!("[TCP Offramp] Buffer full, dropping message");
+        self.cb(CBAction::Fail, message);
+    }
+
+    /// Sleeps for the given duration, returns `false` if the offramp got
+    /// stopped in the meantime
+    fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut buffer = if let Ok(buffer) = self.buffer.lock() {
+            buffer
+        } else {
+            return false;
+        };
+        while !buffer.stop {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            buffer = match self.ready.wait_timeout(buffer, deadline - now) {
+                Ok((buffer, _)) => buffer,
+                Err(_) => return false,
+            };
+        }
+        false
+    }
+
+    /// Stops the offramp, failing the messages not sent yet
+    fn stop(&self) {
+        let unsent: Vec<_> = if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.stop = true;
+            buffer.queue.drain(..).collect()
+        } else {
+            vec![]
+        };
+        self.ready.notify_all();
+        for message in &unsent {
+            self.cb(CBAction::Fail, message);
+        }
+    }
+
+    /// Acknowledges or fails the event a message was sent for
+    fn cb(&self, cb: CBAction, message: &Message) {
+        let insight = Event::cb(cb, nanotime(), message.id, message.origin_uri.clone());
+        self.send_insight(&insight);
+    }
+
+    fn insight(&self, connected: bool, error: Option<String>) {
+        let mut m = Object::new();
+        m.insert("connected".into(), connected.into());
+        if let Some(error) = error {
+            m.insert("error".into(), error.into());
+        }
+        let insight = Event {
+            is_batch: false,
+            id: 0,
+            data: (Value::null(), m).into(),
+            ingest_ns: nanotime(),
+            origin_uri: None,
+            kind: None,
+        };
+        self.send_insight(&insight);
+    }
+
+    fn send_insight(&self, insight: &Event) {
+        if let Ok(pipelines) = self.pipelines.lock() {
+            for (pid, p) in pipelines.iter() {
+                if p.addr
+                    .send(pipeline::Msg::Insight(insight.clone()))
+                    .is_err()
+                {
+                    error!("Failed to send contraflow to pipeline {}", pid)
+                };
+            }
+        }
+    }
+}
+
+fn connect(config: &Config, tls_config: &Option<Arc<rustls::ClientConfig>>) -> Result<Stream> {
+    let mut last_error = None;
+    for addr in (config.host.as_str(), config.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_ttl(config.ttl)?;
+                stream.set_nodelay(config.is_no_delay)?;
+                return match (&config.tls, tls_config) {
+                    (Some(tls), Some(tls_config)) => {
+                        let session = tls.session(tls_config, &config.host)?;
+                        Ok(Stream::Tls(Box::new(StreamOwned::new(session, stream))))
+                    }
+                    _ => Ok(Stream::Plain(stream)),
+                };
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.map_or_else(
+        || format!("No address found for {}", config.host).into(),
+        Error::from,
+    ))
+}
+
+fn writer_loop(shared: &Shared, config: &Config, tls_config: &Option<Arc<rustls::ClientConfig>>) {
+    let initial_backoff = Duration::from_millis(config.backoff_ms);
+    let max_backoff = Duration::from_millis(config.max_backoff_ms);
+    let mut backoff = initial_backoff;
+    loop {
+        let mut stream = match connect(config, tls_config) {
+            Ok(stream) => {
+                info!("[TCP Offramp] Connected to {}:{}", config.host, config.port);
+                backoff = initial_backoff;
+                shared.insight(true, None);
+                stream
+            }
+            Err(e) => {
+                warn!(
+                    "[TCP Offramp] Failed to connect to {}:{}, retrying in {:?}: {}",
+                    config.host, config.port, backoff, e
+                );
+                if !shared.sleep(backoff) {
+                    return;
+                }
+                backoff = (backoff * 2).min(max_backoff);
+                continue;
+            }
+        };
+        loop {
+            let mut message = if let Some(message) = shared.next() {
+                message
+            } else {
+                return;
+            };
+            let mut written = Ok(());
+            while let Some(packet) = message.packets.front() {
+                written = stream.write_all(packet).and_then(|_| stream.flush());
+                if written.is_err() {
+                    break;
+                }
+                message.packets.pop_front();
+            }
+            if let Err(e) = written {
+                error!(
+                    "[TCP Offramp] Lost connection to {}:{}: {}",
+                    config.host, config.port, e
+                );
+                // Only the packets not written yet are sent again
+                shared.requeue(message);
+                shared.insight(false, Some(e.to_string()));
+                break;
+            }
+            shared.cb(CBAction::Ack, &message);
+        }
+    }
+}
+
+/// An offramp streams over TCP/IP
+pub struct Tcp {
+    shared: Arc<Shared>,
+    postprocessors: Postprocessors,
+}
+
+impl Tcp {
+    fn with_config(config: Config) -> Result<Self> {
+        let tls_config = config
+            .tls
+            .as_ref()
+            .map(tls::ClientConfig::load)
+            .transpose()?;
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer {
+                queue: VecDeque::new(),
+                capacity: config.buffer,
+                drop: config.drop,
+                stop: false,
+            }),
+            ready: Condvar::new(),
+            pipelines: Mutex::new(HashMap::new()),
+        });
+        let writer = shared.clone();
+        std::thread::Builder::new()
+            .name(format!("offramp-tcp-{}:{}", config.host, config.port))
+            .spawn(move || writer_loop(&writer, &config, &tls_config))?;
+        Ok(Self {
+            shared,
+            postprocessors: vec![],
+        })
+    }
+}
+
+impl Drop for Tcp {
+    fn drop(&mut self) {
+        self.shared.stop();
+    }
+}
+
 impl offramp::Impl for Tcp {
     fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
         if let Some(config) = config {
             let config: Config = Config::new(config)?;
-            let stream = TcpStream::connect((config.host.as_str(), config.port))?;
-            stream.set_ttl(config.ttl)?;
-            stream.set_nodelay(config.is_no_delay)?;
-            Ok(Box::new(Self {
-                stream,
-                pipelines: HashMap::new(),
-                postprocessors: vec![],
-            }))
+            Ok(Box::new(Self::with_config(config)?))
         } else {
             Err("TCP offramp requires a config".into())
         }
@@ -64,27 +416,146 @@ impl offramp::Impl for Tcp {
 
 impl Offramp for Tcp {
     fn on_event(&mut self, codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+        let mut message = Message {
+            id: event.id,
+            origin_uri: event.origin_uri.clone(),
+            packets: VecDeque::new(),
+        };
         for value in event.value_iter() {
-            let raw = codec.encode(value)?;
-            let packets = postprocess(&mut self.postprocessors, event.ingest_ns, raw.to_vec())?;
-            for packet in packets {
-                self.stream.write_all(&packet)?;
+            let packets = codec.encode(value).and_then(|raw| {
+                postprocess(&mut self.postprocessors, event.ingest_ns, raw.to_vec())
+            });
+            match packets {
+                Ok(packets) => message.packets.extend(packets),
+                Err(e) => {
+                    self.shared.cb(CBAction::Fail, &message);
+                    return Err(e);
+                }
             }
         }
-        Ok(())
+        self.shared.push(message)
     }
     fn add_pipeline(&mut self, id: TremorURL, addr: pipeline::Addr) {
-        self.pipelines.insert(id, addr);
+        if let Ok(mut pipelines) = self.shared.pipelines.lock() {
+            pipelines.insert(id, addr);
+        }
     }
     fn remove_pipeline(&mut self, id: TremorURL) -> bool {
-        self.pipelines.remove(&id);
-        self.pipelines.is_empty()
+        if let Ok(mut pipelines) = self.shared.pipelines.lock() {
+            pipelines.remove(&id);
+            pipelines.is_empty()
+        } else {
+            true
+        }
     }
     fn default_codec(&self) -> &str {
         "json"
     }
-    fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
+    fn auto_ack(&self) -> bool {
+        false
+    }
+    fn start(
+        &mut self,
+        _codec: &Box<dyn Codec>,
+        postprocessors: &[PostprocessorConfig],
+    ) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossbeam_channel::{bounded, Receiver};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn buffer(drop: DropPolicy) -> Buffer {
+        Buffer {
+            queue: VecDeque::new(),
+            capacity: 2,
+            drop,
+            stop: false,
+        }
+    }
+
+    fn message(id: u64) -> Message {
+        Message {
+            id,
+            origin_uri: None,
+            packets: vec![id.to_string().into_bytes()].into(),
+        }
+    }
+
+    fn ids(b: &Buffer) -> Vec<u64> {
+        b.queue.iter().map(|m| m.id).collect()
+    }
+
+    fn dropped(m: Option<Message>) -> Option<u64> {
+        m.map(|m| m.id)
+    }
+
+    fn cbs(rx: &Receiver<pipeline::Msg>, n: usize) -> Vec<(CBAction, u64)> {
+        (0..n)
+            .filter_map(|_| match rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(pipeline::Msg::Insight(insight)) => Some((insight.cb_action()?, insight.id)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drop_policy() {
+        let mut b = buffer(DropPolicy::Oldest);
+        assert_eq!(None, dropped(b.push(message(1))));
+        assert_eq!(None, dropped(b.push(message(2))));
+        assert_eq!(Some(1), dropped(b.push(message(3))));
+        assert_eq!(vec![2, 3], ids(&b));
+        assert_eq!(Some(1), dropped(b.requeue(message(1))));
+        assert_eq!(vec![2, 3], ids(&b));
+
+        let mut b = buffer(DropPolicy::Newest);
+        assert_eq!(None, dropped(b.push(message(1))));
+        assert_eq!(None, dropped(b.push(message(2))));
+        assert_eq!(Some(3), dropped(b.push(message(3))));
+        assert_eq!(vec![1, 2], ids(&b));
+        assert_eq!(Some(2), dropped(b.requeue(message(0))));
+        assert_eq!(vec![0, 1], ids(&b));
+    }
+
+    #[test]
+    fn connects_late() -> Result<()> {
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let mut tcp = Tcp::with_config(Config {
+            host: "127.0.0.1".to_string(),
+            port,
+            ttl: 64,
+            is_no_delay: true,
+            tls: None,
+            backoff_ms: 10,
+            max_backoff_ms: 50,
+            buffer: 2,
+            drop: DropPolicy::Oldest,
+        })?;
+        let (tx, rx) = bounded(64);
+        let id = TremorURL::parse("/pipeline/test/1/in")?;
+        tcp.add_pipeline(id.clone(), pipeline::Addr { addr: tx, id });
+        // nobody is listening yet, so this is buffered
+        tcp.shared.push(message(1))?;
+        tcp.shared.push(message(2))?;
+        tcp.shared.push(message(3))?;
+        // the oldest message is dropped from the full buffer
+        assert_eq!(vec![(CBAction::Fail, 1)], cbs(&rx, 1));
+
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (mut conn, _) = listener.accept()?;
+        let mut data = [0_u8; 2];
+        conn.read_exact(&mut data)?;
+        assert_eq!(b"23", &data);
+        // messages are acknowledged once they are written, after the
+        // insight that the offramp connected
+        assert_eq!(vec![(CBAction::Ack, 2), (CBAction::Ack, 3)], cbs(&rx, 3));
+        Ok(())
+    }
+}
@@ -12,13 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::codec::CodecConfig;
 use crate::errors::Result;
 use crate::metrics::RampReporter;
 use crate::pipeline;
+use crate::preprocessor::PreprocessorConfig;
 use crate::repository::ServantId;
 use crate::url::TremorURL;
 use serde_yaml::Value;
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tremor_pipeline::{CBAction, Event};
 mod blaster;
 mod crononome;
 mod file;
@@ -33,7 +38,7 @@ mod udp;
 use async_std::sync::{self, channel};
 use async_std::task::{self, JoinHandle};
 use crossbeam_channel::Sender as CbSender;
-// mod rest;
+mod rest;
 mod ws;
 
 pub(crate) type Sender = sync::Sender<ManagerMsg>;
@@ -45,16 +50,75 @@ pub(crate) trait Impl {
 #[derive(Clone, Debug)]
 pub enum Msg {
     Connect(Vec<(TremorURL, pipeline::Addr)>),
-    Disconnect { id: TremorURL, tx: CbSender<bool> },
+    Disconnect {
+        id: TremorURL,
+        tx: CbSender<bool>,
+    },
+    /// An acknowledgement insight for an event sent by this onramp
+    Cb(CBAction, Event),
 }
 
-pub type Addr = sync::Sender<Msg>;
+/// Most acknowledgements that may wait for room in the channel of an onramp
+const MAX_PENDING_CBS: usize = 1024;
+
+/// Address of an onramp
+#[derive(Clone, Debug)]
+pub struct Addr {
+    tx: sync::Sender<Msg>,
+    /// If the onramp handles acknowledgements, the others aren't sent any
+    pub acks: bool,
+    /// Acknowledgements waiting for room in the channel
+    pending: Arc<AtomicUsize>,
+}
+
+impl From<sync::Sender<Msg>> for Addr {
+    fn from(tx: sync::Sender<Msg>) -> Self {
+        Self {
+            tx,
+            acks: false,
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Addr {
+    /// Address of an onramp that handles acknowledgements
+    pub(crate) fn acking(tx: sync::Sender<Msg>) -> Self {
+        Self {
+            acks: true,
+            ..Self::from(tx)
+        }
+    }
+
+    pub(crate) async fn send(&self, msg: Msg) {
+        self.tx.send(msg).await
+    }
+
+    /// Sends an acknowledgement without blocking, if the onramp is busy
+    /// it waits for room in a bounded number of tasks and is dropped
+    /// beyond that
+    pub(crate) fn send_cb(&self, cb: CBAction, insight: Event) {
+        if let Err(sync::TrySendError::Full(msg)) = self.tx.try_send(Msg::Cb(cb, insight)) {
+            if self.pending.fetch_add(1, Ordering::AcqRel) < MAX_PENDING_CBS {
+                let tx = self.tx.clone();
+                let pending = self.pending.clone();
+                task::spawn(async move {
+                    tx.send(msg).await;
+                    pending.fetch_sub(1, Ordering::AcqRel);
+                });
+            } else {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                warn!("Dropping an acknowledgement, the onramp is overloaded");
+            }
+        }
+    }
+}
 
 pub(crate) trait Onramp: Send {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<Addr>;
     fn default_codec(&self) -> &str;
@@ -74,7 +138,7 @@ pub(crate) fn lookup(name: &str, config: &Option<Value>) -> Result<Box<dyn Onram
         "crononome" => crononome::Crononome::from_config(config),
         "udp" => udp::Udp::from_config(config),
         "tcp" => tcp::Tcp::from_config(config),
-        // "rest" => rest::Rest::from_config(config),
+        "rest" => rest::Rest::from_config(config),
         "ws" => ws::Ws::from_config(config),
         _ => Err(format!("Onramp {} not known", name).into()),
     }
@@ -83,8 +147,8 @@ pub(crate) fn lookup(name: &str, config: &Option<Value>) -> Result<Box<dyn Onram
 pub(crate) struct Create {
     pub id: ServantId,
     pub stream: Box<dyn Onramp>,
-    pub codec: String,
-    pub preprocessors: Vec<String>,
+    pub codec: CodecConfig,
+    pub preprocessors: Vec<PreprocessorConfig>,
     pub metrics_reporter: RampReporter,
 }
 
@@ -256,7 +320,7 @@ mod test {
     macro_rules! rampercize {
         ($onramp_config:expr, $offramp_config:expr, $test:tt) => {
             let storage_directory = Some("./storage".to_string());
-            let (world, _handle) = b!(system::World::start(50, storage_directory))?;
+            let (world, _handle) = b!(system::World::start(50, storage_directory, None))?;
             let config = serde_yaml::to_value($onramp_config).expect("json to yaml not ok");
 
             let onramp: crate::config::OnRamp = serde_yaml::from_value(config)?;
@@ -452,4 +516,20 @@ links:
         );
         Ok(())
     }
+
+    #[test]
+    fn acks_wait_for_room() {
+        let (tx, rx) = channel(1);
+        let addr = Addr::acking(tx);
+        addr.send_cb(CBAction::Ack, Event::cb(CBAction::Ack, 0, 1, None));
+        // the channel is full, so this one waits
+        addr.send_cb(CBAction::Fail, Event::cb(CBAction::Fail, 0, 2, None));
+        for id in 1..=2 {
+            if let Ok(Msg::Cb(_, insight)) = b!(rx.recv()) {
+                assert_eq!(id, insight.id);
+            } else {
+                panic!("no acknowledgement for {}", id);
+            }
+        }
+    }
 }
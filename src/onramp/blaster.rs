@@ -144,21 +144,21 @@ fn onramp_loop(
 impl Onramp for Blaster {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let (tx, rx) = channel(1);
         let data2 = self.data.clone();
         let config2 = self.config.clone();
-        let codec = codec::lookup(&codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
         thread::Builder::new()
             .name(format!("onramp-blaster-{}", "???"))
             .spawn(move || {
                 onramp_loop(&rx, data2, &config2, preprocessors, codec, metrics_reporter)
             })?;
-        Ok(tx)
+        Ok(tx.into())
     }
 
     fn default_codec(&self) -> &str {
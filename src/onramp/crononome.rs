@@ -306,8 +306,8 @@ fn onramp_loop(
 impl Onramp for Crononome {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let mut config = self.config.clone();
@@ -322,12 +322,12 @@ impl Onramp for Crononome {
             }
         }
         let (tx, rx) = channel(1);
-        let codec = codec::lookup(codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
         thread::Builder::new()
             .name(format!("onramp-crononome-{}", "???"))
             .spawn(move || onramp_loop(&rx, &config, preprocessors, codec, metrics_reporter))?;
-        Ok(tx)
+        Ok(tx.into())
     }
 
     fn default_codec(&self) -> &str {
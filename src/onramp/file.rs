@@ -15,27 +15,47 @@
 use crate::dflt;
 use crate::onramp::prelude::*;
 use serde_yaml::Value;
-use std::fs::File as FSFile;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs::{self, File as FSFile, Metadata};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use xz2::read::XzDecoder;
 
+/// How long a moved file keeps being read for lines the writer still
+/// appends to it before it reopens the file under its path
+const DRAIN_MOVED: Duration = Duration::from_secs(5);
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     /// source file to read data from, it will be iterated over repeatedly,
-    /// can be xz compressed
+    /// can be xz compressed. Glob patterns read every matching file.
     pub source: String,
     #[serde(default = "dflt::d_false")]
     pub close_on_done: bool,
     #[serde(default = "dflt::d")]
     pub sleep_on_done: u64,
+    /// keep reading data appended to the source files and follow them when
+    /// they are rotated or truncated, compressed files can't be followed
+    #[serde(default = "dflt::d_false")]
+    pub follow: bool,
+    /// milliseconds to wait for new data when following, defaults to 500
+    #[serde(default = "dflt_poll_interval")]
+    pub poll_interval_ms: u64,
+    /// file to store read offsets in when following, so that a restart
+    /// resumes where it left off
+    #[serde(default = "dflt::d")]
+    pub checkpoint: Option<String>,
 }
 
 impl ConfigImpl for Config {}
 
+fn dflt_poll_interval() -> u64 {
+    500
+}
+
 pub struct File {
     pub config: Config,
 }
@@ -51,53 +71,202 @@ impl onramp::Impl for File {
     }
 }
 
-fn onramp_loop(
+/// Read offset of a followed file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+struct Checkpoint {
+    /// identity of the file the offset belongs to
+    file_id: u64,
+    offset: u64,
+}
+
+type Checkpoints = BTreeMap<String, Checkpoint>;
+
+fn load_checkpoints(path: &Path) -> Result<Checkpoints> {
+    if path.exists() {
+        let mut data = fs::read(path)?;
+        Ok(simd_json::from_slice(&mut data)?)
+    } else {
+        Ok(Checkpoints::new())
+    }
+}
+
+fn save_checkpoints(path: &Path, checkpoints: &Checkpoints) -> Result<()> {
+    // write and rename so a crash never leaves a partial checkpoint behind
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, simd_json::to_vec(checkpoints)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Identifies a file independently of its path so renames can be detected
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.ino()
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> u64 {
+    0
+}
+
+fn sources(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = glob::glob(pattern)?.filter_map(Result::ok).collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn origin_uri(path: &Path) -> EventOriginUri {
+    tremor_pipeline::EventOriginUri {
+        scheme: "tremor-file".to_string(),
+        host: hostname(),
+        port: None,
+        path: vec![path.to_string_lossy().to_string()],
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Rotation {
+    None,
+    Truncated,
+    Moved,
+}
+
+/// A file that is being followed
+struct Tail {
+    path: PathBuf,
+    origin_uri: EventOriginUri,
+    reader: BufReader<FSFile>,
+    file_id: u64,
+    /// offset right after the last complete line
+    offset: u64,
+    /// data read past `offset` that isn't terminated by a newline yet
+    partial: Vec<u8>,
+}
+
+impl Tail {
+    fn open(path: PathBuf, checkpoint: Option<&Checkpoint>) -> Result<Self> {
+        let file = FSFile::open(&path)?;
+        let meta = file.metadata()?;
+        let file_id = file_id(&meta);
+        let offset = match checkpoint {
+            Some(c) if c.file_id == file_id && c.offset <= meta.len() => c.offset,
+            _ => 0,
+        };
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            origin_uri: origin_uri(&path),
+            path,
+            reader,
+            file_id,
+            offset,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Reads all complete lines appended since the last read
+    fn read_lines(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut lines = Vec::new();
+        while self.reader.read_until(b'\n', &mut self.partial)? > 0 {
+            if self.partial.last() == Some(&b'\n') {
+                self.offset += self.partial.len() as u64;
+                let mut line = std::mem::take(&mut self.partial);
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Checks if the file at our path got truncated or replaced
+    fn rotation(&self) -> Rotation {
+        match fs::metadata(&self.path) {
+            Ok(meta) if file_id(&meta) != self.file_id => Rotation::Moved,
+            Ok(meta) if meta.len() < self.offset + self.partial.len() as u64 => Rotation::Truncated,
+            Ok(_) => Rotation::None,
+            Err(_) => Rotation::Moved,
+        }
+    }
+
+    fn rewind(&mut self) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.offset = 0;
+        self.partial.clear();
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            file_id: self.file_id,
+            offset: self.offset,
+        }
+    }
+}
+
+fn read_once(
     rx: &Receiver<onramp::Msg>,
     config: &Config,
     mut preprocessors: Preprocessors,
     mut codec: Box<dyn Codec>,
     mut metrics_reporter: RampReporter,
 ) -> Result<()> {
-    let source_data_file = FSFile::open(&config.source)?;
+    let paths = sources(&config.source)?;
+    if paths.is_empty() {
+        return Err(format!("No file matches source {}", config.source).into());
+    }
     let mut pipelines: Vec<(TremorURL, pipeline::Addr)> = Vec::new();
     let mut id = 0;
-    let ext = Path::new(&config.source)
-        .extension()
-        .map(std::ffi::OsStr::to_str);
-    let reader: Box<dyn BufRead> = if ext == Some(Some("xz")) {
-        Box::new(BufReader::new(XzDecoder::new(source_data_file)))
-    } else {
-        Box::new(BufReader::new(source_data_file))
-    };
+    for path in paths {
+        let source_data_file = match FSFile::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("[Onramp::File] Failed to open {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let ext = path.extension().map(std::ffi::OsStr::to_str);
+        let reader: Box<dyn BufRead> = if ext == Some(Some("xz")) {
+            Box::new(BufReader::new(XzDecoder::new(source_data_file)))
+        } else {
+            Box::new(BufReader::new(source_data_file))
+        };
 
-    let origin_uri = tremor_pipeline::EventOriginUri {
-        scheme: "tremor-file".to_string(),
-        host: hostname(),
-        port: None,
-        path: vec![config.source.clone()],
-    };
+        let origin_uri = origin_uri(&path);
 
-    for line in reader.lines() {
-        loop {
-            match task::block_on(handle_pipelines(&rx, &mut pipelines, &mut metrics_reporter))? {
-                PipeHandlerResult::Retry => continue,
-                PipeHandlerResult::Terminate => return Ok(()),
-                PipeHandlerResult::Normal => break,
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("[Onramp::File] Failed to read {}: {}", path.display(), e);
+                    break;
+                }
+            };
+            loop {
+                match task::block_on(handle_pipelines(&rx, &mut pipelines, &mut metrics_reporter))?
+                {
+                    PipeHandlerResult::Retry => continue,
+                    PipeHandlerResult::Terminate => return Ok(()),
+                    PipeHandlerResult::Normal => break,
+                }
             }
-        }
 
-        let mut ingest_ns = nanotime();
-        send_event(
-            &pipelines,
-            &mut preprocessors,
-            &mut codec,
-            &mut metrics_reporter,
-            &mut ingest_ns,
-            &origin_uri,
-            id,
-            line?.as_bytes().to_vec(),
-        );
-        id += 1;
+            let mut ingest_ns = nanotime();
+            send_event(
+                &pipelines,
+                &mut preprocessors,
+                &mut codec,
+                &mut metrics_reporter,
+                &mut ingest_ns,
+                &origin_uri,
+                id,
+                line.into_bytes(),
+            );
+            id += 1;
+        }
     }
     // Esnure that we do not terminate before all pipelines are empty
     'outer: loop {
@@ -119,16 +288,202 @@ fn onramp_loop(
     Ok(())
 }
 
+/// Sends lines read from a followed file to the pipelines, returns false
+/// if the onramp was told to terminate.
+#[allow(clippy::borrowed_box, clippy::too_many_arguments)]
+fn send_lines(
+    rx: &Receiver<onramp::Msg>,
+    pipelines: &mut Vec<(TremorURL, pipeline::Addr)>,
+    preprocessors: &mut Preprocessors,
+    codec: &mut Box<dyn Codec>,
+    metrics_reporter: &mut RampReporter,
+    origin_uri: &EventOriginUri,
+    id: &mut u64,
+    lines: Vec<Vec<u8>>,
+) -> Result<bool> {
+    for line in lines {
+        loop {
+            match task::block_on(handle_pipelines(rx, pipelines, metrics_reporter))? {
+                PipeHandlerResult::Retry => continue,
+                PipeHandlerResult::Terminate => return Ok(false),
+                PipeHandlerResult::Normal => break,
+            }
+        }
+        let mut ingest_ns = nanotime();
+        send_event(
+            pipelines,
+            preprocessors,
+            codec,
+            metrics_reporter,
+            &mut ingest_ns,
+            origin_uri,
+            *id,
+            line,
+        );
+        *id += 1;
+    }
+    Ok(true)
+}
+
+#[allow(clippy::too_many_lines)]
+fn follow(
+    rx: &Receiver<onramp::Msg>,
+    config: &Config,
+    mut preprocessors: Preprocessors,
+    mut codec: Box<dyn Codec>,
+    mut metrics_reporter: RampReporter,
+) -> Result<()> {
+    let checkpoint_path = config.checkpoint.as_ref().map(PathBuf::from);
+    let mut checkpoints = if let Some(path) = &checkpoint_path {
+        load_checkpoints(path)?
+    } else {
+        Checkpoints::new()
+    };
+    let mut tails: BTreeMap<PathBuf, Tail> = BTreeMap::new();
+    let mut draining: Vec<(Instant, Tail)> = Vec::new();
+    let mut pipelines: Vec<(TremorURL, pipeline::Addr)> = Vec::new();
+    let mut id = 0;
+
+    loop {
+        for path in sources(&config.source)? {
+            if tails.contains_key(&path) {
+                continue;
+            }
+            if path.extension().and_then(std::ffi::OsStr::to_str) == Some("xz") {
+                warn!(
+                    "[Onramp::File] Can't follow compressed file {}",
+                    path.display()
+                );
+                continue;
+            }
+            let key = path.to_string_lossy().to_string();
+            match Tail::open(path.clone(), checkpoints.get(&key)) {
+                Ok(tail) => {
+                    tails.insert(path, tail);
+                }
+                Err(e) => warn!("[Onramp::File] Failed to open {}: {}", path.display(), e),
+            }
+        }
+
+        let mut idle = true;
+        let mut moved = Vec::new();
+        let mut failed = Vec::new();
+        for (path, tail) in &mut tails {
+            let lines = match tail.read_lines() {
+                Ok(lines) => lines,
+                Err(e) => {
+                    warn!("[Onramp::File] Failed to read {}: {}", path.display(), e);
+                    failed.push(path.clone());
+                    continue;
+                }
+            };
+            idle &= lines.is_empty();
+            let sent = send_lines(
+                rx,
+                &mut pipelines,
+                &mut preprocessors,
+                &mut codec,
+                &mut metrics_reporter,
+                &tail.origin_uri,
+                &mut id,
+                lines,
+            )?;
+            if !sent {
+                return Ok(());
+            }
+            match tail.rotation() {
+                Rotation::None => (),
+                Rotation::Truncated => tail.rewind()?,
+                Rotation::Moved => moved.push(path.clone()),
+            }
+        }
+        for path in failed {
+            tails.remove(&path);
+        }
+        // the writer may still append to a moved file for a while so it is
+        // drained before it is dropped, the new file is picked up from the
+        // start under its path
+        let now = Instant::now();
+        for path in moved {
+            if let Some(tail) = tails.remove(&path) {
+                draining.push((now + DRAIN_MOVED, tail));
+            }
+        }
+        for (_, tail) in &mut draining {
+            let lines = tail.read_lines().unwrap_or_else(|e| {
+                warn!(
+                    "[Onramp::File] Failed to read {}: {}",
+                    tail.path.display(),
+                    e
+                );
+                vec![]
+            });
+            idle &= lines.is_empty();
+            let sent = send_lines(
+                rx,
+                &mut pipelines,
+                &mut preprocessors,
+                &mut codec,
+                &mut metrics_reporter,
+                &tail.origin_uri,
+                &mut id,
+                lines,
+            )?;
+            if !sent {
+                return Ok(());
+            }
+        }
+        draining.retain(|(until, _)| *until > now);
+
+        if let Some(path) = &checkpoint_path {
+            let current: Checkpoints = tails
+                .iter()
+                .map(|(p, t)| (p.to_string_lossy().to_string(), t.checkpoint()))
+                .collect();
+            if current != checkpoints {
+                save_checkpoints(path, &current)?;
+                checkpoints = current;
+            }
+        }
+
+        if idle {
+            loop {
+                match task::block_on(handle_pipelines(&rx, &mut pipelines, &mut metrics_reporter))?
+                {
+                    PipeHandlerResult::Retry => continue,
+                    PipeHandlerResult::Terminate => return Ok(()),
+                    PipeHandlerResult::Normal => break,
+                }
+            }
+            thread::sleep(Duration::from_millis(config.poll_interval_ms));
+        }
+    }
+}
+
+fn onramp_loop(
+    rx: &Receiver<onramp::Msg>,
+    config: &Config,
+    preprocessors: Preprocessors,
+    codec: Box<dyn Codec>,
+    metrics_reporter: RampReporter,
+) -> Result<()> {
+    if config.follow {
+        follow(rx, config, preprocessors, codec, metrics_reporter)
+    } else {
+        read_once(rx, config, preprocessors, codec, metrics_reporter)
+    }
+}
+
 impl Onramp for File {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let (tx, rx) = channel(1);
         let config = self.config.clone();
-        let codec = codec::lookup(&codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
         thread::Builder::new()
             .name(format!("onramp-file-{}", "???"))
@@ -137,9 +492,84 @@ impl Onramp for File {
                     error!("[Onramp] Error: {}", e)
                 }
             })?;
-        Ok(tx)
+        Ok(tx.into())
     }
     fn default_codec(&self) -> &str {
         "json"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn tail_lines_and_truncation() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("tail.log");
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        file.write_all(b"snot\nbad")?;
+        let mut tail = Tail::open(path.clone(), None)?;
+        assert_eq!(vec![b"snot".to_vec()], tail.read_lines()?);
+        assert_eq!(5, tail.offset);
+        file.write_all(b"ger\r\n")?;
+        assert_eq!(vec![b"badger".to_vec()], tail.read_lines()?);
+        assert_eq!(Rotation::None, tail.rotation());
+
+        file.set_len(0)?;
+        assert_eq!(Rotation::Truncated, tail.rotation());
+        tail.rewind()?;
+        file.write_all(b"again\n")?;
+        assert_eq!(vec![b"again".to_vec()], tail.read_lines()?);
+
+        // resuming from a checkpoint skips what was already read
+        file.write_all(b"more\n")?;
+        let checkpoint = tail.checkpoint();
+        let mut resumed = Tail::open(path, Some(&checkpoint))?;
+        assert_eq!(vec![b"more".to_vec()], resumed.read_lines()?);
+        Ok(())
+    }
+
+    #[test]
+    fn tail_moved_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("moved.log");
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        file.write_all(b"snot\n")?;
+        let mut tail = Tail::open(path.clone(), None)?;
+        assert_eq!(vec![b"snot".to_vec()], tail.read_lines()?);
+
+        fs::rename(&path, dir.path().join("moved.log.1"))?;
+        assert_eq!(Rotation::Moved, tail.rotation());
+        // lines the writer appends after the move are still read
+        file.write_all(b"badger\n")?;
+        assert_eq!(vec![b"badger".to_vec()], tail.read_lines()?);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("checkpoints.json");
+        assert!(load_checkpoints(&path)?.is_empty());
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.insert(
+            "/var/log/snot.log".to_string(),
+            Checkpoint {
+                file_id: 42,
+                offset: 23,
+            },
+        );
+        save_checkpoints(&path, &checkpoints)?;
+        assert_eq!(checkpoints, load_checkpoints(&path)?);
+        Ok(())
+    }
+}
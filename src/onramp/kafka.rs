@@ -12,21 +12,39 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! # Kafka Onramp
+//!
+//! Consumes messages from kafka topics.
+//!
+//! With `ack` set offsets are only committed once all events decoded from a
+//! message were acknowledged by the offramps. A failed event rewinds its
+//! partition to the message it came from, so the message and everything
+//! after it is read again. A message failing more than `max_retries` times
+//! is skipped.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
 use crate::dflt;
 use crate::errors::Result;
 use crate::onramp::prelude::*;
 
 //NOTE: This is required for StreamHander's stream
+use async_std::future;
 use futures::StreamExt;
 use halfbrown::HashMap;
 use rdkafka::client::ClientContext;
 use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
 use rdkafka::consumer::stream_consumer::StreamConsumer;
-use rdkafka::consumer::{Consumer, ConsumerContext};
+use rdkafka::consumer::{CommitMode, Consumer, ConsumerContext};
 use rdkafka::error::KafkaResult;
-use rdkafka::Message;
+use rdkafka::{Message, Offset, TopicPartitionList};
 use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
 use std::time::Duration;
+use tremor_pipeline::{CBAction, Event};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -41,6 +59,15 @@ pub struct Config {
     /// to `false`. Do not use in combination with batching offramps!
     #[serde(default = "dflt::d_false")]
     pub sync: bool,
+    /// If ack is set to true offsets are only committed once the offramps
+    /// acknowledged the events of a message, and failed events are read
+    /// again. This disables `enable.auto.commit`. Defaults to `false`.
+    #[serde(default = "dflt::d_false")]
+    pub ack: bool,
+    /// With `ack` set, the number of times a message is read again after
+    /// its events failed before it is skipped. Defaults to `3`.
+    #[serde(default = "d_max_retries")]
+    pub max_retries: u32,
     /// Optional rdkafka configuration
     ///
     /// Default settings:
@@ -51,9 +78,15 @@ pub struct Config {
     /// * `enable.auto.commit` - `"true"`
     /// * `auto.commit.interval.ms"` - `"5000"`
     /// * `enable.auto.offset.store` - `"true"`
+    ///
+    /// With `ack` set `enable.auto.commit` is `"false"`.
     pub rdkafka_options: Option<HashMap<String, String>>,
 }
 
+fn d_max_retries() -> u32 {
+    3
+}
+
 impl ConfigImpl for Config {}
 
 pub struct Kafka {
@@ -88,6 +121,283 @@ impl ConsumerContext for LoggingConsumerContext {
 
 pub type LoggingConsumer = StreamConsumer<LoggingConsumerContext>;
 
+/// How often acknowledgements are handled while no messages arrive
+const ACK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Room for acknowledgements, so pipelines rarely have to wait for us
+const QSIZE: usize = 64;
+
+/// Where a message was read from
+#[derive(Debug, Clone, PartialEq)]
+struct Position {
+    topic: String,
+    partition: i32,
+    offset: i64,
+}
+
+impl Position {
+    fn path(&self) -> Vec<String> {
+        vec![
+            self.topic.clone(),
+            self.partition.to_string(),
+            self.offset.to_string(),
+        ]
+    }
+}
+
+#[derive(Debug, Default)]
+struct Partition {
+    /// Offsets of messages not acknowledged yet, with the number of
+    /// acknowledgements still outstanding for them
+    pending: BTreeMap<i64, usize>,
+    /// Offset after the last message read
+    next: i64,
+    /// Offset last committed
+    committed: i64,
+    /// Offset the partition was rewound to but not read again yet
+    rewind: Option<i64>,
+    /// Offset of the last message read
+    last: Option<i64>,
+    /// How often the messages at these offsets failed so far
+    failures: HashMap<i64, u32>,
+}
+
+impl Partition {
+    /// Stops waiting for the message at `position`, returns the offset to
+    /// commit if it moved forward
+    fn done(&mut self, position: Position) -> Option<Position> {
+        self.pending.remove(&position.offset);
+        self.failures.remove(&position.offset);
+        let offset = self.pending.keys().next().copied().unwrap_or(self.next);
+        if offset > self.committed {
+            self.committed = offset;
+            Some(Position { offset, ..position })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Failure {
+    /// Read the partition again from this position
+    Rewind(Position),
+    /// The message failed too often and is skipped, with the offset to
+    /// commit if it moved forward
+    Skip(Position, Option<Position>),
+}
+
+/// Tracks messages until their events are acknowledged
+#[derive(Debug)]
+struct Tracker {
+    /// Positions of the messages events were decoded from, by event id
+    in_flight: HashMap<u64, Position>,
+    partitions: HashMap<(String, i32), Partition>,
+    max_retries: u32,
+}
+
+impl Tracker {
+    fn new(max_retries: u32) -> Self {
+        Self {
+            in_flight: HashMap::new(),
+            partitions: HashMap::new(),
+            max_retries,
+        }
+    }
+
+    fn partition(&mut self, position: &Position) -> Option<&mut Partition> {
+        self.partitions
+            .get_mut(&(position.topic.clone(), position.partition))
+    }
+
+    /// Tracks a message read from `position`, returns `false` if the
+    /// message has to be skipped as its partition is being rewound
+    fn track(&mut self, position: &Position) -> bool {
+        let offset = position.offset;
+        let p = self
+            .partitions
+            .entry((position.topic.clone(), position.partition))
+            .or_insert_with(|| Partition {
+                next: offset,
+                committed: offset,
+                ..Partition::default()
+            });
+        // offsets only go back once the consumer reads from the rewound
+        // offset again, or the first offset after it if that one is gone
+        let restarted = p.last.map_or(false, |last| offset <= last);
+        p.last = Some(offset);
+        if let Some(rewind) = p.rewind {
+            if offset <= rewind || restarted {
+                p.rewind = None;
+            } else {
+                return false;
+            }
+        }
+        p.pending.insert(offset, 0);
+        p.next = p.next.max(offset + 1);
+        true
+    }
+
+    /// Records the events decoded from the message at `position`, each of
+    /// them is acknowledged once by every one of the `pipelines` it was sent
+    /// to. Returns the offset to commit if there is nothing to wait for.
+    fn sent(
+        &mut self,
+        ids: RangeInclusive<u64>,
+        pipelines: usize,
+        position: Position,
+    ) -> Option<Position> {
+        let mut outstanding = 0;
+        for id in ids {
+            self.in_flight.insert(id, position.clone());
+            outstanding += pipelines;
+        }
+        let p = self.partition(&position)?;
+        if outstanding == 0 {
+            p.done(position)
+        } else {
+            p.pending.insert(position.offset, outstanding);
+            None
+        }
+    }
+
+    /// Returns the message of the event if it is ours
+    fn get(&self, id: u64, origin_uri: &Option<EventOriginUri>) -> Option<Position> {
+        let position = self.in_flight.get(&id)?;
+        if origin_uri.as_ref()?.path == position.path() {
+            Some(position.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Marks a delivery of an event as acknowledged, returns the offset to
+    /// commit if its message is done and the offset moved forward
+    fn ack(&mut self, id: u64, origin_uri: &Option<EventOriginUri>) -> Option<Position> {
+        let position = self.get(id, origin_uri)?;
+        let p = self.partition(&position)?;
+        let outstanding = p.pending.get_mut(&position.offset)?;
+        *outstanding = outstanding.saturating_sub(1);
+        if *outstanding > 0 {
+            return None;
+        }
+        let commit = p.done(position.clone());
+        self.in_flight.retain(|_, q| q != &position);
+        commit
+    }
+
+    /// Forgets a failed event and everything read after it from its
+    /// partition so it is read again, unless its message failed more than
+    /// `max_retries` times already, then the message is skipped
+    fn fail(&mut self, id: u64, origin_uri: &Option<EventOriginUri>) -> Option<Failure> {
+        let position = self.get(id, origin_uri)?;
+        let max_retries = self.max_retries;
+        let p = self.partition(&position)?;
+        let failures = p.failures.entry(position.offset).or_insert(0);
+        *failures += 1;
+        if *failures > max_retries {
+            let commit = p.done(position.clone());
+            self.in_flight.retain(|_, q| q != &position);
+            return Some(Failure::Skip(position, commit));
+        }
+        p.pending.split_off(&position.offset);
+        p.next = position.offset;
+        p.rewind = Some(position.offset);
+        self.in_flight.retain(|_, q| {
+            q.topic != position.topic
+                || q.partition != position.partition
+                || q.offset < position.offset
+        });
+        Some(Failure::Rewind(position))
+    }
+
+    /// Gives up on rewinding a partition when seeking failed, so its
+    /// messages are no longer skipped
+    fn rewind_failed(&mut self, position: &Position) {
+        if let Some(p) = self.partition(position) {
+            p.rewind = None;
+        }
+    }
+
+    fn handle(&mut self, consumer: &LoggingConsumer, cb: CBAction, insight: &Event) {
+        match cb {
+            CBAction::Ack => {
+                if let Some(p) = self.ack(insight.id, &insight.origin_uri) {
+                    commit(consumer, &p);
+                }
+            }
+            CBAction::Fail => match self.fail(insight.id, &insight.origin_uri) {
+                Some(Failure::Rewind(p)) => {
+                    warn!("[kafka] event {} failed, rewinding to {:?}", insight.id, p);
+                    if let Err(e) = consumer.seek(
+                        &p.topic,
+                        p.partition,
+                        Offset::Offset(p.offset),
+                        Duration::from_secs(1),
+                    ) {
+                        error!("[kafka] failed to rewind to {:?}: {}", p, e);
+                        self.rewind_failed(&p);
+                    }
+                }
+                Some(Failure::Skip(p, c)) => {
+                    error!(
+                        "[kafka] message {:?} failed more than {} times, skipping it",
+                        p, self.max_retries
+                    );
+                    if let Some(c) = c {
+                        commit(consumer, &c);
+                    }
+                }
+                None => (),
+            },
+        }
+    }
+}
+
+fn commit(consumer: &LoggingConsumer, p: &Position) {
+    let mut tpl = TopicPartitionList::new();
+    tpl.add_partition_offset(&p.topic, p.partition, Offset::Offset(p.offset));
+    if let Err(e) = consumer.commit(&tpl, CommitMode::Async) {
+        error!("[kafka] failed to commit {:?}: {}", p, e);
+    }
+}
+
+// Handles messages until events can be sent, `false` once the onramp is done
+async fn ready(
+    rx: &Receiver<onramp::Msg>,
+    pipelines: &mut Vec<(TremorURL, pipeline::Addr)>,
+    metrics_reporter: &mut RampReporter,
+    consumer: &LoggingConsumer,
+    tracker: &mut Tracker,
+) -> Result<bool> {
+    loop {
+        match handle_msgs(rx, pipelines, metrics_reporter, consumer, tracker).await? {
+            PipeHandlerResult::Retry => continue,
+            PipeHandlerResult::Terminate => return Ok(false),
+            PipeHandlerResult::Normal => return Ok(true),
+        }
+    }
+}
+
+// Like `handle_pipelines` but also handles acknowledgements
+async fn handle_msgs(
+    rx: &Receiver<onramp::Msg>,
+    pipelines: &mut Vec<(TremorURL, pipeline::Addr)>,
+    metrics_reporter: &mut RampReporter,
+    consumer: &LoggingConsumer,
+    tracker: &mut Tracker,
+) -> Result<PipeHandlerResult> {
+    if !pipelines.is_empty() && rx.is_empty() {
+        return Ok(PipeHandlerResult::Normal);
+    }
+    match rx.recv().await? {
+        onramp::Msg::Cb(cb, insight) => {
+            tracker.handle(consumer, cb, &insight);
+            Ok(PipeHandlerResult::Retry)
+        }
+        msg => handle_pipelines_msg(msg, pipelines, metrics_reporter),
+    }
+}
+
 #[allow(clippy::too_many_lines, clippy::cognitive_complexity)]
 async fn onramp_loop(
     rx: Receiver<onramp::Msg>,
@@ -107,8 +417,11 @@ async fn onramp_loop(
         .set("bootstrap.servers", &config.brokers.join(","))
         .set("enable.partition.eof", "false")
         .set("session.timeout.ms", "6000")
-        // Commit automatically every 5 seconds.
-        .set("enable.auto.commit", "true")
+        // Commit automatically every 5 seconds, unless we commit acknowledged offsets
+        .set(
+            "enable.auto.commit",
+            if config.ack { "false" } else { "true" },
+        )
         .set("auto.commit.interval.ms", "5000")
         // but only commit the offsets explicitly stored via `consumer.store_offset`.
         .set("enable.auto.offset.store", "true")
@@ -186,48 +499,94 @@ async fn onramp_loop(
         Err(e) => error!("Kafka error for topics '{:?}': {}", good_topics, e),
     };
 
+    let mut tracker = Tracker::new(config.max_retries);
     // We do this twice so we don't consume a message from kafka and then wait
     // as this could lead to timeouts
-    loop {
-        match task::block_on(handle_pipelines(&rx, &mut pipelines, &mut metrics_reporter))? {
-            PipeHandlerResult::Retry => continue,
-            PipeHandlerResult::Terminate => return Ok(()),
-            PipeHandlerResult::Normal => break,
-        }
+    if !task::block_on(ready(
+        &rx,
+        &mut pipelines,
+        &mut metrics_reporter,
+        &consumer,
+        &mut tracker,
+    ))? {
+        return Ok(());
     }
-    while let Some(m) = stream.next().await {
-        loop {
-            match task::block_on(handle_pipelines(&rx, &mut pipelines, &mut metrics_reporter))? {
-                PipeHandlerResult::Retry => continue,
-                PipeHandlerResult::Terminate => return Ok(()),
-                PipeHandlerResult::Normal => break,
+    loop {
+        let m = if config.ack {
+            // wake up regularly to handle acknowledgements while idle
+            if let Ok(m) = future::timeout(ACK_POLL_INTERVAL, stream.next()).await {
+                m
+            } else if task::block_on(ready(
+                &rx,
+                &mut pipelines,
+                &mut metrics_reporter,
+                &consumer,
+                &mut tracker,
+            ))? {
+                continue;
+            } else {
+                return Ok(());
             }
+        } else {
+            stream.next().await
+        };
+        let m = if let Some(m) = m {
+            m
+        } else {
+            break;
+        };
+        if !task::block_on(ready(
+            &rx,
+            &mut pipelines,
+            &mut metrics_reporter,
+            &consumer,
+            &mut tracker,
+        ))? {
+            return Ok(());
         }
         if let Ok(m) = m {
-            if let Some(data) = m.payload_view::<[u8]>() {
-                if let Ok(data) = data {
-                    id += 1;
+            let position = Position {
+                topic: m.topic().to_string(),
+                partition: m.partition(),
+                offset: m.offset(),
+            };
+            origin_uri.path = position.path();
+            if config.ack && !tracker.track(&position) {
+                continue;
+            }
+            // every event gets its own id so the message is only done once
+            // all events decoded from it are acknowledged
+            let first = id + 1;
+            match m.payload_view::<[u8]>() {
+                Some(Ok(data)) => {
                     let mut ingest_ns = nanotime();
-                    origin_uri.path = vec![
-                        m.topic().to_string(),
-                        m.partition().to_string(),
-                        m.offset().to_string(),
-                    ];
-                    send_event(
+                    send_events(
                         &pipelines,
                         &mut preprocessors,
                         &mut codec,
                         &mut metrics_reporter,
                         &mut ingest_ns,
                         &origin_uri,
-                        id,
+                        || {
+                            id += 1;
+                            id
+                        },
                         data.to_vec(),
+                        None,
                     );
-                } else {
-                    error!("failed to fetch data from kafka")
                 }
-            } else {
-                error!("Failed to fetch kafka message.");
+                Some(Err(_)) => error!("failed to fetch data from kafka"),
+                None => error!("Failed to fetch kafka message."),
+            };
+            if config.ack {
+                let receivers = pipelines
+                    .iter()
+                    .filter(|(input, _)| input.instance_port().is_some())
+                    .count();
+                if let Some(p) = tracker.sent(first..=id, receivers, position) {
+                    // nothing to wait for
+                    commit(&consumer, &p);
+                }
             }
         }
     }
@@ -237,13 +596,13 @@ async fn onramp_loop(
 impl Onramp for Kafka {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
-        let (tx, rx) = channel(1);
+        let (tx, rx) = channel(QSIZE);
         let config = self.config.clone();
-        let codec = codec::lookup(&codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
         task::Builder::new()
             .name(format!("onramp-kafka-{}", "???"))
@@ -254,9 +613,160 @@ impl Onramp for Kafka {
                     error!("[Onramp] Error: {}", e)
                 }
             })?;
-        Ok(tx)
+        if self.config.ack {
+            Ok(onramp::Addr::acking(tx))
+        } else {
+            Ok(tx.into())
+        }
     }
     fn default_codec(&self) -> &str {
         "json"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn position(partition: i32, offset: i64) -> Position {
+        Position {
+            topic: "snot".to_string(),
+            partition,
+            offset,
+        }
+    }
+
+    fn origin(position: &Position) -> Option<EventOriginUri> {
+        Some(EventOriginUri {
+            scheme: "tremor-kafka".to_string(),
+            host: "localhost".to_string(),
+            port: None,
+            path: position.path(),
+        })
+    }
+
+    // reads the message at `position` and sends `events` events decoded
+    // from it to a single pipeline
+    fn read(t: &mut Tracker, id: &mut u64, events: u64, position: Position) -> Option<Position> {
+        assert!(t.track(&position));
+        let first = *id + 1;
+        *id += events;
+        t.sent(first..=*id, 1, position)
+    }
+
+    #[test]
+    fn commit_in_order() {
+        let mut t = Tracker::new(3);
+        let mut id = 0;
+        for offset in 10..13 {
+            assert_eq!(None, read(&mut t, &mut id, 1, position(0, offset)));
+        }
+        assert_eq!(None, read(&mut t, &mut id, 1, position(1, 0)));
+
+        // acknowledging a later message doesn't commit past an earlier one
+        assert_eq!(None, t.ack(2, &origin(&position(0, 11))));
+        assert_eq!(Some(position(0, 12)), t.ack(1, &origin(&position(0, 10))));
+        assert_eq!(Some(position(0, 13)), t.ack(3, &origin(&position(0, 12))));
+        // other partitions are independent
+        assert_eq!(Some(position(1, 1)), t.ack(4, &origin(&position(1, 0))));
+        // unknown or foreign acknowledgements are ignored
+        assert_eq!(None, t.ack(4, &origin(&position(1, 0))));
+        assert_eq!(None, read(&mut t, &mut id, 1, position(0, 13)));
+        assert_eq!(None, t.ack(5, &origin(&position(0, 42))));
+        assert_eq!(None, t.ack(5, &None));
+        // messages without events are done right away
+        assert_eq!(Some(position(0, 14)), t.ack(5, &origin(&position(0, 13))));
+        assert_eq!(
+            Some(position(0, 15)),
+            read(&mut t, &mut id, 0, position(0, 14))
+        );
+    }
+
+    #[test]
+    fn commit_once_all_events_are_acknowledged() {
+        let mut t = Tracker::new(3);
+        assert!(t.track(&position(0, 10)));
+        // three events sent to two pipelines each
+        assert_eq!(None, t.sent(1..=3, 2, position(0, 10)));
+        for id in &[1, 2, 3, 1, 3] {
+            assert_eq!(None, t.ack(*id, &origin(&position(0, 10))));
+        }
+        assert_eq!(Some(position(0, 11)), t.ack(2, &origin(&position(0, 10))));
+        // the message is forgotten once it is done
+        assert_eq!(None, t.ack(1, &origin(&position(0, 10))));
+        assert!(t.in_flight.is_empty());
+    }
+
+    #[test]
+    fn rewind_on_fail() {
+        let mut t = Tracker::new(3);
+        let mut id = 0;
+        for offset in 10..14 {
+            assert_eq!(None, read(&mut t, &mut id, 1, position(0, offset)));
+        }
+        assert_eq!(Some(position(0, 11)), t.ack(1, &origin(&position(0, 10))));
+        assert_eq!(
+            Some(Failure::Rewind(position(0, 11))),
+            t.fail(2, &origin(&position(0, 11)))
+        );
+        // events after the failed one are forgotten, they are read again
+        assert_eq!(None, t.ack(3, &origin(&position(0, 12))));
+        // messages still on their way from before the rewind are skipped
+        assert!(!t.track(&position(0, 14)));
+        assert_eq!(None, read(&mut t, &mut id, 1, position(0, 11)));
+        assert_eq!(None, read(&mut t, &mut id, 1, position(0, 12)));
+        assert_eq!(Some(position(0, 12)), t.ack(5, &origin(&position(0, 11))));
+        assert_eq!(Some(position(0, 13)), t.ack(6, &origin(&position(0, 12))));
+    }
+
+    #[test]
+    fn skip_after_max_retries() {
+        let mut t = Tracker::new(1);
+        let mut id = 0;
+        assert_eq!(None, read(&mut t, &mut id, 2, position(0, 10)));
+        assert_eq!(
+            Some(Failure::Rewind(position(0, 10))),
+            t.fail(1, &origin(&position(0, 10)))
+        );
+        // the other event of the failed message is forgotten too
+        assert_eq!(None, t.fail(2, &origin(&position(0, 10))));
+        assert_eq!(None, read(&mut t, &mut id, 2, position(0, 10)));
+        assert_eq!(None, read(&mut t, &mut id, 1, position(0, 11)));
+        assert_eq!(
+            Some(Failure::Skip(position(0, 10), Some(position(0, 11)))),
+            t.fail(4, &origin(&position(0, 10)))
+        );
+        assert_eq!(None, t.ack(3, &origin(&position(0, 10))));
+        assert_eq!(Some(position(0, 12)), t.ack(5, &origin(&position(0, 11))));
+        // a message read again after being skipped starts over
+        assert_eq!(None, read(&mut t, &mut id, 1, position(0, 10)));
+        assert_eq!(
+            Some(Failure::Rewind(position(0, 10))),
+            t.fail(6, &origin(&position(0, 10)))
+        );
+    }
+
+    #[test]
+    fn rewind_to_missing_offset() {
+        let mut t = Tracker::new(3);
+        let mut id = 0;
+        for offset in 10..14 {
+            assert_eq!(None, read(&mut t, &mut id, 1, position(0, offset)));
+        }
+        assert_eq!(
+            Some(Failure::Rewind(position(0, 11))),
+            t.fail(2, &origin(&position(0, 11)))
+        );
+        assert!(!t.track(&position(0, 14)));
+        // offset 11 was compacted away, reading starts over at 12
+        assert_eq!(None, read(&mut t, &mut id, 1, position(0, 12)));
+
+        assert_eq!(
+            Some(Failure::Rewind(position(0, 12))),
+            t.fail(5, &origin(&position(0, 12)))
+        );
+        // a failed seek keeps reading where the consumer is
+        t.rewind_failed(&position(0, 12));
+        assert!(t.track(&position(0, 15)));
+    }
+}
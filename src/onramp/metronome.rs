@@ -89,18 +89,18 @@ fn onramp_loop(
 impl Onramp for Metronome {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let config = self.config.clone();
         let (tx, rx) = channel(1);
-        let codec = codec::lookup(codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
         thread::Builder::new()
             .name(format!("onramp-metronome-{}", "???"))
             .spawn(move || onramp_loop(&rx, &config, preprocessors, codec, metrics_reporter))?;
-        Ok(tx)
+        Ok(tx.into())
     }
 
     fn default_codec(&self) -> &str {
@@ -311,13 +311,13 @@ fn onramp_loop(
 impl Onramp for Postgres {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let (tx, rx) = channel(1);
         let config = self.config.clone();
-        let codec = codec::lookup(&codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
 
         thread::Builder::new()
@@ -327,7 +327,7 @@ impl Onramp for Postgres {
                     error!("[Onramp] Error: {}", e)
                 }
             })?;
-        Ok(tx)
+        Ok(tx.into())
     }
 
     fn default_codec(&self) -> &str {
@@ -12,12 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-pub(crate) use crate::codec::{self, Codec};
+pub(crate) use crate::codec::{self, Codec, CodecConfig};
 pub(crate) use crate::errors::*;
 pub(crate) use crate::metrics::RampReporter;
 pub(crate) use crate::onramp::{self, Onramp};
 pub(crate) use crate::pipeline;
-pub(crate) use crate::preprocessor::{self, Preprocessors};
+pub(crate) use crate::preprocessor::{self, PreprocessorConfig, Preprocessors};
 pub(crate) use crate::system::METRICS_PIPELINE;
 pub(crate) use crate::url::TremorURL;
 pub(crate) use crate::utils::{hostname, nanotime, ConfigImpl};
@@ -27,13 +27,15 @@ pub(crate) use simd_json::json;
 pub(crate) use tremor_pipeline::EventOriginUri;
 
 // TODO pub here too?
+use simd_json::prelude::*;
 use std::mem;
 pub(crate) use std::thread;
+use tremor_script::Value;
 
-pub fn make_preprocessors(preprocessors: &[String]) -> Result<Preprocessors> {
+pub fn make_preprocessors(preprocessors: &[PreprocessorConfig]) -> Result<Preprocessors> {
     preprocessors
         .iter()
-        .map(|n| preprocessor::lookup(&n))
+        .map(preprocessor::from_config)
         .collect()
 }
 
@@ -60,32 +62,110 @@ pub fn handle_pp(
     Ok(data)
 }
 
+// We are borrowing a dyn box as we don't want to pass ownership.
+#[allow(clippy::borrowed_box, clippy::too_many_arguments)]
+pub(crate) fn send_event(
+    pipelines: &[(TremorURL, pipeline::Addr)],
+    preprocessors: &mut Preprocessors,
+    codec: &mut Box<dyn Codec>,
+    metrics_reporter: &mut RampReporter,
+    ingest_ns: &mut u64,
+    origin_uri: &tremor_pipeline::EventOriginUri,
+    id: u64,
+    data: Vec<u8>,
+) -> usize {
+    send_event_with_meta(
+        pipelines,
+        preprocessors,
+        codec,
+        metrics_reporter,
+        ingest_ns,
+        origin_uri,
+        id,
+        data,
+        None,
+    )
+}
+
+/// Adds the keys of `meta` to the metadata a codec set on an event,
+/// replacing it if either of them isn't a record.
+fn merge_meta<'event>(event_meta: &mut Value<'event>, meta: &Value<'static>) {
+    if let (Some(event_meta), Some(meta)) = (event_meta.as_object_mut(), meta.as_object()) {
+        for (k, v) in meta {
+            event_meta.insert(k.clone(), v.clone());
+        }
+        return;
+    }
+    if !meta.is_null() {
+        *event_meta = meta.clone();
+    }
+}
+
+// Like `send_event` but adds the given metadata on every decoded event.
+// Returns the number of events sent to the pipelines.
+// We are borrowing a dyn box as we don't want to pass ownership.
+#[allow(clippy::borrowed_box, clippy::too_many_arguments)]
+pub(crate) fn send_event_with_meta(
+    pipelines: &[(TremorURL, pipeline::Addr)],
+    preprocessors: &mut Preprocessors,
+    codec: &mut Box<dyn Codec>,
+    metrics_reporter: &mut RampReporter,
+    ingest_ns: &mut u64,
+    origin_uri: &tremor_pipeline::EventOriginUri,
+    id: u64,
+    data: Vec<u8>,
+    meta: Option<&Value<'static>>,
+) -> usize {
+    send_events(
+        pipelines,
+        preprocessors,
+        codec,
+        metrics_reporter,
+        ingest_ns,
+        origin_uri,
+        || id,
+        data,
+        meta,
+    )
+}
+
+// Like `send_event_with_meta` but takes the id of every decoded event from
+// `next_id`, so onramps can tell the acknowledgements of events decoded from
+// the same data apart.
 // We are borrowing a dyn box as we don't want to pass ownership.
 #[allow(
     clippy::borrowed_box,
     clippy::too_many_lines,
     clippy::too_many_arguments
 )]
-pub(crate) fn send_event(
+pub(crate) fn send_events<F>(
     pipelines: &[(TremorURL, pipeline::Addr)],
     preprocessors: &mut Preprocessors,
     codec: &mut Box<dyn Codec>,
     metrics_reporter: &mut RampReporter,
     ingest_ns: &mut u64,
     origin_uri: &tremor_pipeline::EventOriginUri,
-    id: u64,
+    mut next_id: F,
     data: Vec<u8>,
-) {
+    meta: Option<&Value<'static>>,
+) -> usize
+where
+    F: FnMut() -> u64,
+{
+    let mut sent = 0;
     if let Ok(data) = handle_pp(preprocessors, ingest_ns, data) {
         for d in data {
             match codec.decode(d, *ingest_ns) {
-                Ok(Some(data)) => {
+                Ok(Some(mut data)) => {
+                    if let Some(meta) = meta {
+                        data.rent_mut(|data| merge_meta(data.meta_mut(), meta));
+                    }
                     metrics_reporter.periodic_flush(*ingest_ns);
                     metrics_reporter.increment_out();
 
                     let event = tremor_pipeline::Event {
                         is_batch: false,
-                        id,
+                        id: next_id(),
                         data,
                         ingest_ns: *ingest_ns,
                         // TODO make origin_uri non-optional here too?
@@ -115,6 +195,7 @@ pub(crate) fn send_event(
                             error!("[Onramp] failed to send to pipeline: {}", e);
                         }
                     }
+                    sent += 1;
                 }
                 Ok(None) => (),
                 Err(e) => {
@@ -127,6 +208,7 @@ pub(crate) fn send_event(
         // record preprocessor failures too
         metrics_reporter.increment_error();
     };
+    sent
 }
 
 pub(crate) enum PipeHandlerResult {
@@ -173,6 +255,7 @@ pub(crate) fn handle_pipelines_msg(
                 tx.send(true)?;
                 Ok(PipeHandlerResult::Terminate)
             }
+            onramp::Msg::Cb(..) => Ok(PipeHandlerResult::Retry),
         }
     } else {
         match msg {
@@ -186,7 +269,25 @@ pub(crate) fn handle_pipelines_msg(
                     tx.send(false)?;
                 }
             }
+            // onramps that support acknowledgements handle them themselves
+            onramp::Msg::Cb(..) => (),
         };
         Ok(PipeHandlerResult::Normal)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge_meta_keeps_codec_meta() {
+        let mut event_meta = Value::from(json!({"codec": 1, "request": 1}));
+        merge_meta(&mut event_meta, &Value::from(json!({"request": 2})));
+        assert_eq!(Value::from(json!({"codec": 1, "request": 2})), event_meta);
+
+        let mut event_meta = Value::null();
+        merge_meta(&mut event_meta, &Value::from(json!({"request": 2})));
+        assert_eq!(Value::from(json!({"request": 2})), event_meta);
+    }
+}
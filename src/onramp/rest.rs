@@ -11,18 +11,22 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-/*
-use crate::errors::Error;
+
 use crate::onramp::prelude::*;
-use actix_router::ResourceDef;
-use actix_web::{
-    dev::Payload, web, web::Data, App, FromRequest, HttpRequest, HttpResponse, HttpServer,
-};
-use futures::future::{result, Future};
+use crate::ramp::link;
+use async_std::future::timeout;
+use async_std::io::{self as aio, Read as AsyncRead, ReadExt};
+use async_std::sync::Sender;
+use futures::{select, FutureExt};
 use halfbrown::HashMap;
-use http::{Method, StatusCode};
+use http_types::headers::HeaderName;
+use http_types::{Method, StatusCode};
 use serde_yaml::Value;
-use simd_json::json;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+use tide::{Request, Response};
+use tremor_script::{Object, Value as TremorValue};
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct Config {
@@ -32,13 +36,26 @@ pub struct Config {
     /// port to listen to, defaults to 8000
     #[serde(default = "dflt_port")]
     pub port: u16,
+    /// Endpoints to accept requests on, if empty every path and method is accepted
+    #[serde(default)]
     pub resources: Vec<EndpointConfig>,
+    /// Hold requests open until the `response` offramp answers them, defaults to false
+    #[serde(default)]
+    pub link: bool,
+    /// Milliseconds to wait for a linked response before replying with 504, defaults to 10000
+    #[serde(default = "dflt_link_timeout")]
+    pub link_timeout_ms: u64,
+    /// Largest request body in bytes, larger ones are rejected with 413,
+    /// defaults to 10MiB
+    #[serde(default = "dflt_max_body_size")]
+    pub max_body_size: usize,
 }
 
 impl ConfigImpl for Config {}
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct EndpointConfig {
+    /// Path pattern, segments of the form `{name}` are captured as path parameters
     path: String,
     allow: Vec<ResourceConfig>,
 }
@@ -47,7 +64,7 @@ pub struct EndpointConfig {
 pub struct ResourceConfig {
     method: HttpMethod,
     params: Option<Vec<String>>,
-    status_code: usize,
+    status_code: u16,
 }
 
 fn dflt_host() -> String {
@@ -58,6 +75,14 @@ fn dflt_port() -> u16 {
     8000
 }
 
+fn dflt_link_timeout() -> u64 {
+    10_000
+}
+
+fn dflt_max_body_size() -> usize {
+    10 * 1024 * 1024
+}
+
 #[derive(Clone, Debug)]
 pub struct Rest {
     pub config: Config,
@@ -74,226 +99,410 @@ impl onramp::Impl for Rest {
     }
 }
 
-impl Onramp for Rest {
-    fn start(
-        &mut self,
-        codec: &str,
-        preprocessors: &[String],
-        metrics_reporter: RampReporter,
-    ) -> Result<onramp::Addr> {
-        let config = self.config.clone();
-        let (tx, rx) = bounded(0);
-        let codec = codec::lookup(&codec)?;
-        // rest is special
-        let preprocessors = preprocessors.to_vec();
-        thread::Builder::new()
-            .name(format!("onramp-rest-{}", "???"))
-            .spawn(move || onramp_loop(&rx, config, preprocessors, codec, metrics_reporter))?;
-
-        Ok(tx)
-    }
-
-    fn default_codec(&self) -> &str {
-        "json"
-    }
-}
-
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub enum HttpMethod {
-    //    GET,
+    GET,
     POST,
     PUT,
     PATCH,
     DELETE,
-    //    HEAD,
+}
+
+impl HttpMethod {
+    fn matches(&self, method: Method) -> bool {
+        match self {
+            Self::GET => method == Method::Get,
+            Self::POST => method == Method::Post,
+            Self::PUT => method == Method::Put,
+            Self::PATCH => method == Method::Patch,
+            Self::DELETE => method == Method::Delete,
+        }
+    }
+}
+
+/// Incoming request body alongside the origin and metadata of the request
+struct RestOnrampMessage {
+    origin_uri: EventOriginUri,
+    meta: TremorValue<'static>,
+    data: Vec<u8>,
 }
 
 #[derive(Clone)]
-struct OnrampState {
+struct ServerState {
     tx: Sender<RestOnrampMessage>,
     config: Config,
 }
 
-impl Default for OnrampState {
-    fn default() -> Self {
-        let (tx, _) = bounded(1);
-        Self {
-            tx,
-            config: Config::default(),
+/// Matches a request path against an endpoint pattern, returning the
+/// captured path parameters if it matches
+fn match_path(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut pattern_segments = pattern.trim_matches('/').split('/');
+    let mut path_segments = path.trim_matches('/').split('/');
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (None, None) => return Some(params),
+            (Some(p), Some(s)) if p.starts_with('{') && p.ends_with('}') && !s.is_empty() => {
+                params.insert(p[1..p.len() - 1].to_string(), s.to_string());
+            }
+            (Some(p), Some(s)) if p == s => (),
+            _ => return None,
         }
     }
 }
-impl actix_web::error::ResponseError for Error {}
 
-impl FromRequest for OnrampState {
-    type Config = Self;
-    type Error = Error;
-    type Future = Result<Self>;
+fn default_status(method: Method) -> StatusCode {
+    match method {
+        Method::Post => StatusCode::Created,
+        Method::Delete | Method::Get => StatusCode::Ok,
+        _ => StatusCode::NoContent,
+    }
+}
 
-    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Result<Self> {
-        let state = req.get_app_data::<Self>();
-        if let Some(st) = state {
-            Ok(st.get_ref().to_owned())
-        } else {
-            Err("app cannot register state".into())
+/// Finds the endpoint for a request and the status code to reply with,
+/// alongside the matched pattern and its path parameters.
+fn route(
+    resources: &[EndpointConfig],
+    method: Method,
+    path: &str,
+) -> std::result::Result<(StatusCode, String, HashMap<String, String>), StatusCode> {
+    if resources.is_empty() {
+        return Ok((default_status(method), String::new(), HashMap::new()));
+    }
+    for endpoint in resources {
+        if let Some(params) = match_path(&endpoint.path, path) {
+            return endpoint
+                .allow
+                .iter()
+                .find(|r| r.method.matches(method))
+                .map(|r| {
+                    let status = StatusCode::try_from(r.status_code)
+                        .unwrap_or_else(|_| default_status(method));
+                    let params = if let Some(allowed) = &r.params {
+                        params
+                            .into_iter()
+                            .filter(|(k, _)| allowed.contains(k))
+                            .collect()
+                    } else {
+                        params
+                    };
+                    (status, endpoint.path.clone(), params)
+                })
+                .ok_or(StatusCode::MethodNotAllowed);
         }
     }
+    Err(StatusCode::NotFound)
+}
+
+/// Reads a body of at most `max` bytes, `None` if it is larger
+async fn read_body<R: AsyncRead + Unpin>(body: R, max: usize) -> aio::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let limit = u64::try_from(max).unwrap_or(u64::MAX).saturating_add(1);
+    body.take(limit).read_to_end(&mut data).await?;
+    if data.len() > max {
+        Ok(None)
+    } else {
+        Ok(Some(data))
+    }
 }
-fn handler(
-    sp: (Data<OnrampState>, web::Bytes, HttpRequest),
-) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
-    let state = sp.0;
-    let payload = sp.1;
-    let req = sp.2;
-    let tx = state.tx.clone();
-    // apply preprocessors
-    let body = payload.to_vec();
-
-    let pp = path_params(state.config.resources.clone(), req.match_info().path());
-    let response = Response {
-        path: req.path().to_string(),
-        actual_path: pp.0,
-        query_params: req.query_string().to_owned(),
-        path_params: pp.1,
-        headers: header(req.headers()),
-        body,
-        method: req.method().as_str().to_owned(),
+
+async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response> {
+    let method = req.method();
+    let url = req.url().clone();
+    let (status, endpoint, path_params) =
+        match route(&req.state().config.resources, method, url.path()) {
+            Ok(r) => r,
+            Err(status) => return Ok(Response::new(status)),
+        };
+    let max_body_size = req.state().config.max_body_size;
+    if req.len().map_or(false, |len| len > max_body_size) {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+    // The length is only known up front if the client sent it
+    let data = if let Some(data) = read_body(req.take_body(), max_body_size).await? {
+        data
+    } else {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    };
+
+    let mut headers = Object::with_capacity(8);
+    for (name, values) in req.iter() {
+        let values: Vec<TremorValue<'static>> = values
+            .iter()
+            .map(|v| TremorValue::from(v.as_str().to_string()))
+            .collect();
+        headers.insert(name.as_str().to_string().into(), TremorValue::from(values));
+    }
+    let path_params: Object<'static> = path_params
+        .into_iter()
+        .map(|(k, v)| (k.into(), TremorValue::from(v)))
+        .collect();
+
+    let mut request = Object::with_capacity(6);
+    request.insert("method".into(), TremorValue::from(method.to_string()));
+    request.insert("path".into(), TremorValue::from(url.path().to_string()));
+    request.insert("endpoint".into(), TremorValue::from(endpoint));
+    request.insert("path_params".into(), TremorValue::from(path_params));
+    request.insert(
+        "query".into(),
+        TremorValue::from(url.query().unwrap_or_default().to_string()),
+    );
+    request.insert("headers".into(), TremorValue::from(headers));
+    let mut meta = Object::with_capacity(2);
+    meta.insert("request".into(), TremorValue::from(request));
+
+    let link = if req.state().config.link {
+        let (id, rx) = link::register(1)?;
+        meta.insert(link::CORRELATION.into(), TremorValue::from(id));
+        Some((id, rx))
+    } else {
+        None
     };
+
     // TODO cache parts of this and update host only on new request
-    let origin_uri = tremor_pipeline::EventOriginUri {
+    let origin_uri = EventOriginUri {
         scheme: "tremor-rest".to_string(),
         host: req
-            .connection_info()
-            .remote()
+            .peer_addr()
             .unwrap_or("tremor-rest-client-host.remote")
             .to_string(),
         port: None,
-        // TODO add server port here (like for tcp onramp) -- can be done via OnrampState
-        path: vec![String::default()],
+        path: url
+            .path_segments()
+            .map(|s| s.map(String::from).collect())
+            .unwrap_or_default(),
     };
 
-    if let Err(_e) = tx.send((origin_uri, response)) {
-        Box::new(result(Err("Failed to send to pipeline".into())))
-    } else {
-        let status = StatusCode::from_u16(match *req.method() {
-            Method::POST => 201_u16,
-            Method::DELETE => 200_u16,
-            _ => 204_u16,
+    req.state()
+        .tx
+        .send(RestOnrampMessage {
+            origin_uri,
+            meta: TremorValue::from(meta),
+            data,
         })
-        .unwrap_or_default();
-        Box::new(result(Ok(HttpResponse::build(status).body("".to_string()))))
-    }
-}
+        .await;
 
-fn header(headers: &actix_web::http::header::HeaderMap) -> HashMap<String, String> {
-    headers
-        .iter()
-        .filter_map(|(key, value)| {
-            Some((key.as_str().to_string(), value.to_str().ok()?.to_string()))
-        })
-        .collect()
+    if let Some((id, rx)) = link {
+        let wait = Duration::from_millis(req.state().config.link_timeout_ms);
+        let response = timeout(wait, rx.recv()).await;
+        link::unregister(id)?;
+        match response {
+            Ok(Ok(response)) => linked_response(status, response),
+            _ => Ok(Response::new(StatusCode::GatewayTimeout)),
+        }
+    } else {
+        Ok(Response::new(status))
+    }
 }
 
-fn path_params(patterns: Vec<EndpointConfig>, path: &str) -> (String, HashMap<String, String>) {
-    for pattern in patterns {
-        let mut path = actix_web::dev::Path::new(path);
-        if ResourceDef::new(&pattern.path).match_path(&mut path) {
-            return (
-                pattern.path,
-                path.iter()
-                    .map(|(k, v)| (k.to_string(), v.to_string()))
-                    .collect(),
-            );
+/// Turns a response from the `response` offramp into an HTTP response,
+/// falling back to the routed status if none was given.
+fn linked_response(status: StatusCode, response: link::Response) -> tide::Result<Response> {
+    let status = match response.status {
+        Some(code) => StatusCode::try_from(code).unwrap_or(StatusCode::InternalServerError),
+        None => status,
+    };
+    let mut res = Response::new(status);
+    for (name, values) in response.headers {
+        if let Ok(name) = HeaderName::from_str(&name) {
+            for value in values {
+                res.append_header(name.clone(), value.as_str());
+            }
         }
     }
-    (String::default(), HashMap::default())
+    res.set_body(response.data);
+    Ok(res)
 }
 
-// We got to allow this because of the way that the onramp works
-// by creating new instances during runtime.
-#[allow(clippy::needless_pass_by_value)]
-fn onramp_loop(
+async fn onramp_loop(
     rx: &Receiver<onramp::Msg>,
     config: Config,
-    preprocessors: Vec<String>,
-    mut codec: std::boxed::Box<dyn codec::Codec>,
+    preprocessors: Vec<PreprocessorConfig>,
+    codec: Box<dyn Codec>,
+    metrics_reporter: RampReporter,
+) -> Result<()> {
+    let (loop_tx, loop_rx) = channel(64);
+
+    let addr = format!("{}:{}", config.host, config.port);
+
+    let mut app = tide::with_state(ServerState {
+        tx: loop_tx,
+        config,
+    });
+    app.at("/").all(handle_request);
+    app.at("/*").all(handle_request);
+
+    info!("[Onramp::Rest] Listening on: {}", addr);
+    let server = task::spawn(async move {
+        if let Err(e) = app.listen(addr).await {
+            error!("[Onramp::Rest] Error while listening: {}", e)
+        }
+    });
+
+    let result = handle_messages(rx, &loop_rx, codec, preprocessors, metrics_reporter).await;
+    // Stop the server so the port is released for the next onramp bound to it
+    server.cancel().await;
+    result
+}
+
+// for select!
+#[allow(clippy::mut_mut)]
+async fn handle_messages(
+    rx: &Receiver<onramp::Msg>,
+    loop_rx: &Receiver<RestOnrampMessage>,
+    mut codec: Box<dyn Codec>,
+    preprocessors: Vec<PreprocessorConfig>,
     mut metrics_reporter: RampReporter,
 ) -> Result<()> {
-    let host = format!("{}:{}", config.host, config.port);
-    let (tx, dr) = bounded::<RestOnrampMessage>(1);
-    thread::Builder::new()
-        .name(format!("onramp-rest-{}", "???"))
-        .spawn(move || {
-            let data = Data::new(OnrampState { tx, config });
-            let s = HttpServer::new(move || {
-                App::new()
-                    .register_data(data.clone())
-                    //.service(web::resource("/*").to(handler))
-            })
-            .bind(host);
-
-            if let Err(err) = s.and_then(HttpServer::run) {
-                return error!("Cannot run server: {}", err);
-            }
-        })?;
-    let mut pipelines: Vec<(TremorURL, pipeline::Addr)> = Vec::new();
+    let mut pipelines = Vec::new();
+    let mut id = 0;
     let mut preprocessors = make_preprocessors(&preprocessors)?;
 
     loop {
-        if pipelines.is_empty() {
-            match rx.recv() {
-                Ok(onramp::Msg::Connect(ps)) => {
-                    for p in &ps {
-                        if p.0 == *METRICS_PIPELINE {
-                            metrics_reporter.set_metrics_pipeline(p.clone());
-                        } else {
-                            pipelines.push(p.clone());
-                        }
-                    }
-                }
-                Ok(onramp::Msg::Disconnect { tx, .. }) => {
-                    tx.send(true)?;
-                    return Ok(());
-                }
-                Err(e) => error!("{}", e),
-            };
-            continue;
-        } else {
-            match dr.try_recv() {
-                Ok((origin_uri, data)) => {
-                    let data = json!(data).encode().into_bytes();
-                    let mut ingest_ns = nanotime();
-                    send_event(
-                        &pipelines,
-                        &mut preprocessors,
-                        &mut codec,
-                        &mut metrics_reporter,
-                        &mut ingest_ns,
-                        &origin_uri,
-                        0,
-                        data,
-                    );
-                    continue;
-                }
-                Err(TryRecvError::Empty) => (),
-                Err(TryRecvError::Disconnected) => {
-                    return Ok(());
+        loop {
+            match handle_pipelines(&rx, &mut pipelines, &mut metrics_reporter).await? {
+                PipeHandlerResult::Retry => continue,
+                PipeHandlerResult::Terminate => return Ok(()),
+                PipeHandlerResult::Normal => break,
+            }
+        }
+
+        select! {
+            msg = loop_rx.recv().fuse() => if let Ok(RestOnrampMessage { origin_uri, meta, data }) = msg {
+                let mut ingest_ns = nanotime();
+                send_event_with_meta(
+                    &pipelines,
+                    &mut preprocessors,
+                    &mut codec,
+                    &mut metrics_reporter,
+                    &mut ingest_ns,
+                    &origin_uri,
+                    id,
+                    data,
+                    Some(&meta),
+                );
+                id += 1;
+            },
+            msg = rx.recv().fuse() => if let Ok(msg) = msg {
+                match handle_pipelines_msg(msg, &mut pipelines, &mut metrics_reporter)? {
+                    PipeHandlerResult::Retry | PipeHandlerResult::Normal => continue,
+                    PipeHandlerResult::Terminate => break,
                 }
             }
         }
     }
+    Ok(())
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Response {
-    path: String,
-    query_params: String,
-    actual_path: String,
-    path_params: HashMap<String, String>,
-    headers: HashMap<String, String>,
-    body: Vec<u8>,
-    method: String,
+impl Onramp for Rest {
+    fn start(
+        &mut self,
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
+        metrics_reporter: RampReporter,
+    ) -> Result<onramp::Addr> {
+        let (tx, rx) = channel(1);
+        let config = self.config.clone();
+        let codec = codec::from_config(codec)?;
+        let preprocessors = preprocessors.to_vec();
+        task::Builder::new()
+            .name(format!("onramp-rest-{}", "???"))
+            .spawn(async move {
+                if let Err(e) =
+                    onramp_loop(&rx, config, preprocessors, codec, metrics_reporter).await
+                {
+                    error!("[Onramp] Error: {}", e)
+                }
+            })?;
+        Ok(tx.into())
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
 }
 
-type RestOnrampMessage = (tremor_pipeline::EventOriginUri, Response);
-*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn endpoint(path: &str, method: HttpMethod, status_code: u16) -> EndpointConfig {
+        EndpointConfig {
+            path: path.to_string(),
+            allow: vec![ResourceConfig {
+                method,
+                params: None,
+                status_code,
+            }],
+        }
+    }
+
+    #[test]
+    fn body_size_limit() -> Result<()> {
+        let body = task::block_on(read_body(&b"snot"[..], 4))?;
+        assert_eq!(Some(b"snot".to_vec()), body);
+        assert_eq!(None, task::block_on(read_body(&b"badger"[..], 4))?);
+        Ok(())
+    }
+
+    #[test]
+    fn path_matching() {
+        assert_eq!(Some(HashMap::new()), match_path("/", "/"));
+        assert_eq!(Some(HashMap::new()), match_path("/snot", "/snot/"));
+        assert_eq!(None, match_path("/snot", "/badger"));
+        assert_eq!(None, match_path("/snot/{id}", "/snot"));
+        assert_eq!(None, match_path("/snot/{id}", "/snot/1/2"));
+        let params = match_path("/snot/{id}/badger/{name}", "/snot/1/badger/two")
+            .expect("path didn't match");
+        assert_eq!(Some(&"1".to_string()), params.get("id"));
+        assert_eq!(Some(&"two".to_string()), params.get("name"));
+    }
+
+    #[test]
+    fn routing() {
+        let resources = vec![
+            endpoint("/snot/{id}", HttpMethod::PUT, 202),
+            endpoint("/badger", HttpMethod::POST, 201),
+        ];
+        let (status, endpoint, params) =
+            route(&resources, Method::Put, "/snot/42").expect("no route");
+        assert_eq!(StatusCode::Accepted, status);
+        assert_eq!("/snot/{id}", endpoint);
+        assert_eq!(Some(&"42".to_string()), params.get("id"));
+        assert_eq!(
+            Err(StatusCode::MethodNotAllowed),
+            route(&resources, Method::Post, "/snot/42")
+        );
+        assert_eq!(
+            Err(StatusCode::NotFound),
+            route(&resources, Method::Post, "/snot")
+        );
+        let (status, _, _) = route(&[], Method::Post, "/anything").expect("no route");
+        assert_eq!(StatusCode::Created, status);
+    }
+
+    #[test]
+    fn linked_responses() -> tide::Result<()> {
+        let res = linked_response(StatusCode::Created, link::Response::default())?;
+        assert_eq!(StatusCode::Created, res.status());
+        let res = linked_response(
+            StatusCode::Created,
+            link::Response {
+                status: Some(404),
+                headers: vec![("x-snot".to_string(), vec!["badger".to_string()])],
+                data: vec![],
+            },
+        )?;
+        assert_eq!(StatusCode::NotFound, res.status());
+        assert!(res.header("x-snot").is_some());
+        let res = linked_response(
+            StatusCode::Created,
+            link::Response {
+                status: Some(1),
+                ..link::Response::default()
+            },
+        )?;
+        assert_eq!(StatusCode::InternalServerError, res.status());
+        Ok(())
+    }
+}
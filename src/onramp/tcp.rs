@@ -13,10 +13,14 @@
 // limitations under the License.
 
 use crate::onramp::prelude::*;
+use crate::ramp::tls;
 use mio::net::{TcpListener, TcpStream};
 use mio::{Events, Interest, Poll, Token};
+use rustls::{ServerSession, Session};
 use serde_yaml::Value;
+use simd_json::BorrowedValue;
 use std::io::{ErrorKind, Read};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
@@ -29,6 +33,9 @@ const BUFFER_SIZE_BYTES: usize = 8192;
 pub struct Config {
     pub port: u16,
     pub host: String,
+    /// Accept TLS connections only
+    #[serde(default)]
+    pub tls: Option<tls::ServerConfig>,
 }
 
 impl ConfigImpl for Config {}
@@ -52,14 +59,74 @@ struct TremorTcpConnection {
     stream: TcpStream,
     origin_uri: tremor_pipeline::EventOriginUri,
     preprocessors: Preprocessors,
+    tls: Option<ServerSession>,
+    /// peer certificate info, once the TLS handshake is done
+    meta: Option<BorrowedValue<'static>>,
 }
 
 impl TremorTcpConnection {
     fn register(&mut self, poll: &Poll, token: Token) -> std::io::Result<()> {
+        // TLS connections write handshake messages, so they wait for both
+        let interest = if self.tls.is_some() {
+            Interest::READABLE | Interest::WRITABLE
+        } else {
+            Interest::READABLE
+        };
         // register the socket w/ poll
-        poll.registry()
-            .register(&mut self.stream, token, Interest::READABLE)
+        poll.registry().register(&mut self.stream, token, interest)
+    }
+}
+
+/// Reads and decrypts everything available on a TLS connection, returns the
+/// plaintext and if the connection got closed
+fn read_tls(
+    session: &mut ServerSession,
+    stream: &mut TcpStream,
+    buffer: &mut [u8],
+) -> (Vec<u8>, bool) {
+    let mut data = Vec::new();
+    let mut closed = false;
+    loop {
+        match session.read_tls(stream) {
+            Ok(0) => {
+                closed = true;
+                break;
+            }
+            Ok(_) => {
+                if let Err(e) = session.process_new_packets() {
+                    error!("TLS error on tcp client connection: {}", e);
+                    // try to tell the client before hanging up
+                    let _ = session.write_tls(stream);
+                    return (data, true);
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => {
+                error!("Failed to read data from tcp client connection: {}", e);
+                closed = true;
+                break;
+            }
+        }
+        loop {
+            match session.read(buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => data.extend_from_slice(&buffer[..n]),
+            }
+        }
+    }
+    while session.wants_write() {
+        match session.write_tls(stream) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                error!("Failed to write to tcp client connection: {}", e);
+                closed = true;
+                break;
+            }
+        }
     }
+    (data, closed)
 }
 
 // We got to allow this because of the way that the onramp works
@@ -72,7 +139,8 @@ impl TremorTcpConnection {
 fn onramp_loop(
     rx: &Receiver<onramp::Msg>,
     config: &Config,
-    preprocessors: Vec<String>,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    preprocessors: Vec<PreprocessorConfig>,
     mut codec: Box<dyn Codec>,
     mut metrics_reporter: RampReporter,
 ) -> Result<()> {
@@ -127,6 +195,8 @@ fn onramp_loop(
                                 stream,
                                 origin_uri,
                                 preprocessors: make_preprocessors(&preprocessors)?,
+                                tls: tls_config.as_ref().map(ServerSession::new),
+                                meta: None,
                             };
 
                             // if there are any returned tokens, use it to keep track of the
@@ -157,8 +227,38 @@ fn onramp_loop(
                         ref mut stream,
                         ref origin_uri,
                         ref mut preprocessors,
+                        ref mut tls,
+                        ref mut meta,
                     }) = connections[token.0]
                     {
+                        if let Some(session) = tls {
+                            let (data, closed) = read_tls(session, stream, &mut buffer);
+                            if meta.is_none() && !session.is_handshaking() {
+                                *meta = tls::peer_meta(session);
+                            }
+                            if !data.is_empty() {
+                                let mut ingest_ns = nanotime();
+                                send_event_with_meta(
+                                    &pipelines,
+                                    preprocessors,
+                                    &mut codec,
+                                    &mut metrics_reporter,
+                                    &mut ingest_ns,
+                                    origin_uri,
+                                    id,
+                                    data,
+                                    meta.as_ref(),
+                                );
+                                id += 1;
+                            }
+                            if closed {
+                                debug!("Connection closed by client: {}", origin_uri.host_port());
+                                connections[token.0] = None;
+                                returned_tokens.push(token.0);
+                                trace!("Returned token number for reuse: {}", token.0);
+                            }
+                            continue;
+                        }
                         loop {
                             let mut ingest_ns = nanotime();
                             match stream.read(&mut buffer) {
@@ -233,22 +333,34 @@ fn onramp_loop(
 impl Onramp for Tcp {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let (tx, rx) = channel(1);
         let config = self.config.clone();
         let preprocessors = preprocessors.to_vec();
-        let codec = codec::lookup(codec)?;
+        let codec = codec::from_config(codec)?;
+        let tls_config = config
+            .tls
+            .as_ref()
+            .map(tls::ServerConfig::load)
+            .transpose()?;
         thread::Builder::new()
             .name(format!("onramp-tcp-{}", "???"))
             .spawn(move || {
-                if let Err(e) = onramp_loop(&rx, &config, preprocessors, codec, metrics_reporter) {
+                if let Err(e) = onramp_loop(
+                    &rx,
+                    &config,
+                    tls_config,
+                    preprocessors,
+                    codec,
+                    metrics_reporter,
+                ) {
                     error!("[Onramp] Error: {}", e)
                 }
             })?;
-        Ok(tx)
+        Ok(tx.into())
     }
 
     fn default_codec(&self) -> &str {
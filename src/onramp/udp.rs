@@ -133,13 +133,13 @@ fn onramp_loop(
 impl Onramp for Udp {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let (tx, rx) = channel(1);
         let config = self.config.clone();
-        let codec = codec::lookup(codec)?;
+        let codec = codec::from_config(codec)?;
         let preprocessors = make_preprocessors(&preprocessors)?;
         thread::Builder::new()
             .name(format!("onramp-udp-{}", "???"))
@@ -148,7 +148,7 @@ impl Onramp for Udp {
                     error!("[Onramp] Error: {}", e)
                 }
             })?;
-        Ok(tx)
+        Ok(tx.into())
     }
     fn default_codec(&self) -> &str {
         "string"
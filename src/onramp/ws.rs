@@ -13,9 +13,11 @@
 // limitations under the License.
 
 use crate::onramp::prelude::*;
+use crate::ramp::link;
 use async_std::sync::Sender;
-use futures::{select, FutureExt, StreamExt};
+use futures::{select, FutureExt, SinkExt, StreamExt};
 use serde_yaml::Value;
+use tremor_script::{Object, Value as TremorValue};
 use tungstenite::protocol::Message;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -24,6 +26,10 @@ pub struct Config {
     pub port: u16,
     /// Host to listen on
     pub host: String,
+    /// Send events from the `response` offramp back to the client
+    /// that caused them, defaults to false
+    #[serde(default)]
+    pub link: bool,
 }
 
 pub struct Ws {
@@ -41,18 +47,22 @@ impl onramp::Impl for Ws {
     }
 }
 enum WsOnrampMessage {
-    Data(u64, EventOriginUri, Vec<u8>),
+    Data(u64, EventOriginUri, Option<TremorValue<'static>>, Vec<u8>),
 }
 
 use async_std::net::{TcpListener, TcpStream};
 use async_std::task;
 
+// for select!
+#[allow(clippy::mut_mut)]
 async fn handle_connection(
     loop_tx: Sender<WsOnrampMessage>,
     raw_stream: TcpStream,
     mut preprocessors: Preprocessors,
+    linked: bool,
 ) -> Result<()> {
-    let mut ws_stream = async_tungstenite::accept_async(raw_stream).await?;
+    let ws_stream = async_tungstenite::accept_async(raw_stream).await?;
+    let (mut ws_write, mut ws_read) = ws_stream.split();
 
     let origin_uri = tremor_pipeline::EventOriginUri {
         scheme: "tremor-ws".to_string(),
@@ -62,43 +72,66 @@ async fn handle_connection(
         path: vec![String::default()],
     };
 
-    while let Some(msg) = ws_stream.next().await {
-        match msg {
-            Ok(Message::Text(t)) => {
-                let mut ingest_ns = nanotime();
-                if let Ok(data) = handle_pp(&mut preprocessors, &mut ingest_ns, t.into_bytes()) {
-                    for d in data {
-                        loop_tx
-                            .send(WsOnrampMessage::Data(
-                                ingest_ns,
-                                // TODO possible to avoid clone here? we clone again inside send_event
-                                origin_uri.clone(),
-                                d,
-                            ))
-                            .await;
+    let linked = if linked {
+        Some(link::register(64)?)
+    } else {
+        None
+    };
+    let meta = linked.as_ref().map(|(id, _)| {
+        let mut meta = Object::with_capacity(1);
+        meta.insert(link::CORRELATION.into(), TremorValue::from(*id));
+        TremorValue::from(meta)
+    });
+
+    loop {
+        let msg = if let Some((_, link_rx)) = &linked {
+            select! {
+                msg = ws_read.next().fuse() => msg,
+                response = link_rx.recv().fuse() => {
+                    if let Ok(response) = response {
+                        let msg = match String::from_utf8(response.data) {
+                            Ok(t) => Message::Text(t),
+                            Err(e) => Message::Binary(e.into_bytes()),
+                        };
+                        if let Err(e) = ws_write.send(msg).await {
+                            error!("WS error returned while sending a response: {}", e);
+                            break;
+                        }
                     }
+                    continue;
                 }
             }
-            Ok(Message::Binary(b)) => {
-                let mut ingest_ns = nanotime();
-                if let Ok(data) = handle_pp(&mut preprocessors, &mut ingest_ns, b) {
-                    for d in data {
-                        loop_tx
-                            .send(WsOnrampMessage::Data(
-                                ingest_ns,
-                                // TODO possible to avoid clone here? we clone again inside send_event
-                                origin_uri.clone(),
-                                d,
-                            ))
-                            .await;
-                    }
-                }
+        } else {
+            ws_read.next().await
+        };
+        let data = match msg {
+            Some(Ok(Message::Text(t))) => t.into_bytes(),
+            Some(Ok(Message::Binary(b))) => b,
+            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+            Some(Ok(Message::Close(_))) | None => break,
+            Some(Err(e)) => {
+                error!("WS error returned while waiting for client data: {}", e);
+                continue;
+            }
+        };
+        let mut ingest_ns = nanotime();
+        if let Ok(data) = handle_pp(&mut preprocessors, &mut ingest_ns, data) {
+            for d in data {
+                loop_tx
+                    .send(WsOnrampMessage::Data(
+                        ingest_ns,
+                        // TODO possible to avoid clone here? we clone again inside send_event
+                        origin_uri.clone(),
+                        meta.clone(),
+                        d,
+                    ))
+                    .await;
             }
-            Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => (),
-            Ok(Message::Close(_)) => break,
-            Err(e) => error!("WS error returned while waiting for client data: {}", e),
         }
     }
+    if let Some((id, _)) = linked {
+        link::unregister(id)?;
+    }
     Ok(())
 }
 
@@ -107,7 +140,7 @@ async fn handle_connection(
 async fn onramp_loop(
     rx: &Receiver<onramp::Msg>,
     config: Config,
-    preprocessors: Vec<String>,
+    preprocessors: Vec<PreprocessorConfig>,
     mut codec: Box<dyn Codec>,
     mut metrics_reporter: RampReporter,
 ) -> Result<()> {
@@ -136,11 +169,11 @@ async fn onramp_loop(
             msg = listener.accept().fuse() => if let Ok((stream, _socket)) = msg {
                 let preprocessors = make_preprocessors(&preprocessors)?;
 
-                task::spawn(handle_connection(loop_tx.clone(), stream, preprocessors));
+                task::spawn(handle_connection(loop_tx.clone(), stream, preprocessors, config.link));
             },
-            msg = loop_rx.recv().fuse() => if let Ok(WsOnrampMessage::Data(mut ingest_ns, origin_uri, data)) = msg {
+            msg = loop_rx.recv().fuse() => if let Ok(WsOnrampMessage::Data(mut ingest_ns, origin_uri, meta, data)) = msg {
                 id += 1;
-                send_event(
+                send_event_with_meta(
                     &pipelines,
                     &mut no_pp,
                     &mut  codec,
@@ -148,7 +181,8 @@ async fn onramp_loop(
                     &mut ingest_ns,
                     &origin_uri,
                     id,
-                    data
+                    data,
+                    meta.as_ref(),
                 );
 
             },
@@ -166,13 +200,13 @@ async fn onramp_loop(
 impl Onramp for Ws {
     fn start(
         &mut self,
-        codec: &str,
-        preprocessors: &[String],
+        codec: &CodecConfig,
+        preprocessors: &[PreprocessorConfig],
         metrics_reporter: RampReporter,
     ) -> Result<onramp::Addr> {
         let (tx, rx) = channel(1);
         let config = self.config.clone();
-        let codec = codec::lookup(&codec)?;
+        let codec = codec::from_config(codec)?;
         // we need to change this here since ws is special
         let preprocessors = preprocessors.to_vec();
         task::Builder::new()
@@ -184,7 +218,7 @@ impl Onramp for Ws {
                     error!("[Onramp] Error: {}", e)
                 }
             })?;
-        Ok(tx)
+        Ok(tx.into())
     }
 
     fn default_codec(&self) -> &str {
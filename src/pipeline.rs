@@ -13,19 +13,35 @@
 // limitations under the License.
 use crate::errors::{Error, Result};
 use crate::offramp;
+use crate::onramp;
 use crate::registry::ServantId;
 use crate::repository::PipelineArtefact;
 use crate::url::TremorURL;
+use crate::utils::nanotime;
 use async_std::sync::channel;
 use async_std::task::{self, JoinHandle};
-use crossbeam_channel::{bounded, Sender as CbSender};
+use crossbeam_channel::{bounded, select, Sender as CbSender, TrySendError};
+use simd_json::prelude::*;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
 use std::thread;
-use tremor_pipeline::Event;
+use std::time::{Duration, Instant};
+use tremor_pipeline::{CBAction, Event, EventKey, ExecutableGraph, SignalKind};
 
 pub(crate) type Sender = async_std::sync::Sender<ManagerMsg>;
 
+/// How often pipelines write a snapshot of their state
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long to wait for pipelines to write their snapshot on shutdown
+const CHECKPOINT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often pipelines that need them send a tick signal through their
+/// operators
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Address for a a pipeline
 #[derive(Clone)]
 pub struct Addr {
@@ -47,8 +63,9 @@ pub(crate) enum Msg {
     },
     ConnectOfframp(Cow<'static, str>, TremorURL, offramp::Addr),
     ConnectPipeline(Cow<'static, str>, TremorURL, Addr),
+    ConnectInput(Cow<'static, str>, TremorURL, Upstream),
     Disconnect(Cow<'static, str>, TremorURL),
-    #[allow(dead_code)]
+    DisconnectInput(TremorURL),
     Signal(Event),
     Insight(Event),
 }
@@ -68,6 +85,152 @@ impl Dest {
     }
 }
 
+/// Where the events of an input come from, acknowledgements are sent back there
+#[derive(Debug)]
+pub(crate) enum Upstream {
+    Onramp(onramp::Addr),
+    Pipeline(Addr),
+}
+
+type Dests = halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, Dest)>>;
+type Inputs = Vec<(Cow<'static, str>, TremorURL, Upstream)>;
+
+/// Tracks events through the pipeline until the offramps, or the pipelines
+/// they are sent on to, acknowledged them
+#[derive(Default)]
+struct Acks {
+    /// Input port of events that were sent on or are held by an operator
+    sources: HashMap<EventKey, Cow<'static, str>>,
+    /// Number of destinations that still have to acknowledge a sent event
+    pending: HashMap<EventKey, usize>,
+}
+
+impl Acks {
+    /// Counts the destinations events are sent to, returns the events that
+    /// aren't sent anywhere
+    fn sent(&mut self, eventset: &[(Cow<'static, str>, Event)], dests: &Dests) -> Vec<Event> {
+        let mut unsent: Vec<Event> = Vec::new();
+        for (output, event) in eventset {
+            let n = dests.get(output).map_or(0, Vec::len);
+            let key = (event.id, event.origin_uri.clone());
+            if n > 0 {
+                *self.pending.entry(key).or_insert(0) += n;
+            } else if !unsent
+                .iter()
+                .any(|e| e.id == event.id && e.origin_uri == event.origin_uri)
+            {
+                unsent.push(Event::cb(CBAction::Ack, nanotime(), key.0, key.1));
+            }
+        }
+        let pending = &self.pending;
+        unsent.retain(|e| !pending.contains_key(&(e.id, e.origin_uri.clone())));
+        unsent
+    }
+
+    /// Remembers where an event came from, returns its `ack` if it is
+    /// already done with
+    fn received(
+        &mut self,
+        pipeline: &ExecutableGraph,
+        input: Cow<'static, str>,
+        key: EventKey,
+    ) -> Option<Event> {
+        let done = !self.pending.contains_key(&key) && !pipeline.holds(key.0, &key.1);
+        self.sources.insert(key.clone(), input);
+        if done {
+            Some(Event::cb(CBAction::Ack, nanotime(), key.0, key.1))
+        } else {
+            None
+        }
+    }
+
+    /// Counts an acknowledgement of a sent event, `false` while other
+    /// destinations still have to acknowledge it. A single `fail` fails it.
+    fn settle(&mut self, action: CBAction, key: &EventKey) -> bool {
+        match (action, self.pending.get_mut(key)) {
+            (CBAction::Ack, Some(n)) if *n > 1 => {
+                *n -= 1;
+                false
+            }
+            _ => {
+                self.pending.remove(key);
+                true
+            }
+        }
+    }
+
+    /// Maps an acknowledgement back through the pipeline and sends it to
+    /// the onramps or pipelines the covered events came from
+    fn cb(&mut self, pipeline: &mut ExecutableGraph, inputs: &Inputs, insight: Event) {
+        for insight in pipeline.contraflow(insight) {
+            let key = (insight.id, insight.origin_uri.clone());
+            if let (Some(cb), Some(input)) = (insight.cb_action(), self.sources.remove(&key)) {
+                send_cb(inputs, &input, cb, &insight);
+            }
+        }
+    }
+}
+
+/// Sends an acknowledgement to the onramps and pipelines connected to an input
+fn send_cb(inputs: &Inputs, input: &str, cb: CBAction, insight: &Event) {
+    for (_, _, upstream) in inputs.iter().filter(|(port, _, _)| port == input) {
+        // Neither a full onramp nor a full pipeline, that might itself be
+        // waiting to send to us, may block the pipeline
+        match upstream {
+            // Only onramps that handle acknowledgements are sent any
+            Upstream::Onramp(onramp) if onramp.acks => onramp.send_cb(cb, insight.clone()),
+            Upstream::Onramp(_) => (),
+            Upstream::Pipeline(pipeline) => {
+                if let Err(TrySendError::Full(msg)) =
+                    pipeline.addr.try_send(Msg::Insight(insight.clone()))
+                {
+                    let addr = pipeline.addr.clone();
+                    task::spawn_blocking(move || addr.send(msg));
+                }
+            }
+        }
+    }
+}
+
+/// The file the state of a pipeline instance is snapshotted to
+fn state_file(dir: &str, id: &TremorURL) -> PathBuf {
+    let name: String = format!(
+        "{}-{}",
+        id.artefact().unwrap_or_default(),
+        id.instance().unwrap_or_default()
+    )
+    .chars()
+    .map(|c| {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            c
+        } else {
+            '_'
+        }
+    })
+    .collect();
+    Path::new(dir).join(format!("{}.json", name))
+}
+
+/// Writes a snapshot of the state of a pipeline, the previous snapshot is
+/// replaced atomically so a crash can't leave a partial one behind
+fn checkpoint(pipeline: &ExecutableGraph, path: &Path) -> Result<()> {
+    let snapshot = pipeline.snapshot()?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, snapshot.encode())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Restores the state of a pipeline from its last snapshot, if there is one
+fn restore(pipeline: &mut ExecutableGraph, path: &Path) -> Result<()> {
+    if path.exists() {
+        let mut data = fs::read(path)?;
+        let snapshot = simd_json::to_borrowed_value(&mut data)?;
+        pipeline.restore(&snapshot)?;
+    }
+    Ok(())
+}
+
 pub struct Create {
     pub config: PipelineArtefact,
     pub id: ServantId,
@@ -81,13 +244,55 @@ pub(crate) enum ManagerMsg {
 #[derive(Default, Debug)]
 pub(crate) struct Manager {
     qsize: usize,
+    /// Directory pipelines snapshot their state to
+    state_directory: Option<String>,
+    /// Asks a pipeline to write a snapshot, answered once it is written,
+    /// along with a handle that is gone once the pipeline stopped
+    checkpoints: Vec<(Weak<()>, CbSender<CbSender<()>>)>,
 }
 
 impl Manager {
-    pub fn new(qsize: usize) -> Self {
-        Self { qsize }
+    pub fn new(qsize: usize, state_directory: Option<String>) -> Self {
+        Self {
+            qsize,
+            state_directory,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Forgets about pipelines that stopped
+    fn prune_checkpoints(&mut self) {
+        self.checkpoints
+            .retain(|(running, _)| running.upgrade().is_some());
+    }
+
+    /// Asks all pipelines to write a snapshot of their state and waits
+    /// for them to be written
+    async fn checkpoint(&mut self) {
+        self.prune_checkpoints();
+        let pending: Vec<_> = self
+            .checkpoints
+            .drain(..)
+            .filter_map(|(_, checkpoint)| {
+                let (tx, rx) = bounded(1);
+                checkpoint.send(tx).ok()?;
+                Some(rx)
+            })
+            .collect();
+        // The pipelines write their snapshots concurrently, so they all get
+        // the same deadline
+        let deadline = Instant::now() + CHECKPOINT_TIMEOUT;
+        task::spawn_blocking(move || {
+            for rx in pending {
+                if rx.recv_deadline(deadline).is_err() {
+                    warn!("Pipeline did not write its snapshot in time");
+                }
+            }
+        })
+        .await;
     }
-    pub fn start(self) -> (JoinHandle<bool>, Sender) {
+
+    pub fn start(mut self) -> (JoinHandle<bool>, Sender) {
         let (tx, rx) = channel(64);
         let h = task::spawn(async move {
             info!("Pipeline manager started");
@@ -95,6 +300,7 @@ impl Manager {
                 match rx.recv().await {
                     Ok(ManagerMsg::Stop) => {
                         info!("Stopping onramps...");
+                        self.checkpoint().await;
                         break;
                     }
                     Ok(ManagerMsg::Create(r, create)) => r.send(self.start_pipeline(create)).await,
@@ -111,11 +317,11 @@ impl Manager {
     }
 
     #[allow(clippy::too_many_lines)]
-    fn start_pipeline(&self, req: Create) -> Result<Addr> {
+    fn start_pipeline(&mut self, req: Create) -> Result<Addr> {
         #[inline]
         fn send_events(
             eventset: &mut Vec<(Cow<'static, str>, Event)>,
-            dests: &halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, Dest)>>,
+            dests: &Dests,
         ) -> Result<()> {
             for (output, event) in eventset.drain(..) {
                 if let Some(dest) = dests.get(&output) {
@@ -149,39 +355,134 @@ impl Manager {
         }
         let config = req.config;
         let id = req.id.clone();
-        let mut dests: halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, Dest)>> =
-            halfbrown::HashMap::new();
+        let mut dests: Dests = halfbrown::HashMap::new();
         let mut eventset: Vec<(Cow<'static, str>, Event)> = Vec::new();
+        let mut inputs: Inputs = Vec::new();
+        let mut acks = Acks::default();
         let (tx, rx) = bounded::<Msg>(self.qsize);
         let mut pipeline = config.to_executable_graph(tremor_pipeline::buildin_ops)?;
         let mut pid = req.id.clone();
         pid.trim_to_instance();
         pipeline.id = pid.to_string();
+        let state_file = if let Some(dir) = &self.state_directory {
+            fs::create_dir_all(dir)?;
+            let path = state_file(dir, &pid);
+            if let Err(e) = restore(&mut pipeline, &path) {
+                error!(
+                    "[Pipeline:{}] failed to restore state from {}: {}",
+                    id,
+                    path.display(),
+                    e
+                );
+            }
+            Some(path)
+        } else {
+            None
+        };
+        let (checkpoint_tx, mut checkpoint_rx) = bounded::<CbSender<()>>(1);
+        let running = Arc::new(());
+        self.prune_checkpoints();
+        let checkpoint_tick = if state_file.is_some() {
+            self.checkpoints
+                .push((Arc::downgrade(&running), checkpoint_tx));
+            crossbeam_channel::tick(CHECKPOINT_INTERVAL)
+        } else {
+            crossbeam_channel::never()
+        };
         thread::Builder::new()
             .name(format!("pipeline-{}", id.clone()))
             .spawn(move || {
                 info!("[Pipeline:{}] starting thread.", id);
-                for req in rx {
+                // Dropped with the thread, so the manager knows it stopped
+                let _running = running;
+                let signal_tick = if pipeline.needs_ticks() {
+                    crossbeam_channel::tick(TICK_INTERVAL)
+                } else {
+                    crossbeam_channel::never()
+                };
+                let mut signal_id = 0;
+                let write_checkpoint = |pipeline: &ExecutableGraph| {
+                    if let Some(path) = &state_file {
+                        if let Err(e) = checkpoint(pipeline, path) {
+                            error!("[Pipeline:{}] failed to write snapshot: {}", id, e);
+                        }
+                    }
+                };
+                loop {
+                    let req = select! {
+                        recv(rx) -> req => if let Ok(req) = req { req } else { break },
+                        recv(checkpoint_rx) -> done => {
+                            if let Ok(done) = done {
+                                write_checkpoint(&pipeline);
+                                done.send(()).ok();
+                            } else {
+                                // The manager is gone, we only checkpoint on the tick now
+                                checkpoint_rx = crossbeam_channel::never();
+                            }
+                            continue;
+                        },
+                        recv(checkpoint_tick) -> _ => {
+                            write_checkpoint(&pipeline);
+                            continue;
+                        },
+                        recv(signal_tick) -> _ => {
+                            signal_id += 1;
+                            Msg::Signal(Event::signal(SignalKind::Tick, nanotime(), signal_id))
+                        },
+                    };
                     match req {
                         Msg::Event { input, event } => {
+                            let key = (event.id, event.origin_uri.clone());
                             match pipeline.enqueue(&input, event, &mut eventset) {
                                 Ok(()) => {
+                                    let mut unsent = acks.sent(&eventset, &dests);
+                                    unsent.append(&mut pipeline.take_cbs());
                                     if let Err(e) = send_events(&mut eventset, &dests) {
                                         error!("Failed to send event: {}", e)
                                     }
+                                    // Events that went nowhere are done with right away, their
+                                    // ack still passes the operators that cover events with them
+                                    if let Some(ack) = acks.received(&pipeline, input, key) {
+                                        if !unsent.iter().any(|e| {
+                                            e.id == ack.id && e.origin_uri == ack.origin_uri
+                                        }) {
+                                            unsent.push(ack);
+                                        }
+                                    }
+                                    for ack in unsent {
+                                        acks.cb(&mut pipeline, &inputs, ack);
+                                    }
+                                }
+                                Err(e) => {
+                                    error!("error: {:?}", e);
+                                    // Replaying the event would fail again
+                                    let (id, origin_uri) = key;
+                                    let ack = Event::cb(CBAction::Ack, nanotime(), id, origin_uri);
+                                    send_cb(&inputs, &input, CBAction::Ack, &ack);
                                 }
-                                Err(e) => error!("error: {:?}", e),
                             }
                         }
                         Msg::Insight(insight) => {
-                            pipeline.contraflow(insight);
+                            if let Some(cb) = insight.cb_action() {
+                                let key = (insight.id, insight.origin_uri.clone());
+                                if acks.settle(cb, &key) {
+                                    acks.cb(&mut pipeline, &inputs, insight);
+                                }
+                            } else {
+                                pipeline.contraflow(insight);
+                            }
                         }
                         Msg::Signal(signal) => match pipeline.enqueue_signal(signal, &mut eventset)
                         {
                             Ok(()) => {
+                                let mut unsent = acks.sent(&eventset, &dests);
+                                unsent.append(&mut pipeline.take_cbs());
                                 if let Err(e) = send_events(&mut eventset, &dests) {
                                     error!("Failed to send event: {}", e)
                                 }
+                                for ack in unsent {
+                                    acks.cb(&mut pipeline, &inputs, ack);
+                                }
                             }
                             Err(e) => error!("error: {:?}", e),
                         },
@@ -208,6 +509,13 @@ impl Manager {
                                 dests.insert(output, vec![(pipeline_id, Dest::Pipeline(pipeline))]);
                             }
                         }
+                        Msg::ConnectInput(input, upstream_id, upstream) => {
+                            info!("[Pipeline:{}] connecting {} to {}", id, upstream_id, input);
+                            inputs.push((input, upstream_id, upstream));
+                        }
+                        Msg::DisconnectInput(upstream_id) => {
+                            inputs.retain(|(_, this_id, _)| this_id != &upstream_id);
+                        }
                         Msg::Disconnect(output, to_delete) => {
                             let mut remove = false;
                             if let Some(offramp_vec) = dests.get_mut(&output) {
@@ -220,6 +528,7 @@ impl Manager {
                         }
                     };
                 }
+                write_checkpoint(&pipeline);
                 info!("[Pipeline:{}] stopping thread.", id);
             })?;
         Ok(Addr {
@@ -228,3 +537,40 @@ impl Manager {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn acks_count_pipelines() -> Result<()> {
+        let (tx, _rx) = bounded(1);
+        let downstream = Addr {
+            addr: tx,
+            id: TremorURL::parse("/pipeline/downstream/01")?,
+        };
+        let mut dests: Dests = halfbrown::HashMap::new();
+        dests.insert(
+            "out".into(),
+            vec![(
+                TremorURL::parse("/pipeline/downstream/01/in")?,
+                Dest::Pipeline(downstream),
+            )],
+        );
+        let event = Event {
+            id: 42,
+            ..Event::default()
+        };
+        let mut acks = Acks::default();
+        let unsent = acks.sent(&[("out".into(), event.clone())], &dests);
+        assert!(unsent.is_empty());
+        assert!(acks.settle(CBAction::Ack, &(42, None)));
+
+        let unsent = acks.sent(&[("err".into(), event)], &dests);
+        assert_eq!(
+            Some(CBAction::Ack),
+            unsent.get(0).and_then(Event::cb_action)
+        );
+        Ok(())
+    }
+}
@@ -15,42 +15,120 @@
 mod gelf;
 pub(crate) use gelf::GELF;
 
+use crate::config::NameWithConfig;
 use crate::errors::{Error, Result};
-use byteorder::{BigEndian, WriteBytesExt};
+use crate::preprocessor::{Endian, LengthPrefixConfig};
+use crate::utils::ConfigImpl;
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use serde_yaml::Value;
 use std::default::Default;
 pub type Postprocessors = Vec<Box<dyn Postprocessor>>;
+/// The postprocessor setting of an offramp
+pub type PostprocessorConfig = NameWithConfig;
 use std::io::Write;
 
 pub trait Postprocessor: Send {
     fn process(&mut self, ingres_ns: u64, egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>>;
 }
 
+pub(crate) trait Impl {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Postprocessor>>;
+}
+
 // just a lookup
 #[cfg_attr(tarpaulin, skip)]
-pub fn lookup(name: &str) -> Result<Box<dyn Postprocessor>> {
+pub fn lookup(name: &str, config: &Option<Value>) -> Result<Box<dyn Postprocessor>> {
     match name {
-        "lines" => Ok(Box::new(Lines::default())),
+        "lines" => Lines::from_config(config),
         "base64" => Ok(Box::new(Base64::default())),
         "gzip" => Ok(Box::new(Gzip::default())),
         "zlib" => Ok(Box::new(Zlib::default())),
-        "xz2" => Ok(Box::new(Xz2::default())),
+        "xz2" => Xz2::from_config(config),
         "snappy" => Ok(Box::new(Snappy::default())),
-        "lz4" => Ok(Box::new(Lz4::default())),
+        "lz4" => Lz4::from_config(config),
         "ingest-ns" => Ok(Box::new(AttachIngresTS {})),
-        "length-prefixed" => Ok(Box::new(LengthPrefix::default())),
-        "gelf-chunking" => Ok(Box::new(GELF::default())),
+        "length-prefixed" => LengthPrefix::from_config(config),
+        "gelf-chunking" => GELF::from_config(config),
+        "octet-counting" => Ok(Box::new(OctetCounting {})),
         _ => Err(format!("Postprocessor '{}' not found.", name).into()),
     }
 }
 
-#[derive(Default)]
-pub struct Lines {}
+/// Looks up the postprocessor for a postprocessor setting
+pub fn from_config(postprocessor: &PostprocessorConfig) -> Result<Box<dyn Postprocessor>> {
+    lookup(&postprocessor.name, &postprocessor.config)
+}
+
+/// Configuration of the compression level
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LevelConfig {
+    /// Compression level, defaults to the level of the algorithm
+    pub level: Option<u32>,
+}
+
+impl ConfigImpl for LevelConfig {}
+
+impl LevelConfig {
+    fn level(config: &Option<Value>, default: u32, max: u32) -> Result<u32> {
+        let level = if let Some(config) = config {
+            Self::new(config)?.level.unwrap_or(default)
+        } else {
+            default
+        };
+        if level <= max {
+            Ok(level)
+        } else {
+            Err(format!("Compression level must be at most {}, not {}", max, level).into())
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LinesConfig {
+    /// Appended to every event, defaults to `"\n"`
+    #[serde(default = "dflt_separator")]
+    pub separator: String,
+}
+
+impl ConfigImpl for LinesConfig {}
+
+fn dflt_separator() -> String {
+    "\n".to_string()
+}
+
+pub struct Lines {
+    separator: Vec<u8>,
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self {
+            separator: vec![b'\n'],
+        }
+    }
+}
+
+impl Impl for Lines {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Postprocessor>> {
+        if let Some(config) = config {
+            let config = LinesConfig::new(config)?;
+            Ok(Box::new(Self {
+                separator: config.separator.into_bytes(),
+            }))
+        } else {
+            Ok(Box::new(Self::default()))
+        }
+    }
+}
+
 impl Postprocessor for Lines {
     fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
-        // padding capacity with 1 to account for the new line char we will be pushing
-        let mut framed: Vec<u8> = Vec::with_capacity(data.len() + 1);
+        // padding capacity to account for the separator we will be pushing
+        let mut framed: Vec<u8> = Vec::with_capacity(data.len() + self.separator.len());
         framed.extend_from_slice(data);
-        framed.push(b'\n');
+        framed.extend_from_slice(&self.separator);
         Ok(vec![framed])
     }
 }
@@ -86,12 +164,28 @@ impl Postprocessor for Zlib {
     }
 }
 
-#[derive(Default)]
-pub struct Xz2 {}
+pub struct Xz2 {
+    level: u32,
+}
+
+impl Default for Xz2 {
+    fn default() -> Self {
+        Self { level: 9 }
+    }
+}
+
+impl Impl for Xz2 {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Postprocessor>> {
+        Ok(Box::new(Self {
+            level: LevelConfig::level(config, 9, 9)?,
+        }))
+    }
+}
+
 impl Postprocessor for Xz2 {
     fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
         use xz2::write::XzEncoder as Encoder;
-        let mut encoder = Encoder::new(Vec::new(), 9);
+        let mut encoder = Encoder::new(Vec::new(), self.level);
         encoder.write_all(&data)?;
         Ok(vec![encoder.finish()?])
     }
@@ -111,13 +205,29 @@ impl Postprocessor for Snappy {
     }
 }
 
-#[derive(Default)]
-pub struct Lz4 {}
+pub struct Lz4 {
+    level: u32,
+}
+
+impl Default for Lz4 {
+    fn default() -> Self {
+        Self { level: 4 }
+    }
+}
+
+impl Impl for Lz4 {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Postprocessor>> {
+        Ok(Box::new(Self {
+            level: LevelConfig::level(config, 4, 16)?,
+        }))
+    }
+}
+
 impl Postprocessor for Lz4 {
     fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
         use lz4::EncoderBuilder;
         let buffer = Vec::<u8>::new();
-        let mut encoder = EncoderBuilder::new().level(4).build(buffer)?;
+        let mut encoder = EncoderBuilder::new().level(self.level).build(buffer)?;
         encoder.write_all(&data)?;
         Ok(vec![encoder.finish().0])
     }
@@ -135,23 +245,58 @@ impl Postprocessor for AttachIngresTS {
 }
 
 #[derive(Clone, Default)]
-pub struct LengthPrefix {}
+pub struct LengthPrefix {
+    config: LengthPrefixConfig,
+}
+
+impl Impl for LengthPrefix {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Postprocessor>> {
+        Ok(Box::new(Self {
+            config: LengthPrefixConfig::from_config(config)?,
+        }))
+    }
+}
+
 impl Postprocessor for LengthPrefix {
     fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
-        let mut res = Vec::with_capacity(data.len() + 8);
-        res.write_u64::<BigEndian>(data.len() as u64)?;
+        let width = self.config.width;
+        let len = data.len() as u64;
+        if width < 8 && len >> (width * 8) > 0 {
+            return Err(format!(
+                "Data of {} bytes is too long for a {} byte length prefix",
+                len, width
+            )
+            .into());
+        }
+        let mut res = Vec::with_capacity(data.len() + width);
+        match self.config.endian {
+            Endian::Big => res.write_uint::<BigEndian>(len, width)?,
+            Endian::Little => res.write_uint::<LittleEndian>(len, width)?,
+        }
         res.write_all(&data)?;
         Ok(vec![res])
     }
 }
 
+/// Octet counting framing of syslog over TCP (RFC 6587)
+#[derive(Clone, Default)]
+pub struct OctetCounting {}
+
+impl Postprocessor for OctetCounting {
+    fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut res = format!("{} ", data.len()).into_bytes();
+        res.extend_from_slice(data);
+        Ok(vec![res])
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn line() {
-        let mut line = Lines {};
+        let mut line = Lines::default();
         let data: [u8; 0] = [];
         assert_eq!(Ok(vec![vec![b'\n']]), line.process(0, 0, &data));
         assert_eq!(
@@ -160,6 +305,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn line_separator() -> Result<()> {
+        let config = Some(serde_yaml::from_str("{separator: \"\\r\\n\"}")?);
+        let mut line = lookup("lines", &config)?;
+        assert_eq!(vec![b"snot\r\n".to_vec()], line.process(0, 0, b"snot")?);
+        Ok(())
+    }
+
+    #[test]
+    fn compression_level() -> Result<()> {
+        let config = Some(serde_yaml::from_str("{level: 1}")?);
+        assert!(lookup("xz2", &config).is_ok());
+        assert!(lookup("lz4", &config).is_ok());
+        let config = Some(serde_yaml::from_str("{level: 10}")?);
+        assert!(lookup("xz2", &config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn length_prefix_too_long() -> Result<()> {
+        let config = Some(serde_yaml::from_str("{width: 1}")?);
+        let mut post = lookup("length-prefixed", &config)?;
+        assert_eq!(vec![vec![1, 42]], post.process(0, 0, &[42])?);
+        assert!(post.process(0, 0, &[0; 256]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn base64() {
         let mut post = Base64 {};
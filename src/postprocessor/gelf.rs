@@ -12,8 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::Postprocessor;
+use super::{Impl, Postprocessor};
 use crate::errors::Result;
+use crate::utils::ConfigImpl;
+use serde_yaml::Value;
+
+/// Size of the GELF chunk header
+const HEADER_SIZE: usize = 12;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Maximum size of a chunk including its 12 byte header, defaults to 8192
+    #[serde(default = "dflt_chunk_size")]
+    pub chunk_size: usize,
+}
+
+impl ConfigImpl for Config {}
+
+fn dflt_chunk_size() -> usize {
+    8192
+}
 
 #[derive(Clone)]
 pub struct GELF {
@@ -25,7 +44,28 @@ impl Default for GELF {
     fn default() -> Self {
         Self {
             id: 0,
-            chunk_size: 8192,
+            chunk_size: dflt_chunk_size(),
+        }
+    }
+}
+
+impl Impl for GELF {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Postprocessor>> {
+        if let Some(config) = config {
+            let config = Config::new(config)?;
+            if config.chunk_size <= HEADER_SIZE {
+                return Err(format!(
+                    "GELF chunk size must be larger than the {} byte header",
+                    HEADER_SIZE
+                )
+                .into());
+            }
+            Ok(Box::new(Self {
+                id: 0,
+                chunk_size: config.chunk_size,
+            }))
+        } else {
+            Ok(Box::new(Self::default()))
         }
     }
 }
@@ -34,7 +74,7 @@ impl GELF {
     // We cut i and n to u8 but check that n <= 128 before so it is safe.
     #[allow(clippy::cast_possible_truncation)]
     fn encode_gelf(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
-        let chunks = data.chunks(self.chunk_size - 12);
+        let chunks = data.chunks(self.chunk_size - HEADER_SIZE);
         let n = chunks.len();
         let id = self.id;
         if n > 128 {
@@ -48,7 +88,7 @@ impl GELF {
         Ok(chunks
             .enumerate()
             .map(|(i, chunk)| {
-                let mut buf: Vec<u8> = Vec::with_capacity(chunk.len() + 12);
+                let mut buf: Vec<u8> = Vec::with_capacity(chunk.len() + HEADER_SIZE);
                 // Serialize header
 
                 // magic number
@@ -108,4 +148,14 @@ mod test {
         assert_eq!(r[0], input_data);
         Ok(())
     }
+
+    #[test]
+    fn chunk_size_config() -> Result<()> {
+        let config = Some(serde_yaml::from_str("{chunk_size: 16}")?);
+        let mut encoder = postprocessor::lookup("gelf-chunking", &config)?;
+        assert_eq!(5, encoder.process(0, 0, &[0; 20])?.len());
+        let config = Some(serde_yaml::from_str("{chunk_size: 12}")?);
+        assert!(postprocessor::lookup("gelf-chunking", &config).is_err());
+        Ok(())
+    }
 }
@@ -16,30 +16,35 @@ mod gelf;
 pub(crate) use gelf::GELF;
 pub mod lines;
 
+use crate::config::NameWithConfig;
 use crate::errors::Result;
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use crate::utils::ConfigImpl;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
 use bytes::buf::Buf;
 use bytes::BytesMut;
+use serde_yaml::Value;
 
 use std::io::{self, Read};
 
 //pub type Lines = lines::Lines;
 
 pub type Preprocessors = Vec<Box<dyn Preprocessor>>;
+/// The preprocessor setting of an onramp
+pub type PreprocessorConfig = NameWithConfig;
+
 pub trait Preprocessor: Sync + Send {
     fn process(&mut self, ingest_ns: &mut u64, data: &[u8]) -> Result<Vec<Vec<u8>>>;
 }
 
+pub(crate) trait Impl {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Preprocessor>>;
+}
+
 // just a lookup
 #[cfg_attr(tarpaulin, skip)]
-pub fn lookup(name: &str) -> Result<Box<dyn Preprocessor>> {
+pub fn lookup(name: &str, config: &Option<Value>) -> Result<Box<dyn Preprocessor>> {
     match name {
-        // TODO once preprocessors allow configuration, remove multiple entries for lines here
-        "lines" => Ok(Box::new(Lines::new('\n', 1_048_576, true))),
-        "lines-null" => Ok(Box::new(Lines::new('\0', 1_048_576, true))),
-        "lines-pipe" => Ok(Box::new(Lines::new('|', 1_048_576, true))),
-        "lines-no-buffer" => Ok(Box::new(Lines::new('\n', 0, false))),
-        "lines-cr-no-buffer" => Ok(Box::new(Lines::new('\r', 0, false))),
+        "lines" => Lines::from_config(config),
         "base64" => Ok(Box::new(Base64::default())),
         "gzip" => Ok(Box::new(Gzip::default())),
         "zlib" => Ok(Box::new(Zlib::default())),
@@ -48,14 +53,19 @@ pub fn lookup(name: &str) -> Result<Box<dyn Preprocessor>> {
         "lz4" => Ok(Box::new(Lz4::default())),
         "decompress" => Ok(Box::new(Decompress {})),
         "remove-empty" => Ok(Box::new(FilterEmpty::default())),
-        "gelf-chunking" => Ok(Box::new(GELF::default())),
-        "gelf-chunking-tcp" => Ok(Box::new(GELF::tcp())),
+        "gelf-chunking" => GELF::from_config(config),
         "ingest-ns" => Ok(Box::new(ExtractIngresTs {})),
-        "length-prefixed" => Ok(Box::new(LengthPrefix::default())),
+        "length-prefixed" => LengthPrefix::from_config(config),
+        "octet-counting" => Ok(Box::new(OctetCounting::default())),
         _ => Err(format!("Preprocessor '{}' not found.", name).into()),
     }
 }
 
+/// Looks up the preprocessor for a preprocessor setting
+pub fn from_config(preprocessor: &PreprocessorConfig) -> Result<Box<dyn Preprocessor>> {
+    lookup(&preprocessor.name, &preprocessor.config)
+}
+
 trait SliceTrim {
     fn trim(&self) -> &Self;
 }
@@ -232,11 +242,81 @@ impl Preprocessor for Decompress {
         Ok(vec![r])
     }
 }
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+impl Default for Endian {
+    fn default() -> Self {
+        Self::Big
+    }
+}
+
+/// Length prefix framing, shared with the `length-prefixed` postprocessor
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct LengthPrefixConfig {
+    /// Number of bytes of the length prefix, 1 to 8, defaults to 8
+    #[serde(default = "dflt_width")]
+    pub width: usize,
+    /// Byte order of the length prefix, `big` or `little`, defaults to `big`
+    #[serde(default)]
+    pub endian: Endian,
+}
+
+impl ConfigImpl for LengthPrefixConfig {}
+
+fn dflt_width() -> usize {
+    8
+}
+
+impl Default for LengthPrefixConfig {
+    fn default() -> Self {
+        Self {
+            width: dflt_width(),
+            endian: Endian::default(),
+        }
+    }
+}
+
+impl LengthPrefixConfig {
+    pub(crate) fn from_config(config: &Option<Value>) -> Result<Self> {
+        let config = if let Some(config) = config {
+            Self::new(config)?
+        } else {
+            Self::default()
+        };
+        if config.width >= 1 && config.width <= 8 {
+            Ok(config)
+        } else {
+            Err(format!(
+                "Length prefix width must be between 1 and 8 bytes, not {}",
+                config.width
+            )
+            .into())
+        }
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct LengthPrefix {
+    config: LengthPrefixConfig,
     len: Option<usize>,
     buffer: BytesMut,
 }
+
+impl Impl for LengthPrefix {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Preprocessor>> {
+        Ok(Box::new(Self {
+            config: LengthPrefixConfig::from_config(config)?,
+            ..Self::default()
+        }))
+    }
+}
+
 impl Preprocessor for LengthPrefix {
     #[allow(clippy::cast_possible_truncation)]
     fn process(&mut self, _ingest_ns: &mut u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
@@ -254,9 +334,14 @@ impl Preprocessor for LengthPrefix {
                     break;
                 }
             }
-            if self.buffer.len() > 8 {
-                self.len = Some(BigEndian::read_u64(&self.buffer) as usize);
-                self.buffer.advance(8);
+            let width = self.config.width;
+            if self.buffer.len() >= width {
+                let len = match self.config.endian {
+                    Endian::Big => BigEndian::read_uint(&self.buffer, width),
+                    Endian::Little => LittleEndian::read_uint(&self.buffer, width),
+                };
+                self.len = Some(len as usize);
+                self.buffer.advance(width);
             } else {
                 break;
             }
@@ -264,6 +349,78 @@ impl Preprocessor for LengthPrefix {
         Ok(res)
     }
 }
+
+/// Longest octet count we accept, 20 digits fit any `usize`
+const MAX_OCTET_COUNT_DIGITS: usize = 20;
+
+/// Largest message we accept with octet counting, larger ones are
+/// rejected rather than buffered
+const MAX_OCTET_COUNT_FRAME: usize = 1_048_576;
+
+/// Octet counting framing of syslog over TCP (RFC 6587), every message is
+/// prefixed with its length in decimal and a space
+#[derive(Clone, Default, Debug)]
+pub struct OctetCounting {
+    len: Option<usize>,
+    buffer: BytesMut,
+}
+
+impl OctetCounting {
+    fn split(&mut self, res: &mut Vec<Vec<u8>>) -> Result<()> {
+        loop {
+            if let Some(l) = self.len {
+                if self.buffer.len() >= l {
+                    let mut part = self.buffer.split_off(l);
+                    std::mem::swap(&mut part, &mut self.buffer);
+                    res.push(part.to_vec());
+                    self.len = None;
+                } else {
+                    break;
+                }
+            }
+            let digits = self
+                .buffer
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count();
+            if digits > MAX_OCTET_COUNT_DIGITS {
+                return Err("Invalid octet count, too many digits".into());
+            } else if digits == self.buffer.len() {
+                // the count is not complete yet
+                break;
+            } else if digits == 0 || self.buffer[digits] != b' ' {
+                return Err("Invalid octet count, expected digits followed by a space".into());
+            }
+            let len = std::str::from_utf8(&self.buffer[..digits])?.parse()?;
+            if len > MAX_OCTET_COUNT_FRAME {
+                return Err(format!(
+                    "Octet counted message of {} bytes is larger than {} bytes",
+                    len, MAX_OCTET_COUNT_FRAME
+                )
+                .into());
+            }
+            self.len = Some(len);
+            self.buffer.advance(digits + 1);
+        }
+        Ok(())
+    }
+}
+
+impl Preprocessor for OctetCounting {
+    fn process(&mut self, _ingest_ns: &mut u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.buffer.extend(data);
+        let mut res = Vec::new();
+        if let Err(e) = self.split(&mut res) {
+            // the framing is lost, start over with the next data instead of
+            // failing on what is left of the bad frame forever
+            self.buffer.clear();
+            self.len = None;
+            return Err(e);
+        }
+        Ok(res)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -287,10 +444,53 @@ mod test {
         Ok(())
     }
 
-    const LOOKUP_TABLE: [&'static str; 15] = [
+    #[test]
+    fn length_prefix_little_endian() -> Result<()> {
+        let mut it = 0;
+        let config: Option<Value> = Some(serde_yaml::from_str("{width: 2, endian: little}")?);
+
+        let mut pre_p = pre::lookup("length-prefixed", &config)?;
+        let mut post_p = post::lookup("length-prefixed", &config)?;
+
+        let data = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let wire = post_p.process(0, 0, &data)?;
+        assert_eq!(&[10, 0], &wire[0][0..2]);
+        let recv = pre_p.process(&mut it, &wire[0])?;
+        assert_eq!(recv[0], data);
+
+        let config: Option<Value> = Some(serde_yaml::from_str("{width: 9}")?);
+        assert!(pre::lookup("length-prefixed", &config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn octet_counting() -> Result<()> {
+        let mut it = 0;
+        let mut pre_p = pre::lookup("octet-counting", &None)?;
+        let mut post_p = post::lookup("octet-counting", &None)?;
+
+        let wire = post_p.process(0, 0, b"<13>1 - - - - - - snot")?;
+        assert_eq!(b"22 <13>1".to_vec(), wire[0][0..8].to_vec());
+        let (start, end) = wire[0].split_at(1);
+        assert!(pre_p.process(&mut it, start)?.is_empty());
+        let mut both = end.to_vec();
+        both.extend_from_slice(b"6 badger");
+        let recv = pre_p.process(&mut it, &both)?;
+        assert_eq!(
+            vec![b"<13>1 - - - - - - snot".to_vec(), b"badger".to_vec()],
+            recv
+        );
+
+        assert!(pre_p.process(&mut it, b"snot badger").is_err());
+        // the bad frame is dropped so the next one is read again
+        assert_eq!(vec![b"snot".to_vec()], pre_p.process(&mut it, b"4 snot")?);
+        assert!(pre_p.process(&mut it, b"1048577 ").is_err());
+        assert_eq!(vec![b"snot".to_vec()], pre_p.process(&mut it, b"4 snot")?);
+        Ok(())
+    }
+
+    const LOOKUP_TABLE: [&'static str; 13] = [
         "lines",
-        "lines-null",
-        "lines-pipe",
         "base64",
         "gzip",
         "zlib",
@@ -300,18 +500,18 @@ mod test {
         "decompress",
         "remove-empty",
         "gelf-chunking",
-        "gelf-chunking-tcp",
         "ingest-ns",
         "length-prefixed",
+        "octet-counting",
     ];
 
     #[test]
     fn test_lookup() -> Result<()> {
         for t in LOOKUP_TABLE.iter() {
-            assert!(lookup(t).is_ok());
+            assert!(lookup(t, &None).is_ok());
         }
         let t = "snot";
-        assert!(lookup(&t).is_err());
+        assert!(lookup(&t, &None).is_err());
         Ok(())
     }
 
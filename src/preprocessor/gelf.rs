@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::Preprocessor;
+use super::{Impl, Preprocessor};
 use crate::errors::{ErrorKind, Result};
+use crate::utils::ConfigImpl;
 use hashbrown::{hash_map::Entry, HashMap};
 use rand::{self, RngCore};
+use serde_yaml::Value;
 
 const FIVE_SEC: u64 = 5_000_000_000;
 
@@ -28,27 +30,44 @@ pub struct GELF {
     is_tcp: bool,
 }
 
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Messages arrive over TCP and end in a null byte that is stripped,
+    /// defaults to false
+    #[serde(default)]
+    pub tcp: bool,
+}
+
+impl ConfigImpl for Config {}
+
 impl GELF {
     pub fn default() -> Self {
-        Self {
-            buffer: HashMap::new(),
-            last_buffer: HashMap::new(),
-            last_swap: 0,
-            cnt: 0,
-            is_tcp: false,
-        }
+        Self::with_config(&Config::default())
     }
-    pub fn tcp() -> Self {
+
+    fn with_config(config: &Config) -> Self {
         Self {
             buffer: HashMap::new(),
             last_buffer: HashMap::new(),
             last_swap: 0,
             cnt: 0,
-            is_tcp: true,
+            is_tcp: config.tcp,
         }
     }
 }
 
+impl Impl for GELF {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Preprocessor>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        Ok(Box::new(Self::with_config(&config)))
+    }
+}
+
 #[derive(Clone, Default)]
 struct GELFMsgs {
     count: u8,
@@ -12,10 +12,47 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::Preprocessor;
+use super::{Impl, Preprocessor};
+use crate::dflt;
 use crate::errors::Result;
+use crate::utils::ConfigImpl;
+use serde_yaml::Value;
 use std::cmp::min;
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Single byte character to split on, defaults to `"\n"`
+    #[serde(default = "dflt_separator")]
+    pub separator: String,
+    /// Maximum length of a line in bytes, 0 means no limit, defaults to 1048576
+    #[serde(default = "dflt_max_length")]
+    pub max_length: usize,
+    /// Keep incomplete lines until the rest of them arrives, defaults to true
+    #[serde(default = "dflt::d_true")]
+    pub buffered: bool,
+}
+
+impl ConfigImpl for Config {}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            separator: dflt_separator(),
+            max_length: dflt_max_length(),
+            buffered: true,
+        }
+    }
+}
+
+fn dflt_separator() -> String {
+    "\n".to_string()
+}
+
+fn dflt_max_length() -> usize {
+    1_048_576
+}
+
 #[derive(Clone)]
 pub struct Lines {
     separator: u8,
@@ -32,12 +69,38 @@ impl Default for Lines {
     }
 }
 
+impl Impl for Lines {
+    fn from_config(config: &Option<Value>) -> Result<Box<dyn Preprocessor>> {
+        let config: Config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        if let [separator] = config.separator.as_bytes() {
+            Ok(Box::new(Self::with_separator(
+                *separator,
+                config.max_length,
+                config.buffered,
+            )))
+        } else {
+            Err(format!(
+                "Lines separator must be a single byte character, not {:?}",
+                config.separator
+            )
+            .into())
+        }
+    }
+}
+
 impl Lines {
-    // TODO have the params here as a config struct
-    // also break lines on string (eg: \r\n)
+    // TODO break lines on string (eg: \r\n)
     pub fn new(separator: char, max_length: usize, is_buffered: bool) -> Self {
+        Self::with_separator(separator as u8, max_length, is_buffered)
+    }
+
+    fn with_separator(separator: u8, max_length: usize, is_buffered: bool) -> Self {
         Self {
-            separator: separator as u8,
+            separator,
             max_length,
             fragment_length: 0,
             // allocating at once with enough capacity to ensure we don't do re-allocations
@@ -160,3 +223,30 @@ impl Preprocessor for Lines {
             .collect::<Vec<Vec<u8>>>())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_config() -> Result<()> {
+        let mut ingest_ns = 0;
+        let config = Some(serde_yaml::from_str("{separator: \"|\", buffered: false}")?);
+        let mut lines = Lines::from_config(&config)?;
+        assert_eq!(
+            vec![b"snot".to_vec(), b"badger".to_vec()],
+            lines.process(&mut ingest_ns, b"snot|badger")?
+        );
+
+        let mut lines = Lines::from_config(&None)?;
+        assert!(lines.process(&mut ingest_ns, b"snot")?.is_empty());
+        assert_eq!(
+            vec![b"snotbadger".to_vec()],
+            lines.process(&mut ingest_ns, b"badger\n")?
+        );
+
+        let config = Some(serde_yaml::from_str("{separator: \"\\r\\n\"}")?);
+        assert!(Lines::from_config(&config).is_err());
+        Ok(())
+    }
+}
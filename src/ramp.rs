@@ -22,7 +22,10 @@ use std::io::Write;
 use std::ops::DerefMut;
 use std::path::Path;
 
+pub mod link;
 pub mod postgres;
+pub mod queue;
+pub mod tls;
 
 pub trait KV {
     fn get(&mut self) -> Result<simd_json::OwnedValue>;
@@ -0,0 +1,96 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Links requests that arrive on an onramp to the responses the
+//! `response` offramp sends back.
+//!
+//! A linked onramp registers a handle and stores its id in the `$correlation`
+//! metadata of the events it sends. When the `response` offramp receives an
+//! event it uses that id to hand the encoded data back to the onramp.
+
+use crate::errors::Result;
+use async_std::sync::{channel, Receiver, Sender};
+use halfbrown::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Metadata key holding the correlation id of a linked event
+pub(crate) const CORRELATION: &str = "correlation";
+
+/// A response for a linked request
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Response {
+    /// Status code taken from `$response.status`
+    pub status: Option<u16>,
+    /// Headers taken from `$response.headers`
+    pub headers: Vec<(String, Vec<String>)>,
+    /// Encoded and postprocessed data
+    pub data: Vec<u8>,
+}
+
+lazy_static! {
+    static ref LINKS: Mutex<HashMap<u64, Sender<Response>>> = Mutex::new(HashMap::new());
+}
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Registers a new link that can receive up to `qsize` queued responses
+pub(crate) fn register(qsize: usize) -> Result<(u64, Receiver<Response>)> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = channel(qsize);
+    LINKS.lock()?.insert(id, tx);
+    Ok((id, rx))
+}
+
+/// Removes a link, responses for it will no longer be delivered
+pub(crate) fn unregister(id: u64) -> Result<()> {
+    LINKS.lock()?.remove(&id);
+    Ok(())
+}
+
+/// Sends a response to the onramp waiting for the link. Responses are
+/// dropped rather than waited on if the link has no room left for them so
+/// a slow client can't hold up the responses to everyone else.
+pub(crate) fn reply(id: u64, response: Response) -> Result<()> {
+    let tx = LINKS.lock()?.get(&id).cloned();
+    if let Some(tx) = tx {
+        tx.try_send(response)
+            .map_err(|_| format!("Dropped response for busy correlation id {}", id).into())
+    } else {
+        Err(format!("No client is waiting for correlation id {}", id).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn link_roundtrip() -> Result<()> {
+        let (id, rx) = register(1)?;
+        let response = Response {
+            status: Some(202),
+            headers: vec![],
+            data: b"snot".to_vec(),
+        };
+        reply(id, response)?;
+        // The link only has room for one response
+        assert!(reply(id, Response::default()).is_err());
+        let response = async_std::task::block_on(rx.recv())?;
+        assert_eq!(Some(202), response.status);
+        assert_eq!(b"snot".to_vec(), response.data);
+        unregister(id)?;
+        assert!(reply(id, Response::default()).is_err());
+        Ok(())
+    }
+}
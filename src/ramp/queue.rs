@@ -0,0 +1,335 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A write-ahead queue on local disk.
+//!
+//! Records are appended to `queue.dat` in the queue directory, each prefixed
+//! with its length as a little endian `u32`. The offset of the first record
+//! that was not yet taken off the queue is kept in `queue.head`, so a queue
+//! picks up where it left off after a restart. Both files are synced to
+//! disk on every change, records written partially when tremor stopped are
+//! dropped when the queue is opened.
+
+use crate::errors::{Error, Result};
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+const DATA: &str = "queue.dat";
+const HEAD: &str = "queue.head";
+/// Size of the length prefix of a record
+const PREFIX: u64 = 4;
+
+/// Settings of an on-disk queue
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Directory to keep the queue in, every queue needs its own
+    pub path: String,
+    /// Maximum size of the queue on disk in bytes (default: 1GB)
+    #[serde(default = "d_max_size")]
+    pub max_size: u64,
+    /// Milliseconds to wait before retrying to deliver queued events
+    /// (default: 1000)
+    #[serde(default = "d_retry_ms")]
+    pub retry_ms: u64,
+}
+
+fn d_max_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn d_retry_ms() -> u64 {
+    1000
+}
+
+/// A queue of records on disk
+#[derive(Debug)]
+pub struct Queue {
+    path: PathBuf,
+    max_size: u64,
+    data: File,
+    head_file: File,
+    /// Offset of the first record
+    head: u64,
+    /// Offset past the last record
+    end: u64,
+    len: u64,
+}
+
+/// Reads the length prefix of the record at the current position, `None`
+/// at the end of the file
+fn read_len(data: &mut File) -> Result<Option<u32>> {
+    let mut prefix = [0_u8; 4];
+    match data.read_exact(&mut prefix) {
+        Ok(()) => Ok(Some(u32::from_le_bytes(prefix))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Queue {
+    /// Opens the queue in the configured directory, creating it if needed
+    pub fn open(config: &Config) -> Result<Self> {
+        let path = PathBuf::from(&config.path);
+        fs::create_dir_all(&path)
+            .map_err(|e| Error::from(format!("Failed to create queue {}: {}", config.path, e)))?;
+        let open = |name: &str| -> Result<File> {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(path.join(name))
+                .map_err(|e| Error::from(format!("Failed to open queue {}: {}", config.path, e)))
+        };
+        let mut data = open(DATA)?;
+        let mut head_file = open(HEAD)?;
+        let mut head = [0_u8; 8];
+        let head = match head_file.read_exact(&mut head) {
+            Ok(()) => u64::from_le_bytes(head),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let size = data.metadata()?.len();
+        let head = if head > size { size } else { head };
+
+        // Count the records and drop what was only written partially
+        let mut end = head;
+        let mut len = 0;
+        data.seek(SeekFrom::Start(head))?;
+        while let Some(record) = read_len(&mut data)? {
+            let record = u64::from(record);
+            if end + PREFIX + record > size {
+                break;
+            }
+            end += PREFIX + record;
+            len += 1;
+            data.seek(SeekFrom::Start(end))?;
+        }
+        if end < size {
+            warn!(
+                "Dropping {} bytes of partially written records from queue {}",
+                size - end,
+                config.path
+            );
+            data.set_len(end)?;
+        }
+        let mut queue = Self {
+            path,
+            max_size: config.max_size,
+            data,
+            head_file,
+            head,
+            end,
+            len,
+        };
+        if len == 0 {
+            queue.clear()?;
+        }
+        Ok(queue)
+    }
+
+    /// Number of records in the queue
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// If the queue holds no records
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends a record, fails if that would grow the queue beyond its
+    /// maximum size
+    pub fn push(&mut self, record: &[u8]) -> Result<()> {
+        let record_len = u32::try_from(record.len())
+            .map_err(|_| Error::from("Record too large for the queue"))?;
+        let size = PREFIX + u64::from(record_len);
+        if self.end - self.head + size > self.max_size {
+            return Err(format!("Queue {} is full", self.path.to_string_lossy()).into());
+        }
+        if self.end + size > self.max_size {
+            self.compact()?;
+        }
+        self.data.seek(SeekFrom::Start(self.end))?;
+        let mut buf = Vec::with_capacity(record.len() + 4);
+        buf.extend_from_slice(&record_len.to_le_bytes());
+        buf.extend_from_slice(record);
+        self.data.write_all(&buf)?;
+        self.data.sync_data()?;
+        self.end += size;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The first record of the queue
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn front(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        self.data.seek(SeekFrom::Start(self.head))?;
+        if let Some(len) = read_len(&mut self.data)? {
+            let mut record = vec![0; len as usize];
+            self.data.read_exact(&mut record)?;
+            Ok(Some(record))
+        } else {
+            Err(format!("Queue {} is corrupted", self.path.to_string_lossy()).into())
+        }
+    }
+
+    /// Takes the first record off the queue
+    pub fn pop(&mut self) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        self.data.seek(SeekFrom::Start(self.head))?;
+        if let Some(len) = read_len(&mut self.data)? {
+            self.head += PREFIX + u64::from(len);
+            self.len -= 1;
+            if self.is_empty() {
+                self.clear()
+            } else {
+                self.write_head()
+            }
+        } else {
+            Err(format!("Queue {} is corrupted", self.path.to_string_lossy()).into())
+        }
+    }
+
+    fn write_head(&mut self) -> Result<()> {
+        self.head_file.seek(SeekFrom::Start(0))?;
+        self.head_file.write_all(&self.head.to_le_bytes())?;
+        self.head_file.sync_data()?;
+        Ok(())
+    }
+
+    /// Empties the files, the data first so a crash in between can't replay
+    /// records that were already taken off the queue
+    fn clear(&mut self) -> Result<()> {
+        self.data.set_len(0)?;
+        self.data.sync_data()?;
+        self.head = 0;
+        self.end = 0;
+        self.write_head()
+    }
+
+    /// Moves the records to the start of the file
+    fn compact(&mut self) -> Result<()> {
+        let tmp = self.path.join(format!("{}.tmp", DATA));
+        let mut compacted = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        self.data.seek(SeekFrom::Start(self.head))?;
+        io::copy(
+            &mut (&mut self.data).take(self.end - self.head),
+            &mut compacted,
+        )?;
+        compacted.sync_data()?;
+        // Should we crash before the rename records are replayed rather than lost
+        self.end -= self.head;
+        self.head = 0;
+        self.write_head()?;
+        fs::rename(&tmp, self.path.join(DATA))?;
+        // Makes the rename itself durable
+        File::open(&self.path)?.sync_all()?;
+        self.data = compacted;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn config(path: &std::path::Path, max_size: u64) -> Config {
+        Config {
+            path: path.to_string_lossy().to_string(),
+            max_size,
+            retry_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn survives_restart() -> Result<()> {
+        let dir = tempdir()?;
+        let config = config(dir.path(), 1024);
+        let mut q = Queue::open(&config)?;
+        assert!(q.is_empty());
+        assert_eq!(None, q.front()?);
+        q.push(b"snot")?;
+        q.push(b"badger")?;
+        q.push(b"")?;
+        assert_eq!(Some(b"snot".to_vec()), q.front()?);
+        q.pop()?;
+        drop(q);
+
+        let mut q = Queue::open(&config)?;
+        assert_eq!(2, q.len());
+        assert_eq!(Some(b"badger".to_vec()), q.front()?);
+        q.pop()?;
+        assert_eq!(Some(Vec::new()), q.front()?);
+        q.pop()?;
+        assert!(q.is_empty());
+        assert_eq!(0, fs::metadata(dir.path().join(DATA))?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn drops_partial_records() -> Result<()> {
+        let dir = tempdir()?;
+        let config = config(dir.path(), 1024);
+        let mut q = Queue::open(&config)?;
+        q.push(b"snot")?;
+        drop(q);
+        let mut data = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(DATA))?;
+        data.write_all(&[10, 0, 0, 0, b'b'])?;
+
+        let mut q = Queue::open(&config)?;
+        assert_eq!(1, q.len());
+        q.push(b"badger")?;
+        q.pop()?;
+        assert_eq!(Some(b"badger".to_vec()), q.front()?);
+        Ok(())
+    }
+
+    #[test]
+    fn max_size() -> Result<()> {
+        let dir = tempdir()?;
+        // room for two records of 4 bytes
+        let mut q = Queue::open(&config(dir.path(), 16))?;
+        q.push(b"snot")?;
+        q.push(b"snot")?;
+        assert!(q.push(b"snot").is_err());
+        q.pop()?;
+        // compacts the file to make room
+        q.push(b"grmp")?;
+        assert_eq!(2, q.len());
+        assert_eq!(16, fs::metadata(dir.path().join(DATA))?.len());
+        q.pop()?;
+        assert_eq!(Some(b"grmp".to_vec()), q.front()?);
+        drop(q);
+
+        let mut q = Queue::open(&config(dir.path(), 16))?;
+        assert_eq!(Some(b"grmp".to_vec()), q.front()?);
+        Ok(())
+    }
+}
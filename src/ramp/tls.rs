@@ -0,0 +1,314 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS settings shared by onramps and offramps.
+//!
+//! Certificates, keys and CA bundles are read from PEM files. Onramps put
+//! the subject and issuer of the peer certificate into the `$tls` metadata
+//! of their events.
+
+use crate::errors::{Error, Result};
+use rustls::internal::pemfile;
+use rustls::{AllowAnyAuthenticatedClient, Certificate, NoClientAuth, PrivateKey, RootCertStore};
+use simd_json::borrowed::{Object, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// Metadata key holding the peer certificate info
+pub(crate) const TLS: &str = "tls";
+
+/// TLS settings of a listening onramp
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// PEM file with the server certificate chain
+    pub cert: String,
+    /// PEM file with the private key of the server certificate
+    pub key: String,
+    /// PEM file with the CAs client certificates have to be signed by,
+    /// clients without a valid certificate are rejected if this is set
+    #[serde(default)]
+    pub client_ca: Option<String>,
+}
+
+/// TLS settings of a connecting offramp
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    /// PEM file with the CAs the server certificate has to be signed by
+    pub ca: String,
+    /// Server name to send and verify, defaults to the host
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// PEM file with a client certificate chain
+    #[serde(default)]
+    pub cert: Option<String>,
+    /// PEM file with the private key of the client certificate
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::from(format!("Failed to open {}: {}", path, e)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let certs = pemfile::certs(&mut open(path)?)
+        .map_err(|_| Error::from(format!("Invalid certificates in {}", path)))?;
+    if certs.is_empty() {
+        Err(format!("No certificates found in {}", path).into())
+    } else {
+        Ok(certs)
+    }
+}
+
+fn load_key(path: &str) -> Result<PrivateKey> {
+    let invalid = |_| Error::from(format!("Invalid private key in {}", path));
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(path)?).map_err(invalid)?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(path)?).map_err(invalid)?;
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| format!("No private key found in {}", path).into())
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let (valid, _invalid) = roots
+        .add_pem_file(&mut open(path)?)
+        .map_err(|_| Error::from(format!("Invalid CA certificates in {}", path)))?;
+    if valid == 0 {
+        Err(format!("No CA certificates found in {}", path).into())
+    } else {
+        Ok(roots)
+    }
+}
+
+impl ServerConfig {
+    pub(crate) fn load(&self) -> Result<Arc<rustls::ServerConfig>> {
+        let verifier = if let Some(client_ca) = &self.client_ca {
+            AllowAnyAuthenticatedClient::new(load_roots(client_ca)?)
+        } else {
+            NoClientAuth::new()
+        };
+        let mut config = rustls::ServerConfig::new(verifier);
+        config.set_single_cert(load_certs(&self.cert)?, load_key(&self.key)?)?;
+        Ok(Arc::new(config))
+    }
+}
+
+impl ClientConfig {
+    pub(crate) fn load(&self) -> Result<Arc<rustls::ClientConfig>> {
+        let mut config = rustls::ClientConfig::new();
+        config.root_store = load_roots(&self.ca)?;
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                config.set_single_client_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            (None, None) => (),
+            _ => return Err("TLS client `cert` and `key` have to be set together".into()),
+        }
+        Ok(Arc::new(config))
+    }
+
+    /// Opens a client session for the configured domain or the given host
+    pub(crate) fn session(
+        &self,
+        config: &Arc<rustls::ClientConfig>,
+        host: &str,
+    ) -> Result<rustls::ClientSession> {
+        let domain = self.domain.as_deref().unwrap_or(host);
+        let domain = webpki::DNSNameRef::try_from_ascii_str(domain)
+            .map_err(|_| Error::from(format!("Invalid TLS server name {}", domain)))?;
+        Ok(rustls::ClientSession::new(config, domain))
+    }
+}
+
+const SEQUENCE: u8 = 0x30;
+const OID: u8 = 0x06;
+const VERSION: u8 = 0xa0;
+
+/// Splits a DER encoded value into tag, content and what follows it
+fn der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (tag, data) = data.split_first()?;
+    let (len, mut data) = data.split_first()?;
+    let len = if len & 0x80 == 0 {
+        usize::from(*len)
+    } else {
+        let n = usize::from(len & 0x7f);
+        if n == 0 || n > 4 || data.len() < n {
+            return None;
+        }
+        let (bytes, rest) = data.split_at(n);
+        data = rest;
+        bytes.iter().fold(0, |len, b| len << 8 | usize::from(*b))
+    };
+    if data.len() < len {
+        None
+    } else {
+        Some((*tag, &data[..len], &data[len..]))
+    }
+}
+
+fn oid_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        _ => {
+            // anything else in dotted notation
+            let mut parts = Vec::new();
+            let mut v = 0_u64;
+            for b in oid {
+                v = v << 7 | u64::from(b & 0x7f);
+                if b & 0x80 == 0 {
+                    // the first byte holds the first two components
+                    if parts.is_empty() {
+                        parts.push((v / 40).min(2));
+                        parts.push(v - parts[0] * 40);
+                    } else {
+                        parts.push(v);
+                    }
+                    v = 0;
+                }
+            }
+            parts
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(".")
+        }
+    }
+}
+
+/// Turns a distinguished name into a record of its attributes
+fn name(mut data: &[u8]) -> Value<'static> {
+    let mut o = Object::new();
+    while let Some((_, mut set, rest)) = der(data) {
+        data = rest;
+        while let Some((_, attr, rest)) = der(set) {
+            set = rest;
+            if let Some((OID, oid, value)) = der(attr) {
+                if let Some((_, value, _)) = der(value) {
+                    o.insert(
+                        oid_name(oid).into(),
+                        Value::from(String::from_utf8_lossy(value).to_string()),
+                    );
+                }
+            }
+        }
+    }
+    Value::from(o)
+}
+
+/// Subject and issuer of a DER encoded certificate
+fn subject_and_issuer(cert: &[u8]) -> Option<(Value<'static>, Value<'static>)> {
+    let (_, cert, _) = der(cert)?;
+    let (tag, tbs, _) = der(cert)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, _, rest) = der(tbs)?;
+    // the version is optional
+    let tbs = if tag == VERSION { rest } else { tbs };
+    let (_, _, rest) = der(tbs)?; // serial number
+    let (_, _, rest) = der(rest)?; // signature algorithm
+    let (_, issuer, rest) = der(rest)?;
+    let (_, _, rest) = der(rest)?; // validity
+    let (_, subject, _) = der(rest)?;
+    Some((name(subject), name(issuer)))
+}
+
+/// Metadata describing the peer certificate of a session
+pub(crate) fn peer_meta(session: &dyn rustls::Session) -> Option<Value<'static>> {
+    let certs = session.get_peer_certificates()?;
+    let (subject, issuer) = subject_and_issuer(&certs.first()?.0)?;
+    let mut tls = Object::with_capacity(2);
+    tls.insert("subject".into(), subject);
+    tls.insert("issuer".into(), issuer);
+    let mut meta = Object::with_capacity(1);
+    meta.insert(TLS.into(), Value::from(tls));
+    Some(Value::from(meta))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn der_value(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut res = vec![tag];
+        if content.len() < 0x80 {
+            res.push(content.len() as u8);
+        } else {
+            res.push(0x82);
+            res.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+        res.extend_from_slice(content);
+        res
+    }
+
+    fn dn(attrs: &[(&[u8], &str)]) -> Vec<u8> {
+        let mut sets = Vec::new();
+        for (oid, value) in attrs {
+            let mut attr = der_value(OID, oid);
+            attr.extend(der_value(0x0c, value.as_bytes()));
+            sets.extend(der_value(0x31, &der_value(SEQUENCE, &attr)));
+        }
+        der_value(SEQUENCE, &sets)
+    }
+
+    #[test]
+    fn certificate_names() {
+        let mut tbs = der_value(VERSION, &der_value(0x02, &[2]));
+        tbs.extend(der_value(0x02, &[1, 2, 3, 4]));
+        tbs.extend(der_value(SEQUENCE, &der_value(OID, &[0x2a, 0x03])));
+        tbs.extend(dn(&[
+            (&[0x55, 0x04, 0x0a], "Snot Inc"),
+            (&[0x55, 0x04, 0x03], "Snot CA"),
+        ]));
+        tbs.extend(der_value(SEQUENCE, &[0; 200]));
+        tbs.extend(dn(&[
+            (&[0x55, 0x04, 0x03], "badger"),
+            (&[0x2b, 0x06, 0x01], "x"),
+        ]));
+        let cert = der_value(SEQUENCE, &der_value(SEQUENCE, &tbs));
+
+        let (subject, issuer) = subject_and_issuer(&cert).expect("no names");
+        let expected: Value = json!({"CN": "badger", "1.3.6.1": "x"}).into();
+        assert_eq!(expected, subject);
+        let expected: Value = json!({"CN": "Snot CA", "O": "Snot Inc"}).into();
+        assert_eq!(expected, issuer);
+        assert!(subject_and_issuer(&cert[..20]).is_none());
+    }
+
+    #[test]
+    fn missing_files() {
+        let config = ServerConfig {
+            cert: "/does/not/exist.pem".to_string(),
+            key: "/does/not/exist.key".to_string(),
+            client_ca: None,
+        };
+        assert!(config.load().is_err());
+    }
+}
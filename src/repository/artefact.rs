@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::codec;
+use crate::codec::{self, CodecConfig};
 use crate::errors::{Error, Result};
 use crate::metrics::RampReporter;
 use crate::offramp;
@@ -103,6 +103,13 @@ pub trait Artefact: Clone {
     fn servant_id(u: &TremorURL) -> Result<ServantId>;
 }
 
+/// Identifies the output of a pipeline another pipeline is linked to
+fn upstream_id(id: &TremorURL, output: &str) -> TremorURL {
+    let mut id = id.clone();
+    id.set_port(output.to_string());
+    id
+}
+
 #[async_trait]
 impl Artefact for Pipeline {
     type SpawnResult = pipeline::Addr;
@@ -146,6 +153,16 @@ impl Artefact for Pipeline {
                     Some(ResourceType::Pipeline) => {
                         info!("[Pipeline:{}] Linking port {} to {}", id, from, to);
                         if let Some(p) = system.reg.find_pipeline(&to).await? {
+                            // so acknowledgements can find their way back
+                            p.addr
+                                .send(pipeline::Msg::ConnectInput(
+                                    to.instance_port().unwrap_or_default().into(),
+                                    upstream_id(id, &from),
+                                    pipeline::Upstream::Pipeline(pipeline.clone()),
+                                ))
+                                .map_err(|e| -> Error {
+                                    format!("Could not send to pipeline: {:?}", e).into()
+                                })?;
                             pipeline
                                 .addr
                                 .clone()
@@ -188,6 +205,11 @@ impl Artefact for Pipeline {
                             .map_err(|_e| Error::from("Failed to unlink pipeline"))?;
                     }
                     Some(ResourceType::Pipeline) => {
+                        if let Some(p) = system.reg.find_pipeline(&to).await? {
+                            p.addr
+                                .send(pipeline::Msg::DisconnectInput(upstream_id(id, &from)))
+                                .map_err(|_e| Error::from("Failed to unlink pipeline"))?;
+                        }
                         pipeline
                             .addr
                             .send(pipeline::Msg::Disconnect(from.clone().into(), to))
@@ -232,9 +254,9 @@ impl Artefact for OfframpArtefact {
         //TODO: define offramp by config!
         let offramp = offramp::lookup(&self.binding_type, &self.config)?;
         let codec = if let Some(codec) = &self.codec {
-            codec::lookup(&codec)?
+            codec::from_config(codec)?
         } else {
-            codec::lookup(offramp.default_codec())?
+            codec::lookup(offramp.default_codec(), &None)?
         };
         let postprocessors = if let Some(postprocessors) = &self.postprocessors {
             postprocessors.clone()
@@ -255,6 +277,7 @@ impl Artefact for OfframpArtefact {
                     offramp,
                     postprocessors,
                     metrics_reporter,
+                    queue: self.queue.clone(),
                 },
             ))
             .await;
@@ -337,7 +360,7 @@ impl Artefact for OnrampArtefact {
         let codec = if let Some(codec) = &self.codec {
             codec.clone()
         } else {
-            stream.default_codec().to_string()
+            CodecConfig::from(stream.default_codec())
         };
         let preprocessors = if let Some(preprocessors) = &self.preprocessors {
             preprocessors.clone()
@@ -375,6 +398,13 @@ impl Artefact for OnrampArtefact {
                 //TODO: Check that we really have the right onramp!
                 if let Some(ResourceType::Pipeline) = to.resource_type() {
                     if let Some(pipeline) = system.reg.find_pipeline(&to).await? {
+                        // so acknowledgements can find their way back
+                        let input = to.instance_port().unwrap_or_default();
+                        pipeline.addr.send(pipeline::Msg::ConnectInput(
+                            input.into(),
+                            id.clone(),
+                            pipeline::Upstream::Onramp(onramp.clone()),
+                        ))?;
                         onramp
                             .send(onramp::Msg::Connect(vec![(to.clone(), pipeline)]))
                             .await;
@@ -405,6 +435,11 @@ impl Artefact for OnrampArtefact {
                 links.push(to.to_owned())
             }
             for (_port, pipeline_id) in mappings {
+                if let Some(pipeline) = system.reg.find_pipeline(&pipeline_id).await? {
+                    pipeline
+                        .addr
+                        .send(pipeline::Msg::DisconnectInput(id.clone()))?;
+                }
                 onramp
                     .send(onramp::Msg::Disconnect {
                         id: pipeline_id,
@@ -461,6 +496,7 @@ impl Artefact for Binding {
     ) -> Result<Self::LinkResult> {
         let mut pipelines: Vec<(TremorURL, TremorURL)> = Vec::new();
         let mut onramps: Vec<(TremorURL, TremorURL)> = Vec::new();
+        let mut outputs: Vec<(TremorURL, TremorURL)> = Vec::new();
         let mut res = self.clone();
         res.binding.links.clear();
         for (src, dsts) in self.binding.links.clone() {
@@ -499,9 +535,12 @@ impl Artefact for Binding {
                             | (Some(ResourceType::Pipeline), Some(ResourceType::Pipeline)) => {
                                 pipelines.push((from.clone(), to))
                             }
+                            (Some(ResourceType::Offramp), Some(ResourceType::Pipeline)) => {
+                                outputs.push((from.clone(), to))
+                            }
                             (_, _) => {
                                 return Err(
-                                    "links require the form of onramp -> pipeline or pipeline -> offramp or pipeline -> pipeline"
+                                    "links require the form of onramp -> pipeline or pipeline -> offramp or pipeline -> pipeline or offramp -> pipeline"
                                         .into(),
                                 );
                             }
@@ -594,6 +633,34 @@ impl Artefact for Binding {
                 )
                 .await?;
         }
+
+        for (from, to) in outputs {
+            if system.reg.find_pipeline(&to).await?.is_none() {
+                info!("Pipeline (dst) not found during binding process, binding {} to create a new instance.", to);
+                system.bind_pipeline(&to).await?;
+            }
+            if system.reg.find_offramp(&from).await?.is_none() {
+                info!(
+                    "Offramp not found during binding process, binding {} to create a new instance.",
+                    from
+                );
+                system.bind_offramp(&from).await?;
+            }
+            let port = from
+                .instance_port()
+                .ok_or_else(|| Error::from(format!("{} is missing an instnace port", from)))?;
+            if let (Some(offramp), Some(pipeline)) = (
+                system.reg.find_offramp(&from).await?,
+                system.reg.find_pipeline(&to).await?,
+            ) {
+                info!("Linking output {} of offramp {} to {}", port, from, to);
+                offramp.send(offramp::Msg::ConnectOutput {
+                    port: port.into(),
+                    id: to,
+                    addr: pipeline,
+                })?;
+            }
+        }
         res.mapping = Some(vec![(id.clone(), mappings)].into_iter().collect());
         Ok(res)
     }
@@ -650,6 +717,20 @@ impl Artefact for Binding {
                 }
             }
         }
+        for (from, tos) in &self.binding.links {
+            if from.resource_type() == Some(ResourceType::Offramp) {
+                if let (Some(offramp), Some(port)) =
+                    (system.reg.find_offramp(from).await?, from.instance_port())
+                {
+                    for to in tos {
+                        offramp.send(offramp::Msg::DisconnectOutput {
+                            port: port.clone().into(),
+                            id: to.clone(),
+                        })?;
+                    }
+                }
+            }
+        }
         Ok(true)
     }
 
@@ -96,6 +96,8 @@ impl Manager {
                         self.pipeline.send(pipeline::ManagerMsg::Stop).await;
                         info!("Stopping onramps...");
                         self.onramp.send(onramp::ManagerMsg::Stop).await;
+                        // Wait for the pipelines to write their snapshots
+                        self.pipeline_h.await;
                         break;
                     }
                     Err(e) => {
@@ -547,14 +549,16 @@ impl World {
         Err(format!("Binding {:?} not found.", id).into())
     }
 
-    /// Starts the runtime system
+    /// Starts the runtime system, pipelines snapshot their state to
+    /// `state_directory` if one is given
     pub async fn start(
         qsize: usize,
         storage_directory: Option<String>,
+        state_directory: Option<String>,
     ) -> Result<(Self, JoinHandle<()>)> {
         let (onramp_h, onramp) = onramp::Manager::new(qsize).start();
         let (offramp_h, offramp) = offramp::Manager::new(qsize).start();
-        let (pipeline_h, pipeline) = pipeline::Manager::new(qsize).start();
+        let (pipeline_h, pipeline) = pipeline::Manager::new(qsize, state_directory).start();
 
         let (system_h, system) = Manager {
             offramp,
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use pretty_assertions::assert_eq;
+use simd_json::prelude::*;
 use std::fs::File;
 use std::io::prelude::*;
 use tremor_pipeline;
@@ -49,6 +50,7 @@ macro_rules! test_cases {
                 let query_file = concat!("tests/queries/", stringify!($file), "/query.trickle");
                 let in_file = concat!("tests/queries/", stringify!($file), "/in.xz");
                 let out_file = concat!("tests/queries/", stringify!($file), "/out.xz");
+                let ports_file = concat!("tests/queries/", stringify!($file), "/ports.xz");
                 let module_path = ModulePath { mounts: vec![query_dir, "tremor-script/lib/".to_string()] };
 
                 println!("Loading query: {}", query_file);
@@ -63,6 +65,15 @@ macro_rules! test_cases {
                 let mut out_json = load_event_file(out_file)?;
 
                 out_json.reverse();
+                // Cases that emit on more than one port list the expected port per event
+                let mut out_ports = if std::path::Path::new(ports_file).exists() {
+                    println!("Loading expected ports: {}", ports_file);
+                    let mut ports = load_event_file(ports_file)?;
+                    ports.reverse();
+                    Some(ports)
+                } else {
+                    None
+                };
 
                 let mut results = Vec::new();
                 for (id, json) in in_json.into_iter().enumerate() {
@@ -79,7 +90,10 @@ macro_rules! test_cases {
                     results.append(&mut r);
                 }
                 assert_eq!(results.len(), out_json.len(), "Number of events differ error");
-                for (_, result) in results {
+                for (port, result) in results {
+                    if let Some(expected) = out_ports.as_mut().and_then(Vec::pop) {
+                        assert_eq!(Some(port.as_ref()), expected.as_str());
+                    }
                     for value in result.value_iter() {
                         if let Some(expected) = out_json.pop() {
                             assert_eq!(sorsorted_serialize(value)?, sorsorted_serialize(&expected)?);
@@ -120,6 +134,12 @@ test_cases!(
     window_by_two_scripted,
     window_by_two,
     window_size_tilted,
+    window_sliding_size,
+    window_sliding_time,
+    window_event_time,
+    window_session,
+    join_request_response,
+    order_by_limit,
     // Preprocessor + modules
     pp_win,
     pp_script,
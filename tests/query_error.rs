@@ -135,4 +135,6 @@ test_cases!(
     pp_embed_unrecognized_token3,
     pp_embed_unrecognized_token4,
     pp_embed_unrecognized_token5,
+    watermark_without_timestamp,
+    order_by_sliding_window,
 );
@@ -142,6 +142,7 @@ test_cases!(
     pp_alias3,
     // regression
     empty_array_pattern,
+    session_ident,
     // TODO
     // const_in_const_lookup,
     //INSERT
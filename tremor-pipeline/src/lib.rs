@@ -255,6 +255,75 @@ pub enum SignalKind {
     // Step, TODO ( into, over, to next breakpoint )
     /// Control
     Control,
+    /// Periodic tick, lets operators act on the passing of time
+    Tick,
+}
+
+/// Acknowledgement of an event, sent back to its onramp as contraflow
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum CBAction {
+    /// The event was delivered
+    Ack,
+    /// The event could not be delivered and should be replayed
+    Fail,
+}
+
+/// Metadata key of the acknowledgement in an insight
+const CB: &str = "cb";
+
+/// Id and origin of an event, identifying it in acknowledgements
+pub type EventKey = (u64, Option<EventOriginUri>);
+
+impl CBAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Ack => "ack",
+            Self::Fail => "fail",
+        }
+    }
+}
+
+impl Event {
+    /// A signal of the given kind
+    pub fn signal(kind: SignalKind, ingest_ns: u64, id: u64) -> Self {
+        Self {
+            id,
+            data: (Value::null(), Value::object()).into(),
+            ingest_ns,
+            origin_uri: None,
+            is_batch: false,
+            kind: Some(kind),
+        }
+    }
+
+    /// An insight acknowledging or failing the event with the given id
+    /// and origin
+    pub fn cb(
+        action: CBAction,
+        ingest_ns: u64,
+        id: u64,
+        origin_uri: Option<EventOriginUri>,
+    ) -> Self {
+        let mut meta = simd_json::borrowed::Object::with_capacity(1);
+        meta.insert(CB.into(), Value::from(action.as_str()));
+        Self {
+            id,
+            data: (Value::null(), meta).into(),
+            ingest_ns,
+            origin_uri,
+            kind: None,
+            is_batch: false,
+        }
+    }
+
+    /// The acknowledgement this insight carries, if any
+    pub fn cb_action(&self) -> Option<CBAction> {
+        match self.data.suffix().meta().get(CB).and_then(Value::as_str) {
+            Some("ack") => Some(CBAction::Ack),
+            Some("fail") => Some(CBAction::Fail),
+            _ => None,
+        }
+    }
 }
 
 /// Configuration for a node
@@ -316,9 +385,11 @@ impl Operator for OperatorNode {
     fn handles_signal(&self) -> bool {
         self.op.handles_signal()
     }
+    fn needs_ticks(&self) -> bool {
+        self.op.needs_ticks()
+    }
     fn on_signal(&mut self, signal: &mut Event) -> Result<Vec<(Cow<'static, str>, Event)>> {
-        self.op.on_signal(signal)?;
-        Ok(vec![])
+        self.op.on_signal(signal)
     }
 
     fn handles_contraflow(&self) -> bool {
@@ -327,6 +398,15 @@ impl Operator for OperatorNode {
     fn on_contraflow(&mut self, contraevent: &mut Event) {
         self.op.on_contraflow(contraevent)
     }
+    fn on_cb(&mut self, insight: Event) -> Vec<Event> {
+        self.op.on_cb(insight)
+    }
+    fn holds(&self, id: u64, origin_uri: &Option<EventOriginUri>) -> bool {
+        self.op.holds(id, origin_uri)
+    }
+    fn take_cbs(&mut self) -> Vec<Event> {
+        self.op.take_cbs()
+    }
     fn metrics(
         &self,
         tags: HashMap<Cow<'static, str>, Value<'static>>,
@@ -338,6 +418,14 @@ impl Operator for OperatorNode {
     fn skippable(&self) -> bool {
         self.op.skippable()
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        self.op.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        self.op.restore(snapshot)
+    }
 }
 
 // TODO We need an actual operator registry ...
@@ -864,13 +952,79 @@ impl ExecutableGraph {
             }
         }
     }
-    /// Enque a contraflow insight
-    pub fn contraflow(&mut self, mut insight: Event) -> Event {
+    /// Enque a contraflow insight, `ack` and `fail` insights are mapped
+    /// back to the events they cover by the operators they pass
+    pub fn contraflow(&mut self, insight: Event) -> Vec<Event> {
+        let mut insights = vec![insight];
         for idx in &self.contraflow {
             let op = unsafe { self.graph.get_unchecked_mut(*idx) }; // We know this exists
-            op.on_contraflow(&mut insight);
+            let mut next = Vec::with_capacity(insights.len());
+            for mut insight in insights {
+                op.on_contraflow(&mut insight);
+                if insight.cb_action().is_some() {
+                    next.append(&mut op.on_cb(insight));
+                } else {
+                    next.push(insight);
+                }
+            }
+            insights = next;
+        }
+        insights
+    }
+    /// If an operator holds on to an event that was enqueued
+    pub fn holds(&self, id: u64, origin_uri: &Option<EventOriginUri>) -> bool {
+        self.contraflow.iter().any(|idx| {
+            self.graph
+                .get(*idx)
+                .map_or(false, |op| op.holds(id, origin_uri))
+        })
+    }
+    /// Takes the `ack`s of events operators let go of without emitting them
+    pub fn take_cbs(&mut self) -> Vec<Event> {
+        let mut cbs = Vec::new();
+        for idx in &self.contraflow {
+            if let Some(op) = self.graph.get_mut(*idx) {
+                cbs.append(&mut op.take_cbs());
+            }
+        }
+        cbs
+    }
+    /// Captures the state of all operators in the graph, keyed by node id,
+    /// so the graph can be restored after a restart
+    pub fn snapshot(&self) -> Result<Value<'static>> {
+        let mut nodes = simd_json::borrowed::Object::new();
+        for (node, state) in self.graph.iter().zip(self.state.ops.iter()) {
+            let mut snapshot = simd_json::borrowed::Object::new();
+            if !state.is_null() {
+                snapshot.insert("state".into(), state.clone());
+            }
+            if let Some(op) = node.snapshot()? {
+                snapshot.insert("op".into(), op);
+            }
+            if !snapshot.is_empty() {
+                nodes.insert(node.id.to_string().into(), Value::from(snapshot));
+            }
         }
-        insight
+        Ok(Value::from(nodes))
+    }
+    /// Restores the operators of the graph from a snapshot taken by
+    /// `snapshot`, nodes not present in the snapshot are left untouched
+    pub fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        for (node, state) in self.graph.iter_mut().zip(self.state.ops.iter_mut()) {
+            if let Some(snapshot) = snapshot.get(node.id.as_ref()) {
+                if let Some(s) = snapshot.get("state") {
+                    *state = s.clone_static();
+                }
+                if let Some(op) = snapshot.get("op") {
+                    node.restore(op)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// If any operator of the graph relies on tick signals
+    pub fn needs_ticks(&self) -> bool {
+        self.graph.iter().any(OperatorNode::needs_ticks)
     }
     /// Enque a signal
     pub fn enqueue_signal(&mut self, signal: Event, returns: &mut Returns) -> Result<()> {
@@ -1037,6 +1191,18 @@ mod test {
         }
     }
 
+    #[test]
+    fn cb_insight() {
+        let e = Event::cb(CBAction::Fail, 1, 42, None);
+        assert_eq!(42, e.id);
+        assert_eq!(Some(CBAction::Fail), e.cb_action());
+        assert_eq!(
+            Some(CBAction::Ack),
+            Event::cb(CBAction::Ack, 1, 42, None).cb_action()
+        );
+        assert_eq!(None, Event::default().cb_action());
+    }
+
     #[test]
     fn simple_graph_exec() {
         let c = slurp("tests/configs/simple_graph.yaml");
@@ -25,7 +25,7 @@ use crate::errors::Result;
 use halfbrown::HashMap;
 use regex::Regex;
 use std::borrow::Cow;
-use tremor_script::Value;
+use tremor_script::{EventOriginUri, Value};
 
 /// The operator trait, this reflects the functionality of an operator in the
 /// pipeline graph
@@ -45,6 +45,12 @@ pub trait Operator: std::fmt::Debug + Send {
     fn handles_signal(&self) -> bool {
         false
     }
+    /// If the operator relies on periodic tick signals to make progress
+    /// without events, defaults to `false`. Only pipelines with such an
+    /// operator are sent ticks.
+    fn needs_ticks(&self) -> bool {
+        false
+    }
     /// Handle singal events, defaults to returning an empty vector.
     fn on_signal(&mut self, signal: &mut Event) -> Result<Vec<(Cow<'static, str>, Event)>> {
         // Make the trait signature nicer
@@ -62,6 +68,26 @@ pub trait Operator: std::fmt::Debug + Send {
         // Make the trait signature nicer
     }
 
+    /// Maps an `ack` or `fail` of an event this operator emitted to the
+    /// events it was built from, defaults to passing it on unchanged. Only
+    /// called if `handles_contraflow` is `true`.
+    fn on_cb(&mut self, insight: Event) -> Vec<Event> {
+        vec![insight]
+    }
+
+    /// If the operator holds on to an event to emit it later, defaults to
+    /// `false`. Held events are acknowledged once what they were emitted as is.
+    fn holds(&self, id: u64, origin_uri: &Option<EventOriginUri>) -> bool {
+        false
+    }
+
+    /// Takes the `ack`s of held events the operator let go of without
+    /// emitting them, defaults to none. Only called if `handles_contraflow`
+    /// is `true`.
+    fn take_cbs(&mut self) -> Vec<Event> {
+        Vec::new()
+    }
+
     /// Returns metrics for this operator, defaults to no extra metrics.
     fn metrics(
         &self,
@@ -76,6 +102,18 @@ pub trait Operator: std::fmt::Debug + Send {
     fn skippable(&self) -> bool {
         false
     }
+
+    /// Captures the state the operator needs to resume after a restart,
+    /// defaults to `None` for stateless operators.
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        Ok(None)
+    }
+
+    /// Restores the operator from a snapshot taken by `snapshot`, defaults
+    /// to a noop.
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Initialisable trait that can be turned from a `NodeConfig`
@@ -14,6 +14,8 @@
 
 use crate::config::dflt;
 use crate::op::prelude::*;
+use crate::EventKey;
+use std::collections::HashSet;
 use tremor_script::prelude::*;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,6 +38,12 @@ pub struct Batch {
     pub first_ns: u64,
     pub id: Cow<'static, str>,
     pub event_id: u64,
+    /// Events in the current batch
+    pub held: Vec<EventKey>,
+    /// Events in emitted batches that are not yet acknowledged, by batch id
+    pub covers: HashMap<u64, Vec<EventKey>>,
+    /// All events held in the current or emitted batches
+    pub holding: HashSet<EventKey>,
 }
 
 pub fn empty() -> LineValue {
@@ -58,12 +66,37 @@ if let Some(map) = &node.config {
         max_delay_ns,
         first_ns: 0,
         id: node.id.clone(),
+        held: Vec::new(),
+        covers: HashMap::new(),
+        holding: HashSet::new(),
     }))
 } else {
     Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
 
 }});
 
+impl Batch {
+    /// Emits the current batch and remembers the events it covers
+    fn flush(&mut self) -> Event {
+        //TODO: This is ugly
+        let mut data = empty();
+        std::mem::swap(&mut data, &mut self.data);
+        self.len = 0;
+        self.covers
+            .insert(self.event_id, std::mem::take(&mut self.held));
+        let event = Event {
+            id: self.event_id,
+            data,
+            ingest_ns: self.first_ns,
+            origin_uri: None,
+            kind: None,
+            is_batch: true,
+        };
+        self.event_id += 1;
+        event
+    }
+}
+
 impl Operator for Batch {
     fn on_event(
         &mut self,
@@ -77,8 +110,12 @@ impl Operator for Batch {
             data,
             ingest_ns,
             is_batch,
+            origin_uri,
             ..
         } = event;
+        let key = (id, origin_uri);
+        self.holding.insert(key.clone());
+        self.held.push(key);
         self.data.consume(
             data,
             move |this: &mut ValueAndMeta<'static>, other: ValueAndMeta<'static>| -> Result<()> {
@@ -116,48 +153,81 @@ impl Operator for Batch {
             _ => self.len == self.config.count,
         };
         if flush {
-            //TODO: This is ugly
-            let mut data = empty();
-            std::mem::swap(&mut data, &mut self.data);
-            self.len = 0;
-            let event = Event {
-                id: self.event_id,
-                data,
-                ingest_ns: self.first_ns,
-                origin_uri: None,
-                kind: None,
-                is_batch: true,
-            };
-            self.event_id += 1;
-            Ok(vec![("out".into(), event)])
+            Ok(vec![("out".into(), self.flush())])
         } else {
             Ok(vec![])
         }
     }
 
+    fn handles_contraflow(&self) -> bool {
+        true
+    }
+
+    fn on_cb(&mut self, insight: Event) -> Vec<Event> {
+        // batches we emitted have no origin
+        let covered = if insight.origin_uri.is_none() {
+            self.covers.remove(&insight.id)
+        } else {
+            None
+        };
+        if let (Some(covered), Some(action)) = (covered, insight.cb_action()) {
+            covered
+                .into_iter()
+                .map(|key| {
+                    self.holding.remove(&key);
+                    let (id, origin_uri) = key;
+                    Event::cb(action, insight.ingest_ns, id, origin_uri)
+                })
+                .collect()
+        } else {
+            vec![insight]
+        }
+    }
+
+    fn holds(&self, id: u64, origin_uri: &Option<EventOriginUri>) -> bool {
+        self.holding.contains(&(id, origin_uri.clone()))
+    }
+
+    // Events held in the current batch are not part of the snapshot since
+    // their onramps can't acknowledge them after a restart either
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        let mut snapshot = Object::with_capacity(4);
+        snapshot.insert_nocheck("data".into(), self.data.suffix().value().clone_static());
+        snapshot.insert_nocheck("len".into(), self.len.into());
+        snapshot.insert_nocheck("first_ns".into(), self.first_ns.into());
+        snapshot.insert_nocheck("event_id".into(), self.event_id.into());
+        Ok(Some(Value::from(snapshot)))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let data = snapshot
+            .get("data")
+            .filter(|data| data.is_array())
+            .ok_or_else(|| Error::from(format!("Invalid snapshot for batch {}", self.id)))?
+            .clone_static();
+        self.data = LineValue::new(vec![], |_| ValueAndMeta::from(data));
+        self.len = snapshot.get("len").and_then(Value::as_usize).unwrap_or(0);
+        self.first_ns = snapshot
+            .get("first_ns")
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        self.event_id = snapshot
+            .get("event_id")
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        Ok(())
+    }
+
     fn handles_signal(&self) -> bool {
         true
     }
 
     fn on_signal(&mut self, signal: &mut Event) -> Result<Vec<(Cow<'static, str>, Event)>> {
         if let Some(delay_ns) = self.max_delay_ns {
-            if signal.ingest_ns - self.first_ns > delay_ns {
+            if self.len > 0 && signal.ingest_ns.saturating_sub(self.first_ns) > delay_ns {
                 // We don't want to modify the original signal we clone it to
                 // create a new event.
-
-                let mut data = empty();
-                std::mem::swap(&mut data, &mut self.data);
-                self.len = 0;
-                let event = Event {
-                    id: self.event_id,
-                    data,
-                    ingest_ns: self.first_ns,
-                    origin_uri: None,
-                    kind: None,
-                    is_batch: true,
-                };
-                self.event_id += 1;
-                Ok(vec![("out".into(), event)])
+                Ok(vec![("out".into(), self.flush())])
             } else {
                 Ok(vec![])
             }
@@ -170,6 +240,7 @@ impl Operator for Batch {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::CBAction;
     use tremor_script::Value;
 
     #[test]
@@ -185,6 +256,9 @@ mod test {
             data: empty(),
             len: 0,
             id: "badger".into(),
+            held: Vec::new(),
+            covers: HashMap::new(),
+            holding: HashSet::new(),
         };
         let event1 = Event {
             is_batch: false,
@@ -251,6 +325,9 @@ mod test {
             data: empty(),
             len: 0,
             id: "badger".into(),
+            held: Vec::new(),
+            covers: HashMap::new(),
+            holding: HashSet::new(),
         };
         let event1 = Event {
             is_batch: false,
@@ -339,6 +416,9 @@ mod test {
             data: empty(),
             len: 0,
             id: "badger".into(),
+            held: Vec::new(),
+            covers: HashMap::new(),
+            holding: HashSet::new(),
         };
         let event1 = Event {
             is_batch: false,
@@ -401,4 +481,99 @@ mod test {
             .expect("failed to run piepeline");
         assert_eq!(r.len(), 0);
     }
+
+    #[test]
+    fn cb() {
+        let mut op = Batch {
+            config: Config {
+                count: 2,
+                timeout: None,
+            },
+            event_id: 0,
+            first_ns: 0,
+            max_delay_ns: None,
+            data: empty(),
+            len: 0,
+            id: "badger".into(),
+            held: Vec::new(),
+            covers: HashMap::new(),
+            holding: HashSet::new(),
+        };
+        let origin_uri = Some(EventOriginUri {
+            scheme: "tremor-kafka".to_string(),
+            host: "localhost".to_string(),
+            port: None,
+            path: vec!["snot".to_string(), "0".to_string(), "42".to_string()],
+        });
+        let mut state = Value::null();
+        for id in 1..=2 {
+            let event = Event {
+                is_batch: false,
+                id,
+                ingest_ns: 1,
+                origin_uri: origin_uri.clone(),
+                data: Value::from("snot").into(),
+                kind: None,
+            };
+            op.on_event("in", &mut state, event)
+                .expect("could not run pipeline");
+        }
+        assert!(op.holds(1, &origin_uri));
+        assert!(!op.holds(1, &None));
+
+        // a cb for someone else's event is passed on
+        let other = op.on_cb(Event::cb(CBAction::Ack, 2, 0, origin_uri.clone()));
+        assert_eq!(1, other.len());
+        assert_eq!(0, other[0].id);
+
+        let acks = op.on_cb(Event::cb(CBAction::Fail, 2, 0, None));
+        let ids: Vec<_> = acks.iter().map(|e| e.id).collect();
+        assert_eq!(vec![1, 2], ids);
+        assert!(acks
+            .iter()
+            .all(|e| e.cb_action() == Some(CBAction::Fail) && e.origin_uri == origin_uri));
+        assert!(!op.holds(1, &origin_uri));
+        assert!(op.covers.is_empty());
+    }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let batch = || Batch {
+            config: Config {
+                count: 2,
+                timeout: None,
+            },
+            event_id: 0,
+            first_ns: 0,
+            max_delay_ns: None,
+            data: empty(),
+            len: 0,
+            id: "badger".into(),
+            held: Vec::new(),
+            covers: HashMap::new(),
+            holding: HashSet::new(),
+        };
+        let event = |id, data: &'static str| Event {
+            is_batch: false,
+            id,
+            ingest_ns: id,
+            origin_uri: None,
+            data: Value::from(data).into(),
+            kind: None,
+        };
+        let mut state = Value::null();
+        let mut op = batch();
+        op.on_event("in", &mut state, event(1, "snot"))?;
+        let snapshot = op.snapshot()?.expect("no snapshot");
+
+        let mut op = batch();
+        op.restore(&snapshot)?;
+        assert_eq!(1, op.len);
+        assert_eq!(1, op.first_ns);
+        let mut r = op.on_event("in", &mut state, event(2, "badger"))?;
+        let (_, batched) = r.pop().expect("no batch emitted");
+        let values: Vec<&Value> = batched.value_iter().collect();
+        assert_eq!(values, vec![&Value::from("snot"), &Value::from("badger")]);
+        Ok(())
+    }
 }
@@ -15,10 +15,14 @@
 // [x] PERF0001: handle select without grouping or windows easier.
 
 use crate::errors::{Error, ErrorKind, Result};
-use crate::{Event, Operator};
+use crate::{CBAction, Event, EventKey, Operator};
 use halfbrown::HashMap;
+use indexmap::IndexMap;
 use simd_json::borrowed::Value;
 use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::mem;
 use std::sync::Arc;
 use tremor_script::interpreter::Env;
@@ -27,14 +31,19 @@ use tremor_script::utils::sorsorted_serialize;
 use tremor_script::{
     self,
     ast::{
-        InvokeAggrFn, Select, SelectStmt, WindowDecl, ARGS_CONST_ID, GROUP_CONST_ID,
-        WINDOW_CONST_ID,
+        InvokeAggrFn, Join, JoinKind, NodeMetas, OrderDirection, Select, SelectStmt, WindowDecl,
+        ARGS_CONST_ID, GROUP_CONST_ID, WINDOW_CONST_ID,
     },
     prelude::*,
     query::StmtRental,
 };
 
 pub type Aggrs<'script> = Vec<InvokeAggrFn<'script>>;
+/// The arguments an event passed to each of the aggregate functions
+pub type AggrArgs = Vec<Vec<Value<'static>>>;
+/// Emitted events that aren't acknowledged within this time fail the
+/// events they cover
+const COVERS_TTL_NS: u64 = 300_000_000_000;
 
 #[derive(Debug, Clone)]
 pub struct GroupData<'groups> {
@@ -85,6 +94,73 @@ pub struct TrickleSelect {
     pub id: String,
     pub select: rentals::Select,
     pub windows: Vec<Window>,
+    /// Events aggregated in each window, by group
+    pub held: Vec<HashMap<String, Vec<EventKey>>>,
+    /// Events covered by emitted windows, by the emitted event
+    pub covers: HashMap<EventKey, Vec<EventKey>>,
+    /// Events of evicted groups and events joins stopped buffering,
+    /// covered by the next emitted window
+    pub orphans: Vec<EventKey>,
+    /// When the events in `covers` were emitted, oldest first
+    pub covered_at: VecDeque<(u64, EventKey)>,
+    /// How often each held event is aggregated
+    pub holding: HashMap<EventKey, usize>,
+    /// Acknowledgements of held events that were let go of without being
+    /// emitted
+    pub cbs: Vec<Event>,
+    /// Events waiting for a match if the select has a join
+    pub join: Option<JoinState>,
+    /// The latest event of each group in the open window of ordered
+    /// selects and sessions, groups their window closes for without an
+    /// event of their own are emitted with it
+    pub last_events: HashMap<String, Event>,
+}
+
+/// The events of both sides of a join that wait for a match, by key.
+/// Like held events they are not part of snapshots.
+#[derive(Debug, Default)]
+pub struct JoinState {
+    /// The window events wait for a match in, tables have none
+    window: Option<WindowImpl>,
+    /// Events of the selected stream and if they were matched
+    left: IndexMap<String, Vec<(Event, bool)>>,
+    /// Events of the joined stream, tables only keep the latest one
+    right: IndexMap<String, Vec<Event>>,
+}
+
+/// Orders the keys of an `order by` clause, numbers compare by value and
+/// strings lexically, keys that can't be compared keep their order
+fn cmp_order_keys(l: &Value, r: &Value) -> Ordering {
+    if let (Some(l), Some(r)) = (l.as_str(), r.as_str()) {
+        l.cmp(r)
+    } else if let (Some(l), Some(r)) = (l.as_i64(), r.as_i64()) {
+        l.cmp(&r)
+    } else if let (Some(l), Some(r)) = (l.cast_f64(), r.cast_f64()) {
+        l.partial_cmp(&r).unwrap_or(Ordering::Equal)
+    } else {
+        Ordering::Equal
+    }
+}
+
+/// Joins two events into one with `left` and `right` fields, the joined
+/// event takes the id of `event` and the metadata of `left`
+fn joined(event: &Event, left: &Event, right: Option<&Event>) -> Event {
+    let mut data = Object::with_capacity(2);
+    data.insert_nocheck("left".into(), left.data.suffix().value().clone_static());
+    data.insert_nocheck(
+        "right".into(),
+        right.map_or_else(Value::null, |right| {
+            right.data.suffix().value().clone_static()
+        }),
+    );
+    Event {
+        id: event.id,
+        ingest_ns: event.ingest_ns,
+        origin_uri: event.origin_uri.clone(),
+        is_batch: false,
+        kind: None,
+        data: (Value::from(data), left.data.suffix().meta().clone_static()).into(),
+    }
 }
 
 pub trait WindowTrait: std::fmt::Debug {
@@ -126,9 +202,181 @@ impl Window {
 pub enum WindowImpl {
     TumblingCountBased(TumblingWindowOnNumber),
     TumblingTimeBased(TumblingWindowOnTime),
+    SlidingCountBased(SlidingWindowOnNumber),
+    SlidingTimeBased(SlidingWindowOnTime),
+    Session(SessionWindow),
     No(NoWindow),
 }
 
+impl WindowImpl {
+    /// Sliding windows emit on every event and retract the
+    /// events that leave them instead of being reset.
+    pub fn is_sliding(&self) -> bool {
+        matches!(self, Self::SlidingCountBased(_) | Self::SlidingTimeBased(_))
+    }
+
+    /// Session windows close once their group was inactive for a while.
+    pub fn is_session(&self) -> bool {
+        matches!(self, Self::Session(_))
+    }
+
+    /// Windows on event time track a watermark and reject late events.
+    pub fn has_watermark(&self) -> bool {
+        match self {
+            Self::TumblingTimeBased(w) => w.watermark.is_some(),
+            Self::SlidingTimeBased(w) => w.watermark.is_some(),
+            _ => false,
+        }
+    }
+
+    /// If the window is a session that was inactive for longer than its
+    /// gap at `now`.
+    pub fn expired(&self, now: u64) -> bool {
+        if let Self::Session(w) = self {
+            w.expired(now)
+        } else {
+            false
+        }
+    }
+
+    /// Records the aggregate arguments of the event that was last
+    /// accumulated into the window and returns the arguments of all
+    /// events that slid out of the window as a result.
+    pub fn slide(&mut self, args: AggrArgs) -> Vec<AggrArgs> {
+        match self {
+            Self::SlidingTimeBased(w) => w.slide(args),
+            Self::SlidingCountBased(w) => w.slide(args),
+            Self::TumblingTimeBased(_)
+            | Self::TumblingCountBased(_)
+            | Self::Session(_)
+            | Self::No(_) => vec![],
+        }
+    }
+
+    /// The aggregate arguments of the events still inside a sliding window
+    pub fn retained(&self) -> Vec<&AggrArgs> {
+        match self {
+            Self::SlidingTimeBased(w) => w.buffer.iter().map(|(_, args)| args).collect(),
+            Self::SlidingCountBased(w) => w.buffer.iter().collect(),
+            Self::TumblingTimeBased(_)
+            | Self::TumblingCountBased(_)
+            | Self::Session(_)
+            | Self::No(_) => vec![],
+        }
+    }
+
+    /// Captures the position of the window and, for sliding windows,
+    /// the aggregate arguments of the events inside it.
+    pub fn snapshot(&self) -> Value<'static> {
+        let mut snapshot = Object::with_capacity(2);
+        match self {
+            Self::TumblingTimeBased(w) => {
+                let next_window = w.next_window.map_or_else(Value::null, Value::from);
+                snapshot.insert_nocheck("next_window".into(), next_window);
+                if let Some(watermark) = &w.watermark {
+                    snapshot.insert_nocheck("max_time".into(), watermark.max_time.into());
+                }
+            }
+            Self::TumblingCountBased(w) => {
+                snapshot.insert_nocheck("count".into(), w.count.into());
+            }
+            Self::SlidingTimeBased(w) => {
+                let buffer: Vec<Value> = w
+                    .buffer
+                    .iter()
+                    .map(|(time, args)| Value::from(vec![Value::from(*time), args_to_value(args)]))
+                    .collect();
+                snapshot.insert_nocheck("current".into(), w.current.into());
+                snapshot.insert_nocheck("buffer".into(), Value::from(buffer));
+                if let Some(watermark) = &w.watermark {
+                    snapshot.insert_nocheck("max_time".into(), watermark.max_time.into());
+                }
+            }
+            Self::SlidingCountBased(w) => {
+                let buffer: Vec<Value> = w.buffer.iter().map(args_to_value).collect();
+                snapshot.insert_nocheck("buffer".into(), Value::from(buffer));
+            }
+            Self::Session(w) => {
+                let last = w.last.map_or_else(Value::null, Value::from);
+                snapshot.insert_nocheck("last".into(), last);
+                let seen = w.seen.map_or_else(Value::null, Value::from);
+                snapshot.insert_nocheck("seen".into(), seen);
+            }
+            Self::No(w) => {
+                snapshot.insert_nocheck("open".into(), w.open.into());
+            }
+        }
+        Value::from(snapshot)
+    }
+
+    /// Restores the window from a snapshot taken by `snapshot`.
+    pub fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        self.restore_(snapshot)
+            .ok_or_else(|| Error::from("Invalid window snapshot"))
+    }
+
+    fn restore_(&mut self, snapshot: &Value) -> Option<()> {
+        match self {
+            Self::TumblingTimeBased(w) => {
+                w.next_window = snapshot.get("next_window")?.as_u64();
+                if let Some(watermark) = &mut w.watermark {
+                    watermark.max_time = snapshot.get("max_time")?.as_u64()?;
+                }
+            }
+            Self::TumblingCountBased(w) => {
+                w.count = snapshot.get("count")?.as_u64()?;
+            }
+            Self::SlidingTimeBased(w) => {
+                w.current = snapshot.get("current")?.as_u64()?;
+                if let Some(watermark) = &mut w.watermark {
+                    watermark.max_time = snapshot.get("max_time")?.as_u64()?;
+                }
+                w.buffer = snapshot
+                    .get("buffer")?
+                    .as_array()?
+                    .iter()
+                    .map(|entry| {
+                        let time = entry.get_idx(0)?.as_u64()?;
+                        Some((time, args_from_value(entry.get_idx(1)?)?))
+                    })
+                    .collect::<Option<_>>()?;
+            }
+            Self::SlidingCountBased(w) => {
+                w.buffer = snapshot
+                    .get("buffer")?
+                    .as_array()?
+                    .iter()
+                    .map(args_from_value)
+                    .collect::<Option<_>>()?;
+            }
+            Self::Session(w) => {
+                w.last = snapshot.get("last")?.as_u64();
+                w.seen = snapshot.get("seen").and_then(Value::as_u64).or(w.last);
+            }
+            Self::No(w) => {
+                w.open = snapshot.get("open")?.as_bool()?;
+            }
+        }
+        Some(())
+    }
+}
+
+fn args_to_value(args: &AggrArgs) -> Value<'static> {
+    Value::from(
+        args.iter()
+            .map(|argv| Value::from(argv.clone()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn args_from_value(value: &Value) -> Option<AggrArgs> {
+    value
+        .as_array()?
+        .iter()
+        .map(|argv| Some(argv.as_array()?.iter().map(Value::clone_static).collect()))
+        .collect()
+}
+
 impl std::default::Default for WindowImpl {
     fn default() -> Self {
         TumblingWindowOnTime {
@@ -136,6 +384,7 @@ impl std::default::Default for WindowImpl {
             next_window: None,
             script: None,
             ttl: None,
+            watermark: None,
         }
         .into()
     }
@@ -146,6 +395,9 @@ impl WindowTrait for WindowImpl {
         match self {
             Self::TumblingTimeBased(w) => w.on_event(event),
             Self::TumblingCountBased(w) => w.on_event(event),
+            Self::SlidingTimeBased(w) => w.on_event(event),
+            Self::SlidingCountBased(w) => w.on_event(event),
+            Self::Session(w) => w.on_event(event),
             Self::No(w) => w.on_event(event),
         }
     }
@@ -153,6 +405,9 @@ impl WindowTrait for WindowImpl {
         match self {
             Self::TumblingTimeBased(w) => w.eviction_ns(),
             Self::TumblingCountBased(w) => w.eviction_ns(),
+            Self::SlidingTimeBased(w) => w.eviction_ns(),
+            Self::SlidingCountBased(w) => w.eviction_ns(),
+            Self::Session(w) => w.eviction_ns(),
             Self::No(w) => w.eviction_ns(),
         }
     }
@@ -174,6 +429,21 @@ impl From<TumblingWindowOnTime> for WindowImpl {
         Self::TumblingTimeBased(w)
     }
 }
+impl From<SlidingWindowOnNumber> for WindowImpl {
+    fn from(w: SlidingWindowOnNumber) -> Self {
+        Self::SlidingCountBased(w)
+    }
+}
+impl From<SlidingWindowOnTime> for WindowImpl {
+    fn from(w: SlidingWindowOnTime) -> Self {
+        Self::SlidingTimeBased(w)
+    }
+}
+impl From<SessionWindow> for WindowImpl {
+    fn from(w: SessionWindow) -> Self {
+        Self::Session(w)
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct WindowEvent {
@@ -181,6 +451,64 @@ pub struct WindowEvent {
     open: bool,
     /// Close the window before this event and opeen the next one
     emit: bool,
+    /// The event is behind the watermark and belongs to no open window
+    late: bool,
+}
+
+impl WindowEvent {
+    fn late() -> Self {
+        Self {
+            open: false,
+            emit: false,
+            late: true,
+        }
+    }
+}
+
+/// What event-time windows compare the time of an event with to decide
+/// if it is late
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatermarkStrategy {
+    /// The start of the open window
+    Window,
+    /// The latest event time seen so far
+    Max,
+}
+
+/// Tracks the progress of event time in a window, events behind the
+/// watermark by more than the allowed lateness are late
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    strategy: WatermarkStrategy,
+    allowed_lateness: u64,
+    /// The latest event time seen so far
+    max_time: u64,
+}
+
+impl Watermark {
+    pub fn new(strategy: WatermarkStrategy, allowed_lateness: u64) -> Self {
+        Self {
+            strategy,
+            allowed_lateness,
+            max_time: 0,
+        }
+    }
+
+    /// Checks if an event with the given time is late for a window that
+    /// started at `window_start`, the watermark only advances with events
+    /// that are not.
+    fn is_late(&mut self, time: u64, window_start: u64) -> bool {
+        let mark = match self.strategy {
+            WatermarkStrategy::Window => window_start,
+            WatermarkStrategy::Max => self.max_time,
+        };
+        if time.saturating_add(self.allowed_lateness) < mark {
+            true
+        } else {
+            self.max_time = self.max_time.max(time);
+            false
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -197,43 +525,82 @@ impl WindowTrait for NoWindow {
             Ok(WindowEvent {
                 open: false,
                 emit: true,
+                late: false,
             })
         } else {
             self.open = true;
             Ok(WindowEvent {
                 open: true,
                 emit: true,
+                late: false,
             })
         }
     }
 }
 
+/// Binds the script of a window declaration to the statement it was
+/// declared for.
+fn window_script(script: Option<&WindowDecl>, stmt: &StmtRentalWrapper) -> Option<rentals::Window> {
+    script.map(|s| {
+        rentals::Window::new(stmt.stmt.clone(), |_| unsafe {
+            // This is safe since `stmt.stmt` is an Arc that holds the
+            // referenced data and we clone it into the rental. This ensures
+            // the referenced data isn't dropped until the rental is dropped.
+            mem::transmute::<WindowDecl<'_>, WindowDecl<'static>>(s.clone())
+        })
+    })
+}
+
+/// Runs the script of a window declaration, if there is one, against an
+/// event and returns the numeric value it produced.
+fn script_value(script: Option<&rentals::Window>, event: &Event) -> Result<Option<u64>> {
+    script
+        .and_then(|script| script.suffix().script.as_ref())
+        .map(|script| {
+            // TODO avoid origin_uri clone here
+            let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
+            let (mut unwind_event, mut event_meta) = event.data.parts();
+            let value = script.run(
+                &context,
+                AggrType::Emit,
+                &mut unwind_event,  // event
+                &mut Value::null(), // state for the window
+                &mut event_meta,    // $
+            )?;
+            let data = match value {
+                Return::Emit { value, .. } => value.as_u64(),
+                Return::EmitEvent { .. } => unwind_event.as_u64(),
+                Return::Drop { .. } => None,
+            };
+            data.ok_or_else(|| Error::from("Data based window didn't provide a valid value"))
+        })
+        .transpose()
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct TumblingWindowOnTime {
     next_window: Option<u64>,
     size: u64,
     ttl: Option<u64>,
     script: Option<rentals::Window>,
+    /// Set for windows on event time
+    watermark: Option<Watermark>,
 }
 impl TumblingWindowOnTime {
     pub fn from_stmt(
         size: u64,
         ttl: Option<u64>,
         script: Option<&WindowDecl>,
+        watermark: Option<Watermark>,
         stmt: &StmtRentalWrapper,
     ) -> Self {
-        let script = script.map(|s| {
-            rentals::Window::new(stmt.stmt.clone(), |_| unsafe {
-                // This is sound since stmt.stmt is an arc that holds
-                // the borrwed data
-                mem::transmute::<WindowDecl<'_>, WindowDecl<'static>>(s.clone())
-            })
-        });
+        let script = window_script(script, stmt);
         Self {
             next_window: None,
             size,
             ttl,
             script,
+            watermark,
         }
     }
 }
@@ -243,35 +610,22 @@ impl WindowTrait for TumblingWindowOnTime {
         self.ttl
     }
     fn on_event(&mut self, event: &Event) -> Result<WindowEvent> {
-        let time = self
-            .script
-            .as_ref()
-            .and_then(|script| script.suffix().script.as_ref())
-            .map(|script| {
-                // TODO avoid origin_uri clone here
-                let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
-                let (mut unwind_event, mut event_meta) = event.data.parts();
-                let value = script.run(
-                    &context,
-                    AggrType::Emit,
-                    &mut unwind_event,  // event
-                    &mut Value::null(), // state for the window
-                    &mut event_meta,    // $
-                )?;
-                let data = match value {
-                    Return::Emit { value, .. } => value.as_u64(),
-                    Return::EmitEvent { .. } => unwind_event.as_u64(),
-                    Return::Drop { .. } => None,
-                };
-                data.ok_or_else(|| Error::from("Data based window didn't provide a valid value"))
-            })
-            .unwrap_or(Ok(event.ingest_ns))?;
+        let time = script_value(self.script.as_ref(), event)?.unwrap_or(event.ingest_ns);
+        if let Some(watermark) = &mut self.watermark {
+            let window_start = self
+                .next_window
+                .map_or(0, |next_window| next_window.saturating_sub(self.size));
+            if watermark.is_late(time, window_start) {
+                return Ok(WindowEvent::late());
+            }
+        }
         match self.next_window {
             None => {
                 self.next_window = Some(time + self.size);
                 Ok(WindowEvent {
                     open: true,
                     emit: false,
+                    late: false,
                 })
             }
             Some(next_window) if next_window <= time => {
@@ -279,11 +633,13 @@ impl WindowTrait for TumblingWindowOnTime {
                 Ok(WindowEvent {
                     open: true,
                     emit: true,
+                    late: false,
                 })
             }
             Some(_) => Ok(WindowEvent {
                 open: false,
                 emit: false,
+                late: false,
             }),
         }
     }
@@ -304,15 +660,7 @@ impl TumblingWindowOnNumber {
         script: Option<&WindowDecl>,
         stmt: &StmtRentalWrapper,
     ) -> Self {
-        let script = script.map(|s| {
-            rentals::Window::new(stmt.stmt.clone(), |_| unsafe {
-                // This is safe since `stmt.stmt` is an Arc that
-                // hods the referenced data and we clone it into the rental.
-                // This ensures refferenced data isn't dropped until the rental
-                // is dropped.
-                mem::transmute::<WindowDecl<'_>, WindowDecl<'static>>(s.clone())
-            })
-        });
+        let script = window_script(script, stmt);
         Self {
             count: 0,
             size,
@@ -326,29 +674,7 @@ impl WindowTrait for TumblingWindowOnNumber {
         self.ttl
     }
     fn on_event(&mut self, event: &Event) -> Result<WindowEvent> {
-        let count = self
-            .script
-            .as_ref()
-            .and_then(|script| script.suffix().script.as_ref())
-            .map(|script| {
-                // TODO avoid origin_uri clone here
-                let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
-                let (mut unwind_event, mut event_meta) = event.data.parts();
-                let value = script.run(
-                    &context,
-                    AggrType::Emit,
-                    &mut unwind_event,  // event
-                    &mut Value::null(), // state for the window
-                    &mut event_meta,    // $
-                )?;
-                let data = match value {
-                    Return::Emit { value, .. } => value.as_u64(),
-                    Return::EmitEvent { .. } => unwind_event.as_u64(),
-                    Return::Drop { .. } => None,
-                };
-                data.ok_or_else(|| Error::from("Data based window didn't provide a valid value"))
-            })
-            .unwrap_or(Ok(1))?;
+        let count = script_value(self.script.as_ref(), event)?.unwrap_or(1);
 
         // If we're above count we emit and  set the new count to 1
         // ( we emit on the ) previous event
@@ -357,24 +683,305 @@ impl WindowTrait for TumblingWindowOnNumber {
             Ok(WindowEvent {
                 open: true,
                 emit: true,
+                late: false,
             })
         } else {
             self.count += count;
             Ok(WindowEvent {
                 open: false,
                 emit: false,
+                late: false,
             })
         }
     }
 }
 
+#[derive(Default, Debug, Clone)]
+pub struct SlidingWindowOnTime {
+    /// Latest time the window has seen
+    current: u64,
+    /// Time of the event that is being accumulated
+    time: u64,
+    size: u64,
+    ttl: Option<u64>,
+    script: Option<rentals::Window>,
+    /// Set for windows on event time
+    watermark: Option<Watermark>,
+    /// Timestamps and aggregate arguments of the events inside the window,
+    /// ordered by time
+    buffer: VecDeque<(u64, AggrArgs)>,
+}
+
+impl SlidingWindowOnTime {
+    pub fn from_stmt(
+        size: u64,
+        ttl: Option<u64>,
+        script: Option<&WindowDecl>,
+        watermark: Option<Watermark>,
+        stmt: &StmtRentalWrapper,
+    ) -> Self {
+        let script = window_script(script, stmt);
+        Self {
+            current: 0,
+            time: 0,
+            size,
+            ttl,
+            script,
+            watermark,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    fn slide(&mut self, args: AggrArgs) -> Vec<AggrArgs> {
+        // Events can arrive out of order, late ones are inserted before
+        // the newer ones so the oldest events are always in front
+        let time = self.time;
+        let at = self
+            .buffer
+            .iter()
+            .rposition(|(t, _)| *t <= time)
+            .map_or(0, |i| i + 1);
+        self.buffer.insert(at, (time, args));
+        // Everything that is `size` or older than the latest event leaves
+        // the window
+        let mut evicted = Vec::new();
+        while let Some((t, _)) = self.buffer.front() {
+            if t + self.size > self.current {
+                break;
+            }
+            if let Some((_, args)) = self.buffer.pop_front() {
+                evicted.push(args);
+            }
+        }
+        evicted
+    }
+}
+
+impl WindowTrait for SlidingWindowOnTime {
+    fn eviction_ns(&self) -> Option<u64> {
+        self.ttl
+    }
+    fn on_event(&mut self, event: &Event) -> Result<WindowEvent> {
+        let time = script_value(self.script.as_ref(), event)?.unwrap_or(event.ingest_ns);
+        if let Some(watermark) = &mut self.watermark {
+            if watermark.is_late(time, self.current.saturating_sub(self.size)) {
+                return Ok(WindowEvent::late());
+            }
+        }
+        self.time = time;
+        self.current = self.current.max(time);
+        // Sliding windows emit once the event has been accumulated
+        Ok(WindowEvent {
+            open: false,
+            emit: false,
+            late: false,
+        })
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct SlidingWindowOnNumber {
+    size: u64,
+    ttl: Option<u64>,
+    /// Aggregate arguments of the events inside the window
+    buffer: VecDeque<AggrArgs>,
+}
+
+impl SlidingWindowOnNumber {
+    pub fn from_stmt(size: u64, ttl: Option<u64>) -> Self {
+        Self {
+            size,
+            ttl,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    fn slide(&mut self, args: AggrArgs) -> Vec<AggrArgs> {
+        self.buffer.push_back(args);
+        let mut evicted = Vec::new();
+        while self.buffer.len() as u64 > self.size {
+            if let Some(args) = self.buffer.pop_front() {
+                evicted.push(args);
+            }
+        }
+        evicted
+    }
+}
+
+impl WindowTrait for SlidingWindowOnNumber {
+    fn eviction_ns(&self) -> Option<u64> {
+        self.ttl
+    }
+    fn on_event(&mut self, _event: &Event) -> Result<WindowEvent> {
+        // Sliding windows emit once the event has been accumulated
+        Ok(WindowEvent {
+            open: false,
+            emit: false,
+            late: false,
+        })
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct SessionWindow {
+    /// Inactivity after which the session closes
+    gap: u64,
+    ttl: Option<u64>,
+    script: Option<rentals::Window>,
+    /// Time of the latest event in the session
+    last: Option<u64>,
+    /// Ingest time of the latest event in the session, sessions on event
+    /// time close once the gap passed on it too
+    seen: Option<u64>,
+}
+
+impl SessionWindow {
+    pub fn from_stmt(
+        gap: u64,
+        ttl: Option<u64>,
+        script: Option<&WindowDecl>,
+        stmt: &StmtRentalWrapper,
+    ) -> Self {
+        let script = window_script(script, stmt);
+        Self {
+            gap,
+            ttl,
+            script,
+            last: None,
+            seen: None,
+        }
+    }
+
+    /// Signals carry processing time, so sessions close once no event
+    /// arrived for longer than the gap, on event time as well
+    fn expired(&self, now: u64) -> bool {
+        self.seen
+            .map_or(false, |seen| now.saturating_sub(seen) > self.gap)
+    }
+}
+
+impl WindowTrait for SessionWindow {
+    fn eviction_ns(&self) -> Option<u64> {
+        self.ttl
+    }
+    fn on_event(&mut self, event: &Event) -> Result<WindowEvent> {
+        let time = script_value(self.script.as_ref(), event)?.unwrap_or(event.ingest_ns);
+        let window_event = match self.last {
+            None => WindowEvent {
+                open: true,
+                emit: false,
+                late: false,
+            },
+            // The session was inactive for too long, the event opens a new one
+            Some(last) if time.saturating_sub(last) > self.gap => WindowEvent {
+                open: true,
+                emit: true,
+                late: false,
+            },
+            Some(_) => WindowEvent {
+                open: false,
+                emit: false,
+                late: false,
+            },
+        };
+        if window_event.open {
+            self.last = Some(time);
+        } else {
+            self.last = self.last.map(|last| last.max(time));
+        }
+        self.seen = Some(
+            self.seen
+                .map_or(event.ingest_ns, |seen| seen.max(event.ingest_ns)),
+        );
+        Ok(window_event)
+    }
+}
+
 const NO_AGGRS: [InvokeAggrFn<'static>; 0] = [];
 
+/// Captures the groups of a window along with the state of their window
+/// and aggregates. Aggregates that can't be captured are listed as
+/// `skipped` and start over when the snapshot is restored.
+fn snapshot_groups(groups: &Groups, node_meta: &NodeMetas) -> Value<'static> {
+    let mut snapshot = Object::with_capacity(groups.len());
+    for (group_str, data) in groups {
+        let mut aggrs = Vec::with_capacity(data.aggrs.len());
+        let mut skipped = Vec::new();
+        for (i, aggr) in data.aggrs.iter().enumerate() {
+            match aggr.invocable.snapshot() {
+                Ok(state) => aggrs.push(state),
+                Err(e) => {
+                    let r: Option<&Registry> = None;
+                    warn!(
+                        "Skipping aggregate in snapshot: {}",
+                        e.into_err(aggr, aggr, r, node_meta)
+                    );
+                    aggrs.push(Value::null());
+                    skipped.push(Value::from(i as u64));
+                }
+            }
+        }
+        let mut group = Object::with_capacity(4);
+        group.insert_nocheck("group".into(), data.group.clone());
+        group.insert_nocheck("window".into(), data.window.snapshot());
+        group.insert_nocheck("aggrs".into(), Value::from(aggrs));
+        group.insert_nocheck("skipped".into(), Value::from(skipped));
+        snapshot.insert_nocheck(group_str.clone().into(), Value::from(group));
+    }
+    Value::from(snapshot)
+}
+
+/// Replaces the groups of a window with the ones captured by `snapshot_groups`.
+fn restore_groups(
+    groups: &mut Groups<'static>,
+    window_impl: &WindowImpl,
+    aggregates: &Aggrs<'static>,
+    snapshot: &Value,
+    node_meta: &NodeMetas,
+) -> Result<()> {
+    let invalid = || Error::from("Invalid group snapshot");
+    groups.clear();
+    for (group_str, data) in snapshot.as_object().ok_or_else(invalid)?.iter() {
+        let mut window = window_impl.clone();
+        window.restore(data.get("window").ok_or_else(invalid)?)?;
+        let states = data
+            .get("aggrs")
+            .and_then(Value::as_array)
+            .filter(|states| states.len() == aggregates.len())
+            .ok_or_else(invalid)?;
+        let skipped: Vec<u64> = data
+            .get("skipped")
+            .and_then(Value::as_array)
+            .map_or_else(Vec::new, |s| s.iter().filter_map(Value::as_u64).collect());
+        let mut aggrs = aggregates.clone();
+        for (i, (aggr, state)) in aggrs.iter_mut().zip(states).enumerate() {
+            if skipped.contains(&(i as u64)) {
+                continue;
+            }
+            aggr.invocable.restore(state).map_err(|e| {
+                let r: Option<&Registry> = None;
+                e.into_err(aggr, aggr, r, node_meta)
+            })?;
+        }
+        let group = data.get("group").ok_or_else(invalid)?.clone_static();
+        groups.insert(
+            group_str.to_string(),
+            GroupData {
+                group,
+                window,
+                aggrs,
+            },
+        );
+    }
+    Ok(())
+}
+
 impl TrickleSelect {
     pub fn with_stmt(
         id: String,
         dims: &SelectDims,
         windows: Vec<(String, WindowImpl)>,
+        join_window: Option<WindowImpl>,
         stmt_rentwrapped: &tremor_script::query::StmtRentalWrapper,
     ) -> Result<Self> {
         let select = match stmt_rentwrapped.suffix() {
@@ -387,6 +994,32 @@ impl TrickleSelect {
             }
         };
 
+        if windows.len() > 1 && windows.iter().any(|(_, w)| w.is_sliding()) {
+            return Err(ErrorKind::PipelineError(
+                "Sliding windows can not be combined with other windows".into(),
+            )
+            .into());
+        }
+        if windows.len() > 1 && windows.iter().any(|(_, w)| w.is_session()) {
+            return Err(ErrorKind::PipelineError(
+                "Session windows can not be combined with other windows".into(),
+            )
+            .into());
+        }
+        if join_window
+            .as_ref()
+            .map_or(false, |w| w.is_sliding() || w.is_session())
+        {
+            return Err(
+                ErrorKind::PipelineError("Joins can only use tumbling windows".into()).into(),
+            );
+        }
+        let join = select.stmt.maybe_join.as_ref().map(|_| JoinState {
+            window: join_window,
+            ..JoinState::default()
+        });
+
+        let held = windows.iter().map(|_| HashMap::new()).collect();
         let windows = windows
             .into_iter()
             .map(|(fqwn, window_impl)| Window {
@@ -401,6 +1034,14 @@ impl TrickleSelect {
         Ok(Self {
             id,
             windows,
+            held,
+            covers: HashMap::new(),
+            orphans: Vec::new(),
+            covered_at: VecDeque::new(),
+            holding: HashMap::new(),
+            cbs: Vec::new(),
+            join,
+            last_events: HashMap::new(),
             select: rentals::Select::new(stmt_rentwrapped.stmt.clone(), move |_| unsafe {
                 // This is safe since `stmt_rentwrapped.stmt` is an Arc that
                 // hods the referenced data and we clone it into the rental.
@@ -416,45 +1057,204 @@ impl TrickleSelect {
             aggr: AggrType::Emit,
         }
     }
-}
 
-impl Operator for TrickleSelect {
-    #[allow(
-        mutable_transmutes,
-        clippy::transmute_ptr_to_ptr,
-        clippy::too_many_lines
-    )]
-    fn on_event(
+    /// Covers held events by an emitted event
+    fn cover(&mut self, key: EventKey, mut ids: Vec<EventKey>, ingest_ns: u64) {
+        if let Some(covered) = self.covers.get_mut(&key) {
+            covered.append(&mut ids);
+        } else {
+            self.covered_at.push_back((ingest_ns, key.clone()));
+            self.covers.insert(key, ids);
+        }
+    }
+
+    /// Acknowledges held events that were let go of without being emitted,
+    /// once no other group holds them
+    fn drop_held(&mut self, ids: Vec<EventKey>, ingest_ns: u64) {
+        for key in ids {
+            match self.holding.get_mut(&key) {
+                Some(n) if *n > 1 => *n -= 1,
+                Some(_) => {
+                    self.holding.remove(&key);
+                    let (id, origin_uri) = key;
+                    self.cbs
+                        .push(Event::cb(CBAction::Ack, ingest_ns, id, origin_uri));
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// Fails the events covered by emitted events that were not
+    /// acknowledged in time
+    fn expire_covers(&mut self, ingest_ns: u64) {
+        while let Some((emitted_ns, _)) = self.covered_at.front() {
+            if emitted_ns.saturating_add(COVERS_TTL_NS) > ingest_ns {
+                break;
+            }
+            if let Some((_, key)) = self.covered_at.pop_front() {
+                for covered in self.covers.remove(&key).unwrap_or_default() {
+                    if self.holding.remove(&covered).is_some() {
+                        let (id, origin_uri) = covered;
+                        self.cbs
+                            .push(Event::cb(CBAction::Fail, ingest_ns, id, origin_uri));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Matches an event of either side of the join against the events
+    /// waiting on the other side and sends the joined events to `out`.
+    /// Events of the joined stream arrive on the `join` port.
+    #[allow(mutable_transmutes, clippy::transmute_ptr_to_ptr)]
+    fn join_event(
         &mut self,
-        _port: &str,
+        port: &str,
         state: &mut Value<'static>,
         event: Event,
     ) -> Result<Vec<(Cow<'static, str>, Event)>> {
         let opts = Self::opts();
-        // We guarantee at compile time that select in itself can't have locals, so this is safe
-
-        // NOTE We are unwrapping our rental wrapped stmt
-
-        // FIXME: reason about soundness
+        // This is sound for the same reason as in `select_event`
         let SelectStmt {
             stmt,
-            aggregates,
             consts,
             locals,
             node_meta,
+            ..
         }: &mut SelectStmt = unsafe { mem::transmute(self.select.suffix()) };
+        let (join, kind, key) = match (self.join.as_mut(), &stmt.maybe_join) {
+            (Some(join), Some(Join { kind, key, .. })) => (join, *kind, key),
+            _ => return Ok(vec![("out".into(), event)]),
+        };
         let local_stack = tremor_script::interpreter::LocalStack::with_size(*locals);
         consts[WINDOW_CONST_ID] = Value::null();
         consts[GROUP_CONST_ID] = Value::null();
         consts[ARGS_CONST_ID] = Value::null();
-        // TODO avoid origin_uri clone here
-        let ctx = EventContext::new(event.ingest_ns, event.origin_uri.clone());
-
-        //
-        // Before any select processing, we filter by where clause
-        //
-        // FIXME: ?
-        if let Some(guard) = &stmt.maybe_where {
+        let key = {
+            let ctx = EventContext::new(event.ingest_ns, event.origin_uri.clone());
+            let env = Env {
+                context: &ctx,
+                consts: &consts,
+                aggrs: &NO_AGGRS,
+                meta: &node_meta,
+                recursion_limit: tremor_script::recursion_limit(),
+            };
+            let (unwind_event, event_meta) = event.data.parts();
+            let key = key.run(opts, &env, unwind_event, state, event_meta, &local_stack)?;
+            sorsorted_serialize(&key)?
+        };
+
+        let mut flush = false;
+        if let Some(window) = &mut join.window {
+            let window_event = window.on_event(&event)?;
+            if window_event.late {
+                return Ok(vec![("late".into(), event)]);
+            }
+            flush = window_event.emit;
+        }
+        // The event that closes the window is still matched against it
+        let mut events = vec![];
+        let mut matched = false;
+        if port == "join" {
+            if join.window.is_some() {
+                if let Some(lefts) = join.left.get_mut(&key) {
+                    for (left, left_matched) in lefts.iter_mut() {
+                        *left_matched = true;
+                        events.push(("out".into(), joined(&event, left, Some(&event))));
+                    }
+                }
+            }
+        } else {
+            let rights = join.right.get(&key).map_or(&[][..], Vec::as_slice);
+            for right in rights {
+                events.push(("out".into(), joined(&event, &event, Some(right))));
+            }
+            matched = !rights.is_empty();
+        }
+        // Events that found no match within the window stop waiting
+        let mut released = Vec::new();
+        if flush {
+            for (_, lefts) in mem::take(&mut join.left) {
+                for (left, left_matched) in lefts {
+                    if kind == JoinKind::Outer && !left_matched {
+                        events.push(("out".into(), joined(&event, &left, None)));
+                    }
+                    released.push((left.id, left.origin_uri));
+                }
+            }
+            for (_, rights) in mem::take(&mut join.right) {
+                released.extend(rights.into_iter().map(|e| (e.id, e.origin_uri)));
+            }
+        }
+        // Buffered events are held until they stop waiting
+        let event_key = (event.id, event.origin_uri.clone());
+        let buffered = if port == "join" {
+            if join.window.is_some() {
+                join.right.entry(key).or_insert_with(Vec::new).push(event);
+            } else {
+                // Tables only keep the latest event for each key
+                if let Some(rights) = join.right.insert(key, vec![event]) {
+                    released.extend(rights.into_iter().map(|e| (e.id, e.origin_uri)));
+                }
+            }
+            true
+        } else if join.window.is_some() {
+            join.left
+                .entry(key)
+                .or_insert_with(Vec::new)
+                .push((event, matched));
+            true
+        } else {
+            if kind == JoinKind::Outer && !matched {
+                events.push(("out".into(), joined(&event, &event, None)));
+            }
+            false
+        };
+        if buffered {
+            *self.holding.entry(event_key).or_insert(0) += 1;
+        }
+        // Released events are covered by what is emitted next, like the
+        // events of evicted groups
+        self.orphans.append(&mut released);
+        Ok(events)
+    }
+
+    #[allow(
+        mutable_transmutes,
+        clippy::transmute_ptr_to_ptr,
+        clippy::too_many_lines
+    )]
+    fn select_event(
+        &mut self,
+        state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let opts = Self::opts();
+        // We guarantee at compile time that select in itself can't have locals, so this is safe
+
+        // NOTE We are unwrapping our rental wrapped stmt
+
+        // FIXME: reason about soundness
+        let SelectStmt {
+            stmt,
+            aggregates,
+            consts,
+            locals,
+            node_meta,
+        }: &mut SelectStmt = unsafe { mem::transmute(self.select.suffix()) };
+        let local_stack = tremor_script::interpreter::LocalStack::with_size(*locals);
+        consts[WINDOW_CONST_ID] = Value::null();
+        consts[GROUP_CONST_ID] = Value::null();
+        consts[ARGS_CONST_ID] = Value::null();
+        // TODO avoid origin_uri clone here
+        let ctx = EventContext::new(event.ingest_ns, event.origin_uri.clone());
+
+        //
+        // Before any select processing, we filter by where clause
+        //
+        // FIXME: ?
+        if let Some(guard) = &stmt.maybe_where {
             let (unwind_event, event_meta) = event.data.parts();
             let env = Env {
                 context: &ctx,
@@ -532,10 +1332,16 @@ impl Operator for TrickleSelect {
         if group_values.is_empty() {
             group_values.push(vec![Value::null()])
         };
+        // TODO avoid origin_uri clone here
+        let key = (event.id, event.origin_uri.clone());
+        // Events covered by the widest window when it emits
+        let mut released = Vec::new();
+        // Events of windows the `having` clause filtered out
+        let mut dropped = Vec::new();
 
         // Handle eviction
 
-        for window in &mut self.windows {
+        for (window, held) in self.windows.iter_mut().zip(self.held.iter_mut()) {
             if let Some(eviction_ns) = window.window_impl.eviction_ns() {
                 if window.next_swap < event.ingest_ns {
                     window.next_swap = event.ingest_ns + eviction_ns;
@@ -545,6 +1351,12 @@ impl Operator for TrickleSelect {
                         // any other window is dropped
                         let this_groups = window.dims.mut_suffix();
                         let last_groups = window.last_dims.mut_suffix();
+                        for group in last_groups.keys() {
+                            if let Some(mut ids) = held.remove(group) {
+                                self.orphans.append(&mut ids);
+                            }
+                            self.last_events.remove(group);
+                        }
                         last_groups.clear();
                         std::mem::swap(this_groups, last_groups);
                     }
@@ -553,10 +1365,26 @@ impl Operator for TrickleSelect {
         }
 
         let group_values: Vec<Value> = group_values.into_iter().map(Value::Array).collect();
+        // If the event was late for any of its groups
+        let mut late_for_any = false;
+        // Ordered selects close the window of all groups together, the
+        // groups of this event and the window state they closed with
+        let ordered = stmt.maybe_order_by.is_some() || stmt.maybe_limit.is_some();
+        let mut touched = Vec::new();
+        let mut closed: Option<WindowImpl> = None;
+        // The window each emitted event belongs to and the key it is
+        // ordered by
+        let mut order_keys: Vec<(usize, Value<'static>)> = Vec::new();
         for group_value in group_values {
             let group_str = sorsorted_serialize(&group_value)?;
+            if ordered {
+                touched.push(group_str.clone());
+            }
             let mut windows = self.windows.iter_mut().peekable();
             let mut emit_depth = 0;
+            let mut late = false;
+            // If the last window that emitted was filtered out
+            let mut filtered = false;
 
             // We first iterate through the windows and emit as far as we would have to emit.
             while let Some(this) = windows.next() {
@@ -578,6 +1406,11 @@ impl Operator for TrickleSelect {
                         )
                     });
                 let window_event = this_group.window.on_event(&event)?;
+                // Late events belong to a window that was already emitted
+                if window_event.late {
+                    late = true;
+                    break;
+                }
                 // The issue with the windows is the following:
                 // We emit on the first event of the next windows, this works well for the inital frame
                 // on a second frame, we have the coordinate with the first we have a problem as we
@@ -603,6 +1436,9 @@ impl Operator for TrickleSelect {
                 // If this window should emit
                 if window_event.emit {
                     emit_depth += 1;
+                    if ordered && closed.is_none() {
+                        closed = Some(this_group.window.clone());
+                    }
                     // See if we need to merge into the next tiltframe
                     // If so merge the aggregates
                     // Then emit the window itself
@@ -632,6 +1468,7 @@ impl Operator for TrickleSelect {
                     if let Some(guard) = &stmt.maybe_having {
                         let test = guard.run(opts, &env, &result, state, &NULL, &local_stack)?;
                         if let Some(test) = test.as_bool() {
+                            filtered = !test;
                             if !test {
                                 continue;
                             }
@@ -642,6 +1479,18 @@ impl Operator for TrickleSelect {
                             )?;
                         }
                     }
+                    if ordered {
+                        let order_key = if let Some(order_by) = &stmt.maybe_order_by {
+                            order_by
+                                .expr
+                                .run(opts, &env, unwind_event, state, event_meta, &local_stack)?
+                                .into_owned()
+                                .into_static()
+                        } else {
+                            Value::null()
+                        };
+                        order_keys.push((emit_depth - 1, order_key));
+                    }
                     let result = result.into_owned();
                     events.push((
                         "out".into(),
@@ -660,6 +1509,11 @@ impl Operator for TrickleSelect {
                 }
             }
 
+            if late {
+                late_for_any = true;
+                continue;
+            }
+
             // Next we take care of propagating the data from narrower to wider
             // windows and clearning windows that we have already emitted (in this
             // order!).
@@ -757,6 +1611,28 @@ impl Operator for TrickleSelect {
                 }
             }
 
+            // The events of emitted windows move on with their aggregates
+            // and are only released once the widest window emitted
+            if emit_count > 0 {
+                let mut ids = Vec::new();
+                for held in self.held.iter_mut().take(emit_count) {
+                    if let Some(mut group_ids) = held.remove(&group_str) {
+                        ids.append(&mut group_ids);
+                    }
+                }
+                if let Some(next) = self.held.get_mut(emit_count) {
+                    if let Some(group_ids) = next.get_mut(&group_str) {
+                        group_ids.append(&mut ids);
+                    } else {
+                        next.insert(group_str.clone(), ids);
+                    }
+                } else if filtered {
+                    dropped.append(&mut ids);
+                } else {
+                    released.append(&mut ids);
+                }
+            }
+
             // If we had at least one window ingest the event into this window
             if let Some(this) = self.windows.first() {
                 let (unwind_event, event_meta) = event.data.parts();
@@ -786,6 +1662,26 @@ impl Operator for TrickleSelect {
                     meta: &node_meta,
                     recursion_limit: tremor_script::recursion_limit(),
                 };
+                let sliding = this_group.window.is_sliding();
+                // Sliding windows emit the event itself so there is nothing to hold
+                if !sliding {
+                    if let Some(held) = self.held.first_mut() {
+                        if let Some(group_ids) = held.get_mut(&group_str) {
+                            group_ids.push(key.clone());
+                        } else {
+                            held.insert(group_str.clone(), vec![key.clone()]);
+                        }
+                    }
+                    if let Some(n) = self.holding.get_mut(&key) {
+                        *n += 1;
+                    } else {
+                        self.holding.insert(key.clone(), 1);
+                    }
+                    if ordered || this_group.window.is_session() {
+                        self.last_events.insert(group_str.clone(), event.clone());
+                    }
+                }
+                let mut event_args: AggrArgs = Vec::new();
                 for aggr in &mut this_group.aggrs {
                     let invocable = &mut aggr.invocable;
                     let mut argv: Vec<Cow<Value>> = Vec::with_capacity(aggr.args.len());
@@ -805,6 +1701,88 @@ impl Operator for TrickleSelect {
                         let r: Option<&Registry> = None;
                         e.into_err(aggr, aggr, r, &node_meta)
                     })?;
+                    // Sliding windows need to remember the arguments so they
+                    // can be compensated once the event leaves the window
+                    if sliding {
+                        event_args.push(argv1.iter().map(|v| v.clone_static()).collect());
+                    }
+                }
+
+                if sliding {
+                    // Retract all events that left the window
+                    let evicted = this_group.window.slide(event_args);
+                    if !evicted.is_empty() {
+                        let retained = this_group.window.retained();
+                        for (i, aggr) in this_group.aggrs.iter_mut().enumerate() {
+                            if aggr.invocable.compensable() {
+                                for args in &evicted {
+                                    let argv: Vec<&Value> = args[i].iter().collect();
+                                    aggr.invocable.compensate(argv.as_slice()).map_err(|e| {
+                                        // FIXME nice error
+                                        let r: Option<&Registry> = None;
+                                        e.into_err(aggr, aggr, r, &node_meta)
+                                    })?;
+                                }
+                            } else {
+                                // Functions like min or max can't retract a
+                                // value, so they start over with the events
+                                // still inside the window
+                                aggr.invocable.init();
+                                for args in &retained {
+                                    let argv: Vec<&Value> = args[i].iter().collect();
+                                    aggr.invocable.accumulate(argv.as_slice()).map_err(|e| {
+                                        // FIXME nice error
+                                        let r: Option<&Registry> = None;
+                                        e.into_err(aggr, aggr, r, &node_meta)
+                                    })?;
+                                }
+                            }
+                        }
+                    }
+
+                    // And emit the window including the current event
+                    let env = Env {
+                        context: &ctx,
+                        consts: &consts,
+                        aggrs: &this_group.aggrs,
+                        meta: &node_meta,
+                        recursion_limit: tremor_script::recursion_limit(),
+                    };
+                    let result = stmt.target.run(
+                        opts,
+                        &env,
+                        unwind_event,
+                        state,
+                        event_meta,
+                        &local_stack,
+                    )?;
+
+                    if let Some(guard) = &stmt.maybe_having {
+                        let test = guard.run(opts, &env, &result, state, &NULL, &local_stack)?;
+                        if let Some(test) = test.as_bool() {
+                            if !test {
+                                continue;
+                            }
+                        } else {
+                            let s: &Select = &stmt;
+                            return tremor_script::errors::query_guard_not_bool(
+                                s, guard, &test, &node_meta,
+                            )?;
+                        }
+                    }
+                    let result = result.into_owned();
+                    events.push((
+                        "out".into(),
+                        Event {
+                            id: event.id,
+                            ingest_ns: event.ingest_ns,
+                            // TODO avoid origin_uri clone here
+                            origin_uri: event.origin_uri.clone(),
+                            is_batch: event.is_batch,
+                            kind: event.kind,
+                            data: (result.into_static(), event_meta.clone_static()).into(),
+                        },
+                    ));
                 }
             } else {
                 // otherwise we just pass it through the select portion of the statement
@@ -853,60 +1831,427 @@ impl Operator for TrickleSelect {
                 ));
             }
         }
+        // The window closed for the groups of this event, so it closes
+        // for all other groups as well and they continue in lockstep
+        if let (Some(closed), Some(window), Some(held)) =
+            (closed, self.windows.first(), self.held.first_mut())
+        {
+            consts[WINDOW_CONST_ID] = Value::from(window.name.to_string());
+            // This is sound since we only add mutability to groups
+            let groups = unsafe { window.dims.mut_suffix() };
+            for (group_str, group) in groups.iter_mut() {
+                if touched.contains(group_str) {
+                    continue;
+                }
+                group.window = closed.clone();
+                // Groups without events in the window have nothing to emit,
+                // the others are emitted with the last event they saw
+                let last = if let Some(last) = self.last_events.remove(group_str) {
+                    last
+                } else {
+                    continue;
+                };
+                let mut ids = held.remove(group_str).unwrap_or_default();
+                let (unwind_event, event_meta) = last.data.parts();
+                consts[GROUP_CONST_ID] = group.group.clone();
+                consts[GROUP_CONST_ID].push(group_str.clone()).ok();
+                let env = Env {
+                    context: &ctx,
+                    consts: &consts,
+                    aggrs: &group.aggrs,
+                    meta: &node_meta,
+                    recursion_limit: tremor_script::recursion_limit(),
+                };
+                let result =
+                    stmt.target
+                        .run(opts, &env, unwind_event, state, event_meta, &local_stack)?;
+                let emit = if let Some(guard) = &stmt.maybe_having {
+                    let test = guard.run(opts, &env, &result, state, &NULL, &local_stack)?;
+                    if let Some(test) = test.as_bool() {
+                        test
+                    } else {
+                        let s: &Select = &stmt;
+                        return tremor_script::errors::query_guard_not_bool(
+                            s, guard, &test, &node_meta,
+                        )?;
+                    }
+                } else {
+                    true
+                };
+                if emit {
+                    let order_key = if let Some(order_by) = &stmt.maybe_order_by {
+                        order_by
+                            .expr
+                            .run(opts, &env, unwind_event, state, event_meta, &local_stack)?
+                            .into_owned()
+                            .into_static()
+                    } else {
+                        Value::null()
+                    };
+                    order_keys.push((0, order_key));
+                    let result = result.into_owned();
+                    events.push((
+                        "out".into(),
+                        Event {
+                            id: event.id,
+                            ingest_ns: event.ingest_ns,
+                            // TODO avoid origin_uri clone here
+                            origin_uri: event.origin_uri.clone(),
+                            is_batch: last.is_batch,
+                            kind: last.kind,
+                            data: (result.into_static(), event_meta.clone_static()).into(),
+                        },
+                    ));
+                }
+                for aggr in &mut group.aggrs {
+                    aggr.invocable.init();
+                }
+                if emit {
+                    released.append(&mut ids);
+                } else {
+                    dropped.append(&mut ids);
+                }
+            }
+        }
+        // Each emitted window is ordered and limited on its own
+        if ordered {
+            let mut keyed: Vec<_> = order_keys.into_iter().zip(events.drain(..)).collect();
+            let desc = stmt
+                .maybe_order_by
+                .as_ref()
+                .map_or(false, |order_by| order_by.direction == OrderDirection::Desc);
+            // The sort is stable, so groups with the same key keep the
+            // order they were emitted in
+            keyed.sort_by(|((l_window, l), _), ((r_window, r), _)| {
+                l_window.cmp(r_window).then_with(|| {
+                    if desc {
+                        cmp_order_keys(r, l)
+                    } else {
+                        cmp_order_keys(l, r)
+                    }
+                })
+            });
+            let limit = stmt.maybe_limit.map_or(usize::MAX, |limit| {
+                usize::try_from(limit).unwrap_or(usize::MAX)
+            });
+            let mut window = None;
+            let mut emitted = 0;
+            for ((i, _), event) in keyed {
+                if window != Some(i) {
+                    window = Some(i);
+                    emitted = 0;
+                }
+                if emitted < limit {
+                    events.push(event);
+                    emitted += 1;
+                }
+            }
+        }
+
+        // All we emitted carries the key of this event, if nothing was
+        // emitted the released events wait for the next emitted window
+        if events.is_empty() {
+            self.orphans.append(&mut released);
+        } else if !released.is_empty() || !self.orphans.is_empty() {
+            released.append(&mut self.orphans);
+            self.cover(key, released, event.ingest_ns);
+        }
+        self.drop_held(dropped, event.ingest_ns);
+        // Late events are passed on as they are, instead of being aggregated
+        if late_for_any {
+            events.push(("late".into(), event));
+        }
         Ok(events)
     }
 }
 
-#[cfg(test)]
-mod test {
-    #![allow(clippy::float_cmp)]
-    use super::*;
-    use simd_json::borrowed::Value;
-    use simd_json::json;
-    use tremor_script::ast::{self, Ident, ImutExpr, Literal};
+impl Operator for TrickleSelect {
+    fn on_event(
+        &mut self,
+        port: &str,
+        state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        self.expire_covers(event.ingest_ns);
+        if self.join.is_none() {
+            return self.select_event(state, event);
+        }
+        let key = (event.id, event.origin_uri.clone());
+        let ingest_ns = event.ingest_ns;
+        let mut events = vec![];
+        for (out_port, event) in self.join_event(port, state, event)? {
+            if out_port == "out" {
+                events.append(&mut self.select_event(state, event)?);
+            } else {
+                events.push((out_port, event));
+            }
+        }
+        // Events the join released are covered by this event, unless
+        // nothing was emitted for it while it is buffered itself
+        if !self.orphans.is_empty() && (!events.is_empty() || !self.holding.contains_key(&key)) {
+            let released = mem::take(&mut self.orphans);
+            self.cover(key, released, ingest_ns);
+        }
+        Ok(events)
+    }
 
-    fn test_target<'test>() -> ast::ImutExpr<'test> {
-        let target: ast::ImutExpr<'test> = ImutExpr::from(ast::Literal {
-            mid: 0,
-            value: Value::from(42),
-        });
-        target
+    fn handles_contraflow(&self) -> bool {
+        !self.windows.is_empty() || self.join.is_some()
     }
 
-    fn test_stmt(target: ast::ImutExpr) -> ast::Select {
-        tremor_script::ast::Select {
-            mid: 0,
-            from: (Ident::from("in"), Ident::from("out")),
-            into: (Ident::from("out"), Ident::from("in")),
-            target,
-            maybe_where: Some(ImutExpr::from(ast::Literal {
-                mid: 0,
-                value: Value::from(true),
-            })),
-            windows: vec![],
-            maybe_group_by: None,
-            maybe_having: None,
+    fn on_cb(&mut self, insight: Event) -> Vec<Event> {
+        let key = (insight.id, insight.origin_uri.clone());
+        let held = self.holding.contains_key(&key);
+        let mut cbs = Vec::new();
+        if let (Some(covered), Some(action)) = (self.covers.remove(&key), insight.cb_action()) {
+            for (id, origin_uri) in covered {
+                let k = (id, origin_uri);
+                let done = match (action, self.holding.get_mut(&k)) {
+                    (CBAction::Ack, Some(n)) if *n > 1 => {
+                        *n -= 1;
+                        false
+                    }
+                    (_, Some(_)) => {
+                        self.holding.remove(&k);
+                        true
+                    }
+                    // it already failed
+                    (_, None) => false,
+                };
+                if done {
+                    let (id, origin_uri) = k;
+                    cbs.push(Event::cb(action, insight.ingest_ns, id, origin_uri));
+                }
+            }
+        }
+        // the event itself is acknowledged once the window holding it is
+        if !held {
+            cbs.push(insight);
         }
+        cbs
     }
 
-    fn test_query(stmt: ast::Stmt) -> ast::Query {
-        ast::Query {
-            stmts: vec![stmt.clone()],
-            node_meta: ast::NodeMetas::new(Vec::new()),
-            windows: HashMap::new(),
-            scripts: HashMap::new(),
-            operators: HashMap::new(),
-            config: HashMap::new(),
-        }
+    fn holds(&self, id: u64, origin_uri: &Option<EventOriginUri>) -> bool {
+        self.holding.contains_key(&(id, origin_uri.clone()))
     }
 
-    fn test_event(s: u64) -> Event {
-        Event {
-            origin_uri: None,
-            is_batch: false,
-            id: s,
-            ingest_ns: s * 1_000_000_000,
-            data: Value::from(json!({
+    fn take_cbs(&mut self) -> Vec<Event> {
+        mem::take(&mut self.cbs)
+    }
+
+    fn handles_signal(&self) -> bool {
+        self.windows.iter().any(|w| w.window_impl.is_session())
+    }
+
+    // Sessions are closed by inactivity, so they need ticks to notice it
+    fn needs_ticks(&self) -> bool {
+        self.handles_signal()
+    }
+
+    /// Closes the sessions that were inactive for longer than their gap
+    #[allow(mutable_transmutes, clippy::transmute_ptr_to_ptr)]
+    fn on_signal(&mut self, signal: &mut Event) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let opts = Self::opts();
+        // This is sound for the same reason as in `select_event`
+        let SelectStmt {
+            stmt,
+            consts,
+            locals,
+            node_meta,
+            ..
+        }: &mut SelectStmt = unsafe { mem::transmute(self.select.suffix()) };
+        let local_stack = tremor_script::interpreter::LocalStack::with_size(*locals);
+        let ctx = EventContext::new(signal.ingest_ns, None);
+        let mut events = vec![];
+        let mut covering = vec![];
+        let mut dropped = vec![];
+        // Session windows are never combined with other windows
+        if let (Some(window), Some(held)) = (self.windows.first(), self.held.first_mut()) {
+            // This is sound since we only add mutability to groups
+            let groups = unsafe { window.dims.mut_suffix() };
+            let expired: Vec<String> = groups
+                .iter()
+                .filter(|(_, group)| group.window.expired(signal.ingest_ns))
+                .map(|(group_str, _)| group_str.clone())
+                .collect();
+            for group_str in expired {
+                let group = if let Some(group) = groups.remove(&group_str) {
+                    group
+                } else {
+                    continue;
+                };
+                let mut ids = held.remove(&group_str).unwrap_or_default();
+                let last = self.last_events.remove(&group_str);
+                consts[WINDOW_CONST_ID] = Value::from(window.name.to_string());
+                consts[GROUP_CONST_ID] = group.group.clone();
+                consts[GROUP_CONST_ID].push(group_str).ok();
+                let env = Env {
+                    context: &ctx,
+                    consts: &consts,
+                    aggrs: &group.aggrs,
+                    meta: &node_meta,
+                    recursion_limit: tremor_script::recursion_limit(),
+                };
+                // The session is emitted with the latest event it saw
+                let (unwind_event, event_meta) = last
+                    .as_ref()
+                    .map_or((&NULL, &NULL), |last| last.data.parts());
+                let result =
+                    stmt.target
+                        .run(opts, &env, unwind_event, &NULL, event_meta, &local_stack)?;
+                if let Some(guard) = &stmt.maybe_having {
+                    let test = guard.run(opts, &env, &result, &NULL, &NULL, &local_stack)?;
+                    if let Some(test) = test.as_bool() {
+                        if !test {
+                            dropped.append(&mut ids);
+                            continue;
+                        }
+                    } else {
+                        let s: &Select = &stmt;
+                        return tremor_script::errors::query_guard_not_bool(
+                            s, guard, &test, &node_meta,
+                        )?;
+                    }
+                }
+                // The session is emitted as its latest event so acknowledging
+                // it acknowledges all events in the session
+                let (id, origin_uri) = ids.last().cloned().unwrap_or((signal.id, None));
+                ids.append(&mut self.orphans);
+                covering.push(((id, origin_uri.clone()), ids));
+                events.push((
+                    "out".into(),
+                    Event {
+                        id,
+                        ingest_ns: signal.ingest_ns,
+                        origin_uri,
+                        is_batch: false,
+                        kind: None,
+                        data: (result.into_owned().into_static(), Value::object()).into(),
+                    },
+                ));
+            }
+        }
+        for (key, ids) in covering {
+            self.cover(key, ids, signal.ingest_ns);
+        }
+        self.drop_held(dropped, signal.ingest_ns);
+        self.expire_covers(signal.ingest_ns);
+        Ok(events)
+    }
+
+    // Events held by the windows are not part of the snapshot since their
+    // onramps can't acknowledge them after a restart either
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        if self.windows.is_empty() {
+            return Ok(None);
+        }
+        let node_meta = &self.select.suffix().node_meta;
+        let mut windows = Vec::with_capacity(self.windows.len());
+        for window in &self.windows {
+            let mut snapshot = Object::with_capacity(4);
+            snapshot.insert_nocheck("name".into(), Value::from(window.name.clone()));
+            snapshot.insert_nocheck("next_swap".into(), window.next_swap.into());
+            snapshot.insert_nocheck(
+                "groups".into(),
+                snapshot_groups(window.dims.suffix(), node_meta),
+            );
+            snapshot.insert_nocheck(
+                "last_groups".into(),
+                snapshot_groups(window.last_dims.suffix(), node_meta),
+            );
+            windows.push(Value::from(snapshot));
+        }
+        Ok(Some(Value::from(windows)))
+    }
+
+    #[allow(clippy::transmute_ptr_to_ptr)]
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let id = &self.id;
+        let invalid = || Error::from(format!("Invalid snapshot for select {}", id));
+        let snapshots = snapshot
+            .as_array()
+            .filter(|windows| windows.len() == self.windows.len())
+            .ok_or_else(invalid)?;
+        // This is sound for the same reason as in `select_event`, the aggregates
+        // are cloned into groups that live no longer than the statement
+        let SelectStmt {
+            aggregates,
+            node_meta,
+            ..
+        }: &SelectStmt<'static> = unsafe { mem::transmute(self.select.suffix()) };
+        for (window, snapshot) in self.windows.iter_mut().zip(snapshots) {
+            if snapshot.get("name").and_then(Value::as_str) != Some(window.name.as_str()) {
+                return Err(invalid());
+            }
+            window.next_swap = snapshot
+                .get("next_swap")
+                .and_then(Value::as_u64)
+                .ok_or_else(invalid)?;
+            for (dims, key) in &[(&window.dims, "groups"), (&window.last_dims, "last_groups")] {
+                // This is sound since we only add mutability to groups
+                let groups = unsafe { dims.mut_suffix() };
+                let snapshot = snapshot.get(*key).ok_or_else(invalid)?;
+                restore_groups(groups, &window.window_impl, aggregates, snapshot, node_meta)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::float_cmp)]
+    use super::*;
+    use crate::SignalKind;
+    use simd_json::borrowed::Value;
+    use simd_json::json;
+    use tremor_script::ast::{self, Ident, ImutExpr, Literal};
+
+    fn test_target<'test>() -> ast::ImutExpr<'test> {
+        let target: ast::ImutExpr<'test> = ImutExpr::from(ast::Literal {
+            mid: 0,
+            value: Value::from(42),
+        });
+        target
+    }
+
+    fn test_stmt(target: ast::ImutExpr) -> ast::Select {
+        tremor_script::ast::Select {
+            mid: 0,
+            from: (Ident::from("in"), Ident::from("out")),
+            into: (Ident::from("out"), Ident::from("in")),
+            target,
+            maybe_where: Some(ImutExpr::from(ast::Literal {
+                mid: 0,
+                value: Value::from(true),
+            })),
+            windows: vec![],
+            maybe_group_by: None,
+            maybe_join: None,
+            maybe_order_by: None,
+            maybe_limit: None,
+            maybe_having: None,
+        }
+    }
+
+    fn test_query(stmt: ast::Stmt) -> ast::Query {
+        ast::Query {
+            stmts: vec![stmt.clone()],
+            node_meta: ast::NodeMetas::new(Vec::new()),
+            windows: HashMap::new(),
+            scripts: HashMap::new(),
+            operators: HashMap::new(),
+            config: HashMap::new(),
+        }
+    }
+
+    fn test_event(s: u64) -> Event {
+        Event {
+            origin_uri: None,
+            is_batch: false,
+            id: s,
+            ingest_ns: s * 1_000_000_000,
+            data: Value::from(json!({
                "h2g2" : 42,
             }))
             .into(),
@@ -926,6 +2271,7 @@ mod test {
                     size: 15_000_000_000,
                     next_window: None,
                     script: None,
+                    watermark: None,
                 }
                 .into(),
             ),
@@ -936,12 +2282,13 @@ mod test {
                     size: 30_000_000_000,
                     next_window: None,
                     script: None,
+                    watermark: None,
                 }
                 .into(),
             ),
         ];
         let id = "select".to_string();
-        TrickleSelect::with_stmt(id, &groups, windows, &stmt)
+        TrickleSelect::with_stmt(id, &groups, windows, None, &stmt)
     }
 
     fn try_enqueue(
@@ -974,10 +2321,10 @@ mod test {
         }
     }
 
-    fn parse_query(
+    fn parse_stmt(
         file_name: String,
         query: &str,
-    ) -> Result<crate::op::trickle::select::TrickleSelect> {
+    ) -> Result<tremor_script::query::StmtRentalWrapper> {
         let reg = tremor_script::registry();
         let aggr_reg = tremor_script::aggr_registry();
         let module_path = tremor_script::path::load();
@@ -992,13 +2339,32 @@ mod test {
         )
         .map_err(tremor_script::errors::CompilerError::error)?;
 
+        // Declarations come first, the statement under test is the last one
         let stmt_rental = tremor_script::query::StmtRental::new(Arc::new(query.clone()), |q| {
-            q.suffix().stmts[0].clone()
+            let stmts = &q.suffix().stmts;
+            stmts[stmts.len() - 1].clone()
         });
-        let stmt = tremor_script::query::StmtRentalWrapper {
+        Ok(tremor_script::query::StmtRentalWrapper {
             stmt: Arc::new(stmt_rental),
-        };
-        Ok(test_select(stmt)?)
+        })
+    }
+
+    fn parse_query(
+        file_name: String,
+        query: &str,
+    ) -> Result<crate::op::trickle::select::TrickleSelect> {
+        test_select(parse_stmt(file_name, query)?)
+    }
+
+    fn parse_sliding_query(
+        file_name: String,
+        query: &str,
+        window: WindowImpl,
+    ) -> Result<crate::op::trickle::select::TrickleSelect> {
+        let stmt = parse_stmt(file_name, query)?;
+        let groups = SelectDims::from_query(stmt.stmt.clone());
+        let windows = vec![("sliding".into(), window)];
+        TrickleSelect::with_stmt("select".to_string(), &groups, windows, None, &stmt)
     }
 
     #[test]
@@ -1032,6 +2398,66 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let query = "select aggr::stats::count() from in group by event.h2g2 into out;";
+        let mut op = parse_query("test.trickle".to_string(), query)?;
+        assert!(try_enqueue(&mut op, test_event(0))?.is_none());
+        assert!(try_enqueue(&mut op, test_event(1))?.is_none());
+        let snapshot = op.snapshot()?.expect("no snapshot");
+
+        let mut op = parse_query("test.trickle".to_string(), query)?;
+        op.restore(&snapshot)?;
+        assert!(try_enqueue(&mut op, test_event(2))?.is_none());
+        let (out, event) = try_enqueue(&mut op, test_event(15))?.expect("no event");
+        assert_eq!("out", out);
+        assert_eq!(*event.data.suffix().value(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_skips_dds_histogram() -> Result<()> {
+        let query = "select aggr::stats::dds(event.h2g2) from in group by event.h2g2 into out;";
+        let mut op = parse_query("test.trickle".to_string(), query)?;
+        // enough values for the aggregate to switch to a histogram
+        for id in 0..10_000 {
+            let event = Event {
+                ingest_ns: 0,
+                ..test_event(id)
+            };
+            assert!(try_enqueue(&mut op, event)?.is_none());
+        }
+        let snapshot = op.snapshot()?.expect("no snapshot");
+
+        let mut op = parse_query("test.trickle".to_string(), query)?;
+        op.restore(&snapshot)?;
+        assert!(try_enqueue(&mut op, test_event(2))?.is_none());
+        let (out, event) = try_enqueue(&mut op, test_event(15))?.expect("no event");
+        assert_eq!("out", out);
+        // the histogram started over
+        assert_eq!(
+            Some(1),
+            event
+                .data
+                .suffix()
+                .value()
+                .get("count")
+                .and_then(Value::as_u64)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn restore_mismatched_windows() -> Result<()> {
+        let mut op = parse_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+        )?;
+        let snapshot = Value::from(vec![Value::null()]);
+        assert!(op.restore(&snapshot).is_err());
+        Ok(())
+    }
+
     #[test]
     fn count_tilt() -> Result<()> {
         // Windows are 15s and 30s
@@ -1076,6 +2502,494 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn cb_tilt() -> Result<()> {
+        // Windows are 15s and 30s
+        let mut op = parse_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+        )?;
+        let ack = |id| Event::cb(CBAction::Ack, 0, id, None);
+        for i in &[0, 1, 15, 16, 30, 31] {
+            try_enqueue(&mut op, test_event(*i))?;
+        }
+        // the 15s window emitted but its events are still in the 30s window
+        assert!(op.on_cb(ack(15)).is_empty());
+        assert!(op.on_cb(ack(30)).is_empty());
+        assert!(op.holds(0, &None));
+
+        // At 45 both windows emit and cover everything before
+        assert!(try_enqueue_two(&mut op, test_event(45))?.is_some());
+        let mut ids: Vec<_> = op.on_cb(ack(45)).iter().map(|e| e.id).collect();
+        ids.sort_unstable();
+        assert_eq!(vec![0, 1, 15, 16, 30, 31], ids);
+        assert!(!op.holds(0, &None));
+        assert!(op.holds(45, &None));
+
+        // events we don't know pass through
+        let other = op.on_cb(ack(99));
+        assert_eq!(1, other.len());
+        assert_eq!(99, other[0].id);
+        Ok(())
+    }
+
+    #[test]
+    fn cb_having() -> Result<()> {
+        let mut op = parse_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out having false;",
+        )?;
+        for i in &[0, 1, 15, 16, 30, 31] {
+            assert!(try_enqueue(&mut op, test_event(*i))?.is_none());
+        }
+        assert!(op.take_cbs().is_empty());
+
+        // At 45 both windows are filtered out, so nothing covers their events
+        assert!(try_enqueue(&mut op, test_event(45))?.is_none());
+        let cbs = op.take_cbs();
+        assert!(cbs.iter().all(|e| e.cb_action() == Some(CBAction::Ack)));
+        let mut ids: Vec<_> = cbs.iter().map(|e| e.id).collect();
+        ids.sort_unstable();
+        assert_eq!(vec![0, 1, 15, 16, 30, 31], ids);
+        assert!(!op.holds(0, &None));
+        assert!(op.holds(45, &None));
+        Ok(())
+    }
+
+    #[test]
+    fn cb_expired() -> Result<()> {
+        let mut op = parse_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+        )?;
+        for i in &[0, 1, 15, 16, 30, 31, 45] {
+            try_enqueue(&mut op, test_event(*i))?;
+        }
+        assert!(op.take_cbs().is_empty());
+
+        // What was emitted at 45 was never acknowledged
+        try_enqueue(&mut op, test_event(345))?;
+        let mut ids: Vec<_> = op
+            .take_cbs()
+            .iter()
+            .filter(|e| e.cb_action() == Some(CBAction::Fail))
+            .map(|e| e.id)
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(vec![0, 1, 15, 16, 30, 31], ids);
+        assert!(op.on_cb(Event::cb(CBAction::Ack, 0, 45, None)).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn sliding_count() -> Result<()> {
+        let mut op = parse_sliding_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::sum(event.h2g2) from in into out;",
+            SlidingWindowOnNumber::from_stmt(3, None).into(),
+        )?;
+        let (_, event) = try_enqueue(&mut op, test_event(0))?.expect("no event 1");
+        assert_eq!(*event.data.suffix().value(), 42.0);
+        let (_, event) = try_enqueue(&mut op, test_event(1))?.expect("no event 2");
+        assert_eq!(*event.data.suffix().value(), 84.0);
+        let (_, event) = try_enqueue(&mut op, test_event(2))?.expect("no event 3");
+        assert_eq!(*event.data.suffix().value(), 126.0);
+        // The first event leaves the window
+        let (_, event) = try_enqueue(&mut op, test_event(3))?.expect("no event 4");
+        assert_eq!(*event.data.suffix().value(), 126.0);
+        Ok(())
+    }
+
+    #[test]
+    fn sliding_time() -> Result<()> {
+        let mut op = parse_sliding_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+            SlidingWindowOnTime {
+                current: 0,
+                time: 0,
+                size: 10_000_000_000,
+                ttl: None,
+                script: None,
+                watermark: None,
+                buffer: VecDeque::new(),
+            }
+            .into(),
+        )?;
+        let (_, event) = try_enqueue(&mut op, test_event(0))?.expect("no event 1");
+        assert_eq!(*event.data.suffix().value(), 1);
+        let (_, event) = try_enqueue(&mut op, test_event(5))?.expect("no event 2");
+        assert_eq!(*event.data.suffix().value(), 2);
+        // The event at 0 leaves the window
+        let (_, event) = try_enqueue(&mut op, test_event(10))?.expect("no event 3");
+        assert_eq!(*event.data.suffix().value(), 2);
+        // Both prior events leave the window
+        let (_, event) = try_enqueue(&mut op, test_event(30))?.expect("no event 4");
+        assert_eq!(*event.data.suffix().value(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn sliding_time_out_of_order() -> Result<()> {
+        let mut op = parse_sliding_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+            SlidingWindowOnTime {
+                current: 0,
+                time: 0,
+                size: 10_000_000_000,
+                ttl: None,
+                script: None,
+                watermark: None,
+                buffer: VecDeque::new(),
+            }
+            .into(),
+        )?;
+        let (_, event) = try_enqueue(&mut op, test_event(5))?.expect("no event 1");
+        assert_eq!(*event.data.suffix().value(), 1);
+        let (_, event) = try_enqueue(&mut op, test_event(0))?.expect("no event 2");
+        assert_eq!(*event.data.suffix().value(), 2);
+        // The event at 0 leaves the window even though it arrived last
+        let (_, event) = try_enqueue(&mut op, test_event(12))?.expect("no event 3");
+        assert_eq!(*event.data.suffix().value(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn sliding_max() -> Result<()> {
+        let mut op = parse_sliding_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::max(event.id) from in into out;",
+            SlidingWindowOnNumber::from_stmt(2, None).into(),
+        )?;
+        let (_, event) = try_enqueue(&mut op, keyed_event(3, 0))?.expect("no event 1");
+        assert_eq!(*event.data.suffix().value(), 3.0);
+        let (_, event) = try_enqueue(&mut op, keyed_event(1, 0))?.expect("no event 2");
+        assert_eq!(*event.data.suffix().value(), 3.0);
+        // The maximum leaves the window, so it is computed again
+        let (_, event) = try_enqueue(&mut op, keyed_event(2, 0))?.expect("no event 3");
+        assert_eq!(*event.data.suffix().value(), 2.0);
+        Ok(())
+    }
+
+    fn parse_join_query(query: &str, window: Option<WindowImpl>) -> Result<TrickleSelect> {
+        let stmt = parse_stmt("test.trickle".to_string(), query)?;
+        let groups = SelectDims::from_query(stmt.stmt.clone());
+        TrickleSelect::with_stmt("select".to_string(), &groups, vec![], window, &stmt)
+    }
+
+    fn keyed_event(id: u64, key: u64) -> Event {
+        Event {
+            origin_uri: None,
+            is_batch: false,
+            id,
+            ingest_ns: id,
+            data: Value::from(json!({
+                "id": id,
+                "key": key,
+            }))
+            .into(),
+            kind: None,
+        }
+    }
+
+    fn join(
+        op: &mut TrickleSelect,
+        port: &str,
+        event: Event,
+    ) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let mut state = Value::null();
+        op.on_event(port, &mut state, event)
+    }
+
+    #[test]
+    fn join_window() -> Result<()> {
+        let mut op = parse_join_query(
+            "select event from in outer join responses[pair] on event.key into out;",
+            Some(
+                TumblingWindowOnNumber {
+                    count: 0,
+                    size: 3,
+                    ttl: None,
+                    script: None,
+                }
+                .into(),
+            ),
+        )?;
+        assert!(join(&mut op, "in", keyed_event(0, 1))?.is_empty());
+        assert!(join(&mut op, "in", keyed_event(1, 2))?.is_empty());
+
+        let mut r = join(&mut op, "join", keyed_event(2, 1))?;
+        assert_eq!(1, r.len());
+        let (out, event) = r.pop().expect("no event 1");
+        assert_eq!("out", out);
+        assert_eq!(2, event.id);
+        assert_eq!(
+            *event.data.suffix().value(),
+            Value::from(json!({
+                "left": {"id": 0, "key": 1},
+                "right": {"id": 2, "key": 1},
+            }))
+        );
+
+        // Waiting events are held
+        let ack = |id| Event::cb(CBAction::Ack, 0, id, None);
+        assert!(op.on_cb(ack(2)).is_empty());
+        assert!(op.holds(0, &None));
+
+        // The window closed, the unmatched event is emitted on its own with
+        // the id of the late response, which has nothing left to match
+        let mut r = join(&mut op, "join", keyed_event(3, 2))?;
+        assert_eq!(1, r.len());
+        let (_, event) = r.pop().expect("no event 2");
+        assert_eq!(3, event.id);
+        assert_eq!(
+            *event.data.suffix().value(),
+            Value::from(json!({
+                "left": {"id": 1, "key": 2},
+                "right": null,
+            }))
+        );
+        // The events of the closed window are covered by it
+        let mut ids: Vec<_> = op.on_cb(ack(3)).iter().map(|e| e.id).collect();
+        ids.sort_unstable();
+        assert_eq!(vec![0, 1, 2], ids);
+        assert!(op.holds(3, &None));
+        Ok(())
+    }
+
+    #[test]
+    fn join_table() -> Result<()> {
+        let mut op = parse_join_query(
+            "select event.right.id from in join users on event.key into out;",
+            None,
+        )?;
+        // Nothing to match yet
+        assert!(join(&mut op, "in", keyed_event(0, 1))?.is_empty());
+        assert!(join(&mut op, "join", keyed_event(1, 1))?.is_empty());
+        assert!(join(&mut op, "join", keyed_event(2, 1))?.is_empty());
+        // The table holds the latest event per key
+        let mut r = join(&mut op, "in", keyed_event(3, 1))?;
+        assert_eq!(1, r.len());
+        let (_, event) = r.pop().expect("no event");
+        assert_eq!(3, event.id);
+        assert_eq!(*event.data.suffix().value(), 2);
+        assert!(join(&mut op, "in", keyed_event(4, 2))?.is_empty());
+        // The replaced event is covered by the next emitted one
+        let ack = |id| Event::cb(CBAction::Ack, 0, id, None);
+        let ids: Vec<_> = op.on_cb(ack(3)).iter().map(|e| e.id).collect();
+        assert_eq!(vec![1, 3], ids);
+        assert!(!op.holds(1, &None));
+        assert!(op.holds(2, &None));
+
+        let mut op = parse_join_query(
+            "select event from in outer join users on event.key into out;",
+            None,
+        )?;
+        let mut r = join(&mut op, "in", keyed_event(0, 1))?;
+        let (_, event) = r.pop().expect("no outer event");
+        assert_eq!(
+            *event.data.suffix().value(),
+            Value::from(json!({
+                "left": {"id": 0, "key": 1},
+                "right": null,
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn join_sliding_window() {
+        assert!(parse_join_query(
+            "select event from in join responses[w] on event.key into out;",
+            Some(SlidingWindowOnNumber::from_stmt(3, None).into()),
+        )
+        .is_err());
+    }
+
+    fn grouped_event(ingest_ns: u64, group: &str) -> Event {
+        Event {
+            origin_uri: None,
+            is_batch: false,
+            id: ingest_ns,
+            ingest_ns,
+            data: Value::from(json!({ "g": group })).into(),
+            kind: None,
+        }
+    }
+
+    #[test]
+    fn order_by_limit() -> Result<()> {
+        let mut op = parse_sliding_query(
+            "test.trickle".to_string(),
+            "define tumbling window ten with interval = 10 end; select {\"g\": event.g, \"count\": aggr::stats::count()} from in[ten] group by event.g into out order by aggr::stats::count() desc limit 2;",
+            TumblingWindowOnTime {
+                ttl: None,
+                size: 10,
+                next_window: None,
+                script: None,
+                watermark: None,
+            }
+            .into(),
+        )?;
+        let mut state = Value::null();
+        for (ingest_ns, group) in &[(0, "a"), (1, "b"), (2, "b"), (3, "c"), (4, "c"), (5, "c")] {
+            assert!(op
+                .on_event("in", &mut state, grouped_event(*ingest_ns, group))?
+                .is_empty());
+        }
+        // The window of `a` closes and takes the other groups with it, they
+        // are emitted with the last event they saw
+        let r = op.on_event("in", &mut state, grouped_event(10, "a"))?;
+        let emitted: Vec<_> = r
+            .iter()
+            .map(|(_, event)| event.data.suffix().value().clone_static())
+            .collect();
+        assert_eq!(
+            emitted,
+            vec![
+                Value::from(json!({"g": "c", "count": 3})),
+                Value::from(json!({"g": "b", "count": 2})),
+            ]
+        );
+        // All groups continue in the same window
+        assert!(op
+            .on_event("in", &mut state, grouped_event(15, "b"))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn order_by_limit_per_window() -> Result<()> {
+        // Windows are 15s and 30s
+        let mut op = test_select(parse_stmt(
+            "test.trickle".to_string(),
+            "define tumbling window ten with interval = 10 end; select aggr::stats::count() from in[ten] into out order by aggr::stats::count() desc limit 1;",
+        )?)?;
+        for i in &[0, 1, 15, 16, 30, 31] {
+            try_enqueue(&mut op, test_event(*i))?;
+        }
+        // Both windows emit at 45, each is limited on its own
+        let [(_, event1), (_, event2)] =
+            try_enqueue_two(&mut op, test_event(45))?.expect("no events");
+        assert_eq!(*event1.data.suffix().value(), 2);
+        assert_eq!(*event2.data.suffix().value(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn order_by_sliding_window() {
+        assert!(parse_stmt(
+            "test.trickle".to_string(),
+            "define sliding window three with size = 3 end; select event from in[three] into out limit 1;",
+        )
+        .is_err());
+    }
+
+    fn count_and_h2g2(event: &Event) -> (Option<u64>, Option<u64>) {
+        let value = event.data.suffix().value();
+        (
+            value.get_idx(0).and_then(Value::as_u64),
+            value.get_idx(1).and_then(Value::as_u64),
+        )
+    }
+
+    #[test]
+    fn session() -> Result<()> {
+        let mut op = parse_sliding_query(
+            "test.trickle".to_string(),
+            "select [aggr::stats::count(), event.h2g2] from in group by event.h2g2 into out;",
+            SessionWindow {
+                gap: 5_000_000_000,
+                ttl: None,
+                script: None,
+                last: None,
+                seen: None,
+            }
+            .into(),
+        )?;
+        assert!(op.handles_signal());
+        assert!(try_enqueue(&mut op, test_event(0))?.is_none());
+        assert!(try_enqueue(&mut op, test_event(3))?.is_none());
+        // The gap passed, the next event closes the session
+        let (out, event) = try_enqueue(&mut op, test_event(10))?.expect("no event 1");
+        assert_eq!("out", out);
+        assert_eq!((Some(2), Some(42)), count_and_h2g2(&event));
+
+        // The session stays open while the gap didn't pass
+        let mut signal = Event::signal(SignalKind::Tick, 12_000_000_000, 0);
+        assert!(op.on_signal(&mut signal)?.is_empty());
+        // And closes without new events once it did
+        let mut signal = Event::signal(SignalKind::Tick, 16_000_000_000, 1);
+        let (out, event) = op.on_signal(&mut signal)?.pop().expect("no event 2");
+        assert_eq!("out", out);
+        // It is emitted as the latest event of the session
+        assert_eq!((Some(1), Some(42)), count_and_h2g2(&event));
+        assert_eq!(10, event.id);
+        assert!(op.on_signal(&mut signal)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn session_expires_on_processing_time() {
+        let session = |last, seen| SessionWindow {
+            gap: 5,
+            ttl: None,
+            script: None,
+            last: Some(last),
+            seen: Some(seen),
+        };
+        // event time running ahead of processing time doesn't keep a
+        // session open, nor does it close early when running behind
+        assert!(session(100, 10).expired(16));
+        assert!(!session(0, 10).expired(14));
+    }
+
+    #[test]
+    fn watermark() {
+        let mut w = Watermark::new(WatermarkStrategy::Window, 2);
+        assert!(!w.is_late(10, 12));
+        assert!(w.is_late(9, 12));
+        // Late events don't advance the watermark
+        assert_eq!(10, w.max_time);
+
+        let mut w = Watermark::new(WatermarkStrategy::Max, 2);
+        assert!(!w.is_late(20, 0));
+        assert!(!w.is_late(18, 0));
+        assert!(w.is_late(17, 0));
+        assert_eq!(20, w.max_time);
+    }
+
+    #[test]
+    fn sliding_not_tilted() -> Result<()> {
+        let stmt = parse_stmt(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+        )?;
+        let groups = SelectDims::from_query(stmt.stmt.clone());
+        let windows = vec![
+            (
+                "sliding".into(),
+                SlidingWindowOnNumber::from_stmt(3, None).into(),
+            ),
+            (
+                "tumbling".into(),
+                TumblingWindowOnTime {
+                    ttl: None,
+                    size: 30_000_000_000,
+                    next_window: None,
+                    script: None,
+                    watermark: None,
+                }
+                .into(),
+            ),
+        ];
+        assert!(
+            TrickleSelect::with_stmt("select".to_string(), &groups, windows, None, &stmt).is_err()
+        );
+        Ok(())
+    }
+
     #[test]
     fn select_nowin_nogrp_nowhr_nohav() -> Result<()> {
         let target = test_target();
@@ -24,7 +24,7 @@ use op::identity::PassthroughFactory;
 use op::trickle::{
     operator::TrickleOperator,
     script::TrickleScript,
-    select::{SelectDims, TrickleSelect},
+    select::{SelectDims, TrickleSelect, Watermark, WatermarkStrategy},
     simple_select::TrickleSimpleSelect,
 };
 use petgraph::algo::is_cyclic_directed;
@@ -62,18 +62,85 @@ fn resolve_output_port(port: &(Ident, Ident)) -> OutputPort {
     }
 }
 
+/// Window parameters that only apply to windows on event time
+const WATERMARK_PARAMS: [&str; 2] = ["allowed_lateness", "watermark"];
+
+fn has_watermark_params(d: &WindowDecl) -> bool {
+    WATERMARK_PARAMS.iter().any(|p| d.params.contains_key(*p))
+}
+
+/// Time windows with a script (or `timestamp`) run on event time and
+/// track a watermark to tell late events apart
+fn window_decl_to_watermark(d: &WindowDecl) -> Result<Option<Watermark>> {
+    if d.script.is_none() {
+        return if has_watermark_params(d) {
+            Err(Error::from(
+                "Bad window configuration, `allowed_lateness` and `watermark` require a `timestamp` or script",
+            ))
+        } else {
+            Ok(None)
+        };
+    }
+    let allowed_lateness = d
+        .params
+        .get("allowed_lateness")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let strategy = match d.params.get("watermark").and_then(Value::as_str) {
+        None | Some("window") => WatermarkStrategy::Window,
+        Some("max") => WatermarkStrategy::Max,
+        Some(other) => {
+            return Err(Error::from(format!(
+                "Bad window configuration, unknown watermark strategy `{}`",
+                other
+            )))
+        }
+    };
+    Ok(Some(Watermark::new(strategy, allowed_lateness)))
+}
+
 fn window_decl_to_impl<'script>(
     d: &WindowDecl<'script>,
     stmt: &StmtRentalWrapper,
 ) -> Result<WindowImpl> {
-    use op::trickle::select::{TumblingWindowOnNumber, TumblingWindowOnTime};
+    use op::trickle::select::{
+        SessionWindow, SlidingWindowOnNumber, SlidingWindowOnTime, TumblingWindowOnNumber,
+        TumblingWindowOnTime,
+    };
+    let on_interval = d.params.contains_key("interval")
+        && (d.kind == WindowKind::Sliding || d.kind == WindowKind::Tumbling);
+    if !on_interval && has_watermark_params(d) {
+        return Err(Error::from(
+            "Bad window configuration, `allowed_lateness` and `watermark` require an `interval`",
+        ));
+    }
     match &d.kind {
-        WindowKind::Sliding => Err("Sliding windows are not yet implemented".into()),
+        WindowKind::Sliding => {
+            let script = if d.script.is_some() { Some(d) } else { None };
+            let ttl = d.params.get("eviction_period").and_then(Value::as_u64);
+            if let Some(interval) = d.params.get("interval").and_then(Value::as_u64) {
+                let watermark = window_decl_to_watermark(d)?;
+                Ok(SlidingWindowOnTime::from_stmt(interval, ttl, script, watermark, stmt).into())
+            } else if let Some(size) = d.params.get("size").and_then(Value::as_u64) {
+                if script.is_some() {
+                    Err(Error::from(
+                        "Bad window configuration, sliding windows based on `size` do not support a script",
+                    ))
+                } else {
+                    Ok(SlidingWindowOnNumber::from_stmt(size, ttl).into())
+                }
+            } else {
+                Err(Error::from(
+                    "Bad window configuration, either `size` or `interval` is required",
+                ))
+            }
+        }
         WindowKind::Tumbling => {
             let script = if d.script.is_some() { Some(d) } else { None };
             let ttl = d.params.get("eviction_period").and_then(Value::as_u64);
             if let Some(interval) = d.params.get("interval").and_then(Value::as_u64) {
-                Ok(TumblingWindowOnTime::from_stmt(interval, ttl, script, stmt).into())
+                let watermark = window_decl_to_watermark(d)?;
+                Ok(TumblingWindowOnTime::from_stmt(interval, ttl, script, watermark, stmt).into())
             } else if let Some(size) = d.params.get("size").and_then(Value::as_u64) {
                 Ok(TumblingWindowOnNumber::from_stmt(size, ttl, script, stmt).into())
             } else {
@@ -82,6 +149,17 @@ fn window_decl_to_impl<'script>(
                 ))
             }
         }
+        WindowKind::Session => {
+            let script = if d.script.is_some() { Some(d) } else { None };
+            let ttl = d.params.get("eviction_period").and_then(Value::as_u64);
+            if let Some(gap) = d.params.get("gap").and_then(Value::as_u64) {
+                Ok(SessionWindow::from_stmt(gap, ttl, script, stmt).into())
+            } else {
+                Err(Error::from(
+                    "Bad window configuration, session windows require a `gap`",
+                ))
+            }
+        }
     }
 }
 /// A Tremor Query
@@ -180,6 +258,7 @@ impl Query {
         let op = pipe_graph[id].to_op(supported_operators, None, None, None)?;
         pipe_ops.insert(id, op);
         outputs.push(id);
+
         let mut port_indexes: PortIndexMap = HashMap::new();
 
         let mut select_num = 0;
@@ -211,6 +290,24 @@ impl Query {
                         )?;
                         return Err("Missing node".into());
                     }
+                    if let Some(join) = &s.maybe_join {
+                        if !nodes.contains_key(&join.stream.0.id) {
+                            let stream = join.stream.0.id.clone().to_string();
+                            let mut h = DumbHighlighter::default();
+                            let butt = query_stream_not_defined(
+                                &s,
+                                &join.stream.0,
+                                stream,
+                                &query.node_meta,
+                            )?;
+                            tremor_script::query::Query::format_error_from_script(
+                                &self.0.source,
+                                &mut h,
+                                &butt,
+                            )?;
+                            return Err("Missing node".into());
+                        }
+                    }
 
                     let select_in = InputPort {
                         id: format!("select_{}", select_num).into(),
@@ -222,6 +319,11 @@ impl Query {
                         port: "out".into(),
                         had_port: false,
                     };
+                    let select_late = OutputPort {
+                        id: format!("select_{}", select_num).into(),
+                        port: "late".into(),
+                        had_port: true,
+                    };
                     select_num += 1;
                     let mut from = resolve_output_port(&s.from);
                     if from.id == "in" && from.port != "out" {
@@ -268,6 +370,17 @@ impl Query {
                     }
 
                     links.entry(from).or_default().push(select_in.clone());
+                    // The joined stream has a port of its own
+                    if let Some(join) = &s.maybe_join {
+                        links
+                            .entry(resolve_output_port(&join.stream))
+                            .or_default()
+                            .push(InputPort {
+                                id: select_in.id.clone(),
+                                port: "join".into(),
+                                had_port: true,
+                            });
+                    }
                     links.entry(select_out).or_default().push(into);
 
                     let node = NodeConfig {
@@ -284,6 +397,32 @@ impl Query {
                     for w in &query.windows {
                         ww.insert(w.0.clone(), window_decl_to_impl(&w.1, &that)?);
                     }
+                    // Selects on event time send late events to the `late` output
+                    if s.windows
+                        .iter()
+                        .any(|w| ww.get(&w.fqwn()).map_or(false, WindowImpl::has_watermark))
+                    {
+                        let late: Cow<'static, str> = "late".into();
+                        if !nodes.contains_key(&late) {
+                            let id = pipe_graph.add_node(NodeConfig {
+                                id: late.clone(),
+                                kind: NodeKind::Output,
+                                op_type: "passthrough".to_string(),
+                                config: None,
+                                defn: None,
+                                node: None,
+                            });
+                            nodes.insert(late.clone(), id);
+                            let op = pipe_graph[id].to_op(supported_operators, None, None, None)?;
+                            pipe_ops.insert(id, op);
+                            outputs.push(id);
+                        }
+                        links.entry(select_late).or_default().push(InputPort {
+                            id: late,
+                            port: "in".into(),
+                            had_port: false,
+                        });
+                    }
                     let op = node.to_op(supported_operators, None, Some(that), Some(ww))?;
                     pipe_ops.insert(id, op);
                     nodes.insert(select_in.id.clone(), id);
@@ -565,6 +704,25 @@ pub(crate) fn supported_operators(
                         )
                         .into());
                     };
+                    let join_window =
+                        if let tremor_script::ast::Stmt::Select(s) = node.stmt.suffix() {
+                            s.stmt
+                                .maybe_join
+                                .as_ref()
+                                .and_then(|join| join.window.as_ref())
+                                .map(|w| {
+                                    let fqwn = w.fqwn();
+                                    windows.get(&fqwn).cloned().ok_or_else(|| {
+                                        Error::from(ErrorKind::BadOpConfig(format!(
+                                            "Unknown window: {}",
+                                            &fqwn
+                                        )))
+                                    })
+                                })
+                                .transpose()?
+                        } else {
+                            None
+                        };
                     let windows: Result<Vec<(String, WindowImpl)>> =
                         if let tremor_script::ast::Stmt::Select(s) = node.stmt.suffix() {
                             s.stmt
@@ -591,6 +749,7 @@ pub(crate) fn supported_operators(
                         config.id.clone().to_string(),
                         &groups,
                         windows?,
+                        join_window,
                         &node,
                     )?)
                 }
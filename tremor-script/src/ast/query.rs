@@ -104,6 +104,7 @@ impl SelectStmt<'_> {
             }))
             && self.stmt.maybe_group_by.is_none()
             && self.stmt.windows.is_empty()
+            && self.stmt.maybe_join.is_none()
         {
             if self.stmt.maybe_having.is_none() && self.stmt.maybe_where.is_none() {
                 SelectType::Passthrough
@@ -220,6 +221,8 @@ pub enum WindowKind {
     Sliding,
     /// we're forced to make this pub because of lalrpop
     Tumbling,
+    /// we're forced to make this pub because of lalrpop
+    Session,
 }
 
 /// A window declration
@@ -270,9 +273,62 @@ pub struct Select<'script> {
     pub maybe_group_by: Option<GroupBy<'script>>,
     /// Window
     pub windows: Vec<WindowDefnRaw<'script>>,
+    /// Join clause
+    pub maybe_join: Option<Join<'script>>,
+    /// Order-By clause
+    pub maybe_order_by: Option<OrderBy<'script>>,
+    /// Limit clause
+    pub maybe_limit: Option<u64>,
 }
 impl_expr2!(Select);
 
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum OrderDirection {
+    /// Smallest first, the default
+    Asc,
+    /// Largest first
+    Desc,
+}
+
+/// An order by clause, it orders the groups a window emits when it closes
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OrderBy<'script> {
+    pub(crate) mid: usize,
+    /// The expression groups are ordered by, like the target it is run
+    /// against the group with its aggregates and event
+    pub expr: ImutExpr<'script>,
+    /// The direction groups are ordered in
+    pub direction: OrderDirection,
+}
+impl_expr2!(OrderBy);
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum JoinKind {
+    /// Only events with a match are emitted
+    Inner,
+    /// Events of the selected stream without a match are emitted as well
+    Outer,
+}
+
+/// A join clause, the joined event has a `left` field with the event of
+/// the selected stream and a `right` field with the event it matched
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Join<'script> {
+    pub(crate) mid: usize,
+    /// The type of join
+    pub kind: JoinKind,
+    /// The joined stream
+    pub stream: (Ident<'script>, Ident<'script>),
+    /// The window events wait for a match in, joins without a window
+    /// keep the latest event of the joined stream for each key instead
+    pub window: Option<WindowDefnRaw<'script>>,
+    /// The key events of both streams are matched on
+    pub key: ImutExpr<'script>,
+}
+impl_expr2!(Join);
+
 /// A group by clause
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct GroupBy<'script>(pub(crate) GroupByInt<'script>);
@@ -20,10 +20,10 @@ use super::super::raw::{
 };
 use super::{
     error_generic, error_no_consts, error_no_locals, AggrRegistry, Builder, Cow, GroupBy,
-    GroupByInt, HashMap, Helper, ImutExpr, Location, NodeMetas, OperatorDecl, OperatorKind,
-    OperatorStmt, Query, Registry, Result, ScriptDecl, ScriptStmt, Select, SelectStmt, Serialize,
-    Stmt, StreamStmt, Upable, Value, Warning, WindowDecl, WindowKind, ARGS_CONST_ID,
-    GROUP_CONST_ID, WINDOW_CONST_ID,
+    GroupByInt, HashMap, Helper, ImutExpr, Join, JoinKind, Location, NodeMetas, OperatorDecl,
+    OperatorKind, OperatorStmt, OrderBy, OrderDirection, Query, Registry, Result, ScriptDecl,
+    ScriptStmt, Select, SelectStmt, Serialize, Stmt, StreamStmt, Upable, Value, Warning,
+    WindowDecl, WindowKind, ARGS_CONST_ID, GROUP_CONST_ID, WINDOW_CONST_ID,
 };
 use crate::impl_expr;
 
@@ -400,7 +400,24 @@ pub struct WindowDeclRaw<'script> {
 impl<'script> Upable<'script> for WindowDeclRaw<'script> {
     type Target = WindowDecl<'script>;
     fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
-        let mut maybe_script = self.script.map(|s| s.up_script(helper)).transpose()?;
+        let mut params = self.params;
+        // The `timestamp` of an event is evaluated for every event, it is
+        // shorthand for a script returning it
+        let script = if let Some(i) = params.iter().position(|(name, _)| name.id == "timestamp") {
+            let (name, timestamp) = params.remove(i);
+            if self.script.is_some() {
+                return error_generic(
+                    &name,
+                    &name,
+                    &"A window can't have both a `timestamp` and a script",
+                    &helper.meta,
+                );
+            }
+            Some(ScriptRaw::new(vec![ExprRaw::Imut(timestamp)], None))
+        } else {
+            self.script
+        };
+        let mut maybe_script = script.map(|s| s.up_script(helper)).transpose()?;
         if let Some((_, ref mut warnings)) = maybe_script {
             helper.warnings.append(warnings);
             helper.warnings.sort();
@@ -411,7 +428,7 @@ impl<'script> Upable<'script> for WindowDeclRaw<'script> {
             module: helper.module.clone(),
             id: self.id,
             kind: self.kind,
-            params: up_params(self.params, helper)?,
+            params: up_params(params, helper)?,
             script: maybe_script.map(|s| s.0),
         })
     }
@@ -459,6 +476,9 @@ pub struct SelectRaw<'script> {
     pub(crate) maybe_having: Option<ImutExprRaw<'script>>,
     pub(crate) maybe_group_by: Option<GroupByRaw<'script>>,
     pub(crate) windows: Option<Vec<WindowDefnRaw<'script>>>,
+    pub(crate) maybe_join: Option<JoinRaw<'script>>,
+    pub(crate) maybe_order_by: Option<OrderByRaw<'script>>,
+    pub(crate) maybe_limit: Option<u64>,
 }
 impl_expr!(SelectRaw);
 
@@ -468,6 +488,24 @@ impl<'script> Upable<'script> for SelectRaw<'script> {
         if !helper.consts.is_empty() {
             return error_no_consts(&(self.start, self.end), &self.target, &helper.meta);
         }
+        // Ordered selects close the window of all groups at once, which
+        // only works for windows that close on time
+        if self.maybe_order_by.is_some() || self.maybe_limit.is_some() {
+            let tumbling_time = match self.windows.as_ref().map(Vec::as_slice) {
+                Some([window]) => helper.windows.get(&window.fqwn()).map_or(false, |decl| {
+                    decl.kind == WindowKind::Tumbling && decl.params.contains_key("interval")
+                }),
+                _ => false,
+            };
+            if !tumbling_time {
+                return error_generic(
+                    &self,
+                    &self,
+                    &"Order by and limit require a single tumbling window with an `interval`",
+                    &helper.meta,
+                );
+            }
+        }
         // reserve const ids for builtin const
         helper
             .consts
@@ -488,6 +526,12 @@ impl<'script> Upable<'script> for SelectRaw<'script> {
                 return error_no_locals(&(self.start, self.end), &definitely, &helper.meta);
             }
         };
+        let maybe_order_by = self.maybe_order_by.up(helper)?;
+        if helper.has_locals() {
+            if let Some(definitely) = maybe_order_by {
+                return error_no_locals(&(self.start, self.end), &definitely, &helper.meta);
+            }
+        };
         if helper.consts.remove(&vec!["window".to_owned()]) != Some(WINDOW_CONST_ID)
             || helper.consts.remove(&vec!["group".to_owned()]) != Some(GROUP_CONST_ID)
             || helper.consts.remove(&vec!["args".to_owned()]) != Some(ARGS_CONST_ID)
@@ -509,6 +553,13 @@ impl<'script> Upable<'script> for SelectRaw<'script> {
             }
         };
 
+        let maybe_join = self.maybe_join.up(helper)?;
+        if helper.has_locals() {
+            if let Some(definitely) = maybe_join {
+                return error_no_locals(&(self.start, self.end), &definitely, &helper.meta);
+            }
+        };
+
         let windows = self.windows.unwrap_or_default();
 
         let from = match self.from {
@@ -536,6 +587,63 @@ impl<'script> Upable<'script> for SelectRaw<'script> {
             maybe_having: maybe_having.map(ImutExpr),
             maybe_group_by,
             windows,
+            maybe_join,
+            maybe_order_by,
+            maybe_limit: self.maybe_limit,
+        })
+    }
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OrderByRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) expr: ImutExprRaw<'script>,
+    pub(crate) direction: OrderDirection,
+}
+impl_expr!(OrderByRaw);
+
+impl<'script> Upable<'script> for OrderByRaw<'script> {
+    type Target = OrderBy<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        Ok(OrderBy {
+            mid: helper.add_meta(self.start, self.end),
+            expr: ImutExpr(self.expr.up(helper)?),
+            direction: self.direction,
+        })
+    }
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct JoinRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) kind: JoinKind,
+    pub(crate) stream: (IdentRaw<'script>, Option<IdentRaw<'script>>),
+    pub(crate) window: Option<WindowDefnRaw<'script>>,
+    pub(crate) key: ImutExprRaw<'script>,
+}
+impl_expr!(JoinRaw);
+
+impl<'script> Upable<'script> for JoinRaw<'script> {
+    type Target = Join<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        let stream = match self.stream {
+            (stream, None) => {
+                let mut port = stream.clone();
+                port.id = Cow::Borrowed("out");
+                (stream, port)
+            }
+            (stream, Some(port)) => (stream, port),
+        };
+        Ok(Join {
+            mid: helper.add_meta(self.start, self.end),
+            kind: self.kind,
+            stream: (stream.0.up(helper)?, stream.1.up(helper)?),
+            window: self.window,
+            key: ImutExpr(self.key.up(helper)?),
         })
     }
 }
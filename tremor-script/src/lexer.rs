@@ -299,6 +299,20 @@ pub enum Token<'input> {
     Tumbling,
     /// The `sliding` keyword
     Sliding,
+    /// The `session` keyword
+    Session,
+    /// The `join` keyword
+    Join,
+    /// The `outer` keyword
+    Outer,
+    /// The `on` keyword
+    On,
+    /// The `limit` keyword
+    Limit,
+    /// The `asc` keyword
+    Asc,
+    /// The `desc` keyword
+    Desc,
     /// The `window` keyword
     Window,
     /// The `stream` keyword
@@ -389,6 +403,13 @@ impl<'input> Token<'input> {
             | Token::Set
             | Token::Use
             | Token::As
+            | Token::Session
+            | Token::Join
+            | Token::Outer
+            | Token::On
+            | Token::Limit
+            | Token::Asc
+            | Token::Desc
             | Token::Sliding
             | Token::State
             | Token::Stream
@@ -643,6 +664,13 @@ impl<'input> fmt::Display for Token<'input> {
             Token::Create => write!(f, "create"),
             Token::Tumbling => write!(f, "tumbling"),
             Token::Sliding => write!(f, "sliding"),
+            Token::Session => write!(f, "session"),
+            Token::Join => write!(f, "join"),
+            Token::Outer => write!(f, "outer"),
+            Token::On => write!(f, "on"),
+            Token::Limit => write!(f, "limit"),
+            Token::Asc => write!(f, "asc"),
+            Token::Desc => write!(f, "desc"),
             Token::Window => write!(f, "window"),
             Token::Stream => write!(f, "stream"),
             Token::Operator => write!(f, "operator"),
@@ -1510,6 +1538,13 @@ impl<'input> Lexer<'input> {
             "create" => Token::Create,
             "tumbling" => Token::Tumbling,
             "sliding" => Token::Sliding,
+            "session" => Token::Session,
+            "join" => Token::Join,
+            "outer" => Token::Outer,
+            "on" => Token::On,
+            "limit" => Token::Limit,
+            "asc" => Token::Asc,
+            "desc" => Token::Desc,
             "window" => Token::Window,
             "stream" => Token::Stream,
             "operator" => Token::Operator,
@@ -126,6 +126,10 @@ impl<'event> ValueAndMeta<'event> {
     pub fn meta(&self) -> &Value<'event> {
         &self.data.1
     }
+    /// Event metadata
+    pub fn meta_mut(&mut self) -> &mut Value<'event> {
+        &mut self.data.1
+    }
     /// Deconstruicts the value into it's parts
     pub fn into_parts(self) -> (Value<'event>, Value<'event>) {
         self.data
@@ -31,6 +31,11 @@ pub trait TremorAggrFn: DowncastSync + Sync + Send {
     fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()>;
     /// Compensate for a value being removed
     fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()>;
+    /// If `compensate` can remove a value, functions that can't are
+    /// recomputed from the values still accumulated instead
+    fn compensable(&self) -> bool {
+        true
+    }
     /// Emits the function
     fn emit<'event>(&mut self) -> FResult<Value<'event>>;
     /// Initialises an aggregate function
@@ -45,6 +50,17 @@ pub trait TremorAggrFn: DowncastSync + Sync + Send {
     /// Merges the state of a differently windowed function into this
     /// this requires `&self` and `&src` to be of the same type.
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()>;
+    /// Captures the state of the function so it can be restored
+    /// after a restart, defaults to an error for functions that can't
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Err(Error::from("Snapshots are not supported by this function").into())
+    }
+    /// Restores the state captured by `snapshot`, defaults to an error for
+    /// functions that can't
+    #[allow(unused_variables)]
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        Err(Error::from("Snapshots are not supported by this function").into())
+    }
     /// allows cloning the functions without implementing
     /// `Clone` to avoid rust complaining
     fn snot_clone(&self) -> Box<dyn TremorAggrFn>;
@@ -634,6 +650,11 @@ impl TremorAggrFnWrapper {
         self.fun.compensate(args)
     }
 
+    /// If `compensate` can remove a value
+    pub fn compensable(&self) -> bool {
+        self.fun.compensable()
+    }
+
     /// Emits the function
     pub fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         self.fun.emit()
@@ -665,6 +686,16 @@ impl TremorAggrFnWrapper {
         use std::borrow::Borrow;
         self.fun.merge(src.fun.borrow())
     }
+
+    /// Captures the state of the function
+    pub fn snapshot(&self) -> FResult<Value<'static>> {
+        self.fun.snapshot()
+    }
+
+    /// Restores the state captured by `snapshot`
+    pub fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        self.fun.restore(state)
+    }
 }
 
 impl fmt::Debug for TremorAggrFnWrapper {
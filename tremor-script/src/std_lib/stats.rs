@@ -45,6 +45,43 @@ pub fn ceil(value: f64, scale: i8) -> f64 {
     (value * multiplier).ceil() / multiplier
 }
 
+fn bad_state(name: &str, arity: usize) -> FunctionError {
+    FunctionError::RuntimeError {
+        mfa: mfa("stats", name, arity),
+        error: "Invalid state snapshot".to_string(),
+    }
+}
+
+/// `None` for values that are neither `null` nor a number
+fn optional_f64(v: &Value) -> Option<Option<f64>> {
+    if v.is_null() {
+        Some(None)
+    } else {
+        v.cast_f64().map(Some)
+    }
+}
+
+fn percentiles_snapshot(percentiles: &[(String, f64)]) -> Value<'static> {
+    Value::from(
+        percentiles
+            .iter()
+            .map(|(name, p)| Value::from(vec![Value::from(name.clone()), Value::from(*p)]))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn restore_percentiles(state: &Value) -> Option<Vec<(String, f64)>> {
+    state
+        .get("percentiles")?
+        .as_array()?
+        .iter()
+        .map(|p| {
+            let name = p.get_idx(0)?.as_str()?.to_string();
+            Some((name, p.get_idx(1)?.cast_f64()?))
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug, Default)]
 struct Count(i64);
 impl TremorAggrFn for Count {
@@ -70,6 +107,13 @@ impl TremorAggrFn for Count {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(Value::from(self.0))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        self.0 = state.as_i64().ok_or_else(|| bad_state("count", 0))?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -115,6 +159,13 @@ impl TremorAggrFn for Sum {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(Value::from(self.0))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        self.0 = state.cast_f64().ok_or_else(|| bad_state("sum", 1))?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -168,6 +219,16 @@ impl TremorAggrFn for Mean {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(Value::from(vec![Value::from(self.0), Value::from(self.1)]))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let bad = || bad_state("mean", 1);
+        let state = state.as_array().ok_or_else(bad)?;
+        self.0 = state.get(0).and_then(Value::as_i64).ok_or_else(bad)?;
+        self.1 = state.get(1).and_then(Value::cast_f64).ok_or_else(bad)?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -209,6 +270,9 @@ impl TremorAggrFn for Min {
 
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         Ok(Value::from(self.0.unwrap_or_default()))
     }
@@ -225,6 +289,13 @@ impl TremorAggrFn for Min {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(self.0.map_or_else(Value::null, Value::from))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        self.0 = optional_f64(state).ok_or_else(|| bad_state("min", 1))?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -252,6 +323,9 @@ impl TremorAggrFn for Max {
         // FIXME: how?
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         Ok(Value::from(self.0.unwrap_or_default()))
     }
@@ -268,6 +342,13 @@ impl TremorAggrFn for Max {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(self.0.map_or_else(Value::null, Value::from))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        self.0 = optional_f64(state).ok_or_else(|| bad_state("max", 1))?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -337,6 +418,24 @@ impl TremorAggrFn for Var {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(Value::from(vec![
+            Value::from(self.n),
+            Value::from(self.k),
+            Value::from(self.ex),
+            Value::from(self.ex2),
+        ]))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let bad = || bad_state("var", 1);
+        let state = state.as_array().ok_or_else(bad)?;
+        let f = |i: usize| state.get(i).and_then(Value::cast_f64).ok_or_else(bad);
+        self.n = state.get(0).and_then(Value::as_u64).ok_or_else(bad)?;
+        self.k = f(1)?;
+        self.ex = f(2)?;
+        self.ex2 = f(3)?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -375,6 +474,12 @@ impl TremorAggrFn for Stdev {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        self.0.snapshot()
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        self.0.restore(state)
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -467,6 +572,7 @@ impl TremorAggrFn for Dds {
                     for v in self.cache.drain(..) {
                         histo.add(v);
                     }
+                    self.histo = Some(histo);
                 }
             }
         }
@@ -601,6 +707,9 @@ impl TremorAggrFn for Dds {
         // FIXME there's no facility for this with dds histogram, punt for now
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
 
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
         let other: Option<&Self> = src.downcast_ref::<Self>();
@@ -660,6 +769,37 @@ impl TremorAggrFn for Dds {
         self.histo = None;
         self.cache.clear();
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        if self.histo.is_some() {
+            // DDSketch gives no access to its buckets
+            return Err(FunctionError::RuntimeError {
+                mfa: mfa("stats", "dds", 2),
+                error: "Can not snapshot a dds histogram".to_string(),
+            });
+        }
+        Ok(Value::from(hashmap! {
+            "cache".into() => Value::from(self.cache.clone()),
+            "percentiles".into() => percentiles_snapshot(&self.percentiles),
+            "percentiles_set".into() => Value::from(self.percentiles_set),
+        }))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let bad = || bad_state("dds", 2);
+        self.cache = state
+            .get("cache")
+            .and_then(Value::as_array)
+            .ok_or_else(bad)?
+            .iter()
+            .map(|v| v.cast_f64().ok_or_else(bad))
+            .collect::<FResult<_>>()?;
+        self.percentiles = restore_percentiles(state).ok_or_else(bad)?;
+        self.percentiles_set = state
+            .get("percentiles_set")
+            .and_then(Value::as_bool)
+            .ok_or_else(bad)?;
+        self.histo = None;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -668,6 +808,26 @@ impl TremorAggrFn for Dds {
     }
 }
 
+/// Rebuilds a histogram from its recorded values and their counts
+fn restore_hdr(recorded: &[Value], max: u64) -> FResult<Histogram<u64>> {
+    let err = |e: &dyn std::fmt::Debug| FunctionError::RuntimeError {
+        mfa: mfa("stats", "hdr", 2),
+        error: format!("failed to restore hdr storage: {:?}", e),
+    };
+    let mut histo: Histogram<u64> = Histogram::new_with_bounds(1, max, 2).map_err(|e| err(&e))?;
+    histo.auto(true);
+    for v in recorded {
+        let value = v.get_idx(0).and_then(Value::as_u64);
+        let count = v.get_idx(1).and_then(Value::as_u64);
+        if let (Some(value), Some(count)) = (value, count) {
+            histo.record_n(value, count).map_err(|e| err(&e))?;
+        } else {
+            return Err(bad_state("hdr", 2));
+        }
+    }
+    Ok(histo)
+}
+
 #[derive(Clone)]
 struct Hdr {
     histo: Option<Histogram<u64>>,
@@ -845,6 +1005,9 @@ impl TremorAggrFn for Hdr {
         // FIXME there's no facility for this with hdr histogram, punt for now
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         let mut p = hashmap! {};
         if let Some(histo) = &self.histo {
@@ -900,6 +1063,50 @@ impl TremorAggrFn for Hdr {
         self.max = 0;
         self.cache.clear();
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        let histo = self.histo.as_ref().map_or_else(Value::null, |histo| {
+            Value::from(
+                histo
+                    .iter_recorded()
+                    .map(|v| {
+                        Value::from(vec![
+                            Value::from(v.value_iterated_to()),
+                            Value::from(v.count_at_value()),
+                        ])
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        });
+        Ok(Value::from(hashmap! {
+            "cache".into() => Value::from(self.cache.clone()),
+            "histo".into() => histo,
+            "max".into() => Value::from(self.max),
+            "percentiles".into() => percentiles_snapshot(&self.percentiles),
+            "percentiles_set".into() => Value::from(self.percentiles_set),
+        }))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let bad = || bad_state("hdr", 2);
+        self.cache = state
+            .get("cache")
+            .and_then(Value::as_array)
+            .ok_or_else(bad)?
+            .iter()
+            .map(|v| v.as_u64().ok_or_else(bad))
+            .collect::<FResult<_>>()?;
+        self.max = state.get("max").and_then(Value::as_u64).ok_or_else(bad)?;
+        self.percentiles = restore_percentiles(state).ok_or_else(bad)?;
+        self.percentiles_set = state
+            .get("percentiles_set")
+            .and_then(Value::as_bool)
+            .ok_or_else(bad)?;
+        self.histo = state
+            .get("histo")
+            .and_then(Value::as_array)
+            .map(|recorded| restore_hdr(recorded, self.max()))
+            .transpose()?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -1130,6 +1337,50 @@ mod test {
         Ok(())
     }
 
+    /// Restores a snapshot of `src` into `dst` by way of its json form
+    fn restore_into(src: &dyn TremorAggrFn, dst: &mut dyn TremorAggrFn) -> Result<()> {
+        let mut json = src.snapshot()?.encode().into_bytes();
+        let snapshot: Value = simd_json::to_owned_value(&mut json)
+            .expect("invalid snapshot")
+            .into();
+        dst.restore(&snapshot)
+    }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let mut a = Mean::default();
+        a.accumulate(&[&Value::from(1)])?;
+        a.accumulate(&[&Value::from(2)])?;
+        let mut b = Mean::default();
+        restore_into(&a, &mut b)?;
+        assert_eq!(b.emit()?, 1.5);
+        assert!(b.restore(&Value::from("snot")).is_err());
+
+        let mut a = Min::default();
+        let mut b = Min::default();
+        b.accumulate(&[&Value::from(3)])?;
+        restore_into(&a, &mut b)?;
+        assert_eq!(b.0, None);
+        a.accumulate(&[&Value::from(2)])?;
+        restore_into(&a, &mut b)?;
+        assert_eq!(b.emit()?, 2.0);
+
+        let percentiles = Value::from(vec!["0.5", "0.9"]);
+        let mut a = Hdr::default();
+        let mut other = Hdr::default();
+        for i in 0..5000 {
+            a.accumulate(&[&Value::from(i % 200 + 1), &percentiles])?;
+            other.accumulate(&[&Value::from(i % 100 + 1), &percentiles])?;
+        }
+        // merging the two caches turns them into a histogram
+        a.merge(&other)?;
+        assert!(a.histo.is_some());
+        let mut b = Hdr::default();
+        restore_into(&a, &mut b)?;
+        assert_eq!(a.emit()?, b.emit()?);
+        Ok(())
+    }
+
     #[test]
     fn dds() -> Result<()> {
         use simd_json::BorrowedValue;
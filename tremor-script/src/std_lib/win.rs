@@ -12,13 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::registry::{Aggr as AggrRegistry, FResult, TremorAggrFn, TremorAggrFnWrapper};
+use crate::registry::{
+    mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
+};
 
 use simd_json::prelude::*;
 use simd_json::BorrowedValue as Value;
 
 use std::ops::RangeInclusive;
 
+fn bad_state(name: &str) -> FunctionError {
+    FunctionError::RuntimeError {
+        mfa: mfa("win", name, 1),
+        error: "Invalid state snapshot".to_string(),
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 struct First(Option<Value<'static>>);
 impl TremorAggrFn for First {
@@ -32,6 +41,9 @@ impl TremorAggrFn for First {
         // FIXME: how?
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         if let Some(v) = &self.0 {
             Ok(v.clone())
@@ -61,6 +73,15 @@ impl TremorAggrFn for First {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        // An empty or single element array to tell no value from `null`
+        Ok(Value::from(self.0.iter().cloned().collect::<Vec<_>>()))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let state = state.as_array().ok_or_else(|| bad_state("first"))?;
+        self.0 = state.first().map(Value::clone_static);
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -80,6 +101,9 @@ impl TremorAggrFn for Last {
         // FIXME: how?
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         if let Some(v) = &self.0 {
             Ok(v.clone())
@@ -109,6 +133,15 @@ impl TremorAggrFn for Last {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        // An empty or single element array to tell no value from `null`
+        Ok(Value::from(self.0.iter().cloned().collect::<Vec<_>>()))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let state = state.as_array().ok_or_else(|| bad_state("last"))?;
+        self.0 = state.first().map(Value::clone_static);
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -128,6 +161,9 @@ impl TremorAggrFn for CollectFlattened {
         // FIXME: how?
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         Ok(Value::from(self.0.clone()))
     }
@@ -148,6 +184,16 @@ impl TremorAggrFn for CollectFlattened {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(Value::from(self.0.clone()))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let state = state
+            .as_array()
+            .ok_or_else(|| bad_state("collect_flattened"))?;
+        self.0 = state.iter().map(Value::clone_static).collect();
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -172,6 +218,9 @@ impl TremorAggrFn for CollectNested {
         // FIXME: how?
         Ok(())
     }
+    fn compensable(&self) -> bool {
+        false
+    }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
         Ok(Value::Array(self.0.clone()))
     }
@@ -192,6 +241,16 @@ impl TremorAggrFn for CollectNested {
         }
         Ok(())
     }
+    fn snapshot(&self) -> FResult<Value<'static>> {
+        Ok(Value::from(self.0.clone()))
+    }
+    fn restore<'event>(&mut self, state: &Value<'event>) -> FResult<()> {
+        let state = state
+            .as_array()
+            .ok_or_else(|| bad_state("collect_nested"))?;
+        self.0 = state.iter().map(Value::clone_static).collect();
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
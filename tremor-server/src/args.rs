@@ -46,6 +46,13 @@ pub fn parse<'a>() -> App<'a, 'a> {
                 .takes_value(true)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("state-directory")
+                .long("state-directory")
+                .help("Directory where pipelines persist their state across restarts.")
+                .takes_value(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("no-api")
                 .long("no-api")
@@ -206,8 +206,11 @@ async fn run_dun() -> Result<()> {
     let storage_directory = matches
         .value_of("storage-directory")
         .map(std::string::ToString::to_string);
+    let state_directory = matches
+        .value_of("state-directory")
+        .map(std::string::ToString::to_string);
     // TODO: Allow configuring this for offramps and pipelines
-    let (world, handle) = World::start(64, storage_directory).await?;
+    let (world, handle) = World::start(64, storage_directory, state_directory).await?;
 
     // We load queries first since those are only pipelines.
     let query_files: Vec<String> = match matches.values_of("query") {
@@ -207,7 +207,7 @@ fn script_run_cmd(cmd: &ArgMatches<'_>) -> Result<()> {
         if l.is_empty() || l.starts_with('#') {
             continue;
         }
-        let mut codec = tremor_runtime::codec::lookup("json")?;
+        let mut codec = tremor_runtime::codec::lookup("json", &None)?;
 
         let enbuf = l.as_bytes().to_vec();
         let debuf = codec.decode(enbuf, 0);
@@ -329,7 +329,7 @@ fn pipe_run_cmd(_app: &TremorApp<'_>, cmd: &ArgMatches<'_>) -> Result<()> {
 
     for (num, line) in input.lines().enumerate() {
         let l = line?;
-        let mut codec = tremor_runtime::codec::lookup("json")?;
+        let mut codec = tremor_runtime::codec::lookup("json", &None)?;
         let data = codec
             .decode(l.as_bytes().to_vec(), 0)?
             .ok_or_else(|| Error::from("Failed to decode input JSON"))?;